@@ -10,32 +10,119 @@
 #![warn(missing_docs)]
 
 // Internal modules.
+mod archive;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod backend;
+mod bootstrap;
+mod cancel;
+mod check;
 mod collect;
+mod compare;
+mod copy;
+mod crypt;
 mod distribute;
+mod estimate;
+mod export;
+mod fleet;
+mod hook;
+mod location;
+mod merge;
+mod migrate;
+mod mirror;
+mod markers;
+mod notify;
+#[cfg(feature = "cli")]
+mod package;
+mod pin;
+mod plan;
+mod policy;
+mod privilege;
+mod prompt;
+mod register;
+mod resume;
+mod reporter;
+mod rollback;
+mod simulate;
+mod snapshot;
+mod space;
+mod status;
+mod store;
+mod template;
+mod toggle;
+mod walk;
 
 // Exports.
+#[cfg(feature = "async")]
+pub use asynchronous::*;
+pub use backend::*;
+pub use bootstrap::*;
+pub use cancel::*;
+pub use check::*;
 pub use collect::*;
+pub use compare::*;
+pub use copy::*;
+pub use crypt::*;
 pub use distribute::*;
+pub use estimate::*;
+pub use export::*;
+pub use fleet::*;
+pub use hook::*;
+pub use location::*;
+pub use merge::*;
+pub use migrate::*;
+pub use mirror::*;
+pub use markers::*;
+pub use notify::*;
+#[cfg(feature = "cli")]
+pub use package::*;
+pub use pin::*;
+pub use plan::*;
+pub use policy::*;
+pub use privilege::*;
+pub use register::*;
+pub use reporter::*;
+pub use resume::*;
+pub use rollback::*;
+pub use simulate::*;
+pub use snapshot::*;
+pub use space::*;
+pub use status::*;
+pub use store::*;
+pub use template::*;
+pub use toggle::*;
+pub use walk::WalkLimits;
+pub use prompt::install_prompter;
+pub use prompt::Prompter;
+pub(crate) use prompt::confirm;
+pub(crate) use prompt::print_file_diff;
+pub(crate) use prompt::prompt_action;
+pub(crate) use prompt::prompt_value;
 
 // Local imports.
-use crate::error::Error;
 use crate::CommonOptions;
+use crate::logger::log_transfer;
+use crate::RunContext;
+use crate::theme::paint;
+use crate::theme::Painted;
 
 // External library imports.
 use log::*;
 
-use colored::Colorize as _;
-use colored::ColoredString;
+use serde::Deserialize;
+use serde::Serialize;
 
 // Standard library imports.
 use std::path::Path;
+use std::path::PathBuf;
 
 
 ////////////////////////////////////////////////////////////////////////////////
-// Common file copy function.
+// Status output.
 ////////////////////////////////////////////////////////////////////////////////
 /// The action taken for a given file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
 pub enum Action {
 	/// The file was copied.
 	Copy,
@@ -46,110 +133,230 @@ pub enum Action {
 }
 
 impl Action {
-	/// Returns a colored string block representation of the Action.
-	fn colored_string(&self) -> ColoredString {
+	/// Returns the fixed-width label of the Action.
+	fn label(&self) -> &'static str {
+		match self {
+			Action::Copy => "copy  ",
+			Action::Skip => "skip  ",
+			Action::Stop => "stop  ",
+		}
+	}
+
+	/// Returns the single character glyph of the Action.
+	fn icon(&self) -> &'static str {
 		match self {
-			Action::Copy => "copy  ".bright_green(),
-			Action::Skip => "skip  ".bright_white(),
-			Action::Stop => "stop  ".bright_red(),
+			Action::Copy => "●",
+			Action::Skip => "·",
+			Action::Stop => "■",
 		}
 	}
+
+	/// Returns a colored string block representation of the Action.
+	fn colored_string(&self) -> Painted {
+		paint(self.label().trim_end(), self.label())
+	}
+
+	/// Returns a colored glyph representation of the Action.
+	fn colored_icon(&self) -> Painted {
+		paint(self.label().trim_end(), self.icon())
+	}
 }
 
 /// The state of the source file relative to the target file.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
 pub enum State {
+	/// The source file of an optional entry was not found.
+	Absent,
+	/// The file exceeded its size or time budget.
+	Budget,
+	/// Both files were modified since they were last copied.
+	Conflict,
+	/// The entry is disabled in the stall file.
+	Disabled,
 	/// The file was not available.
 	Error,
 	/// The file was copied even though it is older than the target.
 	Force,
 	/// The source file was found, but the target was not.
 	Found,
+	/// The entry may not be copied in this direction.
+	OneWay,
 	/// The source file is newer than the target.
 	Newer,
 	/// The source file is older than the target.
 	Older,
+	/// The source file is a special file, such as a FIFO or socket.
+	Special,
+	/// One file is a directory and the other is not.
+	TypeMismatch,
+	/// The status of the file is not known.
+	Unknown,
+	/// Neither file was modified since they were last copied.
+	Synced,
 }
 
 impl State {
-	/// Returns a colored string block representation of the State.
-	fn colored_string(&self) -> ColoredString {
+	/// Returns the fixed-width label of the State.
+	fn label(&self) -> &'static str {
 		match self {
-			State::Error => "error ".bright_red(),
-			State::Force => "force ".bright_white(),
-			State::Found => "found ".bright_green(),
-			State::Newer => "newer ".bright_green(),
-			State::Older => "older ".bright_yellow(),
+			State::Absent   => "absent   ",
+			State::Budget   => "budget   ",
+			State::Conflict => "conflict ",
+			State::Disabled => "disabled ",
+			State::Error    => "error    ",
+			State::Force    => "force    ",
+			State::Found    => "found    ",
+			State::OneWay   => "one-way  ",
+			State::Newer    => "newer    ",
+			State::Older    => "older    ",
+			State::Special  => "special  ",
+			State::Synced   => "synced   ",
+			State::TypeMismatch => "mismatch ",
+			State::Unknown  => "unknown  ",
 		}
 	}
+
+	/// Returns the single character glyph of the State.
+	fn icon(&self) -> &'static str {
+		match self {
+			State::Absent   => "○",
+			State::Budget   => "⧗",
+			State::Conflict => "±",
+			State::Disabled => "⊘",
+			State::Error    => "✗",
+			State::Force    => "!",
+			State::Found    => "+",
+			State::OneWay   => "→",
+			State::Newer    => "↑",
+			State::Older    => "↓",
+			State::Special  => "◇",
+			State::Synced   => "✓",
+			State::TypeMismatch => "≠",
+			State::Unknown  => "?",
+		}
+	}
+
+	/// Returns a colored string block representation of the State.
+	fn colored_string(&self) -> Painted {
+		paint(self.label().trim_end(), self.label())
+	}
+
+	/// Returns a colored glyph representation of the State.
+	fn colored_icon(&self) -> Painted {
+		paint(self.label().trim_end(), self.icon())
+	}
+}
+
+/// Returns the indentation for status output of the current stall.
+pub fn indent(context: &RunContext) -> String {
+	"    ".repeat(context.depth)
 }
 
 /// Prints the status header.
-pub fn print_status_header() {
-	info!("{}", "    STATE ACTION FILE".bright_white().bold());
+pub fn print_status_header(common: &CommonOptions, context: &RunContext) {
+	let header = match common.icons {
+		true  => "    S A FILE",
+		false => "    STATE    ACTION FILE",
+	};
+	info!("{}{}", indent(context), paint("header", header));
+}
+
+/// Prints the meaning of each glyph if the `--icons` option is given.
+pub fn print_status_legend(common: &CommonOptions, context: &RunContext) {
+	use State::*;
+	if !common.icons { return; }
+
+	let states = [Found, Newer, Older, Force, Synced, Conflict, TypeMismatch,
+		OneWay, Special, Absent, Budget, Disabled, Error, Unknown];
+	let actions = [Action::Copy, Action::Skip, Action::Stop];
+	let legend: Vec<_> = states.iter()
+		.map(|state| format!("{} {}",
+			state.colored_icon(),
+			state.label().trim_end()))
+		.chain(actions.iter()
+			.map(|action| format!("{} {}",
+				action.colored_icon(),
+				action.label().trim_end())))
+		.collect();
+	info!("{}{} {}",
+		indent(context),
+		paint("label", "Legend:"),
+		legend.join("  "));
 }
 
 /// Prints the status line for a file.
 pub fn print_status_line(
 	state: State,
 	action: Action,
-	mut path: &Path,
-	common: &CommonOptions)
+	path: &Path,
+	common: &CommonOptions,
+	context: &RunContext)
 {
-	if common.short_names {
-		// Fall back to full name if `Path::file_name` method returns `None`.
-		// This should never happen, but there's no reason to fail.
-		if let Some(name) = path.file_name() {
-			path = name.as_ref();
-		}
+	let path = format_path(path, common);
+	match common.icons {
+		true  => info!("{}    {} {} {}",
+			indent(context),
+			state.colored_icon(),
+			action.colored_icon(),
+			path.display()),
+		false => info!("{}    {}{} {}",
+			indent(context),
+			state.colored_string(),
+			action.colored_string(),
+			path.display()),
 	}
-
-	info!("    {}{} {}", 
-		state.colored_string(),
-		action.colored_string(),
-		path.display());
 }
 
-
-////////////////////////////////////////////////////////////////////////////////
-// Common file copy function.
-////////////////////////////////////////////////////////////////////////////////
-/// Copies a file from `source` to `target` using the given `CopyMethod`
-pub fn copy_file(source: &Path, target: &Path, method: CopyMethod)
-	-> Result<(), Error>
+/// Prints the number of files and bytes copied by a command, and the rate at
+/// which they were copied.
+pub fn print_copy_summary(
+	stats: &CopyStats,
+	common: &CommonOptions,
+	context: &RunContext)
 {
-	use CopyMethod::*;
-	match method {
-		None => trace!("no-run flag was specified: \
-            Not copying data from {:?} to {:?}", source, target),
-
-		Subprocess => {
-			let status = if cfg!(target_os = "windows") {
-			    std::process::Command::new("COPY")
-			            .arg(source)
-			            .arg(target)
-			            .status()
-			} else {
-			    std::process::Command::new("cp")
-			            .arg(source)
-			            .arg(target)
-			            .status()
-			};
-			let _ = status.expect("execute copy command");
-		},
-	}
-	Ok(())
+	let verb = match common.dry_run {
+		true  => "Would copy",
+		false => "Copied",
+	};
+	log_transfer(Level::Info, &stats.transfer(), format_args!(
+		"{}{} {} {} ({}) in {:.2}s, {}/s.",
+		indent(context),
+		paint("label", verb),
+		stats.files,
+		if stats.files == 1 { "file" } else { "files" },
+		format_bytes(stats.bytes),
+		stats.elapsed.as_secs_f64(),
+		format_bytes(stats.throughput() as u64)));
 }
 
 
 ////////////////////////////////////////////////////////////////////////////////
-// CopyMethod
+// Path display.
 ////////////////////////////////////////////////////////////////////////////////
-/// The method to use when copying files.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum CopyMethod {
-	/// Do not copy files.
-	None,
-	/// Copy files using a command in a subprocess.
-	Subprocess,
+/// Formats a path for display in command output.
+///
+/// With the `--short-names` option, only the file name is shown. Otherwise,
+/// paths within the home directory are shown relative to it (as `~/...`),
+/// unless the `--absolute` option is given.
+pub fn format_path(path: &Path, common: &CommonOptions) -> PathBuf {
+	if common.short_names {
+		// Fall back to full name if `Path::file_name` method returns `None`.
+		// This should never happen, but there's no reason to fail.
+		if let Some(name) = path.file_name() {
+			return name.into();
+		}
+	}
+
+	if !common.absolute {
+		let home = std::env::var_os("HOME")
+			.or_else(|| std::env::var_os("USERPROFILE"))
+			.filter(|home| !home.is_empty());
+		if let Some(rel) = home.as_ref()
+			.and_then(|home| path.strip_prefix(home).ok())
+		{
+			return Path::new("~").join(rel);
+		}
+	}
+	path.to_path_buf()
 }
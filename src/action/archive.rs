@@ -0,0 +1,486 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Access to files stored within archives.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+#[cfg(feature = "archive")]
+use crate::action::replace_file;
+#[cfg(feature = "archive")]
+use crate::action::temp_path;
+use crate::error::Error;
+#[cfg(feature = "archive")]
+use crate::error::Context;
+#[cfg(feature = "archive")]
+use crate::error::InvalidFile;
+#[cfg(not(feature = "archive"))]
+use crate::error::FeatureDisabled;
+
+// External library imports.
+#[cfg(feature = "archive")]
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+use std::time::SystemTime;
+#[cfg(feature = "archive")]
+use std::fs::File;
+#[cfg(feature = "archive")]
+use std::io::Read;
+#[cfg(feature = "archive")]
+use std::io::Write;
+#[cfg(feature = "archive")]
+use std::time::Duration;
+#[cfg(feature = "archive")]
+use std::time::UNIX_EPOCH;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ArchiveKind
+////////////////////////////////////////////////////////////////////////////////
+/// The supported archive formats.
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArchiveKind {
+    /// An uncompressed tar archive.
+    Tar,
+    /// A gzip compressed tar archive.
+    TarGz,
+    /// A zip archive.
+    Zip,
+}
+
+#[cfg(feature = "archive")]
+impl ArchiveKind {
+    /// Determines the archive format from the extension of the given path.
+    pub(crate) fn from_path(path: &Path) -> Result<Self, Error> {
+        let name = path.file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(InvalidFile)?
+            .to_lowercase();
+
+        if name.ends_with(".zip") {
+            Ok(ArchiveKind::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Ok(ArchiveKind::Tar)
+        } else {
+            Err(Error::msg(format!(
+                "unrecognized archive format: {}", path.display())))
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MemberInfo
+////////////////////////////////////////////////////////////////////////////////
+/// Metadata for a file within an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+pub(crate) struct MemberInfo {
+    /// The modification time of the file.
+    pub(crate) modified: SystemTime,
+    /// The uncompressed size of the file in bytes.
+    pub(crate) size: u64,
+}
+
+/// Returns the [`MemberInfo`] of the `member` file within the `archive`, or
+/// `None` if the archive does not contain the member.
+///
+/// Zip archives store timestamps without a time zone, so they are treated as
+/// UTC.
+///
+/// [`MemberInfo`]: struct.MemberInfo.html
+#[cfg(feature = "archive")]
+pub(crate) fn member_info(archive: &Path, member: &Path)
+    -> Result<Option<MemberInfo>, Error>
+{
+    let file = File::open(archive)
+        .with_context(|| "open archive")?;
+
+    match ArchiveKind::from_path(archive)? {
+        ArchiveKind::Tar   => tar_member_info(file, member),
+        ArchiveKind::TarGz => tar_member_info(
+            flate2::read::GzDecoder::new(file),
+            member),
+        ArchiveKind::Zip   => {
+            let mut zip = zip::ZipArchive::new(file)
+                .with_context(|| "read zip archive")?;
+            let name = member_name(member)?;
+            let info = match zip.by_name(&name) {
+                Ok(zip_file) => Some(MemberInfo {
+                    modified: system_time_from_zip(&zip_file.last_modified()),
+                    size: zip_file.size(),
+                }),
+                Err(zip::result::ZipError::FileNotFound) => None,
+                Err(e) => return Err(e)
+                    .with_context(|| "read zip archive member"),
+            };
+            Ok(info)
+        },
+    }
+}
+
+/// Returns the [`MemberInfo`] of the `member` file within the `archive`.
+/// Archived entries are not supported without the `archive` feature.
+///
+/// [`MemberInfo`]: struct.MemberInfo.html
+#[cfg(not(feature = "archive"))]
+pub(crate) fn member_info(_archive: &Path, _member: &Path)
+    -> Result<Option<MemberInfo>, Error>
+{
+    Err(FeatureDisabled {
+        feature: "archive",
+        operation: "reading archived entries",
+    }.into())
+}
+
+/// Returns the [`MemberInfo`] of a member in a tar archive.
+///
+/// [`MemberInfo`]: struct.MemberInfo.html
+#[cfg(feature = "archive")]
+fn tar_member_info<R>(reader: R, member: &Path)
+    -> Result<Option<MemberInfo>, Error>
+    where R: Read
+{
+    let mut tar = tar::Archive::new(reader);
+    for tar_entry in tar.entries().with_context(|| "read tar archive")? {
+        let tar_entry = tar_entry.with_context(|| "read tar archive entry")?;
+        if tar_entry.path()? == member {
+            let mtime = tar_entry.header().mtime()?;
+            return Ok(Some(MemberInfo {
+                modified: UNIX_EPOCH + Duration::from_secs(mtime),
+                size: tar_entry.header().size()?,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// extract_member
+////////////////////////////////////////////////////////////////////////////////
+/// Extracts the `member` file from the `archive`, writing it to `target`.
+#[cfg(feature = "archive")]
+pub(crate) fn extract_member(archive: &Path, member: &Path, target: &Path)
+    -> Result<(), Error>
+{
+    trace!("Extracting {:?} from archive {:?} to {:?}",
+        member, archive, target);
+    let mut out = File::create(target)
+        .with_context(|| "create extracted file")?;
+    extract_to(archive, member, &mut out)
+}
+
+/// Extracts the `member` file from the `archive`, writing it to `target`.
+/// Archived entries are not supported without the `archive` feature.
+#[cfg(not(feature = "archive"))]
+pub(crate) fn extract_member(_archive: &Path, _member: &Path, _target: &Path)
+    -> Result<(), Error>
+{
+    Err(FeatureDisabled {
+        feature: "archive",
+        operation: "reading archived entries",
+    }.into())
+}
+
+/// Reads the contents of the `member` file of the `archive`.
+#[cfg(feature = "archive")]
+pub(crate) fn read_member(archive: &Path, member: &Path)
+    -> Result<Vec<u8>, Error>
+{
+    trace!("Reading {:?} from archive {:?}", member, archive);
+    let mut buf = Vec::new();
+    extract_to(archive, member, &mut buf)?;
+    Ok(buf)
+}
+
+/// Reads the contents of the `member` file of the `archive`. Archived entries
+/// are not supported without the `archive` feature.
+#[cfg(not(feature = "archive"))]
+pub(crate) fn read_member(_archive: &Path, _member: &Path)
+    -> Result<Vec<u8>, Error>
+{
+    Err(FeatureDisabled {
+        feature: "archive",
+        operation: "reading archived entries",
+    }.into())
+}
+
+/// Extracts the `member` file from the `archive`, writing it to `out`.
+#[cfg(feature = "archive")]
+fn extract_to<W>(archive: &Path, member: &Path, out: &mut W)
+    -> Result<(), Error>
+    where W: Write
+{
+    let file = File::open(archive)
+        .with_context(|| "open archive")?;
+
+    let found = match ArchiveKind::from_path(archive)? {
+        ArchiveKind::Tar   => tar_extract(file, member, out)?,
+        ArchiveKind::TarGz => tar_extract(
+            flate2::read::GzDecoder::new(file),
+            member,
+            out)?,
+        ArchiveKind::Zip   => {
+            let mut zip = zip::ZipArchive::new(file)
+                .with_context(|| "read zip archive")?;
+            let mut zip_file = zip.by_name(&member_name(member)?)
+                .with_context(|| "read zip archive member")?;
+            let _ = std::io::copy(&mut zip_file, out)
+                .with_context(|| "extract zip archive member")?;
+            true
+        },
+    };
+
+    if !found {
+        return Err(Error::msg(format!("archive {} has no member {}",
+            archive.display(), member.display())));
+    }
+    Ok(())
+}
+
+/// Extracts a member from a tar archive. Returns false if the member was not
+/// found.
+#[cfg(feature = "archive")]
+fn tar_extract<R, W>(reader: R, member: &Path, out: &mut W)
+    -> Result<bool, Error>
+    where
+        R: Read,
+        W: Write,
+{
+    let mut tar = tar::Archive::new(reader);
+    for tar_entry in tar.entries().with_context(|| "read tar archive")? {
+        let mut tar_entry = tar_entry
+            .with_context(|| "read tar archive entry")?;
+        if tar_entry.path()? == member {
+            let _ = std::io::copy(&mut tar_entry, out)
+                .with_context(|| "extract tar archive member")?;
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// update_member
+////////////////////////////////////////////////////////////////////////////////
+/// Replaces the `member` file within the `archive` with the contents of
+/// `source`, adding it if it isn't already present. The archive is created if
+/// it doesn't exist.
+///
+/// The archive is rewritten to a temporary file alongside the original, which
+/// is then moved into place.
+#[cfg(feature = "archive")]
+pub(crate) fn update_member(archive: &Path, member: &Path, source: &Path)
+    -> Result<(), Error>
+{
+    trace!("Updating {:?} in archive {:?} from {:?}",
+        member, archive, source);
+    let kind = ArchiveKind::from_path(archive)?;
+    let temp = temp_path(archive)?;
+
+    let res = match kind {
+        ArchiveKind::Tar => {
+            let out = File::create(&temp)
+                .with_context(|| "create temporary archive")?;
+            tar_update(archive, member, source, out, |r| Box::new(r))
+                .map(|_| ())
+        },
+        ArchiveKind::TarGz => {
+            let out = File::create(&temp)
+                .with_context(|| "create temporary archive")?;
+            let enc = flate2::write::GzEncoder::new(
+                out,
+                flate2::Compression::default());
+            tar_update(archive, member, source, enc, |r|
+                    Box::new(flate2::read::GzDecoder::new(r)))
+                .and_then(|enc| enc.finish()
+                    .map(|_| ())
+                    .with_context(|| "write gzip stream"))
+        },
+        ArchiveKind::Zip => zip_update(archive, member, source, &temp),
+    };
+
+    match res {
+        Ok(()) => replace_file(&temp, archive)
+            .with_context(|| "replace archive"),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        },
+    }
+}
+
+/// Replaces the `member` file within the `archive` with the contents of
+/// `source`. Archived entries are not supported without the `archive`
+/// feature.
+#[cfg(not(feature = "archive"))]
+pub(crate) fn update_member(_archive: &Path, _member: &Path, _source: &Path)
+    -> Result<(), Error>
+{
+    Err(FeatureDisabled {
+        feature: "archive",
+        operation: "writing archived entries",
+    }.into())
+}
+
+/// Rewrites a tar archive into `out`, replacing `member` with the contents of
+/// `source`.
+#[cfg(feature = "archive")]
+fn tar_update<W, F>(
+    archive: &Path,
+    member: &Path,
+    source: &Path,
+    out: W,
+    decoder: F)
+    -> Result<W, Error>
+    where
+        W: Write,
+        F: FnOnce(File) -> Box<dyn Read>,
+{
+    let mut builder = tar::Builder::new(out);
+
+    if archive.exists() {
+        let file = File::open(archive)
+            .with_context(|| "open archive")?;
+        let mut tar = tar::Archive::new(decoder(file));
+        for tar_entry in tar.entries().with_context(|| "read tar archive")? {
+            let mut tar_entry = tar_entry
+                .with_context(|| "read tar archive entry")?;
+            if tar_entry.path()? == member { continue }
+            let header = tar_entry.header().clone();
+            builder.append(&header, &mut tar_entry)
+                .with_context(|| "copy tar archive entry")?;
+        }
+    }
+
+    let mut file = File::open(source)
+        .with_context(|| "open source file")?;
+    builder.append_file(member, &mut file)
+        .with_context(|| "write tar archive member")?;
+    builder.into_inner()
+        .with_context(|| "finish tar archive")
+}
+
+/// Rewrites a zip archive into `temp`, replacing `member` with the contents of
+/// `source`.
+#[cfg(feature = "archive")]
+fn zip_update(archive: &Path, member: &Path, source: &Path, temp: &Path)
+    -> Result<(), Error>
+{
+    let name = member_name(member)?;
+    let out = File::create(temp)
+        .with_context(|| "create temporary archive")?;
+    let mut writer = zip::ZipWriter::new(out);
+
+    if archive.exists() {
+        let file = File::open(archive)
+            .with_context(|| "open archive")?;
+        let mut zip = zip::ZipArchive::new(file)
+            .with_context(|| "read zip archive")?;
+        for i in 0..zip.len() {
+            let zip_file = zip.by_index_raw(i)
+                .with_context(|| "read zip archive member")?;
+            if zip_file.name() == name { continue }
+            writer.raw_copy_file(zip_file)
+                .with_context(|| "copy zip archive member")?;
+        }
+    }
+
+    let modified = source.metadata()
+        .with_context(|| "load source metadata")?
+        .modified()
+        .with_context(|| "load source modified time")?;
+    let options = zip::write::FileOptions::default()
+        .last_modified_time(zip_from_system_time(modified));
+    writer.start_file(name, options)
+        .with_context(|| "write zip archive member")?;
+    let mut file = File::open(source)
+        .with_context(|| "open source file")?;
+    let _ = std::io::copy(&mut file, &mut writer)
+        .with_context(|| "write zip archive member")?;
+    let _ = writer.finish()
+        .with_context(|| "finish zip archive")?;
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the name of a member as stored in a zip archive.
+#[cfg(feature = "archive")]
+fn member_name(member: &Path) -> Result<String, Error> {
+    let name = member.to_str().ok_or(InvalidFile)?;
+    Ok(name.replace('\\', "/"))
+}
+
+/// Converts a zip timestamp into a `SystemTime`.
+#[cfg(feature = "archive")]
+pub(crate) fn system_time_from_zip(dt: &zip::DateTime) -> SystemTime {
+    let days = days_from_civil(
+        i64::from(dt.year()),
+        i64::from(dt.month()),
+        i64::from(dt.day()));
+    let secs = days * 86_400
+        + i64::from(dt.hour()) * 3_600
+        + i64::from(dt.minute()) * 60
+        + i64::from(dt.second());
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+/// Converts a `SystemTime` into a zip timestamp. Zip timestamps cannot
+/// represent times before 1980, so those are clamped to the earliest
+/// representable time.
+#[cfg(feature = "archive")]
+pub(crate) fn zip_from_system_time(time: SystemTime) -> zip::DateTime {
+    let secs = time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+    let rem = secs.rem_euclid(86_400);
+
+    zip::DateTime::from_date_and_time(
+            year as u16,
+            month as u8,
+            day as u8,
+            (rem / 3_600) as u8,
+            (rem % 3_600 / 60) as u8,
+            (rem % 60) as u8)
+        .unwrap_or_default()
+}
+
+/// Returns the number of days since the unix epoch for the given date.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the date for the given number of days since the unix epoch.
+#[cfg(feature = "archive")]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
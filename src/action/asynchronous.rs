@@ -0,0 +1,242 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Async variants of the stall commands for tokio runtimes.
+//!
+//! The stall file is read with `tokio::fs`. Each collect, distribute, or
+//! status operation is run as a whole on tokio's blocking thread pool, so
+//! that programs running many stalls concurrently don't block their executor
+//! threads. This is how `tokio::fs` performs file I/O as well, as operating
+//! systems offer no portable asynchronous file I/O, but moving the operation
+//! once rather than for each file access shares the synchronous copy engine
+//! and avoids a handoff between threads for every read and write. The entries
+//! are cloned into the operation, and its progress is reported to an owned
+//! [`Reporter`].
+//!
+//! ### Example
+//!
+//! ```rust,no_run
+//! # use stall::action::distribute_async;
+//! # use stall::action::load_config_async;
+//! # use stall::CommonOptions;
+//! # use stall::RunContext;
+//! # use std::path::PathBuf;
+//! let runtime = tokio::runtime::Builder::new_current_thread()
+//!     .build()
+//!     .unwrap();
+//! let report = runtime.block_on(async {
+//!     let stall_dir = PathBuf::from("/home/me/stall");
+//!     let config = load_config_async(stall_dir.join(".stall")).await?;
+//!     let context = RunContext::new(&config, &stall_dir,
+//!         &CommonOptions::default());
+//!     distribute_async(stall_dir, &config.files, (), CommonOptions::default(),
+//!             context)
+//!         .await
+//! }).unwrap();
+//! println!("{}", report.summary());
+//! ```
+//!
+//! [`Reporter`]: trait.Reporter.html
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::collect;
+use crate::action::distribute;
+use crate::action::entry_status;
+use crate::action::EntryStatus;
+use crate::action::ModeDrift;
+use crate::action::Reporter;
+use crate::action::RunReport;
+use crate::action::State;
+use crate::action::StatusMode;
+use crate::CommonOptions;
+use crate::Config;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::RunContext;
+use crate::state::LastError;
+
+// Standard library imports.
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// load_config_async
+////////////////////////////////////////////////////////////////////////////////
+/// Loads the stall file at the given path without blocking.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the stall file can't be read or parsed.
+///
+/// [`Error`]: ../error/struct.Error.html
+pub async fn load_config_async<P>(path: P) -> Result<Config, Error>
+    where P: AsRef<Path>
+{
+    let bytes = tokio::fs::read(path.as_ref()).await
+        .with_context(|| "Failed to open config file.")?;
+    Config::from_bytes(&bytes)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// collect_async
+////////////////////////////////////////////////////////////////////////////////
+/// Collects the given entries into the stall directory as with [`collect`],
+/// without blocking.
+///
+/// [`collect`]: fn.collect.html
+pub async fn collect_async<'i, I, R>(
+    into: PathBuf,
+    files: I,
+    mut reporter: R,
+    common: CommonOptions,
+    context: RunContext)
+    -> Result<RunReport, StallError>
+    where
+        I: IntoIterator<Item=&'i Entry>,
+        R: Reporter + Send + 'static,
+{
+    let files: Vec<Entry> = files.into_iter().cloned().collect();
+    run_blocking(move || collect(&into, &files, &mut reporter, common,
+        context)).await
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// distribute_async
+////////////////////////////////////////////////////////////////////////////////
+/// Distributes the given entries from the stall directory as with
+/// [`distribute`], without blocking.
+///
+/// [`distribute`]: fn.distribute.html
+pub async fn distribute_async<'i, I, R>(
+    from: PathBuf,
+    files: I,
+    mut reporter: R,
+    common: CommonOptions,
+    context: RunContext)
+    -> Result<RunReport, StallError>
+    where
+        I: IntoIterator<Item=&'i Entry>,
+        R: Reporter + Send + 'static,
+{
+    let files: Vec<Entry> = files.into_iter().cloned().collect();
+    run_blocking(move || distribute(&from, &files, &mut reporter, common,
+        context)).await
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// status_async
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the status of each of the given entries as with [`entry_status`],
+/// without blocking.
+///
+/// [`entry_status`]: fn.entry_status.html
+pub async fn status_async<'e, I>(
+    stall_dir: PathBuf,
+    files: I,
+    mode: StatusMode,
+    common: CommonOptions,
+    context: RunContext)
+    -> Result<Vec<EntryStatus<'e>>, StallError>
+    where I: IntoIterator<Item=&'e Entry>,
+{
+    let entries: Vec<&'e Entry> = files.into_iter().collect();
+    let files: Vec<Entry> = entries.iter().copied().cloned().collect();
+    let statuses = run_blocking(move || {
+        let statuses = entry_status(&stall_dir, &files, mode, &common,
+            &context)?;
+        Ok(statuses.into_iter()
+            .enumerate()
+            .map(|(index, status)| DetachedStatus::new(index, status))
+            .collect::<Vec<_>>())
+    }).await?;
+
+    Ok(statuses.into_iter()
+        .map(|status| status.attach(&entries))
+        .collect())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// DetachedStatus
+////////////////////////////////////////////////////////////////////////////////
+/// An [`EntryStatus`] of a cloned entry, which refers to the entry by its
+/// index so it can be returned from the blocking thread pool.
+///
+/// [`EntryStatus`]: struct.EntryStatus.html
+#[derive(Debug)]
+struct DetachedStatus {
+    /// The index of the entry.
+    index: usize,
+    /// The state of the entry.
+    state: State,
+    /// The action which would be taken to collect the entry.
+    action: Action,
+    /// The time at which the status was computed.
+    checked: Option<SystemTime>,
+    /// The error encountered the last time the entry failed to be processed.
+    last_error: Option<LastError>,
+    /// The mode of the remote file, if it differs from the entry's mode.
+    mode_drift: Option<ModeDrift>,
+    /// The time the entry was last synced.
+    last_synced: Option<SystemTime>,
+}
+
+impl DetachedStatus {
+    /// Detaches the status of the entry at the given index.
+    fn new(index: usize, status: EntryStatus<'_>) -> Self {
+        DetachedStatus {
+            index,
+            state: status.state,
+            action: status.action,
+            checked: status.checked,
+            last_error: status.last_error,
+            mode_drift: status.mode_drift,
+            last_synced: status.last_synced,
+        }
+    }
+
+    /// Attaches the status to the entry at its index in `entries`.
+    fn attach<'e>(self, entries: &[&'e Entry]) -> EntryStatus<'e> {
+        EntryStatus {
+            entry: entries[self.index],
+            state: self.state,
+            action: self.action,
+            checked: self.checked,
+            last_error: self.last_error,
+            mode_drift: self.mode_drift,
+            last_synced: self.last_synced,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Runs the operation on tokio's blocking thread pool, resuming any panic it
+/// raises.
+async fn run_blocking<T, F>(operation: F) -> Result<T, StallError>
+    where
+        F: FnOnce() -> Result<T, StallError> + Send + 'static,
+        T: Send + 'static,
+{
+    match tokio::task::spawn_blocking(operation).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(Error::msg(format!("stall operation was cancelled: {}",
+            e)).into()),
+    }
+}
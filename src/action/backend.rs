@@ -0,0 +1,893 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Copy backends used to copy the contents of files.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::CopyMethod;
+#[cfg(feature = "progress")]
+use crate::action::format_path;
+#[cfg(feature = "progress")]
+use crate::action::indent;
+use crate::CommonOptions;
+use crate::error::BudgetExceeded;
+use crate::error::Context;
+use crate::error::CopyCommandFailed;
+use crate::error::CopyFailureKind;
+use crate::error::Error;
+use crate::error::InvalidFile;
+use crate::logger::ChildStream;
+use crate::logger::log_child_output;
+use crate::RunContext;
+
+// External library imports.
+#[cfg(feature = "progress")]
+use indicatif::ProgressBar;
+#[cfg(feature = "progress")]
+use indicatif::ProgressFinish;
+#[cfg(feature = "progress")]
+use indicatif::ProgressStyle;
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyBackend
+////////////////////////////////////////////////////////////////////////////////
+/// An implementation of copying the contents of a single file.
+///
+/// Directories, special files, and archived files are handled before a
+/// backend is used, and permissions and timestamps are applied after it, so a
+/// backend only needs to write the contents of `source` to `target`.
+pub trait CopyBackend: Send + Sync {
+    /// Returns the name of the backend, used in log messages.
+    fn name(&self) -> &str;
+
+    /// Copies the contents of the file at `source` to `target`, replacing it
+    /// if it exists.
+    ///
+    /// If a `deadline` is given, the copy should be abandoned with a
+    /// [`BudgetExceeded`] error if it has not completed by that time. The
+    /// progress of the copy should be reported to the [`CopyProgress`] of the
+    /// run context.
+    ///
+    /// [`BudgetExceeded`]: ../error/struct.BudgetExceeded.html
+    /// [`CopyProgress`]: struct.CopyProgress.html
+    fn copy_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        common: &CommonOptions,
+        context: &RunContext,
+        deadline: Option<Instant>)
+        -> Result<(), Error>;
+}
+
+impl CopyMethod {
+    /// Returns the [`CopyBackend`] for the method.
+    ///
+    /// [`CopyBackend`]: trait.CopyBackend.html
+    pub fn backend(&self) -> &'static dyn CopyBackend {
+        match self {
+            CopyMethod::None       => &NoopBackend,
+            CopyMethod::Native     => &NativeBackend,
+            CopyMethod::Subprocess => &SubprocessBackend,
+            CopyMethod::Reflink    => &ReflinkBackend,
+            CopyMethod::Auto       => &AutoBackend,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CustomBackend
+////////////////////////////////////////////////////////////////////////////////
+/// A [`CopyBackend`] registered by a program embedding stall, set in the
+/// [`RunContext`] of a command. When set, it is used in place of the
+/// backend of the copy method for every file, except when nothing is copied
+/// because of the `--dry-run` option.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::action::CopyBackend;
+/// # use stall::action::CustomBackend;
+/// # use stall::CommonOptions;
+/// # use stall::RunContext;
+/// # use stall::error::Context;
+/// # use stall::error::Error;
+/// # use std::path::Path;
+/// # use std::time::Instant;
+/// struct StdCopy;
+///
+/// impl CopyBackend for StdCopy {
+///     fn name(&self) -> &str { "std" }
+///
+///     fn copy_file(
+///         &self,
+///         source: &Path,
+///         target: &Path,
+///         _common: &CommonOptions,
+///         _context: &RunContext,
+///         _deadline: Option<Instant>)
+///         -> Result<(), Error>
+///     {
+///         let _ = std::fs::copy(source, target)
+///             .with_context(|| format!("Unable to copy {:?}", source))?;
+///         Ok(())
+///     }
+/// }
+///
+/// let mut context = RunContext::default();
+/// context.copy_backend = CustomBackend::new(StdCopy);
+/// assert_eq!(context.copy_backend.get().map(|b| b.name()), Some("std"));
+/// ```
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+#[derive(Clone, Default)]
+pub struct CustomBackend {
+    /// The registered backend, if any.
+    backend: Option<Arc<dyn CopyBackend>>,
+}
+
+impl CustomBackend {
+    /// Constructs a new `CustomBackend` using the given [`CopyBackend`].
+    ///
+    /// [`CopyBackend`]: trait.CopyBackend.html
+    pub fn new<B>(backend: B) -> Self
+        where B: CopyBackend + 'static
+    {
+        CustomBackend { backend: Some(Arc::new(backend)) }
+    }
+
+    /// Returns the registered [`CopyBackend`], if any.
+    ///
+    /// [`CopyBackend`]: trait.CopyBackend.html
+    pub fn get(&self) -> Option<&dyn CopyBackend> {
+        self.backend.as_deref()
+    }
+}
+
+impl std::fmt::Debug for CustomBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CustomBackend")
+            .field("backend", &self.get().map(CopyBackend::name))
+            .finish()
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// NoopBackend
+////////////////////////////////////////////////////////////////////////////////
+/// A [`CopyBackend`] which does not copy files, used for dry runs.
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopBackend;
+
+impl CopyBackend for NoopBackend {
+    fn name(&self) -> &str { "noop" }
+
+    fn copy_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        _common: &CommonOptions,
+        _context: &RunContext,
+        _deadline: Option<Instant>)
+        -> Result<(), Error>
+    {
+        trace!("no-run flag was specified: \
+            Not copying data from {:?} to {:?}", source, target);
+        Ok(())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// NativeBackend
+////////////////////////////////////////////////////////////////////////////////
+/// A [`CopyBackend`] which reads and writes files directly, checking the
+/// deadline and reporting progress between each block. Files are written
+/// alongside the target and moved over it once complete.
+///
+/// With the `progress` feature, a progress bar is shown while copying files
+/// larger than the [`PROGRESS_THRESHOLD`], unless the `--no-progress` option
+/// is given.
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+/// [`PROGRESS_THRESHOLD`]: constant.PROGRESS_THRESHOLD.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NativeBackend;
+
+impl CopyBackend for NativeBackend {
+    fn name(&self) -> &str { "native" }
+
+    fn copy_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        common: &CommonOptions,
+        context: &RunContext,
+        deadline: Option<Instant>)
+        -> Result<(), Error>
+    {
+        trace!("Copying data from {:?} to {:?}", source, target);
+        // The data is copied into a temporary file which replaces the target
+        // once it is complete, so that a failed copy leaves the target
+        // unchanged. A target which is a symbolic link is followed.
+        let target = std::fs::canonicalize(target)
+            .unwrap_or_else(|_| target.to_path_buf());
+        let temp = temp_path(&target)?;
+        let result = copy_data(source, &temp, &target, common, context,
+                deadline)
+            .and_then(|()| replace_file(&temp, &target)
+                .with_context(|| format!("Unable to replace {:?}", target)));
+        if result.is_err() {
+            let _ = std::fs::remove_file(&temp);
+        }
+        result?;
+        record_handled_by("native");
+        Ok(())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SubprocessBackend
+////////////////////////////////////////////////////////////////////////////////
+/// A [`CopyBackend`] which copies files with the platform's copy command.
+/// Progress is reported by polling the size of the target while the command
+/// runs.
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubprocessBackend;
+
+impl CopyBackend for SubprocessBackend {
+    fn name(&self) -> &str { "subprocess" }
+
+    fn copy_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        _common: &CommonOptions,
+        context: &RunContext,
+        deadline: Option<Instant>)
+        -> Result<(), Error>
+    {
+        let program = match cfg!(target_os = "windows") {
+            true  => "COPY",
+            false => "cp",
+        };
+        let mut child = Command::new(program)
+            .arg(source)
+            .arg(target)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| "execute copy command")?;
+
+        // Read the output from other threads so that a full pipe can't block
+        // the child.
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+        let total = file_len(source);
+        let status = match (deadline, context.progress.is_enabled()) {
+            (None, false) => Some(child.wait()?),
+            (deadline, _) => loop {
+                if let Some(status) = child.try_wait()? {
+                    break Some(status);
+                }
+                if matches!(deadline, Some(d) if Instant::now() > d) {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    break None;
+                }
+                context.progress.report(source, file_len(target), total);
+                std::thread::sleep(Duration::from_millis(10));
+            },
+        };
+
+        let stderr = stderr.join().unwrap_or_default();
+        log_child_output(program, ChildStream::Stdout,
+            &stdout.join().unwrap_or_default());
+        log_child_output(program, ChildStream::Stderr, &stderr);
+        match status {
+            Some(status) if !status.success() => {
+                let stderr = String::from_utf8_lossy(&stderr).trim().to_owned();
+                Err(CopyCommandFailed {
+                    program: program.into(),
+                    code: status.code(),
+                    kind: CopyFailureKind::from_stderr(&stderr),
+                    stderr,
+                }.into())
+            },
+            Some(_) => {
+                context.progress.report(source, total, total);
+                record_handled_by("subprocess");
+                Ok(())
+            },
+            None => Err(BudgetExceeded.into()),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ReflinkBackend
+////////////////////////////////////////////////////////////////////////////////
+/// A [`CopyBackend`] which clones files on file systems supporting
+/// copy-on-write, such as Btrfs, XFS, and APFS, so that the copy shares the
+/// data of the original until either is modified. Files which can't be
+/// cloned, such as those on other file systems or platforms, are copied
+/// natively.
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReflinkBackend;
+
+impl CopyBackend for ReflinkBackend {
+    fn name(&self) -> &str { "reflink" }
+
+    fn copy_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        common: &CommonOptions,
+        context: &RunContext,
+        deadline: Option<Instant>)
+        -> Result<(), Error>
+    {
+        trace!("Cloning {:?} to {:?}", source, target);
+        match reflink(source, target) {
+            Ok(()) => {
+                let total = file_len(source);
+                context.progress.report(source, total, total);
+                record_handled_by("reflink");
+                Ok(())
+            },
+            Err(e) if is_cross_device(&e) => {
+                debug!("Unable to clone {:?}: the target is on another file \
+                    system. Copying it instead.", source);
+                NativeBackend.copy_file(source, target, common, context,
+                    deadline)
+            },
+            Err(e) => {
+                debug!("Unable to clone {:?}: {}. Copying it instead.",
+                    source, e);
+                NativeBackend.copy_file(source, target, common, context,
+                    deadline)
+            },
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// AutoBackend
+////////////////////////////////////////////////////////////////////////////////
+/// A [`CopyBackend`] which tries each of the built-in backends in turn,
+/// falling back to the next when one is not available.
+///
+/// Files are cloned as with the [`ReflinkBackend`] if the source and target
+/// are on the same file system and it supports cloning. Otherwise, they are
+/// copied as with the [`NativeBackend`], and if that fails, as with the
+/// [`SubprocessBackend`]. Whether a file system supports cloning is probed
+/// the first time a file is copied to it, and recorded in the
+/// [`CapabilityCache`] of the options so that it is only probed once.
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+/// [`ReflinkBackend`]: struct.ReflinkBackend.html
+/// [`NativeBackend`]: struct.NativeBackend.html
+/// [`SubprocessBackend`]: struct.SubprocessBackend.html
+/// [`CapabilityCache`]: struct.CapabilityCache.html
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AutoBackend;
+
+impl CopyBackend for AutoBackend {
+    fn name(&self) -> &str { "auto" }
+
+    fn copy_file(
+        &self,
+        source: &Path,
+        target: &Path,
+        common: &CommonOptions,
+        context: &RunContext,
+        deadline: Option<Instant>)
+        -> Result<(), Error>
+    {
+        let mount = target.parent().and_then(mount_point);
+        let capable = mount.as_deref()
+            .and_then(|mount| context.capabilities.reflink(mount));
+        match (capable, same_file_system(source, target)) {
+            (_, false) => trace!("Not cloning {:?}: the target is on another \
+                file system.", source),
+            (Some(false), true) => trace!("Not cloning {:?}: the file system \
+                does not support cloning.", source),
+            (_, true) => {
+                trace!("Cloning {:?} to {:?}", source, target);
+                match (reflink(source, target), mount) {
+                    (Ok(()), mount) => {
+                        if let (None, Some(mount)) = (capable, mount) {
+                            context.capabilities.record(mount, true);
+                        }
+                        let total = file_len(source);
+                        context.progress.report(source, total, total);
+                        record_handled_by("reflink");
+                        return Ok(());
+                    },
+                    (Err(e), Some(mount)) if is_unsupported(&e) => {
+                        debug!("The file system at {:?} does not support \
+                            cloning files: {}. Copying files to it instead.",
+                            mount, e);
+                        context.capabilities.record(mount, false);
+                    },
+                    (Err(e), _) => debug!("Unable to clone {:?}: {}. \
+                        Copying it instead.", source, e),
+                }
+            },
+        }
+
+        let native = NativeBackend.copy_file(source, target, common, context,
+            deadline);
+        match native {
+            Err(e) if e.is::<BudgetExceeded>() => Err(e),
+            Err(e) => {
+                debug!("Unable to copy {:?} directly: {:#}. Copying it with \
+                    a subprocess instead.", source, e);
+                SubprocessBackend
+                    .copy_file(source, target, common, context, deadline)
+                    .map_err(|subprocess_error| {
+                        debug!("Unable to copy {:?} with a subprocess: {:#}",
+                            source, subprocess_error);
+                        e
+                    })
+            },
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// FsCapabilities
+////////////////////////////////////////////////////////////////////////////////
+/// The capabilities of a file system, probed by the [`AutoBackend`].
+///
+/// [`AutoBackend`]: struct.AutoBackend.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsCapabilities {
+    /// Whether files on the file system can be cloned.
+    pub reflink: bool,
+    /// The time at which the file system was probed.
+    pub probed: SystemTime,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CapabilityCache
+////////////////////////////////////////////////////////////////////////////////
+/// The [`FsCapabilities`] of each file system copied to, keyed by its mount
+/// point. The cache is shared between clones of the [`RunContext`], and is
+/// loaded from and saved to the [`SyncState`] by collects and distributes, so
+/// that each file system is only probed once.
+///
+/// [`FsCapabilities`]: struct.FsCapabilities.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`SyncState`]: ../state/struct.SyncState.html
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityCache {
+    /// The probed capabilities.
+    probed: Arc<Mutex<BTreeMap<PathBuf, FsCapabilities>>>,
+}
+
+impl CapabilityCache {
+    /// Adds the given previously probed capabilities to the cache.
+    pub fn load(&self, probed: &BTreeMap<PathBuf, FsCapabilities>) {
+        if let Ok(mut cache) = self.probed.lock() {
+            cache.extend(probed.iter()
+                .map(|(mount, capabilities)| (mount.clone(), *capabilities)));
+        }
+    }
+
+    /// Returns the capabilities in the cache.
+    pub fn probed(&self) -> BTreeMap<PathBuf, FsCapabilities> {
+        self.probed.lock()
+            .map(|cache| cache.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns whether files on the file system mounted at `mount` can be
+    /// cloned, or `None` if it has not been probed.
+    pub fn reflink(&self, mount: &Path) -> Option<bool> {
+        self.probed.lock()
+            .ok()
+            .and_then(|cache| cache.get(mount).map(|probed| probed.reflink))
+    }
+
+    /// Records whether files on the file system mounted at `mount` can be
+    /// cloned.
+    pub fn record(&self, mount: PathBuf, reflink: bool) {
+        if let Ok(mut cache) = self.probed.lock() {
+            let _ = cache.insert(mount, FsCapabilities {
+                reflink,
+                probed: SystemTime::now(),
+            });
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Handled backends
+////////////////////////////////////////////////////////////////////////////////
+thread_local! {
+    /// The name of the built-in backend which copied the files of the entry
+    /// being copied on the current thread.
+    static HANDLED_BY: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+/// Records that a file of the entry being copied on the current thread was
+/// copied by the named backend. If its files are copied by different
+/// backends, the entry is recorded as copied by `mixed` backends.
+pub(crate) fn record_handled_by(name: &'static str) {
+    HANDLED_BY.with(|handled| handled.set(match handled.get() {
+        Some(previous) if previous != name => Some("mixed"),
+        _ => Some(name),
+    }));
+}
+
+/// Returns the name of the backend which copied the files of the entry being
+/// copied on the current thread, and clears it for the next entry.
+pub(crate) fn take_handled_by() -> Option<&'static str> {
+    HANDLED_BY.with(Cell::take)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// replace_file
+////////////////////////////////////////////////////////////////////////////////
+/// Moves the file at `temp` over `target`, replacing it atomically.
+///
+/// If the files are on different file systems, as when `target` is a bind
+/// mount or `temp` is in another directory, the rename fails and the file is
+/// copied over `target` instead, after which `temp` is removed. The
+/// replacement is not atomic in that case.
+pub(crate) fn replace_file(temp: &Path, target: &Path)
+    -> std::io::Result<()>
+{
+    match std::fs::rename(temp, target) {
+        Err(e) if is_cross_device(&e) => {
+            debug!("Unable to rename {:?} to {:?}: they are on different \
+                file systems. Copying it instead.", temp, target);
+            let _ = std::fs::copy(temp, target)?;
+            std::fs::remove_file(temp)
+        },
+        result => result,
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// temp_path
+////////////////////////////////////////////////////////////////////////////////
+/// Returns a temporary path alongside the given path, to write a file to
+/// before it is moved over the path with [`replace_file`].
+///
+/// [`replace_file`]: fn.replace_file.html
+pub(crate) fn temp_path(path: &Path) -> Result<PathBuf, Error> {
+    let mut name = path.file_name().ok_or(InvalidFile)?.to_os_string();
+    name.push(".stall-tmp");
+    Ok(path.with_file_name(name))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// private_temp_dir
+////////////////////////////////////////////////////////////////////////////////
+/// Creates a new directory in the temporary directory, which only the
+/// current user can access, so that other users can't read or replace the
+/// files written to it. The `purpose` is included in its name.
+pub(crate) fn private_temp_dir(purpose: &str) -> std::io::Result<PathBuf> {
+    let mut builder = std::fs::DirBuilder::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt as _;
+        let _ = builder.mode(0o700);
+    }
+    loop {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let dir = std::env::temp_dir()
+            .join(format!("stall-{}-{}-{}",
+                purpose,
+                std::process::id(),
+                nanos));
+        match builder.create(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// create_private_file
+////////////////////////////////////////////////////////////////////////////////
+/// Creates a new empty file which only the current user can read or write,
+/// failing if the path already exists.
+pub(crate) fn create_private_file(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    let _ = options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        let _ = options.mode(0o600);
+    }
+    options.open(path)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Clones the file at `source` to `target`.
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd as _;
+    let reader = File::open(source)?;
+    let writer = File::create(target)?;
+    // Both files remain open for the duration of the call.
+    let result = unsafe {
+        libc::ioctl(writer.as_raw_fd(), libc::FICLONE, reader.as_raw_fd())
+    };
+    match result {
+        -1 => Err(std::io::Error::last_os_error()),
+        _  => Ok(()),
+    }
+}
+
+/// Clones the file at `source` to `target`.
+#[cfg(target_os = "macos")]
+fn reflink(source: &Path, target: &Path) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt as _;
+    let source_name = CString::new(source.as_os_str().as_bytes())?;
+    let target_name = CString::new(target.as_os_str().as_bytes())?;
+    // Files can't be cloned over an existing file.
+    if target.exists() {
+        std::fs::remove_file(target)?;
+    }
+    let result = unsafe {
+        libc::clonefile(source_name.as_ptr(), target_name.as_ptr(), 0)
+    };
+    match result {
+        -1 => Err(std::io::Error::last_os_error()),
+        _  => Ok(()),
+    }
+}
+
+/// Clones the file at `source` to `target`.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn reflink(_source: &Path, _target: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(ErrorKind::Unsupported,
+        "file cloning is not supported on this platform"))
+}
+
+/// Returns true if the error was caused by an operation across file systems,
+/// such as a rename or clone whose source and target are on different
+/// devices.
+fn is_cross_device(error: &std::io::Error) -> bool {
+    error.kind() == ErrorKind::CrossesDevices
+}
+
+/// Returns true if the error indicates that the file system does not support
+/// cloning files.
+#[cfg(unix)]
+fn is_unsupported(error: &std::io::Error) -> bool {
+    error.kind() == ErrorKind::Unsupported
+        || matches!(error.raw_os_error(), Some(code) if code == libc::ENOTTY
+            || code == libc::EINVAL
+            || code == libc::ENOSYS
+            || code == libc::EOPNOTSUPP)
+}
+
+/// Returns true if the error indicates that the platform does not support
+/// cloning files.
+#[cfg(not(unix))]
+fn is_unsupported(error: &std::io::Error) -> bool {
+    error.kind() == ErrorKind::Unsupported
+}
+
+/// Returns the mount point of the file system containing the directory at
+/// `dir`, or `None` if it can't be determined.
+#[cfg(unix)]
+fn mount_point(dir: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt as _;
+    let mut mount = dir.canonicalize().ok()?;
+    let device = mount.metadata().ok()?.dev();
+    while let Some(parent) = mount.parent() {
+        if parent.metadata().ok()?.dev() != device { break; }
+        mount = parent.to_path_buf();
+    }
+    Some(mount)
+}
+
+/// Returns the mount point of the file system containing the directory at
+/// `dir`, or `None` if it can't be determined.
+#[cfg(not(unix))]
+fn mount_point(_dir: &Path) -> Option<PathBuf> {
+    None
+}
+
+/// Returns true if the file at `source` is on the same file system as the
+/// directory `target` is being copied into, or if it can't be determined.
+#[cfg(unix)]
+fn same_file_system(source: &Path, target: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt as _;
+    let device = |path: &Path| path.metadata().ok().map(|meta| meta.dev());
+    match (device(source), target.parent().and_then(device)) {
+        (Some(source), Some(target)) => source == target,
+        _ => true,
+    }
+}
+
+/// Returns true if the file at `source` is on the same file system as the
+/// directory `target` is being copied into, or if it can't be determined.
+#[cfg(not(unix))]
+fn same_file_system(_source: &Path, _target: &Path) -> bool {
+    true
+}
+
+/// Copies the data of the file at `source` into a new file at `temp`, which
+/// is given the permissions of the existing `target`, checking the deadline
+/// and reporting progress between each block.
+fn copy_data(
+    source: &Path,
+    temp: &Path,
+    target: &Path,
+    common: &CommonOptions,
+    context: &RunContext,
+    deadline: Option<Instant>)
+    -> Result<(), Error>
+{
+    use std::io::Read as _;
+    use std::io::Write as _;
+
+    let mut reader = File::open(source)
+        .with_context(|| format!("Unable to open {:?}", source))?;
+    let mut writer = File::create(temp)
+        .with_context(|| format!("Unable to create {:?}", temp))?;
+    if let Ok(metadata) = target.metadata() {
+        writer.set_permissions(metadata.permissions())
+            .with_context(|| format!("Unable to set permissions of {:?}",
+                temp))?;
+    }
+    let total = reader.metadata()
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let progress = copy_progress(source, total, common, context);
+    context.progress.report(source, 0, total);
+
+    let mut buf = vec![0; 64 * 1024];
+    let mut copied = 0;
+    loop {
+        if matches!(deadline, Some(d) if Instant::now() > d) {
+            return Err(BudgetExceeded.into());
+        }
+        let len = reader.read(&mut buf)
+            .with_context(|| format!("Unable to read {:?}", source))?;
+        if len == 0 { break; }
+        writer.write_all(&buf[..len])
+            .with_context(|| format!("Unable to write {:?}", target))?;
+        if let Some(progress) = &progress {
+            progress.inc(len as u64);
+        }
+        copied += len as u64;
+        context.progress.report(source, copied, total);
+    }
+    Ok(())
+}
+
+/// Returns the size of the file at `path`, or 0 if it can't be read.
+fn file_len(path: &Path) -> u64 {
+    path.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+}
+
+/// Reads a pipe of a child process to its end on a separate thread.
+fn read_pipe<R>(pipe: Option<R>) -> JoinHandle<Vec<u8>>
+    where R: std::io::Read + Send + 'static
+{
+    std::thread::spawn(move || {
+        let mut output = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+/// The size in bytes of the files for which a progress bar is shown while
+/// they are copied.
+pub const PROGRESS_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Returns a progress bar for copying `len` bytes from the file at `source`,
+/// if the file is larger than the [`PROGRESS_THRESHOLD`] and progress output
+/// is enabled. The progress bar is drawn to stderr if it is a terminal, and
+/// cleared when it is dropped.
+///
+/// [`PROGRESS_THRESHOLD`]: constant.PROGRESS_THRESHOLD.html
+#[cfg(feature = "progress")]
+fn copy_progress(
+    source: &Path,
+    len: u64,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Option<ProgressBar>
+{
+    if len < PROGRESS_THRESHOLD || common.no_progress || common.quiet {
+        return None;
+    }
+    let style = ProgressStyle::with_template("{prefix}{bar:30} \
+            {bytes}/{total_bytes} {bytes_per_sec} {wide_msg}")
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    Some(ProgressBar::new(len)
+        .with_style(style)
+        .with_prefix(format!("{}    ", indent(context)))
+        .with_message(format_path(source, common).display().to_string())
+        .with_finish(ProgressFinish::AndClear))
+}
+
+/// Returns a progress bar for copying a file. Without the `progress`
+/// feature, progress bars are never shown, so this always returns `None`.
+#[cfg(not(feature = "progress"))]
+fn copy_progress(
+    _source: &Path,
+    _len: u64,
+    _common: &CommonOptions,
+    _context: &RunContext)
+    -> Option<ProgressBar>
+{
+    None
+}
+
+/// A stand-in for the progress bar shown while copying a file, without the
+/// `progress` feature.
+#[cfg(not(feature = "progress"))]
+struct ProgressBar;
+
+#[cfg(not(feature = "progress"))]
+impl ProgressBar {
+    /// Advances the progress bar by the given number of bytes.
+    fn inc(&self, _delta: u64) {}
+}
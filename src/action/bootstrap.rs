@@ -0,0 +1,263 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Setup of a stall on a new machine.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::create_private_file;
+use crate::action::format_path;
+use crate::action::import;
+use crate::action::private_temp_dir;
+use crate::action::prompt_value;
+use crate::action::undefined_variables;
+use crate::CommonOptions;
+use crate::Config;
+use crate::DEFAULT_CONFIG_PATH;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::error::UndefinedVariable;
+use crate::Platform;
+use crate::RunContext;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+use std::process::Command;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// fetch_stall
+////////////////////////////////////////////////////////////////////////////////
+/// Fetches a stall into the stall directory for the 'stall bootstrap'
+/// command.
+///
+/// Git URLs and local directories are cloned with `git`, which requires the
+/// stall directory to be empty. Other URLs are downloaded with `curl` and
+/// imported as an exported archive, as are local files.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option does not apply to fetching, as the stall is needed
+/// to plan the first distribute.
+///
+/// The `--yes` option will overwrite existing files when importing an archive
+/// without confirmation.
+///
+/// ### Parameters
+/// + `source`: The git URL, archive URL, or archive to fetch.
+/// + `into`: The stall directory to fetch into.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the stall can't be cloned, downloaded, or
+/// imported.
+///
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn fetch_stall(source: &str, into: &Path, common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let mut fetching = common.clone();
+    fetching.dry_run = false;
+
+    match Source::of(source) {
+        Source::Git => {
+            info!("{} {}",
+                paint("label", "Cloning"),
+                source);
+            let mut command = Command::new("git");
+            let _ = command.args(["clone", "--quiet", "--", source])
+                .arg(into);
+            run("git", command)?;
+        },
+
+        Source::Download => {
+            let name = source.rsplit('/')
+                .next()
+                .and_then(|name| name.split(['?', '#']).next())
+                .filter(|name| !matches!(*name, "" | "." | ".."))
+                .unwrap_or("stall.tar.gz");
+            let download_dir = private_temp_dir("bootstrap")
+                .with_context(|| "create download directory")?;
+            let download = download_dir.join(name);
+            info!("{} {}",
+                paint("label", "Downloading"),
+                source);
+            let mut command = Command::new("curl");
+            let _ = command.args([
+                    "--silent",
+                    "--show-error",
+                    "--fail",
+                    "--location",
+                    "--output",
+                ])
+                .arg(&download)
+                .arg(source);
+            let result = create_private_file(&download)
+                .with_context(|| "create download file")
+                .and_then(|_| run("curl", command))
+                .map_err(StallError::from)
+                .and_then(|_| import(into, &download, None, false,
+                    &fetching));
+            let _ = std::fs::remove_dir_all(&download_dir);
+            result?;
+        },
+
+        Source::Archive => {
+            import(into, Path::new(source), None, false, &fetching)?;
+        },
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// bootstrap_entries
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the file entries of a fetched stall to distribute for the 'stall
+/// bootstrap' command.
+///
+/// Disabled entries, and entries restricted to another host or operating
+/// system or whose environment conditions are not met, are skipped. The
+/// variables given with `--var` are added to the template options, and any
+/// other variable used by the templates to distribute which is not defined in
+/// the stall file is prompted for.
+///
+/// ### Command line options
+///
+/// The `--yes` option will not prompt for undefined variables, failing
+/// instead.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' which was fetched.
+/// + `config`: The [`Config`] of the fetched stall.
+/// + `vars`: The `name=value` definitions of template variables.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall. The variables are added to
+///   its template options.
+///
+/// ### Errors
+///
+/// Returns an [`UndefinedVariable`] error if a variable is not given, or an
+/// [`Error`] if a definition is malformed or a template can't be read.
+///
+/// [`Config`]: ../struct.Config.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`UndefinedVariable`]: ../error/struct.UndefinedVariable.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn bootstrap_entries<'c>(
+    stall_dir: &Path,
+    config: &'c Config,
+    vars: &[String],
+    common: &CommonOptions,
+    context: &mut RunContext)
+    -> Result<Vec<&'c Entry>, StallError>
+{
+    let platform = Platform::current();
+    let skipped = config.files.iter()
+        .filter(|entry| !entry.applies_to(&platform))
+        .count();
+    if skipped > 0 {
+        info!("Skipping {} {} for other machines.",
+            skipped,
+            if skipped == 1 { "entry" } else { "entries" });
+    }
+    let files: Vec<_> = config.select_entries(&[])?
+        .into_iter()
+        .filter(|entry| !entry.is_stall())
+        .collect();
+
+    for var in vars {
+        let (name, value) = var.split_once('=')
+            .ok_or_else(|| Error::msg(format!("invalid template variable \
+                {:?}: expected name=value", var)))?;
+        let _ = context.templates.variables
+            .insert(name.trim().into(), value.into());
+    }
+    let mut supplied = !vars.is_empty();
+
+    let undefined = undefined_variables(
+        stall_dir,
+        files.iter().copied(),
+        &context.templates)?;
+    for name in undefined {
+        let value = match common.yes {
+            true  => None,
+            false => prompt_value(&format!("Value for template variable {}",
+                name))?,
+        };
+        let value = value.ok_or_else(|| Error::from(
+            UndefinedVariable { name: name.clone() }))?;
+        let _ = context.templates.variables.insert(name, value);
+        supplied = true;
+    }
+
+    if supplied {
+        info!("Add the given variables to {} to use them in later \
+                distributes.",
+            format_path(&stall_dir.join(DEFAULT_CONFIG_PATH), common)
+                .display());
+    }
+    Ok(files)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Source
+////////////////////////////////////////////////////////////////////////////////
+/// The kind of source a stall is bootstrapped from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// A git repository.
+    Git,
+    /// An archive to download.
+    Download,
+    /// A local archive.
+    Archive,
+}
+
+impl Source {
+    /// Returns the kind of the given source.
+    fn of(source: &str) -> Self {
+        let is_git = source.ends_with(".git")
+            || source.starts_with("git@")
+            || source.starts_with("git://")
+            || source.starts_with("ssh://");
+        let is_url = source.starts_with("http://")
+            || source.starts_with("https://");
+        match (is_git, is_url) {
+            (true, _)      => Source::Git,
+            (false, true)  => Source::Download,
+            (false, false) if Path::new(source).is_dir() => Source::Git,
+            (false, false) => Source::Archive,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Runs the command, returning an error with its output if it fails.
+fn run(name: &str, mut command: Command) -> Result<(), Error> {
+    let output = command.output()
+        .with_context(|| format!("execute {} command", name))?;
+    if !output.status.success() {
+        return Err(Error::msg(format!("{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
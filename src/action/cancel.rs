@@ -0,0 +1,82 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Cooperative cancellation of running commands.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::error::Cancelled;
+use crate::error::Error;
+
+// Standard library imports.
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CancelToken
+////////////////////////////////////////////////////////////////////////////////
+/// A token for cancelling a running collect or distribute from another
+/// thread, such as a ctrl-c handler. Clones of a token share its state.
+///
+/// Cancellation is checked before each entry is copied, and before each file
+/// of a directory entry is copied, so the file being copied is always
+/// finished. The command then fails with a [`Cancelled`] error, and may be
+/// continued with the `--resume` option.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::action::CancelToken;
+/// let token = CancelToken::new();
+/// let handle = token.clone();
+/// assert!(!token.is_cancelled());
+///
+/// handle.cancel();
+/// assert!(token.is_cancelled());
+/// ```
+///
+/// [`Cancelled`]: ../error/struct.Cancelled.html
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    /// Whether the token has been cancelled.
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Constructs a new `CancelToken` which has not been cancelled.
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    /// Constructs a `CancelToken` which is cancelled when the given flag is
+    /// set, as by a signal handler.
+    pub fn from_flag(flag: Arc<AtomicBool>) -> Self {
+        CancelToken { cancelled: flag }
+    }
+
+    /// Cancels the commands using the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true if the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns a [`Cancelled`] error if the token has been cancelled.
+    ///
+    /// [`Cancelled`]: ../error/struct.Cancelled.html
+    pub fn check(&self) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true  => Err(Cancelled.into()),
+            false => Ok(()),
+        }
+    }
+}
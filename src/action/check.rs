@@ -0,0 +1,70 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Validation of stall files.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::format_path;
+use crate::CommonOptions;
+use crate::Config;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::error::ValidationFailed;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// check
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall check' command.
+///
+/// This will log each problem with the stall file found by
+/// [`Config::validate`].
+///
+/// ### Command line options
+///
+/// The `--json` option will not log the problems, leaving the caller to print
+/// those in the returned error as lines of JSON with `kind`, `path`, and
+/// `message` fields, for use by other programs.
+///
+/// ### Parameters
+/// + `config`: The [`Config`] of the stall file to check.
+/// + `json`: Whether the problems will be printed as lines of JSON.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns a [`ValidationFailed`] error holding the problems if any are
+/// found.
+///
+/// [`Config::validate`]: ../struct.Config.html#method.validate
+/// [`Config`]: ../struct.Config.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`ValidationFailed`]: ../error/struct.ValidationFailed.html
+pub fn check(config: &Config, json: bool, common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let findings = config.validate();
+    for finding in findings.iter().filter(|_| !json) {
+        info!("    {} {}: {}",
+            paint("label", finding.kind.name()),
+            format_path(&finding.path, common).display(),
+            finding.message);
+    }
+
+    if !findings.is_empty() {
+        return Err(Error::from(ValidationFailed { findings }).into());
+    }
+    if !json {
+        info!("No problems found in the stall file.");
+    }
+    Ok(())
+}
@@ -9,21 +9,35 @@
 ////////////////////////////////////////////////////////////////////////////////
 
 // Local imports.
-use crate::CommonOptions;
-use crate::error::Error;
-use crate::error::InvalidFile;
-use crate::error::MissingFile;
-use crate::error::Context;
-use crate::action::Action;
-use crate::action::copy_file;
+use crate::action::check_free_space;
+use crate::action::confirm_plan;
 use crate::action::CopyMethod;
+use crate::action::Estimate;
+use crate::action::execute_plan;
+use crate::action::execute_prefetched;
+use crate::action::format_path;
+use crate::action::indent;
+use crate::action::notify;
+use crate::action::Location;
+use crate::action::plan_all;
+use crate::action::PlanInput;
+use crate::action::prepare_run;
 use crate::action::print_status_header;
-use crate::action::print_status_line;
-use crate::action::State;
+use crate::action::print_status_legend;
+use crate::action::Reporter;
+use crate::action::RunReport;
+use crate::action::sort_inputs;
+use crate::action::StallStore;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::StallError;
+use crate::error::InvalidFile;
+use crate::RunContext;
+use crate::state::SyncState;
+use crate::theme::paint;
 
 // External library imports.
 use log::*;
-use colored::Colorize as _;
 
 // Standard library imports.
 use std::path::Path;
@@ -38,10 +52,18 @@ use std::path::Path;
 /// If the file is newer than the one in the stall directory, it will be copied
 /// into the stall directory, overwriting the existing file.
 ///
+/// If both files have been modified since they were last copied, the file is
+/// reported as a conflict and skipped.
+///
+/// Returns a [`RunReport`] listing the state of each file and the action
+/// chosen for it. The progress of each file is sent to the [`Reporter`] as it
+/// is processed, and printing the summary of the copies is left to the
+/// caller.
+///
 /// ### Command line options
 ///
 /// The `--force` option will cause the overwrite to occur even if the file
-/// is older than the one in the stall directory.
+/// is older than the one in the stall directory, or is in conflict with it.
 ///
 /// The `--error` option will cause the function to return with an error if any
 /// of the collected files cannot be opened or read. Further files will not be
@@ -50,14 +72,34 @@ use std::path::Path;
 /// The `--dry-run` option will prevent any file copying, but all of the normal
 /// checks and outputs will be emitted.
 ///
-/// The `--verbose`, `--quiet`, `--xtrace`, and `--short-names` options will
-/// change which outputs are produced.
+/// The `--interactive` option will prompt for confirmation before each file
+/// is copied. Upcoming files are compared while each prompt is answered, so
+/// the free space on the stall directory's file system is not checked
+/// beforehand.
+///
+/// The `--yes` option will suppress all confirmation prompts.
+///
+/// The `--resume` option will continue an interrupted collect, processing the
+/// remaining entries in the order it chose and skipping the entries it copied
+/// which are unchanged since. The progress of each collect is recorded in the
+/// state file as files are copied.
+///
+/// The `--one-file-system` option will prevent directories from being copied
+/// across file system boundaries.
+///
+/// The `--no-preserve` option will prevent the permissions and modification
+/// times of copied files from being set to those of their sources.
+///
+/// The `--verbose`, `--quiet`, `--xtrace`, `--short-names`, `--absolute`, and
+/// `--icons` options will change which outputs are produced.
 ///
 /// ### Parameters
 /// + `into`: The 'stall directory' to collect into. Takes a generic argument
 /// that implements [`AsRef`]`<`[`Path`]`>`.
 /// + `common`: The [`CommonOptions`] to use for the command.
-/// + `files`: An iterator over the [`Path`]s of the files to collect.
+/// + `context`: The [`RunContext`] of the stall.
+/// + `files`: An iterator over the [`Entry`]s of the files to collect.
+/// + `reporter`: The [`Reporter`] to notify of the progress of each file.
 ///
 /// ### Errors
 /// 
@@ -66,6 +108,10 @@ use std::path::Path;
 /// [`AsRef`]: https://doc.rust-lang.org/stable/std/convert/trait.AsRef.html
 /// [`Path`]: https://doc.rust-lang.org/stable/std/path/struct.Path.html
 /// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Entry`]: ../struct.Entry.html
+/// [`RunReport`]: struct.RunReport.html
+/// [`Reporter`]: trait.Reporter.html
 /// [`Error`]: ../error/struct.Error.html
 /// 
 // Release checklist:
@@ -76,74 +122,90 @@ use std::path::Path;
 pub fn collect<'i, P, I>(
     into: P,
     files: I,
-    common: CommonOptions) 
-    -> Result<(), Error>
+    reporter: &mut dyn Reporter,
+    common: CommonOptions,
+    context: RunContext)
+    -> Result<RunReport, StallError>
     where 
         P: AsRef<Path>,
-        I: IntoIterator<Item=&'i Path>
+        I: IntoIterator<Item=&'i Entry>
 {
     let into = into.as_ref();
-    info!("{} {}", 
-        "Destination directory:".bright_white(),
-        into.display());
+    info!("{}{} {}",
+        indent(&context),
+        paint("label", "Destination directory:"),
+        format_path(into, &common).display());
 
     let copy_method = match common.dry_run {
         true  => CopyMethod::None,
-        false => CopyMethod::Subprocess,
+        false => context.copy_method,
     };
     debug!("Copy method: {:?}", copy_method);
 
-    print_status_header();
-
-    for source in files {
-        debug!("Processing source file: {:?}", source);
-        let file_name = source.file_name().ok_or(InvalidFile)?;
-        let target = into.join(file_name);
-
-        use State::*;
-        use Action::*;
-        match (source.exists(), target.exists()) {
-            // Both files exist, compare modify dates.
-            (true,  true) => {
-                let source_last_modified = source.metadata()
-                    .with_context(|| "load source metadata")?
-                    .modified()
-                    .with_context(|| "load source modified time")?;
-                trace!("Source last modified: {:?}", source_last_modified);
-                let target_last_modified = target.metadata()
-                    .with_context(|| "load target metadata")?
-                    .modified()
-                    .with_context(|| "load target modified time")?;
-                trace!("Target last modified: {:?}", source_last_modified);
-
-                if source_last_modified > target_last_modified {
-                    print_status_line(Newer, Copy, source, &common);
-
-                } else if common.force {
-                    print_status_line(Force, Copy, source, &common);
-
-                } else {
-                    print_status_line(Older, Skip, source, &common);
-                    continue;
-                }
-            },
-
-            // Source exists, but not target.
-            (true, false) => print_status_line(Found, Copy, source, &common),
+    // Determine the state of each file.
+    let store = StallStore::new(into);
+    let mut sync_state = SyncState::load_from(&store.state_path())?;
+    context.capabilities.load(sync_state.capabilities());
+    let mut inputs = Vec::new();
+    for entry in files {
+        let file_name = entry.file_name().ok_or(InvalidFile)?;
+        inputs.push(PlanInput {
+            entry,
+            source: Location::remote(entry),
+            target: store.local(file_name),
+            synced: sync_state.get(entry).copied(),
+        });
+    }
 
-            // Source does not exist.
-            (false, _) => if common.promote_warnings_to_errors {
-                print_status_line(Error, Stop, source, &common);
-                return Err(MissingFile { path: source.into() }.into());
-            } else {
-                print_status_line(Error, Skip, source, &common);
-                continue;
-            },
-        }
+    sort_inputs(&mut inputs, &common, &context);
+    let progress = prepare_run(
+        "collect",
+        &mut inputs,
+        &sync_state,
+        &common,
+        &context);
 
-        // If we got this far, we're collecting this file.
-        copy_file(source, &target, copy_method)?;
+    let mut report = RunReport::new("collect", into, &common);
+    let result = match common.interactive && !common.yes {
+        true  => {
+            if !common.dry_run {
+                sync_state.begin_run(progress, &store.state_path())?;
+            }
+            print_status_header(&common, &context);
+            execute_prefetched(inputs, copy_method, &mut sync_state,
+                &mut report, reporter, &common, &context)
+        },
+        false => {
+            let plan = plan_all(inputs, &common, &context)?;
+            check_free_space(&plan, &common, &context)?;
+            let estimate = Estimate::of_plan(&plan,
+                sync_state.throughput("collect"),
+                &common,
+                &context)?;
+            if !confirm_plan(&estimate, 0, &common, &context)? {
+                info!("Collect cancelled.");
+                return Ok(report);
+            }
+            if !common.dry_run {
+                sync_state.begin_run(progress, &store.state_path())?;
+            }
+            print_status_header(&common, &context);
+            execute_plan(plan.into_iter().map(Ok), copy_method,
+                &mut sync_state, &mut report, reporter, &common, &context)
+        },
+    };
+    print_status_legend(&common, &context);
+    report.finish(&result);
+    notify(&report, &common, &context);
+    if let Ok(stats) = &result {
+        sync_state.finish_run();
+        sync_state.record_throughput("collect", stats);
     }
-
-    Ok(())
+    if !common.dry_run {
+        sync_state.record_capabilities(context.capabilities.probed());
+        sync_state.save_to(&store.state_path())?;
+    }
+    let _ = result?;
+    Ok(report)
 }
+
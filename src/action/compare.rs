@@ -0,0 +1,447 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! File comparison strategies.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::digest;
+use crate::action::Location;
+use crate::action::prompt::DiffFile;
+use crate::action::State;
+use crate::CommonOptions;
+use crate::CompareMode;
+use crate::CompareStage;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::RunContext;
+use crate::state::SyncRecord;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::process::Command;
+use std::process::Stdio;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Comparison
+////////////////////////////////////////////////////////////////////////////////
+/// The files of an [`Entry`] to be compared, with the context needed to
+/// compare them.
+///
+/// [`Entry`]: ../struct.Entry.html
+#[derive(Debug, Clone, Copy)]
+pub struct Comparison<'a> {
+    /// The entry being compared.
+    pub entry: &'a Entry,
+    /// The location to copy from.
+    pub source: &'a Location,
+    /// The location to copy to.
+    pub target: &'a Location,
+    /// Whether the source is the entry's remote location.
+    pub collect: bool,
+    /// The sync record for the entry, if it has been copied before.
+    pub synced: Option<&'a SyncRecord>,
+    /// The options of the command.
+    pub common: &'a CommonOptions,
+    /// The run context of the stall.
+    pub context: &'a RunContext,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Comparator
+////////////////////////////////////////////////////////////////////////////////
+/// A strategy for determining the [`State`] of a source file relative to its
+/// target, when both files exist.
+///
+/// [`State`]: enum.State.html
+pub trait Comparator {
+    /// Compares the source and target of the [`Comparison`].
+    ///
+    /// [`Comparison`]: struct.Comparison.html
+    fn compare(&self, comparison: &Comparison<'_>) -> Result<State, Error>;
+}
+
+impl CompareMode {
+    /// Returns the [`Comparator`] for the mode.
+    ///
+    /// [`Comparator`]: action/trait.Comparator.html
+    pub fn comparator(&self) -> &'static dyn Comparator {
+        match self {
+            CompareMode::Mtime     => &MtimeComparator,
+            CompareMode::SizeMtime => &SizeMtimeComparator,
+            CompareMode::Hash      => &HashComparator,
+            CompareMode::Always    => &AlwaysCopy,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MtimeComparator
+////////////////////////////////////////////////////////////////////////////////
+/// Compares files by modification time.
+///
+/// Files are synced if neither has been modified since they were last copied,
+/// or if their modification times are within the `mtime_tolerance`. Files
+/// whose modification times are equal or can't be read are compared by
+/// content instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MtimeComparator;
+
+impl Comparator for MtimeComparator {
+    fn compare(&self, comparison: &Comparison<'_>) -> Result<State, Error> {
+        match modified_times(comparison)? {
+            Some(times) => mtime_state(comparison, times, true),
+            None        => content_state(comparison),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SizeMtimeComparator
+////////////////////////////////////////////////////////////////////////////////
+/// Compares files by size and modification time. Files of different sizes are
+/// never synced, even if their modification times are within the
+/// `mtime_tolerance` or unchanged since they were last copied.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SizeMtimeComparator;
+
+impl Comparator for SizeMtimeComparator {
+    fn compare(&self, comparison: &Comparison<'_>) -> Result<State, Error> {
+        let Comparison { source, target, common, context, .. } = *comparison;
+        let same_size = source.is_dir()
+            || source.size(common, context)? == target.size(common, context)?;
+        match modified_times(comparison)? {
+            Some(times) => mtime_state(comparison, times, same_size),
+            None        => content_state(comparison),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// HashComparator
+////////////////////////////////////////////////////////////////////////////////
+/// Compares files by content. Files with matching content are synced, and
+/// modification times are only used to determine which of two differing files
+/// is newer. Directories are compared by modification time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HashComparator;
+
+impl Comparator for HashComparator {
+    fn compare(&self, comparison: &Comparison<'_>) -> Result<State, Error> {
+        if !hashable(comparison) {
+            return MtimeComparator.compare(comparison);
+        }
+        if contents_match(comparison)? {
+            return Ok(State::Synced);
+        }
+        match modified_times(comparison)? {
+            Some(times) => mtime_state(comparison, times, false),
+            None        => Ok(State::Conflict),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// AlwaysCopy
+////////////////////////////////////////////////////////////////////////////////
+/// Treats every source file as newer than its target, so that it is always
+/// copied.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AlwaysCopy;
+
+impl Comparator for AlwaysCopy {
+    fn compare(&self, _comparison: &Comparison<'_>) -> Result<State, Error> {
+        Ok(State::Newer)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CommandComparator
+////////////////////////////////////////////////////////////////////////////////
+/// Compares files with a user-defined shell command, such as a semantic diff
+/// tool. The command is run with the paths of the files in the
+/// `STALL_COMPARE_SOURCE` and `STALL_COMPARE_TARGET` environment variables.
+/// An exit code of 0 means the files are the same, and 1 means they differ,
+/// in which case modification times determine which is newer. Any other exit
+/// code is an error.
+///
+/// Directories, encrypted entries, and templates are compared by
+/// modification time.
+#[derive(Debug, Clone, Copy)]
+pub struct CommandComparator<'c> {
+    /// The shell command to run.
+    pub command: &'c str,
+}
+
+impl Comparator for CommandComparator<'_> {
+    fn compare(&self, comparison: &Comparison<'_>) -> Result<State, Error> {
+        if !hashable(comparison) {
+            return MtimeComparator.compare(comparison);
+        }
+        if self.same(comparison)? {
+            return Ok(State::Synced);
+        }
+        match modified_times(comparison)? {
+            Some(times) => mtime_state(comparison, times, false),
+            None        => Ok(State::Conflict),
+        }
+    }
+}
+
+impl CommandComparator<'_> {
+    /// Runs the command, returning true if it reports the files are the same.
+    fn same(&self, comparison: &Comparison<'_>) -> Result<bool, Error> {
+        // Archived files are extracted so the command can read them.
+        let source = DiffFile::new(comparison.source)?;
+        let target = DiffFile::new(comparison.target)?;
+
+        let (shell, flag) = match cfg!(target_os = "windows") {
+            true  => ("cmd", "/C"),
+            false => ("sh", "-c"),
+        };
+        debug!("Running compare command {:?}", self.command);
+        let output = Command::new(shell)
+            .arg(flag)
+            .arg(self.command)
+            .env("STALL_COMPARE_SOURCE", source.path())
+            .env("STALL_COMPARE_TARGET", target.path())
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| "execute compare command")?;
+        trace!("Compare command output: {}",
+            String::from_utf8_lossy(&output.stdout).trim());
+        trace!("Compare command errors: {}",
+            String::from_utf8_lossy(&output.stderr).trim());
+
+        match output.status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(Error::msg(format!("compare command failed: {}: {}",
+                self.command,
+                output.status))),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// PipelineComparator
+////////////////////////////////////////////////////////////////////////////////
+/// Compares files with an ordered pipeline of [`CompareStage`]s, stopping at
+/// the first stage which decides whether they are the same. Files which no
+/// stage decides are treated as differing, and differing files are ordered by
+/// modification time.
+///
+/// [`CompareStage`]: ../enum.CompareStage.html
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineComparator<'s> {
+    /// The stages of the pipeline, in order.
+    pub stages: &'s [CompareStage],
+}
+
+impl Comparator for PipelineComparator<'_> {
+    fn compare(&self, comparison: &Comparison<'_>) -> Result<State, Error> {
+        for stage in self.stages {
+            let same = match stage {
+                CompareStage::Size    => size_stage(comparison)?,
+                CompareStage::Mtime   => mtime_stage(comparison)?,
+                CompareStage::Hash    => hash_stage(comparison)?,
+                CompareStage::Command => command_stage(comparison)?,
+            };
+            trace!("Compare stage {:?}: {}", stage, match same {
+                Some(true)  => "same",
+                Some(false) => "differ",
+                None        => "undecided",
+            });
+            match same {
+                Some(true)  => return Ok(State::Synced),
+                Some(false) => break,
+                None        => (),
+            }
+        }
+        match modified_times(comparison)? {
+            Some(times) => mtime_state(comparison, times, false),
+            None        => Ok(State::Conflict),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns false if the sizes of the files differ, for the `Size` stage.
+fn size_stage(comparison: &Comparison<'_>) -> Result<Option<bool>, Error> {
+    let Comparison { source, target, common, context, .. } = *comparison;
+    if source.is_dir() { return Ok(None); }
+    Ok(match source.size(common, context)? == target.size(common, context)? {
+        true  => None,
+        false => Some(false),
+    })
+}
+
+/// Returns true if the files are unchanged since they were last copied, or
+/// their modification times are equal or within the tolerance, for the
+/// `Mtime` stage.
+fn mtime_stage(comparison: &Comparison<'_>) -> Result<Option<bool>, Error> {
+    let Comparison { collect, synced, context, .. } = *comparison;
+    let (source_modified, target_modified) = match modified_times(comparison)?
+    {
+        Some(times) => times,
+        None        => return Ok(None),
+    };
+    let (local_modified, remote_modified) = match collect {
+        true  => (target_modified, source_modified),
+        false => (source_modified, target_modified),
+    };
+    let unchanged = synced.is_some_and(|record|
+        record.is_unchanged(local_modified, remote_modified));
+    let difference = source_modified
+        .duration_since(target_modified)
+        .unwrap_or_else(|e| e.duration());
+    Ok(match unchanged || difference <= context.mtime_tolerance {
+        true  => Some(true),
+        false => None,
+    })
+}
+
+/// Returns whether the contents of the files match, for the `Hash` stage.
+fn hash_stage(comparison: &Comparison<'_>) -> Result<Option<bool>, Error> {
+    match hashable(comparison) {
+        true  => contents_match(comparison).map(Some),
+        false => Ok(None),
+    }
+}
+
+/// Returns whether the compare command of the entry reports the files are the
+/// same, for the `Command` stage.
+fn command_stage(comparison: &Comparison<'_>) -> Result<Option<bool>, Error> {
+    match &comparison.entry.compare_command {
+        Some(command) if hashable(comparison) => CommandComparator { command }
+            .same(comparison)
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Returns the modification times of the source and target, or `None` if they
+/// can't be read and the files can be compared by content instead.
+fn modified_times(comparison: &Comparison<'_>)
+    -> Result<Option<(SystemTime, SystemTime)>, Error>
+{
+    let Comparison { source, target, common, context, .. } = *comparison;
+    match (source.modified(common, context), target.modified(common, context)) {
+        (Ok(source_last_modified), Ok(target_last_modified)) => {
+            trace!("Source last modified: {:?}", source_last_modified);
+            trace!("Target last modified: {:?}", target_last_modified);
+            Ok(Some((source_last_modified, target_last_modified)))
+        },
+
+        (source_modified, target_modified) if hashable(comparison) => {
+            debug!("Modification times unavailable, comparing contents: \
+                {:?}, {:?}", source_modified.err(), target_modified.err());
+            Ok(None)
+        },
+
+        (Err(e), _) => Err(e).with_context(|| "load source modified time"),
+        (_, Err(e)) => Err(e).with_context(|| "load target modified time"),
+    }
+}
+
+/// Returns the [`State`] of the source given the modification times of the
+/// source and target. If `allow_synced` is false, the files are known to
+/// differ, and are never synced.
+///
+/// [`State`]: enum.State.html
+fn mtime_state(
+    comparison: &Comparison<'_>,
+    (source_last_modified, target_last_modified): (SystemTime, SystemTime),
+    allow_synced: bool)
+    -> Result<State, Error>
+{
+    let Comparison { collect, synced, common, context, .. } = *comparison;
+    let (local_modified, remote_modified) = match collect {
+        true  => (target_last_modified, source_last_modified),
+        false => (source_last_modified, target_last_modified),
+    };
+    let conflict = synced.is_some_and(|record|
+        record.is_conflict(local_modified, remote_modified));
+    // Files copied without preserving timestamps will differ after the copy,
+    // so the sync record is used to determine whether they are still in
+    // sync.
+    let unchanged = synced.is_some_and(|record|
+        record.is_unchanged(local_modified, remote_modified));
+    // File systems with coarse timestamps may round the modification time of
+    // a copy.
+    let difference = source_last_modified
+        .duration_since(target_last_modified)
+        .unwrap_or_else(|e| e.duration());
+    let within_tolerance = !context.mtime_tolerance.is_zero()
+        && difference <= context.mtime_tolerance;
+
+    Ok(if conflict {
+        State::Conflict
+    } else if unchanged && allow_synced && !common.force {
+        State::Synced
+    } else if within_tolerance && allow_synced && !common.force {
+        debug!("Modification times differ by {:?}, within the tolerance of \
+            {:?}.", difference, context.mtime_tolerance);
+        State::Synced
+    } else if source_last_modified == target_last_modified
+        && hashable(comparison)
+    {
+        match allow_synced {
+            true  => {
+                debug!("Modification times are equal, comparing contents.");
+                content_state(comparison)?
+            },
+            false => State::Conflict,
+        }
+    } else if source_last_modified > target_last_modified {
+        State::Newer
+    } else {
+        State::Older
+    })
+}
+
+/// Returns true if the files of the entry can be compared by content.
+/// Directories, encrypted entries, and templates differ in content from their
+/// stall copies.
+fn hashable(comparison: &Comparison<'_>) -> bool {
+    let Comparison { entry, source, .. } = *comparison;
+    !entry.encrypted && !entry.template && !source.is_dir()
+}
+
+/// Returns true if the source and target have the same content.
+fn contents_match(comparison: &Comparison<'_>) -> Result<bool, Error> {
+    let Comparison { source, target, common, context, .. } = *comparison;
+    Ok(source.size(common, context)? == target.size(common, context)?
+        && digest(source)? == digest(target)?)
+}
+
+/// Returns the [`State`] of two files whose modification times are
+/// inconclusive: `Synced` if their contents match, and `Conflict` otherwise,
+/// as neither can be said to be newer.
+///
+/// [`State`]: enum.State.html
+fn content_state(comparison: &Comparison<'_>) -> Result<State, Error> {
+    Ok(match contents_match(comparison)? {
+        true  => State::Synced,
+        false => State::Conflict,
+    })
+}
@@ -0,0 +1,816 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! File copy functions.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::apply_ownership;
+use crate::action::copy_encrypted;
+use crate::action::copy_privileged;
+use crate::action::Location;
+use crate::action::PolicyAction;
+use crate::action::record_handled_by;
+use crate::action::render_template;
+use crate::action::State;
+use crate::action::take_handled_by;
+use crate::action::walk;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::Mode;
+use crate::RunContext;
+use crate::Timestamps;
+use crate::error::BudgetExceeded;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::SpecialFile;
+use crate::logger::ChildStream;
+use crate::logger::log_child_output;
+use crate::logger::log_transfer;
+use crate::logger::Transfer;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// copy_entry
+////////////////////////////////////////////////////////////////////////////////
+/// Copies the file for an [`Entry`] from `source` to `target`. If the copy
+/// exceeds the time budget of the entry, it is abandoned.
+///
+/// Privileged entries are distributed with [`copy_privileged`].
+///
+/// Returns the [`CopyStats`] for the entry if the file was copied, or `None`
+/// if the copy was abandoned.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`copy_privileged`]: fn.copy_privileged.html
+/// [`CopyStats`]: struct.CopyStats.html
+pub fn copy_entry(
+    entry: &Entry,
+    source: &Location,
+    target: &Location,
+    method: CopyMethod,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<Option<CopyStats>, Error>
+{
+    let start = Instant::now();
+    let _ = take_handled_by();
+    let deadline = entry.max_seconds
+        .map(|secs| start + Duration::from_secs(secs));
+
+    let result = match entry.privileged && *target == Location::remote(entry) {
+        true  => copy_privileged(entry, source, target, method, common,
+            context),
+        false => copy_unprivileged(entry, source, target, method, common,
+            context, deadline),
+    };
+
+    match result {
+        Ok(()) => {
+            let stats = CopyStats {
+                files: 1,
+                bytes: source.size(common, context)?,
+                elapsed: start.elapsed(),
+            };
+            let backend = take_handled_by();
+            let transfer = Transfer { backend, ..stats.transfer() };
+            match backend {
+                Some(backend) => log_transfer(Level::Debug, &transfer,
+                    format_args!("Copied {} in {:.3}s with the {} backend.",
+                        format_bytes(stats.bytes),
+                        stats.elapsed.as_secs_f64(),
+                        backend)),
+                None => log_transfer(Level::Debug, &transfer, format_args!(
+                    "Copied {} in {:.3}s.",
+                    format_bytes(stats.bytes),
+                    stats.elapsed.as_secs_f64())),
+            }
+            Ok(Some(stats))
+        },
+
+        Err(e) if e.is::<BudgetExceeded>() => {
+            debug!("{}", e);
+            Ok(None)
+        },
+
+        Err(e) => Err(e),
+    }
+}
+
+
+/// Copies the file for an [`Entry`] from `source` to `target` as the current
+/// user, then applies its permissions and timestamps. Its ownership is applied
+/// when it is distributed.
+///
+/// [`Entry`]: ../struct.Entry.html
+fn copy_unprivileged(
+    entry: &Entry,
+    source: &Location,
+    target: &Location,
+    method: CopyMethod,
+    common: &CommonOptions,
+    context: &RunContext,
+    deadline: Option<Instant>)
+    -> Result<(), Error>
+{
+    if entry.encrypted {
+        copy_encrypted(entry, source, target, method, context)?;
+    } else if entry.template {
+        render_template(source, target, method, context)?;
+    } else {
+        let collect = *source == Location::remote(entry);
+        let special = context.policy.action(collect, State::Special);
+        copy(source, target, method, special, common, context, deadline)?;
+    }
+
+    match (method, target) {
+        (CopyMethod::None, _) => Ok(()),
+        (_, Location::File(path)) => {
+            if !common.no_preserve {
+                preserve_permissions(source, path, common, context)?;
+            }
+            if common.timestamps == Some(Timestamps::Preserve)
+                && !common.no_preserve
+            {
+                preserve_timestamps(source, path, common, context)?;
+            }
+            apply_modes(path, entry, common, context)?;
+            match *target == Location::remote(entry) {
+                true  => apply_ownership(path, entry, None),
+                false => Ok(()),
+            }
+        },
+        (_, Location::Archived { .. }) => Ok(()),
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Timestamps.
+////////////////////////////////////////////////////////////////////////////////
+/// Sets the modification time of the copied file or directory at `target` to
+/// that of the `source`. For directories, the modification time of each copied
+/// file is set individually.
+fn preserve_timestamps(
+    source: &Location,
+    target: &Path,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    match source {
+        Location::File(source) if source.is_dir() => {
+            walk::walk_entry_files(source, common, context, |rel, metadata| {
+                if walk::special_kind(&metadata.file_type()).is_some() {
+                    return Ok(());
+                }
+                let modified = metadata.modified()
+                    .with_context(|| "load source modified time")?;
+                set_modified(&target.join(rel), modified)
+            })
+        },
+        _ if source.special_kind().is_some() => {
+            debug!("Not setting modified time of special file {:?}", target);
+            Ok(())
+        },
+        _ => set_modified(target, source.modified(common, context)?),
+    }
+}
+
+/// Sets the modification time of the file at `path`.
+fn set_modified(path: &Path, modified: SystemTime) -> Result<(), Error> {
+    trace!("Setting modified time {:?} for {:?}", modified, path);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(modified))
+        .with_context(|| format!("Unable to set modified time of {:?}", path))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// File modes.
+////////////////////////////////////////////////////////////////////////////////
+/// Sets the permissions of the copied file or directory at `target` to those
+/// of the `source`. For directories, the permissions of each copied file are
+/// set individually. On unix, this copies the mode bits; on windows, the
+/// read-only flag and access control list; on other platforms, only the
+/// read-only flag. Archived files have no permissions to preserve.
+fn preserve_permissions(
+    source: &Location,
+    target: &Path,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    match source {
+        Location::File(source) if source.is_dir() => {
+            walk::walk_entry_files(source, common, context, |rel, metadata| {
+                if walk::special_kind(&metadata.file_type()).is_some() {
+                    return Ok(());
+                }
+                set_permissions(&target.join(rel), metadata.permissions())?;
+                copy_acl(&source.join(rel), &target.join(rel))
+            })?;
+            copy_acl(source, target)
+        },
+        Location::File(source) => {
+            let metadata = std::fs::metadata(source)
+                .with_context(|| format!("Unable to read metadata of {:?}",
+                    source))?;
+            set_permissions(target, metadata.permissions())?;
+            copy_acl(source, target)
+        },
+        Location::Archived { .. } => Ok(()),
+    }
+}
+
+/// Sets the permissions of the file at `path`.
+fn set_permissions(path: &Path, permissions: std::fs::Permissions)
+    -> Result<(), Error>
+{
+    trace!("Setting permissions {:?} for {:?}", permissions, path);
+    std::fs::set_permissions(path, permissions)
+        .with_context(|| format!("Unable to set permissions of {:?}", path))
+}
+
+/// Copies the discretionary access control list of the file at `source` to
+/// the file at `target`. If the source list inherits entries from its parent,
+/// the target list inherits entries from its own parent instead.
+#[cfg(windows)]
+fn copy_acl(source: &Path, target: &Path) -> Result<(), Error> {
+    use std::os::windows::ffi::OsStrExt as _;
+    use std::ptr::null;
+    use std::ptr::null_mut;
+    use windows_sys::Win32::Foundation::ERROR_SUCCESS;
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::ACL;
+    use windows_sys::Win32::Security::Authorization::GetNamedSecurityInfoW;
+    use windows_sys::Win32::Security::Authorization::SE_FILE_OBJECT;
+    use windows_sys::Win32::Security::Authorization::SetNamedSecurityInfoW;
+    use windows_sys::Win32::Security::DACL_SECURITY_INFORMATION;
+    use windows_sys::Win32::Security::GetSecurityDescriptorControl;
+    use windows_sys::Win32::Security::PROTECTED_DACL_SECURITY_INFORMATION;
+    use windows_sys::Win32::Security::PSECURITY_DESCRIPTOR;
+    use windows_sys::Win32::Security::SE_DACL_PROTECTED;
+    use windows_sys::Win32::Security::UNPROTECTED_DACL_SECURITY_INFORMATION;
+
+    trace!("Copying access control list from {:?} to {:?}", source, target);
+    let wide = |path: &Path| -> Vec<u16> {
+        path.as_os_str().encode_wide().chain(Some(0)).collect()
+    };
+    let source_name = wide(source);
+    let target_name = wide(target);
+
+    let mut dacl: *mut ACL = null_mut();
+    let mut descriptor: PSECURITY_DESCRIPTOR = null_mut();
+    // The names are null-terminated, and the DACL points into the security
+    // descriptor, which is freed once the DACL has been applied.
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            source_name.as_ptr(),
+            SE_FILE_OBJECT,
+            DACL_SECURITY_INFORMATION,
+            null_mut(),
+            null_mut(),
+            &mut dacl,
+            null_mut(),
+            &mut descriptor)
+    };
+    if status != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(status as i32))
+            .with_context(|| format!("Unable to read access control list of \
+                {:?}", source));
+    }
+
+    let mut control = 0;
+    let mut revision = 0;
+    let protected = unsafe {
+        GetSecurityDescriptorControl(descriptor, &mut control, &mut revision)
+    } != 0 && control & SE_DACL_PROTECTED != 0;
+    let info = DACL_SECURITY_INFORMATION | match protected {
+        true  => PROTECTED_DACL_SECURITY_INFORMATION,
+        false => UNPROTECTED_DACL_SECURITY_INFORMATION,
+    };
+
+    let status = unsafe {
+        let status = SetNamedSecurityInfoW(
+            target_name.as_ptr(),
+            SE_FILE_OBJECT,
+            info,
+            null_mut(),
+            null_mut(),
+            dacl,
+            null());
+        let _ = LocalFree(descriptor);
+        status
+    };
+    if status != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(status as i32))
+            .with_context(|| format!("Unable to set access control list of \
+                {:?}", target));
+    }
+    Ok(())
+}
+
+/// Copies the access control list of the file at `source` to `target`.
+#[cfg(not(windows))]
+fn copy_acl(_source: &Path, _target: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Applies the file and directory modes of an [`Entry`] to the copied file or
+/// directory at `target`. Modes are applied as written, without the umask.
+///
+/// [`Entry`]: ../struct.Entry.html
+fn apply_modes(
+    target: &Path,
+    entry: &Entry,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    if entry.mode.is_none() && entry.dir_mode.is_none() {
+        return Ok(());
+    }
+    if !target.is_dir() {
+        if let Some(mode) = entry.mode {
+            set_mode(target, mode.0)?;
+        }
+        return Ok(());
+    }
+
+    let mut dirs = BTreeSet::new();
+    let _ = dirs.insert(target.to_path_buf());
+    walk::walk_entry_files(target, common, context, |rel, _| {
+        if let Some(mode) = entry.mode {
+            set_mode(&target.join(rel), mode.0)?;
+        }
+        for parent in rel.ancestors().skip(1) {
+            let _ = dirs.insert(target.join(parent));
+        }
+        Ok(())
+    })?;
+
+    if let Some(mode) = entry.dir_mode {
+        for dir in dirs {
+            set_mode(&dir, mode.0)?;
+        }
+    }
+    Ok(())
+}
+
+/// The mode of a file which differs from the mode set by its [`Entry`].
+///
+/// [`Entry`]: ../struct.Entry.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeDrift {
+    /// The mode of the file.
+    pub actual: Mode,
+    /// The mode set by the entry.
+    pub expected: Mode,
+}
+
+/// Returns the [`ModeDrift`] of the remote file of an [`Entry`], or `None` if
+/// the entry does not set a mode or the file has the expected mode. The file
+/// mode is checked for file entries, and the directory mode for directory
+/// entries.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the metadata of the file can't be read.
+///
+/// [`ModeDrift`]: struct.ModeDrift.html
+/// [`Entry`]: ../struct.Entry.html
+/// [`Error`]: ../error/struct.Error.html
+#[cfg(unix)]
+pub fn mode_drift(entry: &Entry) -> Result<Option<ModeDrift>, Error> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let path = match Location::remote(entry) {
+        Location::File(path) if path.exists() => path,
+        _ => return Ok(None),
+    };
+    let mode = match path.is_dir() {
+        true  => entry.dir_mode,
+        false => entry.mode,
+    };
+    let expected = match mode {
+        Some(mode) => mode,
+        None => return Ok(None),
+    };
+    let actual = std::fs::metadata(&path)
+        .with_context(|| format!("Unable to read metadata of {:?}", path))?
+        .permissions()
+        .mode() & 0o7777;
+    trace!("Mode of {:?} is {:o}, expected {}", path, actual, expected);
+    Ok(match actual == expected.0 {
+        true  => None,
+        false => Some(ModeDrift { actual: Mode(actual), expected }),
+    })
+}
+
+/// Returns the [`ModeDrift`] of the remote file of an [`Entry`]. Modes are
+/// ignored on platforms without unix permissions, so this always returns
+/// `None`.
+///
+/// [`ModeDrift`]: struct.ModeDrift.html
+/// [`Entry`]: ../struct.Entry.html
+#[cfg(not(unix))]
+pub fn mode_drift(_entry: &Entry) -> Result<Option<ModeDrift>, Error> {
+    Ok(None)
+}
+
+/// Sets the permissions of the file at `path` to the given mode.
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt as _;
+    trace!("Setting mode {:o} for {:?}", mode, path);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Unable to set mode of {:?}", path))
+}
+
+/// Sets the permissions of the file at `path` to the given mode.
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Returns the umask of the current process.
+///
+/// The umask is read once and cached, as it can only be read portably by
+/// setting it, which would affect files created by other threads at the same
+/// time. It is first read when the default modes are applied to the stall
+/// file, before any other threads are started.
+#[cfg(unix)]
+pub(crate) fn umask() -> u32 {
+    static UMASK: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+    *UMASK.get_or_init(|| proc_umask().unwrap_or_else(set_umask))
+}
+
+/// Reads the umask of the current process from `/proc/self/status`, which
+/// is supported by Linux 4.7 and later.
+#[cfg(unix)]
+fn proc_umask() -> Option<u32> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("Umask:"))
+        .and_then(|mask| u32::from_str_radix(mask.trim(), 8).ok())
+}
+
+/// Reads the umask of the current process by setting it, then immediately
+/// restoring it.
+#[cfg(unix)]
+#[allow(clippy::useless_conversion)] // `mode_t` is not `u32` on all platforms.
+fn set_umask() -> u32 {
+    unsafe {
+        let mask = libc::umask(0o022);
+        let _ = libc::umask(mask);
+        u32::from(mask)
+    }
+}
+
+/// Returns the umask of the current process.
+#[cfg(not(unix))]
+pub(crate) fn umask() -> u32 {
+    0
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Common file copy function.
+////////////////////////////////////////////////////////////////////////////////
+/// Copies a file from `source` to `target` using the given `CopyMethod`.
+///
+/// Archived files are always copied by reading or rewriting the archive
+/// directly, unless the `CopyMethod` is `None`. Directories are copied
+/// recursively, one file at a time. Special files, such as FIFOs and sockets,
+/// are handled according to the `special` [`PolicyAction`].
+///
+/// If a `deadline` is given, the copy will be abandoned with a
+/// [`BudgetExceeded`] error if it has not completed by that time. Archived
+/// files are only checked for the deadline before they are copied.
+///
+/// [`PolicyAction`]: enum.PolicyAction.html
+/// [`BudgetExceeded`]: ../error/struct.BudgetExceeded.html
+pub fn copy(
+    source: &Location,
+    target: &Location,
+    method: CopyMethod,
+    special: PolicyAction,
+    common: &CommonOptions,
+    context: &RunContext,
+    deadline: Option<Instant>)
+    -> Result<(), Error>
+{
+    use Location::*;
+    let special_kind = source.special_kind();
+    match (source, target) {
+        (_, _) if method == CopyMethod::None => trace!("no-run flag was \
+            specified: Not copying data from {} to {}", source, target),
+
+        (_, _) if matches!(deadline, Some(d) if Instant::now() > d)
+            => return Err(BudgetExceeded.into()),
+
+        (File(source), File(target)) if special_kind.is_some() => copy_special(
+            source,
+            target,
+            special_kind.unwrap_or_default(),
+            special)?,
+
+        (File(source), File(target)) if source.is_dir() => {
+            remove_mismatched(true, target)?;
+            copy_dir(source, target, method, special, common, context,
+                deadline)?
+        },
+
+        (File(source), File(target)) => {
+            remove_mismatched(false, target)?;
+            copy_file(source, target, method, common, context, deadline)?
+        },
+
+        (Archived { archive, member }, File(target)) => {
+            remove_mismatched(false, target)?;
+            archive::extract_member(archive, member, target)?
+        },
+
+        (File(source), Archived { archive, member })
+            => archive::update_member(archive, member, source)?,
+
+        (Archived { .. }, Archived { .. }) => return Err(Error::msg(
+            "unable to copy directly between archives")),
+    }
+    Ok(())
+}
+
+/// Removes the file or directory at `target` if it exists and does not match
+/// the type of the file being copied, so that it can be replaced.
+fn remove_mismatched(source_is_dir: bool, target: &Path) -> Result<(), Error> {
+    let special = target.symlink_metadata()
+        .is_ok_and(|meta| walk::special_kind(&meta.file_type()).is_some());
+    if !special && (!target.exists() || target.is_dir() == source_is_dir) {
+        return Ok(());
+    }
+    debug!("Removing {:?} to replace it.", target);
+    match target.is_dir() {
+        true  => std::fs::remove_dir_all(target),
+        false => std::fs::remove_file(target),
+    }.with_context(|| format!("Unable to remove {:?}", target))
+}
+
+/// Recursively copies the directory `source` to `target` using the given
+/// `CopyMethod`. Special files within the directory are handled according to
+/// the `special` [`PolicyAction`]. If the [`CancelToken`] of the run context
+/// is cancelled, the copy stops with a [`Cancelled`] error once the file
+/// being copied is finished.
+///
+/// [`PolicyAction`]: enum.PolicyAction.html
+/// [`CancelToken`]: struct.CancelToken.html
+/// [`Cancelled`]: ../error/struct.Cancelled.html
+pub fn copy_dir(
+    source: &Path,
+    target: &Path,
+    method: CopyMethod,
+    special: PolicyAction,
+    common: &CommonOptions,
+    context: &RunContext,
+    deadline: Option<Instant>)
+    -> Result<(), Error>
+{
+    walk::walk_entry_files(source, common, context, |rel, metadata| {
+        context.cancel.check()?;
+        let target_file = target.join(rel);
+        let special_kind = walk::special_kind(&metadata.file_type());
+        if method != CopyMethod::None
+            && (special_kind.is_none() || special != PolicyAction::Skip)
+        {
+            if let Some(parent) = target_file.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| "create target directory")?;
+            }
+        }
+        match special_kind {
+            Some(kind) if method == CopyMethod::None
+                && special != PolicyAction::Stop => {
+                trace!("no-run flag was specified: Not copying {} {:?}",
+                    kind, source.join(rel));
+                Ok(())
+            },
+            Some(kind) => copy_special(
+                &source.join(rel),
+                &target_file,
+                kind,
+                special),
+            None => copy_file(
+                &source.join(rel),
+                &target_file,
+                method,
+                common,
+                context,
+                deadline),
+        }
+    })
+}
+
+/// Copies a special file of the given kind from `source` to `target` according
+/// to the `special` [`PolicyAction`]. The file is skipped with a warning, or
+/// the copy is stopped with a [`SpecialFile`] error. FIFOs may be recreated,
+/// but other special files can't be copied.
+///
+/// [`PolicyAction`]: enum.PolicyAction.html
+/// [`SpecialFile`]: ../error/struct.SpecialFile.html
+fn copy_special(
+    source: &Path,
+    target: &Path,
+    kind: &'static str,
+    special: PolicyAction)
+    -> Result<(), Error>
+{
+    match special {
+        PolicyAction::Skip => {
+            warn!("Skipping {} {:?}", kind, source);
+            Ok(())
+        },
+        PolicyAction::Copy | PolicyAction::Ask if kind == walk::FIFO => {
+            if let Ok(metadata) = target.symlink_metadata() {
+                debug!("Removing {:?} to replace it.", target);
+                match metadata.is_dir() {
+                    true  => std::fs::remove_dir_all(target),
+                    false => std::fs::remove_file(target),
+                }.with_context(|| format!("Unable to remove {:?}", target))?;
+            }
+            make_fifo(source, target)
+        },
+        _ => Err(SpecialFile { path: source.into(), kind }.into()),
+    }
+}
+
+/// Creates a FIFO at `target` with the permissions of the FIFO at `source`.
+#[cfg(unix)]
+fn make_fifo(source: &Path, target: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let mode = std::fs::metadata(source)
+        .with_context(|| format!("Unable to read metadata of {:?}", source))?
+        .permissions()
+        .mode();
+    trace!("Creating FIFO {:?}", target);
+    let output = Command::new("mkfifo")
+        .arg("-m")
+        .arg(format!("{:o}", mode & 0o7777))
+        .arg("--")
+        .arg(target)
+        .output()
+        .with_context(|| "execute mkfifo command")?;
+    if !output.status.success() {
+        return Err(Error::msg(format!("mkfifo failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    log_child_output("mkfifo", ChildStream::Stderr, &output.stderr);
+    Ok(())
+}
+
+/// Creates a FIFO at `target` with the permissions of the FIFO at `source`.
+#[cfg(not(unix))]
+fn make_fifo(_source: &Path, target: &Path) -> Result<(), Error> {
+    Err(Error::msg(format!("unable to create FIFO {:?}: FIFOs are not \
+        supported on this platform", target)))
+}
+
+/// Copies a file from `source` to `target` using the [`CopyBackend`] of the
+/// given `CopyMethod`, or the [`CustomBackend`] of the run context if one is
+/// registered.
+///
+/// If a `deadline` is given, the copy will be abandoned with a
+/// [`BudgetExceeded`] error if it has not completed by that time. The
+/// progress of every file is reported to the [`CopyProgress`] of the run
+/// context.
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+/// [`CustomBackend`]: struct.CustomBackend.html
+/// [`BudgetExceeded`]: ../error/struct.BudgetExceeded.html
+/// [`CopyProgress`]: struct.CopyProgress.html
+pub fn copy_file(
+    source: &Path,
+    target: &Path,
+    method: CopyMethod,
+    common: &CommonOptions,
+    context: &RunContext,
+    deadline: Option<Instant>)
+    -> Result<(), Error>
+{
+    let backend = match (method, context.copy_backend.get()) {
+        (CopyMethod::None, _)  => method.backend(),
+        (_, Some(backend))     => backend,
+        (_, None)              => method.backend(),
+    };
+    trace!("Copying {:?} with the {} backend.", source, backend.name());
+    backend.copy_file(source, target, common, context, deadline)?;
+    if context.copy_backend.get().is_some() && method != CopyMethod::None {
+        record_handled_by("custom");
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyStats
+////////////////////////////////////////////////////////////////////////////////
+/// The number of files and bytes copied, and the time taken to copy them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CopyStats {
+    /// The number of files copied. Directories are counted as one file.
+    pub files: usize,
+    /// The number of bytes copied.
+    pub bytes: u64,
+    /// The time taken to copy the files.
+    pub elapsed: Duration,
+}
+
+impl CopyStats {
+    /// Returns the number of bytes copied per second.
+    pub fn throughput(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Returns the [`Transfer`] fields to log for the stats.
+    ///
+    /// [`Transfer`]: ../logger/struct.Transfer.html
+    pub fn transfer(&self) -> Transfer {
+        Transfer {
+            files: self.files,
+            bytes: self.bytes,
+            elapsed: self.elapsed,
+            backend: None,
+        }
+    }
+}
+
+impl std::ops::AddAssign for CopyStats {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.elapsed += other.elapsed;
+    }
+}
+
+/// Formats a number of bytes for display, using binary units.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyMethod
+////////////////////////////////////////////////////////////////////////////////
+/// The method to use when copying files, loaded from the `copy_method` option
+/// of the stall file. Each method has a built-in [`CopyBackend`].
+///
+/// [`CopyBackend`]: trait.CopyBackend.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum CopyMethod {
+    /// Do not copy files.
+    None,
+    /// Copy files by reading and writing them directly.
+    #[default]
+    Native,
+    /// Copy files using a command in a subprocess.
+    Subprocess,
+    /// Clone files on file systems supporting copy-on-write, copying them
+    /// directly otherwise.
+    Reflink,
+    /// Clone files where possible, copying them directly otherwise, or using
+    /// a command in a subprocess if that fails.
+    Auto,
+}
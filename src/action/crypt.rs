@@ -0,0 +1,338 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Encryption of entries within the stall directory.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::CopyMethod;
+use crate::action::create_private_file;
+use crate::action::Location;
+use crate::action::replace_file;
+use crate::action::temp_path;
+use crate::EncryptionBackend;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+#[cfg(not(feature = "encryption"))]
+use crate::error::FeatureDisabled;
+use crate::logger::ChildStream;
+use crate::logger::log_child_output;
+use crate::RunContext;
+
+// External library imports.
+#[cfg(feature = "encryption")]
+use age::armor::ArmoredReader;
+#[cfg(feature = "encryption")]
+use age::armor::ArmoredWriter;
+#[cfg(feature = "encryption")]
+use age::armor::Format;
+#[cfg(feature = "encryption")]
+use age::x25519;
+use log::*;
+
+// Standard library imports.
+use std::io::Write as _;
+#[cfg(feature = "encryption")]
+use std::io::Read as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EncryptionOptions
+////////////////////////////////////////////////////////////////////////////////
+/// Options for encrypting entries, loaded from the stall file.
+#[derive(Debug, Clone, Default)]
+pub struct EncryptionOptions {
+    /// The backend used to encrypt and decrypt entries.
+    pub backend: EncryptionBackend,
+    /// The age identity file used to decrypt entries. Entries are also
+    /// encrypted to the public key of each identity in the file.
+    pub identity: Option<PathBuf>,
+    /// Additional age public keys to encrypt entries to.
+    pub recipients: Vec<String>,
+    /// The GnuPG recipients to encrypt entries to.
+    pub gpg_recipients: Vec<String>,
+}
+
+#[cfg(feature = "encryption")]
+impl EncryptionOptions {
+    /// Loads the identities from the identity file.
+    fn identities(&self) -> Result<Vec<Box<dyn age::Identity>>, Error> {
+        self.identity_file()?
+            .into_identities()
+            .with_context(|| "load age identities")
+    }
+
+    /// Returns the recipients to encrypt entries to.
+    fn recipients(&self) -> Result<Vec<Box<dyn age::Recipient + Send>>, Error>
+    {
+        let mut recipients = self.identity_file()?
+            .to_recipients()
+            .with_context(|| "load age recipients")?;
+        for recipient in &self.recipients {
+            let recipient: x25519::Recipient = recipient.parse()
+                .map_err(|e| Error::msg(format!("invalid age recipient {:?}: \
+                    {}", recipient, e)))?;
+            recipients.push(Box::new(recipient));
+        }
+        Ok(recipients)
+    }
+
+    /// Reads the identity file.
+    fn identity_file(&self)
+        -> Result<age::IdentityFile<age::NoCallbacks>, Error>
+    {
+        let path = self.identity.as_ref()
+            .ok_or_else(|| Error::msg("no age identity file is configured \
+                for encrypted entries"))?;
+        age::IdentityFile::from_file(path.to_string_lossy().into_owned())
+            .with_context(|| format!("Unable to read age identity file {:?}",
+                path))
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// copy_encrypted
+////////////////////////////////////////////////////////////////////////////////
+/// Copies an encrypted [`Entry`] from `source` to `target`. The file is
+/// encrypted when copied into the stall directory, and decrypted when copied
+/// out of it.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if either location is not a regular file, if the
+/// encryption keys can't be loaded, or if the file can't be read, written, or
+/// decrypted.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn copy_encrypted(
+    entry: &Entry,
+    source: &Location,
+    target: &Location,
+    method: CopyMethod,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    let encrypt = *source == Location::remote(entry);
+    let (source, target) = match (source, target) {
+        (Location::File(source), Location::File(target))
+            if !source.is_dir() && !target.is_dir() => (source, target),
+        _ => return Err(Error::msg(format!("unable to copy encrypted entry \
+            {}: encrypted entries must be regular files", source))),
+    };
+
+    if method == CopyMethod::None {
+        trace!("no-run flag was specified: Not {} {:?} to {:?}",
+            if encrypt { "encrypting" } else { "decrypting" },
+            source, target);
+        return Ok(());
+    }
+    trace!("{} {:?} to {:?}",
+        if encrypt { "Encrypting" } else { "Decrypting" },
+        source, target);
+    let data = read_file(source)?;
+    let data = match encrypt {
+        true  => encrypt_data(&data, &context.encryption)?,
+        false => decrypt_data(&data, &context.encryption)?,
+    };
+
+    // The decrypted file is only readable by the current user until its
+    // permissions are applied.
+    let temp = temp_path(target)?;
+    let _ = std::fs::remove_file(&temp);
+    let result = create_private_file(&temp)
+        .and_then(|mut file| file.write_all(&data))
+        .and_then(|()| replace_file(&temp, target));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("Unable to write {:?}", target))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// plaintext_matches
+////////////////////////////////////////////////////////////////////////////////
+/// Returns true if the decrypted contents of an encrypted [`Entry`] in the
+/// stall directory match the contents of its remote file.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if either file can't be read or decrypted.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn plaintext_matches(
+    entry: &Entry,
+    source: &Location,
+    target: &Location,
+    context: &RunContext)
+    -> Result<bool, Error>
+{
+    let (remote, local) = match *source == Location::remote(entry) {
+        true  => (source, target),
+        false => (target, source),
+    };
+    match (remote, local) {
+        (Location::File(remote), Location::File(local))
+            if !remote.is_dir() && !local.is_dir() =>
+        {
+            let plaintext = decrypt_data(&read_file(local)?,
+                &context.encryption)?;
+            Ok(plaintext == read_file(remote)?)
+        },
+        _ => Ok(false),
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Encrypts `plaintext` to the configured recipients, in ASCII armored format.
+fn encrypt_data(plaintext: &[u8], options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    match options.backend {
+        EncryptionBackend::Age => age_encrypt(plaintext, options),
+        EncryptionBackend::Gpg => gpg_encrypt(plaintext, options),
+    }
+}
+
+/// Decrypts `encrypted` using the configured identities.
+fn decrypt_data(encrypted: &[u8], options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    match options.backend {
+        EncryptionBackend::Age => age_decrypt(encrypted, options),
+        EncryptionBackend::Gpg => gpg(&["--decrypt"], encrypted),
+    }
+}
+
+/// Encrypts `plaintext` with age.
+#[cfg(feature = "encryption")]
+fn age_encrypt(plaintext: &[u8], options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    let recipients = options.recipients()?;
+    let encryptor = age::Encryptor::with_recipients(recipients.iter()
+            .map(|recipient| -> &dyn age::Recipient { recipient.as_ref() }))
+        .with_context(|| "encrypt file")?;
+
+    let mut encrypted = Vec::new();
+    let armor = ArmoredWriter::wrap_output(&mut encrypted, Format::AsciiArmor)
+        .with_context(|| "encrypt file")?;
+    let mut writer = encryptor.wrap_output(armor)
+        .with_context(|| "encrypt file")?;
+    let _ = writer.write_all(plaintext)
+        .and_then(|()| writer.finish())
+        .and_then(|armor| armor.finish())
+        .with_context(|| "encrypt file")?;
+    Ok(encrypted)
+}
+
+/// Decrypts `encrypted` with age.
+#[cfg(feature = "encryption")]
+fn age_decrypt(encrypted: &[u8], options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    let identities = options.identities()?;
+    let decryptor = age::Decryptor::new(ArmoredReader::new(encrypted))
+        .with_context(|| "decrypt file")?;
+
+    let mut plaintext = Vec::new();
+    let _ = decryptor
+        .decrypt(identities.iter().map(|identity| identity.as_ref()))
+        .with_context(|| "decrypt file")?
+        .read_to_end(&mut plaintext)
+        .with_context(|| "decrypt file")?;
+    Ok(plaintext)
+}
+
+/// Encrypts `plaintext` with age. The age backend is not supported without
+/// the `encryption` feature.
+#[cfg(not(feature = "encryption"))]
+fn age_encrypt(_plaintext: &[u8], _options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    Err(FeatureDisabled {
+        feature: "encryption",
+        operation: "the age encryption backend",
+    }.into())
+}
+
+/// Decrypts `encrypted` with age. The age backend is not supported without
+/// the `encryption` feature.
+#[cfg(not(feature = "encryption"))]
+fn age_decrypt(_encrypted: &[u8], _options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    Err(FeatureDisabled {
+        feature: "encryption",
+        operation: "the age encryption backend",
+    }.into())
+}
+
+/// Encrypts `plaintext` with the `gpg` command.
+fn gpg_encrypt(plaintext: &[u8], options: &EncryptionOptions)
+    -> Result<Vec<u8>, Error>
+{
+    if options.gpg_recipients.is_empty() {
+        return Err(Error::msg("no gpg recipients are configured for \
+            encrypted entries"));
+    }
+    let mut args = vec!["--armor", "--encrypt"];
+    for recipient in &options.gpg_recipients {
+        args.push("--recipient");
+        args.push(recipient);
+    }
+    gpg(&args, plaintext)
+}
+
+/// Runs the `gpg` command with the given arguments, passing `input` on stdin
+/// and returning its output.
+fn gpg(args: &[&str], input: &[u8]) -> Result<Vec<u8>, Error> {
+    trace!("Running gpg {}", args.join(" "));
+    let mut child = Command::new("gpg")
+        .args(["--batch", "--yes", "--quiet", "--output", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| "execute gpg command")?;
+
+    // Write the input from another thread so that a full stdout pipe can't
+    // block the child.
+    let mut stdin = child.stdin.take().expect("piped gpg stdin");
+    let input = input.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()
+        .with_context(|| "execute gpg command")?;
+    writer.join()
+        .map_err(|_| Error::msg("gpg input thread panicked"))?
+        .with_context(|| "write gpg input")?;
+    if !output.status.success() {
+        return Err(Error::msg(format!("gpg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    log_child_output("gpg", ChildStream::Stderr, &output.stderr);
+    Ok(output.stdout)
+}
+
+/// Reads the file at `path`.
+fn read_file(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path)
+        .with_context(|| format!("Unable to read {:?}", path))
+}
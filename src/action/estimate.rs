@@ -0,0 +1,200 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Estimates of the work done by planned copy operations.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::confirm;
+use crate::action::format_bytes;
+use crate::action::indent;
+use crate::action::PlanItem;
+use crate::CommonOptions;
+use crate::error::Error;
+use crate::RunContext;
+use crate::state::Throughput;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::time::Duration;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// LargePlan
+////////////////////////////////////////////////////////////////////////////////
+/// The size above which a collect or distribute is confirmed before any files
+/// are copied, loaded from the `large_plan` section of the stall file:
+///
+/// ```ron
+/// large_plan: (
+///     files: Some(1000),
+///     bytes: Some(1073741824),
+/// ),
+/// ```
+///
+/// Plans are confirmed if they copy more files or bytes than either limit.
+/// Neither limit is set by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LargePlan {
+    /// The number of files to copy above which a plan is confirmed.
+    #[serde(default)]
+    pub files: Option<usize>,
+
+    /// The number of bytes to copy above which a plan is confirmed.
+    #[serde(default)]
+    pub bytes: Option<u64>,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Estimate
+////////////////////////////////////////////////////////////////////////////////
+/// The expected work of a planned collect or distribute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    /// The number of files to copy. Directories are counted as one file.
+    pub files: usize,
+    /// The number of bytes to copy.
+    pub bytes: u64,
+    /// The expected time to copy the files, or `None` if no earlier runs
+    /// have been recorded.
+    pub duration: Option<Duration>,
+}
+
+impl Estimate {
+    /// Estimates the work of the given plan from the recent [`Throughput`] of
+    /// its command.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the size of a file to copy can't be
+    /// determined.
+    ///
+    /// [`Throughput`]: ../state/struct.Throughput.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn of_plan(
+        plan: &[PlanItem<'_>],
+        throughput: Option<&Throughput>,
+        common: &CommonOptions,
+        context: &RunContext)
+        -> Result<Self, Error>
+    {
+        let mut files = 0;
+        let mut bytes = 0;
+        for item in plan.iter().filter(|item| item.action == Action::Copy) {
+            files += 1;
+            bytes += item.source.size(common, context)?;
+        }
+        Ok(Estimate {
+            files,
+            bytes,
+            duration: throughput
+                .and_then(|throughput| throughput.estimate(files, bytes)),
+        })
+    }
+
+    /// Returns true if the estimate exceeds either limit of the given
+    /// [`LargePlan`].
+    ///
+    /// [`LargePlan`]: struct.LargePlan.html
+    pub fn is_large(&self, limits: &LargePlan) -> bool {
+        limits.files.is_some_and(|files| self.files > files)
+            || limits.bytes.is_some_and(|bytes| self.bytes > bytes)
+    }
+}
+
+impl std::fmt::Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+        -> Result<(), std::fmt::Error>
+    {
+        write!(f, "{} {} ({})",
+            self.files,
+            if self.files == 1 { "file" } else { "files" },
+            format_bytes(self.bytes))?;
+        let secs = match self.duration {
+            Some(duration) => duration.as_secs_f64().round() as u64,
+            None           => return Ok(()),
+        };
+        match secs {
+            0         => write!(f, ", under 1s"),
+            1..=59    => write!(f, ", about {}s", secs),
+            60..=3599 => write!(f, ", about {}m {}s", secs / 60, secs % 60),
+            _         => write!(f, ", about {}h {}m",
+                secs / 3600,
+                secs % 3600 / 60),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// confirm_plan
+////////////////////////////////////////////////////////////////////////////////
+/// Shows the [`Estimate`] of a plan before it is executed, returning false
+/// if the user cancels it.
+///
+/// Plans which overwrite files newer than the stall or exceed the
+/// [`LargePlan`] limits are confirmed, along with their estimate.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option will print the estimate without confirmation.
+///
+/// The `--yes` option will suppress the confirmation.
+///
+/// ### Parameters
+/// + `estimate`: The [`Estimate`] of the plan.
+/// + `forced`: The number of files newer than the stall which the plan will
+///   overwrite.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the prompt can't be shown.
+///
+/// [`Estimate`]: struct.Estimate.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`LargePlan`]: struct.LargePlan.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn confirm_plan(
+    estimate: &Estimate,
+    forced: usize,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<bool, Error>
+{
+    debug!("Estimated work: {}.", estimate);
+    if common.dry_run {
+        info!("{}{} {}.",
+            indent(context),
+            paint("label", "Estimated:"),
+            estimate);
+        return Ok(true);
+    }
+    if common.yes { return Ok(true); }
+
+    let message = match (forced, estimate.is_large(&context.large_plan)) {
+        (0, false)  => return Ok(true),
+        (0, true)   => format!("Copy {}?", estimate),
+        (forced, _) => format!("{} {} newer than the stall and will be \
+                overwritten. Copy {}?",
+            forced,
+            if forced == 1 { "file is" } else { "files are" },
+            estimate),
+    };
+    confirm(&message)
+}
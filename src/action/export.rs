@@ -0,0 +1,915 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Export and import of the stall directory as an archive.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive::days_from_civil;
+#[cfg(feature = "archive")]
+use crate::action::archive::system_time_from_zip;
+#[cfg(feature = "archive")]
+use crate::action::archive::zip_from_system_time;
+use crate::action::confirm;
+use crate::action::DEFAULT_SNAPSHOTS_PATH;
+use crate::action::format_path;
+use crate::action::private_temp_dir;
+use crate::action::Snapshot;
+use crate::action::StallStore;
+use crate::action::walk::walk_files;
+use crate::action::WalkLimits;
+use crate::CommonOptions;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::error::InvalidFile;
+use crate::error::UnknownSnapshot;
+#[cfg(not(feature = "archive"))]
+use crate::error::FeatureDisabled;
+use crate::lock::DEFAULT_LOCK_PATH;
+use crate::state::DEFAULT_STATE_PATH;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+// Standard library imports.
+use std::fs::File;
+use std::fs::Metadata;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+#[cfg(feature = "archive")]
+use std::io::Read;
+#[cfg(feature = "archive")]
+use std::io::Write;
+#[cfg(feature = "archive")]
+use std::path::Component;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ArchiveFormat
+////////////////////////////////////////////////////////////////////////////////
+/// An archive format used to export and import the stall directory.
+///
+/// The paths of archived files are relative to the stall directory. Files
+/// whose paths would be extracted outside of it are ignored.
+pub trait ArchiveFormat {
+    /// Writes the `files` under the `root` directory into an archive.
+    fn pack(&self, root: &Path, files: &[PathBuf], out: File)
+        -> Result<(), Error>;
+
+    /// Returns the paths of the files in an archive.
+    fn list(&self, archive: File) -> Result<Vec<PathBuf>, Error>;
+
+    /// Extracts the files in an archive into the `root` directory, returning
+    /// their paths.
+    fn unpack(&self, archive: File, root: &Path)
+        -> Result<Vec<PathBuf>, Error>;
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ExportFormat
+////////////////////////////////////////////////////////////////////////////////
+/// The supported [`ArchiveFormat`]s.
+///
+/// [`ArchiveFormat`]: trait.ArchiveFormat.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A gzip compressed tar archive.
+    TarGz,
+    /// A zstandard compressed tar archive.
+    TarZst,
+    /// A zip archive.
+    Zip,
+}
+
+impl ExportFormat {
+    /// The names of the available formats.
+    pub const NAMES: &'static [&'static str] = &[
+        "tar.gz",
+        "tar.zst",
+        "zip",
+    ];
+
+    /// Determines the format from the extension of the given path.
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let name = path.file_name()
+            .and_then(|n| n.to_str())
+            .ok_or(InvalidFile)?
+            .to_lowercase();
+
+        if name.ends_with(".zip") {
+            Ok(ExportFormat::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Ok(ExportFormat::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Ok(ExportFormat::TarZst)
+        } else {
+            Err(Error::msg(format!("unrecognized archive format: {}; use the \
+                --format option to choose one", path.display())))
+        }
+    }
+
+    /// Returns the [`ArchiveFormat`] implementation for the format.
+    ///
+    /// ### Errors
+    ///
+    /// Returns a [`FeatureDisabled`] error if the `archive` feature is not
+    /// enabled.
+    ///
+    /// [`ArchiveFormat`]: trait.ArchiveFormat.html
+    /// [`FeatureDisabled`]: ../error/struct.FeatureDisabled.html
+    #[cfg(feature = "archive")]
+    pub fn archive(&self) -> Result<&'static dyn ArchiveFormat, Error> {
+        Ok(match self {
+            ExportFormat::TarGz  => &TarGz,
+            ExportFormat::TarZst => &TarZst,
+            ExportFormat::Zip    => &Zip,
+        })
+    }
+
+    /// Returns the [`ArchiveFormat`] implementation for the format.
+    ///
+    /// ### Errors
+    ///
+    /// Returns a [`FeatureDisabled`] error if the `archive` feature is not
+    /// enabled.
+    ///
+    /// [`ArchiveFormat`]: trait.ArchiveFormat.html
+    /// [`FeatureDisabled`]: ../error/struct.FeatureDisabled.html
+    #[cfg(not(feature = "archive"))]
+    pub fn archive(&self) -> Result<&'static dyn ArchiveFormat, Error> {
+        Err(FeatureDisabled {
+            feature: "archive",
+            operation: "exporting and importing archives",
+        }.into())
+    }
+}
+
+impl std::fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ExportFormat::TarGz  => "tar.gz",
+            ExportFormat::TarZst => "tar.zst",
+            ExportFormat::Zip    => "zip",
+        })
+    }
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tar.gz"  => Ok(ExportFormat::TarGz),
+            "tar.zst" => Ok(ExportFormat::TarZst),
+            "zip"     => Ok(ExportFormat::Zip),
+            _ => Err(format!("invalid archive format: {:?}", s)),
+        }
+    }
+}
+
+impl Serialize for ExportFormat {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ExportFormat {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// export
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall export' command.
+///
+/// This will write the files of the stall directory into an archive. The
+/// format of the archive is determined by its extension, unless the
+/// `--format` option is given.
+///
+/// The lock file and sync state are not exported, as they describe the files
+/// outside of the stall directory on this machine.
+///
+/// ### Command line options
+///
+/// The `--since` option will export only the files changed since the given
+/// snapshot or date, for applying to another machine's stall with
+/// `import --apply-delta`. Files in the snapshot are compared with their
+/// snapshot copies, while other files are exported if they were modified
+/// after the snapshot was created. Dates are given in UTC, as `2024-05-01` or
+/// `2024-05-01T18:30`. Snapshots are not included in such an export, and
+/// files removed since the reference are not recorded.
+///
+/// The `--dry-run` option will print the files without writing the archive.
+///
+/// ### Errors
+///
+/// Returns an [`UnknownSnapshot`] error if the `--since` reference is neither
+/// a snapshot nor a date, or an [`Error`] if the stall directory can't be
+/// read or the archive can't be written.
+///
+/// [`UnknownSnapshot`]: ../error/struct.UnknownSnapshot.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn export(
+    from: &Path,
+    archive: &Path,
+    format: Option<ExportFormat>,
+    since: Option<&str>,
+    common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let format = match format {
+        Some(format) => format,
+        None         => ExportFormat::from_path(archive)?,
+    };
+    let archive_format = format.archive()?;
+    info!("{} {}",
+        paint("label", "Source directory:"),
+        format_path(from, common).display());
+    let since = match since {
+        Some(reference) => Some(Since::resolve(from, reference)?),
+        None            => None,
+    };
+
+    let skipped = [
+        Path::new(DEFAULT_LOCK_PATH).to_path_buf(),
+        Path::new(DEFAULT_STATE_PATH).to_path_buf(),
+        archive.strip_prefix(from).unwrap_or(archive).to_path_buf(),
+    ];
+    let mut files = Vec::new();
+    walk_files(from, false, WalkLimits::unlimited(), |rel, metadata| {
+        if skipped.iter().any(|skip| skip == rel) { return Ok(()); }
+        let changed = match &since {
+            Some(_) if rel.starts_with(DEFAULT_SNAPSHOTS_PATH) => false,
+            Some(since) => since.is_changed(from, rel, metadata)?,
+            None        => true,
+        };
+        if changed {
+            files.push(rel.to_path_buf());
+        }
+        Ok(())
+    })?;
+    files.sort();
+    for file in &files {
+        debug!("    {}", file.display());
+    }
+
+    let verb = match common.dry_run {
+        true  => "Would export",
+        false => "Exported",
+    };
+    if !common.dry_run {
+        let out = File::create(archive)
+            .with_context(|| format!("Unable to create archive {:?}",
+                archive))?;
+        archive_format.pack(from, &files, out)?;
+    }
+    info!("{} {} {}{} to {} ({}).",
+        paint("label", verb),
+        files.len(),
+        if files.len() == 1 { "file" } else { "files" },
+        match &since {
+            Some(since) => format!(" changed since {}", since.reference),
+            None        => String::new(),
+        },
+        format_path(archive, common).display(),
+        format);
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// import
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall import' command.
+///
+/// This will extract the files of an archive written by the 'stall export'
+/// command into the stall directory. The format of the archive is determined
+/// by its extension, unless the `--format` option is given.
+///
+/// Confirmation will be requested before any existing files are overwritten.
+///
+/// ### Command line options
+///
+/// The `--apply-delta` option will merge an archive written with
+/// `export --since` into the stall directory instead. Each archived file is
+/// applied unless the stall's copy was modified more recently, in which case
+/// it is kept and reported.
+///
+/// The `--force` option will apply archived files with `--apply-delta` even
+/// if the stall's copy is newer.
+///
+/// The `--dry-run` option will print the files without extracting them.
+///
+/// The `--yes` option will overwrite existing files without confirmation.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the archive can't be read or the files can't be
+/// written.
+///
+/// [`Error`]: ../error/struct.Error.html
+pub fn import(
+    into: &Path,
+    archive: &Path,
+    format: Option<ExportFormat>,
+    apply_delta: bool,
+    common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let format = match format {
+        Some(format) => format,
+        None         => ExportFormat::from_path(archive)?,
+    };
+    let archive_format = format.archive()?;
+    info!("{} {}",
+        paint("label", "Destination directory:"),
+        format_path(into, common).display());
+    if apply_delta {
+        return import_delta(into, archive, format, archive_format, common);
+    }
+
+    let files = archive_format.list(open_archive(archive)?)?;
+    let existing = files.iter()
+        .filter(|file| into.join(file).exists())
+        .count();
+    if existing > 0 && !common.yes && !common.dry_run {
+        let message = format!("{} {} already in the stall directory and will \
+                be overwritten. Continue?",
+            existing,
+            if existing == 1 { "file is" } else { "files are" });
+        if !confirm(&message)? {
+            info!("Import cancelled.");
+            return Ok(());
+        }
+    }
+
+    let (verb, files) = match common.dry_run {
+        true  => ("Would import", files),
+        false => ("Imported", archive_format
+            .unpack(open_archive(archive)?, into)?),
+    };
+    for file in &files {
+        debug!("    {}", file.display());
+    }
+    info!("{} {} {} from {} ({}).",
+        paint("label", verb),
+        files.len(),
+        if files.len() == 1 { "file" } else { "files" },
+        format_path(archive, common).display(),
+        format);
+    Ok(())
+}
+
+
+/// Merges the files of a differential archive into the stall directory for
+/// `import --apply-delta`.
+fn import_delta(
+    into: &Path,
+    archive: &Path,
+    format: ExportFormat,
+    archive_format: &dyn ArchiveFormat,
+    common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let temp = private_temp_dir("delta")
+        .with_context(|| "create delta directory")?;
+    let result = archive_format.unpack(open_archive(archive)?, &temp)
+        .and_then(|files| apply_delta_files(into, &temp, &files, common));
+    let _ = std::fs::remove_dir_all(&temp);
+    let (applied, kept) = result?;
+
+    info!("{} {} {} from {} ({}).",
+        paint("label", match common.dry_run {
+            true  => "Would apply",
+            false => "Applied",
+        }),
+        applied,
+        if applied == 1 { "file" } else { "files" },
+        format_path(archive, common).display(),
+        format);
+    if kept > 0 {
+        warn!("Kept {} {} newer than the archived {}. Use --force to \
+                overwrite {}.",
+            kept,
+            if kept == 1 { "file" } else { "files" },
+            if kept == 1 { "copy" } else { "copies" },
+            if kept == 1 { "it" } else { "them" });
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Since
+////////////////////////////////////////////////////////////////////////////////
+/// The reference of a differential export.
+#[derive(Debug)]
+struct Since {
+    /// The snapshot name or date the export is relative to.
+    reference: String,
+    /// The time of the reference.
+    time: SystemTime,
+    /// The files directory and stall copies of the reference snapshot.
+    snapshot: Option<(PathBuf, Vec<PathBuf>)>,
+}
+
+impl Since {
+    /// Resolves the reference as a snapshot of the stall in `from`, or as a
+    /// date if there is no such snapshot.
+    fn resolve(from: &Path, reference: &str) -> Result<Self, Error> {
+        let snapshots_dir = StallStore::new(from).snapshots_dir();
+        if let Some(snapshot) = Snapshot::load(&snapshots_dir, reference)? {
+            return Ok(Since {
+                reference: format!("snapshot {:?}", reference),
+                time: snapshot.created,
+                snapshot: Some((
+                    snapshot.files_dir(&snapshots_dir),
+                    snapshot.files)),
+            });
+        }
+        match parse_date(reference) {
+            Some(time) => Ok(Since {
+                reference: reference.into(),
+                time,
+                snapshot: None,
+            }),
+            None => Err(UnknownSnapshot { name: reference.into() }.into()),
+        }
+    }
+
+    /// Returns true if the file at `rel` in the `root` directory has changed
+    /// since the reference.
+    fn is_changed(&self, root: &Path, rel: &Path, metadata: &Metadata)
+        -> Result<bool, Error>
+    {
+        if let Some((files_dir, files)) = &self.snapshot {
+            let copy = files_dir.join(rel);
+            if copy.is_file() {
+                return Ok(!same_contents(&root.join(rel), &copy)?);
+            }
+            if files.iter().any(|file| rel.starts_with(file)) {
+                return Ok(true);
+            }
+        }
+        let modified = metadata.modified()
+            .with_context(|| "load source modified time")?;
+        Ok(modified > self.time)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TarGz
+////////////////////////////////////////////////////////////////////////////////
+/// A gzip compressed tar [`ArchiveFormat`].
+///
+/// [`ArchiveFormat`]: trait.ArchiveFormat.html
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TarGz;
+
+#[cfg(feature = "archive")]
+impl ArchiveFormat for TarGz {
+    fn pack(&self, root: &Path, files: &[PathBuf], out: File)
+        -> Result<(), Error>
+    {
+        let enc = flate2::write::GzEncoder::new(
+            out,
+            flate2::Compression::default());
+        let _ = tar_pack(root, files, enc)?
+            .finish()
+            .with_context(|| "write gzip stream")?;
+        Ok(())
+    }
+
+    fn list(&self, archive: File) -> Result<Vec<PathBuf>, Error> {
+        tar_unpack(flate2::read::GzDecoder::new(archive), None)
+    }
+
+    fn unpack(&self, archive: File, root: &Path)
+        -> Result<Vec<PathBuf>, Error>
+    {
+        tar_unpack(flate2::read::GzDecoder::new(archive), Some(root))
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TarZst
+////////////////////////////////////////////////////////////////////////////////
+/// A zstandard compressed tar [`ArchiveFormat`].
+///
+/// [`ArchiveFormat`]: trait.ArchiveFormat.html
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TarZst;
+
+#[cfg(feature = "archive")]
+impl ArchiveFormat for TarZst {
+    fn pack(&self, root: &Path, files: &[PathBuf], out: File)
+        -> Result<(), Error>
+    {
+        let enc = zstd::stream::write::Encoder::new(out, 0)
+            .with_context(|| "write zstd stream")?;
+        let _ = tar_pack(root, files, enc)?
+            .finish()
+            .with_context(|| "write zstd stream")?;
+        Ok(())
+    }
+
+    fn list(&self, archive: File) -> Result<Vec<PathBuf>, Error> {
+        let dec = zstd::stream::read::Decoder::new(archive)
+            .with_context(|| "read zstd stream")?;
+        tar_unpack(dec, None)
+    }
+
+    fn unpack(&self, archive: File, root: &Path)
+        -> Result<Vec<PathBuf>, Error>
+    {
+        let dec = zstd::stream::read::Decoder::new(archive)
+            .with_context(|| "read zstd stream")?;
+        tar_unpack(dec, Some(root))
+    }
+}
+
+/// Writes the `files` under the `root` directory into a tar archive. Symbolic
+/// links are archived as links.
+#[cfg(feature = "archive")]
+fn tar_pack<W>(root: &Path, files: &[PathBuf], out: W) -> Result<W, Error>
+    where W: Write
+{
+    let mut builder = tar::Builder::new(out);
+    builder.follow_symlinks(false);
+    for file in files {
+        builder.append_path_with_name(root.join(file), file)
+            .with_context(|| format!("Unable to archive {:?}", file))?;
+    }
+    builder.into_inner()
+        .with_context(|| "finish tar archive")
+}
+
+/// Reads the files of a tar archive, extracting them into the `root`
+/// directory if one is given. Files which would be extracted outside of the
+/// `root` directory through an archived symbolic link are rejected.
+#[cfg(feature = "archive")]
+fn tar_unpack<R>(reader: R, root: Option<&Path>)
+    -> Result<Vec<PathBuf>, Error>
+    where R: Read
+{
+    let mut tar = tar::Archive::new(reader);
+    tar.set_preserve_mtime(true);
+    let mut files = Vec::new();
+    for tar_entry in tar.entries().with_context(|| "read tar archive")? {
+        let mut tar_entry = tar_entry
+            .with_context(|| "read tar archive entry")?;
+        let path = match enclosed(&tar_entry.path()?) {
+            Some(path) => path,
+            None       => continue,
+        };
+        if tar_entry.header().entry_type().is_dir() { continue }
+
+        if let Some(root) = root {
+            let _ = tar_entry.unpack_in(root)
+                .with_context(|| format!("Unable to extract {:?}", path))?;
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Zip
+////////////////////////////////////////////////////////////////////////////////
+/// A zip [`ArchiveFormat`].
+///
+/// Zip archives store timestamps without a time zone, so they are treated as
+/// UTC. Symbolic links are archived as the files they refer to.
+///
+/// [`ArchiveFormat`]: trait.ArchiveFormat.html
+#[cfg(feature = "archive")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zip;
+
+#[cfg(feature = "archive")]
+impl ArchiveFormat for Zip {
+    fn pack(&self, root: &Path, files: &[PathBuf], out: File)
+        -> Result<(), Error>
+    {
+        let mut writer = zip::ZipWriter::new(out);
+        for file in files {
+            let path = root.join(file);
+            let name = file.to_str().ok_or(InvalidFile)?.replace('\\', "/");
+            let metadata = path.metadata()
+                .with_context(|| format!("Unable to read metadata of {:?}",
+                    path))?;
+            let modified = metadata.modified()
+                .with_context(|| "load source modified time")?;
+            let options = zip::write::FileOptions::default()
+                .last_modified_time(zip_from_system_time(modified))
+                .unix_permissions(unix_mode(&metadata));
+            writer.start_file(name, options)
+                .with_context(|| "write zip archive member")?;
+            let mut source = File::open(&path)
+                .with_context(|| format!("Unable to read {:?}", path))?;
+            let _ = std::io::copy(&mut source, &mut writer)
+                .with_context(|| "write zip archive member")?;
+        }
+        let _ = writer.finish()
+            .with_context(|| "finish zip archive")?;
+        Ok(())
+    }
+
+    fn list(&self, archive: File) -> Result<Vec<PathBuf>, Error> {
+        zip_unpack(archive, None)
+    }
+
+    fn unpack(&self, archive: File, root: &Path)
+        -> Result<Vec<PathBuf>, Error>
+    {
+        zip_unpack(archive, Some(root))
+    }
+}
+
+/// Reads the files of a zip archive, extracting them into the `root`
+/// directory if one is given.
+#[cfg(feature = "archive")]
+fn zip_unpack(archive: File, root: Option<&Path>)
+    -> Result<Vec<PathBuf>, Error>
+{
+    let mut zip = zip::ZipArchive::new(archive)
+        .with_context(|| "read zip archive")?;
+    let mut files = Vec::new();
+    for i in 0..zip.len() {
+        let mut zip_file = zip.by_index(i)
+            .with_context(|| "read zip archive member")?;
+        let path = match zip_file.enclosed_name().and_then(enclosed) {
+            Some(path) => path,
+            None       => continue,
+        };
+        if zip_file.is_dir() { continue }
+
+        if let Some(root) = root {
+            let target = root.join(&path);
+            create_parent(&target)?;
+            let mut out = File::create(&target)
+                .with_context(|| format!("Unable to write {:?}", target))?;
+            let _ = std::io::copy(&mut zip_file, &mut out)
+                .with_context(|| format!("Unable to extract {:?}", path))?;
+            out.set_modified(system_time_from_zip(&zip_file.last_modified()))
+                .with_context(|| format!("Unable to set modified time of \
+                    {:?}", target))?;
+            set_unix_mode(&target, zip_file.unix_mode())?;
+        }
+        files.push(path);
+    }
+    Ok(files)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the archived `path` if it is a relative path which stays within
+/// the directory it is extracted into.
+#[cfg(feature = "archive")]
+fn enclosed(path: &Path) -> Option<PathBuf> {
+    let mut enclosed = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => enclosed.push(name),
+            Component::CurDir       => (),
+            _ => {
+                warn!("Ignoring archived file {:?}: it is outside of the \
+                    stall directory.", path);
+                return None;
+            },
+        }
+    }
+    match enclosed.as_os_str().is_empty() {
+        true  => None,
+        false => Some(enclosed),
+    }
+}
+
+/// Applies the `files` extracted into the `temp` directory to the stall
+/// directory `into`, returning the number of files applied and kept.
+fn apply_delta_files(
+    into: &Path,
+    temp: &Path,
+    files: &[PathBuf],
+    common: &CommonOptions)
+    -> Result<(usize, usize), Error>
+{
+    let mut applied = 0;
+    let mut kept = 0;
+    for file in files {
+        let source = temp.join(file);
+        let target = into.join(file);
+        let incoming = modified_time(&source)?;
+        let label = match target.exists() {
+            false => "add   ",
+            true if same_contents(&source, &target)? => {
+                debug!("    same   {}", file.display());
+                continue;
+            },
+            true if modified_time(&target)? <= incoming || common.force
+                => "update",
+            true => {
+                info!("    {} {}",
+                    paint("label", "keep  "),
+                    file.display());
+                kept += 1;
+                continue;
+            },
+        };
+        info!("    {} {}",
+            paint("label", label),
+            file.display());
+        applied += 1;
+        if common.dry_run { continue; }
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create directory {:?}",
+                    parent))?;
+        }
+        let _ = std::fs::copy(&source, &target)
+            .with_context(|| format!("Unable to write {:?}", target))?;
+        File::options()
+            .write(true)
+            .open(&target)
+            .and_then(|out| out.set_modified(incoming))
+            .with_context(|| format!("Unable to set modified time of {:?}",
+                target))?;
+    }
+    Ok((applied, kept))
+}
+
+/// Returns the modified time of the file at `path`.
+fn modified_time(path: &Path) -> Result<SystemTime, Error> {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Unable to read modified time of {:?}",
+            path))
+}
+
+/// Returns true if the files at `a` and `b` have the same contents.
+fn same_contents(a: &Path, b: &Path) -> Result<bool, Error> {
+    let read = |path: &Path| std::fs::read(path)
+        .with_context(|| format!("Unable to read {:?}", path));
+    Ok(read(a)? == read(b)?)
+}
+
+/// Parses a UTC date given as `YYYY-MM-DD`, optionally followed by a time as
+/// `THH:MM` or `THH:MM:SS`. A space may separate the date and time.
+fn parse_date(date: &str) -> Option<SystemTime> {
+    let (date, time) = match date.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None               => (date, None),
+    };
+    let number = |part: Option<&str>, range: std::ops::RangeInclusive<i64>| {
+        part.and_then(|part| part.parse::<i64>().ok())
+            .filter(|n| range.contains(n))
+    };
+
+    let mut parts = date.split('-');
+    let year = number(parts.next(), 1970..=9999)?;
+    let month = number(parts.next(), 1..=12)?;
+    let day = number(parts.next(), 1..=31)?;
+    if parts.next().is_some() { return None; }
+
+    let mut secs = days_from_civil(year, month, day) * 86_400;
+    if let Some(time) = time {
+        let mut parts = time.split(':');
+        secs += number(parts.next(), 0..=23)? * 3_600;
+        secs += number(parts.next(), 0..=59)? * 60;
+        secs += match parts.next() {
+            Some(part) => number(Some(part), 0..=59)?,
+            None       => 0,
+        };
+        if parts.next().is_some() { return None; }
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(secs as u64))
+}
+
+/// Opens the archive at `path`.
+fn open_archive(path: &Path) -> Result<File, Error> {
+    File::open(path)
+        .with_context(|| format!("Unable to open archive {:?}", path))
+}
+
+/// Creates the parent directories of `path`.
+#[cfg(feature = "archive")]
+fn create_parent(path: &Path) -> Result<(), Error> {
+    match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create directory {:?}",
+                parent)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the unix permission bits of a file.
+#[cfg(all(unix, feature = "archive"))]
+fn unix_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt as _;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Returns the unix permission bits of a file.
+#[cfg(all(not(unix), feature = "archive"))]
+fn unix_mode(metadata: &Metadata) -> u32 {
+    match metadata.permissions().readonly() {
+        true  => 0o444,
+        false => 0o644,
+    }
+}
+
+/// Applies the unix permission bits of an archived file to the extracted
+/// file at `path`.
+#[cfg(all(unix, feature = "archive"))]
+fn set_unix_mode(path: &Path, mode: Option<u32>) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt as _;
+    match mode {
+        Some(mode) => std::fs::set_permissions(path,
+                std::fs::Permissions::from_mode(mode & 0o7777))
+            .with_context(|| format!("Unable to set permissions of {:?}",
+                path)),
+        None => Ok(()),
+    }
+}
+
+/// Applies the unix permission bits of an archived file to the extracted
+/// file at `path`.
+#[cfg(all(not(unix), feature = "archive"))]
+fn set_unix_mode(_path: &Path, _mode: Option<u32>) -> Result<(), Error> {
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+#[cfg(all(test, unix, feature = "archive"))]
+mod tests {
+    use super::*;
+
+    /// Returns a new empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("stall-export-test-{}-{}", std::process::id(),
+                name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn tar_unpack_rejects_files_through_symlinks() {
+        let dir = test_dir("symlink");
+        let root = dir.join("stall");
+        let outside = dir.join("outside");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder.append_link(&mut header, "a", &outside).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, "a/passwd", &b"secret"[..])
+            .unwrap();
+        let archive = builder.into_inner().unwrap();
+
+        assert!(tar_unpack(&archive[..], Some(&root)).is_err());
+        assert!(!outside.join("passwd").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,312 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Per-host status reports for stalls shared between machines.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::EntryStatus;
+use crate::action::format_path;
+use crate::action::Location;
+use crate::action::replace_file;
+use crate::action::StallStore;
+use crate::action::State;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// DEFAULT_HOSTS_PATH
+////////////////////////////////////////////////////////////////////////////////
+/// The path of the directory of host status files, relative to the stall
+/// directory.
+pub const DEFAULT_HOSTS_PATH: &str = ".stall-hosts";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// HostStatus
+////////////////////////////////////////////////////////////////////////////////
+/// The states of the entries of a stall on one host, as of its last status
+/// check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub struct HostStatus {
+    /// The name of the host.
+    pub host: String,
+    /// The time of the last status check.
+    pub checked: SystemTime,
+    /// The state of each entry, keyed by its remote path.
+    pub states: BTreeMap<PathBuf, State>,
+}
+
+impl HostStatus {
+    /// Loads the `HostStatus` of the given host from the hosts directory.
+    /// Returns `None` if the host has no status file.
+    pub fn load(hosts_dir: &Path, host: &str) -> Result<Option<Self>, Error> {
+        let path = host_status_path(hosts_dir, host)?;
+        match std::fs::read_to_string(&path) {
+            Ok(text) => ron::de::from_str(&text)
+                .map(Some)
+                .with_context(|| format!("Unable to parse host status file: \
+                    {:?}", path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+                .with_context(|| format!("Unable to read host status file: \
+                    {:?}", path)),
+        }
+    }
+
+    /// Loads the `HostStatus` of every host in the hosts directory, ordered
+    /// by host name.
+    pub fn load_all(hosts_dir: &Path) -> Result<Vec<Self>, Error> {
+        let dir = match std::fs::read_dir(hosts_dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+                .with_context(|| format!("Unable to read hosts directory: \
+                    {:?}", hosts_dir)),
+        };
+
+        let mut hosts = Vec::new();
+        for dir_entry in dir {
+            let path = dir_entry
+                .with_context(|| "read hosts directory entry")?
+                .path();
+            if path.extension().is_none_or(|ext| ext != "ron") { continue; }
+            let host = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(host) => host,
+                None       => continue,
+            };
+            hosts.extend(HostStatus::load(hosts_dir, host)?);
+        }
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        Ok(hosts)
+    }
+
+    /// Writes the `HostStatus` into the hosts directory.
+    pub fn save(&self, hosts_dir: &Path) -> Result<(), Error> {
+        let path = host_status_path(hosts_dir, &self.host)?;
+        std::fs::create_dir_all(hosts_dir)
+            .with_context(|| format!("Unable to create hosts directory: {:?}",
+                hosts_dir))?;
+        let temp = path.with_extension("stall-tmp");
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .with_context(|| "serialize host status")?;
+        std::fs::write(&temp, text)
+            .with_context(|| format!("Unable to write host status file: \
+                {:?}", temp))?;
+        replace_file(&temp, &path)
+            .with_context(|| format!("Unable to write host status file: \
+                {:?}", path))?;
+        debug!("Saved host status: {:?}", path);
+        Ok(())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// publish_host_status
+////////////////////////////////////////////////////////////////////////////////
+/// Records the given statuses in the status file of the current host, so that
+/// they are shown by the 'stall fleet status' command on other hosts sharing
+/// the stall. Entries not among the statuses keep their previous states.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the host status file can't be read or written.
+///
+/// [`Error`]: ../error/struct.Error.html
+pub fn publish_host_status(
+    store: &StallStore,
+    statuses: &[EntryStatus<'_>])
+    -> Result<(), Error>
+{
+    let hosts_dir = store.hosts_dir();
+    let host = host_name();
+    let mut host_status = HostStatus::load(&hosts_dir, &host)?
+        .unwrap_or_else(|| HostStatus {
+            host: host.clone(),
+            checked: SystemTime::now(),
+            states: BTreeMap::new(),
+        });
+    host_status.checked = SystemTime::now();
+    for status in statuses {
+        let _ = host_status.states.insert(
+            status.entry.path.to_path_buf(),
+            status.state);
+    }
+    host_status.save(&hosts_dir)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// fleet_status
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall fleet status' command.
+///
+/// This will print a matrix of the state of each entry on each host which has
+/// published its status into the stall directory.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to report the status of.
+/// + `files`: An iterator over the [`Entry`]s of the files to show.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if a host status file can't be read.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn fleet_status<'i, P, I>(
+    stall_dir: P,
+    files: I,
+    common: CommonOptions)
+    -> Result<(), StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let stall_dir = stall_dir.as_ref();
+    info!("{} {}",
+        paint("label", "Stall directory:"),
+        format_path(stall_dir, &common).display());
+
+    let hosts = HostStatus::load_all(&StallStore::new(stall_dir).hosts_dir())?;
+    if hosts.is_empty() {
+        info!("No host has published its status. Set `fleet: true` in the \
+            stall file and run 'stall status' on each host.");
+        return Ok(());
+    }
+
+    // Each column is wide enough for its host name and the state labels.
+    let widths: Vec<usize> = hosts.iter()
+        .map(|host| host.host.chars().count().max(State::Unknown.label().len()))
+        .collect();
+    let mut header = String::new();
+    for (host, width) in hosts.iter().zip(&widths) {
+        header.push_str(&format!("{:<width$} ", host.host, width = width));
+    }
+    info!("    {}{}",
+        paint("label", &header),
+        paint("label", "FILE"));
+
+    for entry in files {
+        let mut row = String::new();
+        for (host, width) in hosts.iter().zip(&widths) {
+            let padding = width - State::Unknown.label().len();
+            match host.states.get(&*entry.path) {
+                Some(state) => row.push_str(&format!("{}{:padding$} ",
+                    state.colored_string(), "", padding = padding)),
+                None => row.push_str(&format!("{:<width$} ", "-",
+                    width = width)),
+            }
+        }
+        let path = Location::remote(entry).display_path();
+        info!("    {}{}",
+            row,
+            format_path(&path, &common).display());
+    }
+
+    for host in &hosts {
+        let age = SystemTime::now()
+            .duration_since(host.checked)
+            .unwrap_or_default();
+        info!("{} last checked {} seconds ago.",
+            host.host,
+            age.as_secs());
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// host_name
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the name of the current host.
+#[cfg(unix)]
+pub fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    // The buffer length is passed, and the name is null-terminated unless it
+    // was truncated, which is handled by searching for the terminator.
+    let res = unsafe {
+        libc::gethostname(buf.as_mut_ptr().cast(), buf.len())
+    };
+    if res != 0 {
+        warn!("Unable to determine host name: {}",
+            std::io::Error::last_os_error());
+        return "localhost".into();
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// Returns the name of the current host.
+#[cfg(not(unix))]
+pub fn host_name() -> String {
+    std::env::var("COMPUTERNAME")
+        .unwrap_or_else(|_| "localhost".into())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the path of the status file of the given host in the hosts
+/// directory.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the host name is empty or contains a path
+/// separator or `..`, so that the file would be outside of the hosts
+/// directory.
+///
+/// [`Error`]: ../error/struct.Error.html
+fn host_status_path(hosts_dir: &Path, host: &str) -> Result<PathBuf, Error> {
+    if host.is_empty() || host.contains("..") || host.contains(['/', '\\']) {
+        return Err(Error::msg(format!("invalid host name {:?}: host names \
+            may not be empty or contain path separators or '..'", host)));
+    }
+    Ok(hosts_dir.join(format!("{}.ron", host)))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_status_path_rejects_escaping_names() {
+        let hosts_dir = Path::new("stall").join(DEFAULT_HOSTS_PATH);
+        assert_eq!(host_status_path(&hosts_dir, "laptop").unwrap(),
+            hosts_dir.join("laptop.ron"));
+        for host in ["", "..", "../x", "a/b", "a\\b", "x/../y"] {
+            assert!(host_status_path(&hosts_dir, host).is_err(),
+                "accepted {:?}", host);
+        }
+    }
+}
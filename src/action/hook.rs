@@ -0,0 +1,163 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Entry hook commands.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::indent;
+use crate::action::Location;
+use crate::CommonOptions;
+use crate::error::Context;
+use crate::error::Error;
+use crate::logger::ChildStream;
+use crate::logger::log_child_output;
+use crate::RunContext;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::process::Command;
+use std::process::Stdio;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// HookStage
+////////////////////////////////////////////////////////////////////////////////
+/// The point at which a hook is run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    /// The hook is run before the entry is copied.
+    Pre,
+    /// The hook is run after the entry is copied.
+    Post,
+}
+
+impl HookStage {
+    /// Returns the name of the entry option for the hook.
+    fn option(&self) -> &'static str {
+        match self {
+            HookStage::Pre  => "pre_hook",
+            HookStage::Post => "post_hook",
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// HookEnv
+////////////////////////////////////////////////////////////////////////////////
+/// The environment passed to a hook command.
+///
+/// Hooks are run with the following environment variables set:
+///
+/// + `STALL_DIR`: The stall directory.
+/// + `STALL_ENTRY_LOCAL`: The path of the entry within the stall directory.
+/// + `STALL_ENTRY_REMOTE`: The path of the entry outside the stall directory.
+/// + `STALL_ACTION`: The command being run: `collect` or `distribute`.
+/// + `STALL_DRY_RUN`: `1` if the `--dry-run` option is given, otherwise `0`.
+///   Hooks are also run for dry runs, and should make no changes when this
+///   is set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookEnv {
+    /// The environment variables, in the order they are printed.
+    vars: Vec<(&'static str, String)>,
+}
+
+impl HookEnv {
+    /// Constructs the `HookEnv` for an entry with the given `local` and
+    /// `remote` locations.
+    pub fn new(
+        local: &Location,
+        remote: &Location,
+        collect: bool,
+        common: &CommonOptions,
+        context: &RunContext)
+        -> Self
+    {
+        let vars = vec![
+            ("STALL_DIR", context.stall_dir.display().to_string()),
+            ("STALL_ENTRY_LOCAL", local.to_string()),
+            ("STALL_ENTRY_REMOTE", remote.to_string()),
+            ("STALL_ACTION", match collect {
+                true  => "collect".into(),
+                false => "distribute".into(),
+            }),
+            ("STALL_DRY_RUN", match common.dry_run {
+                true  => "1".into(),
+                false => "0".into(),
+            }),
+        ];
+        HookEnv { vars }
+    }
+
+    /// Returns the environment variables.
+    pub fn vars(&self) -> &[(&'static str, String)] {
+        &self.vars
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// run_hook
+////////////////////////////////////////////////////////////////////////////////
+/// Runs the hook `command` with the system shell, passing the [`HookEnv`].
+/// The output of the command is logged.
+///
+/// With the `--simulate-hooks` option, the command and its environment are
+/// printed instead.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the command can't be run or fails.
+///
+/// [`HookEnv`]: struct.HookEnv.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn run_hook(
+    command: &str,
+    stage: HookStage,
+    env: &HookEnv,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    if common.simulate_hooks {
+        info!("{}        {} {}",
+            indent(context),
+            paint("label", &format!("{}:", stage.option())),
+            command);
+        for (name, value) in env.vars() {
+            info!("{}            {}={}", indent(context), name, value);
+        }
+        return Ok(());
+    }
+
+    let (shell, flag) = match cfg!(target_os = "windows") {
+        true  => ("cmd", "/C"),
+        false => ("sh", "-c"),
+    };
+    debug!("Running {} {:?}", stage.option(), command);
+    let output = Command::new(shell)
+        .arg(flag)
+        .arg(command)
+        .envs(env.vars().iter().map(|(name, value)| (name, value)))
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("execute {}", stage.option()))?;
+
+    log_child_output(stage.option(), ChildStream::Stdout, &output.stdout);
+    log_child_output(stage.option(), ChildStream::Stderr, &output.stderr);
+    if !output.status.success() {
+        return Err(Error::msg(format!("{} failed: {}: {}",
+            stage.option(),
+            command,
+            output.status)));
+    }
+    Ok(())
+}
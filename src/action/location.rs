@@ -0,0 +1,257 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! File locations.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::walk;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::RunContext;
+
+// Standard library imports.
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Location
+////////////////////////////////////////////////////////////////////////////////
+/// The location of a file to be copied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    /// A file in the file system.
+    File(PathBuf),
+    /// A file stored within an archive.
+    Archived {
+        /// The path of the archive.
+        archive: PathBuf,
+        /// The path of the file within the archive.
+        member: PathBuf,
+    },
+}
+
+impl Location {
+    /// Returns the `Location` of the file at the given path. On windows, the
+    /// path is converted to an extended-length path, so that files nested
+    /// deeper than `MAX_PATH` can be copied.
+    pub fn file<P>(path: P) -> Self
+        where P: AsRef<Path>
+    {
+        Location::File(extended_path(path.as_ref()))
+    }
+
+    /// Returns the `Location` of the file outside the stall directory for the
+    /// given [`Entry`].
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn remote(entry: &Entry) -> Self {
+        match &entry.member {
+            Some(member) => Location::Archived {
+                archive: extended_path(&entry.path),
+                member: member.to_path_buf(),
+            },
+            None => Location::file(&entry.path),
+        }
+    }
+
+    /// Returns the path used to display the location. Extended-length path
+    /// prefixes are removed.
+    pub fn display_path(&self) -> PathBuf {
+        match self {
+            Location::File(path) => display_path(path),
+            Location::Archived { archive, member }
+                => display_path(archive).join(member),
+        }
+    }
+
+    /// Returns true if the location is a directory. Archived files are never
+    /// directories.
+    pub fn is_dir(&self) -> bool {
+        match self {
+            Location::File(path) => path.is_dir(),
+            Location::Archived { .. } => false,
+        }
+    }
+
+    /// Returns a description of the kind of special file at the location, such
+    /// as a FIFO or socket, or `None` if it is not a special file. Archived
+    /// files are never special files.
+    pub fn special_kind(&self) -> Option<&'static str> {
+        match self {
+            Location::File(path) => path.metadata().ok()
+                .and_then(|meta| walk::special_kind(&meta.file_type())),
+            Location::Archived { .. } => None,
+        }
+    }
+
+    /// Returns a description of the kind of file at the location.
+    pub fn kind(&self) -> &'static str {
+        if let Some(kind) = self.special_kind() {
+            return kind;
+        }
+        match self {
+            Location::File(path) if path.is_dir() => "directory",
+            Location::File(_) => "file",
+            Location::Archived { .. } => "archived file",
+        }
+    }
+
+    /// Returns true if the file exists.
+    pub fn exists(&self) -> Result<bool, Error> {
+        match self {
+            Location::File(path) => Ok(path.exists()),
+            Location::Archived { archive, member } => match archive.exists() {
+                true  => Ok(archive::member_info(archive, member)?.is_some()),
+                false => Ok(false),
+            },
+        }
+    }
+
+    /// Returns the last modification time of the file. For directories, this
+    /// is the most recent modification time of any file within it.
+    pub fn modified(&self, common: &CommonOptions, context: &RunContext)
+        -> Result<SystemTime, Error>
+    {
+        match self {
+            Location::File(path) if path.is_dir() => {
+                let mut latest = std::time::UNIX_EPOCH;
+                walk::walk_entry_files(path, common, context, |_, meta| {
+                    let modified = meta.modified()
+                        .with_context(|| "load modified time")?;
+                    if modified > latest { latest = modified; }
+                    Ok(())
+                })?;
+                Ok(latest)
+            },
+
+            Location::File(path) => path.metadata()
+                .with_context(|| "load metadata")?
+                .modified()
+                .with_context(|| "load modified time"),
+
+            Location::Archived { archive, member } => archive::member_info(
+                    archive,
+                    member)?
+                .map(|info| info.modified)
+                .ok_or_else(|| Error::msg("missing archive member")),
+        }
+    }
+
+    /// Returns the size of the file in bytes. For directories, this is the
+    /// total size of all files within it.
+    pub fn size(&self, common: &CommonOptions, context: &RunContext)
+        -> Result<u64, Error>
+    {
+        match self {
+            Location::File(path) if path.is_dir() => {
+                let mut total = 0;
+                walk::walk_entry_files(path, common, context, |_, meta| {
+                    total += meta.len();
+                    Ok(())
+                })?;
+                Ok(total)
+            },
+
+            Location::File(path) => Ok(path.metadata()
+                .with_context(|| "load metadata")?
+                .len()),
+
+            Location::Archived { archive, member } => archive::member_info(
+                    archive,
+                    member)?
+                .map(|info| info.size)
+                .ok_or_else(|| Error::msg("missing archive member")),
+        }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.display_path().display())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Extended-length paths.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the extended-length form of the given path, prefixed with `\\?\`.
+/// Relative paths are made absolute, and `.` and `..` components are resolved,
+/// as they are not interpreted within extended-length paths.
+#[cfg(windows)]
+fn extended_path(path: &Path) -> PathBuf {
+    use std::ffi::OsString;
+    use std::path::Component;
+    use std::path::Prefix;
+
+    let absolute = match std::env::current_dir() {
+        _ if path.is_absolute() => path.to_path_buf(),
+        Ok(dir) => dir.join(path),
+        Err(_)  => return path.to_path_buf(),
+    };
+    let mut components = absolute.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => prefix,
+        _ => return absolute,
+    };
+    let mut extended = match prefix.kind() {
+        Prefix::Disk(_) => {
+            let mut extended = OsString::from(r"\\?\");
+            extended.push(prefix.as_os_str());
+            PathBuf::from(extended)
+        },
+        Prefix::UNC(server, share) => {
+            let mut extended = OsString::from(r"\\?\UNC\");
+            extended.push(server);
+            extended.push(r"\");
+            extended.push(share);
+            PathBuf::from(extended)
+        },
+        // Verbatim and device paths are left unchanged.
+        _ => return absolute,
+    };
+    for component in components {
+        match component {
+            Component::RootDir    => extended.push(r"\"),
+            Component::ParentDir  => { let _ = extended.pop(); },
+            Component::Normal(name) => extended.push(name),
+            Component::CurDir | Component::Prefix(_) => (),
+        }
+    }
+    extended
+}
+
+/// Returns the extended-length form of the given path.
+#[cfg(not(windows))]
+fn extended_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
+
+/// Returns the given path with any extended-length prefix removed.
+#[cfg(windows)]
+fn display_path(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    if let Some(unc) = text.strip_prefix(r"\\?\UNC\") {
+        PathBuf::from(format!(r"\\{}", unc))
+    } else if let Some(disk) = text.strip_prefix(r"\\?\") {
+        PathBuf::from(disk)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Returns the given path with any extended-length prefix removed.
+#[cfg(not(windows))]
+fn display_path(path: &Path) -> PathBuf {
+    path.to_path_buf()
+}
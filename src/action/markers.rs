@@ -0,0 +1,126 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Checks for unresolved template and merge conflict markers.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::Location;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::UnresolvedMarker;
+use crate::RunContext;
+
+// External library imports.
+use log::*;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// check_markers
+////////////////////////////////////////////////////////////////////////////////
+/// Checks that the `source` file of an [`Entry`] being distributed contains
+/// no merge conflict markers, which would indicate a half-edited stall copy.
+/// If `template_markers` is set in the stall file, template delimiters are
+/// also refused, as files such as CI workflows commonly contain them.
+///
+/// Collected files, templates, encrypted entries, directories, and binary
+/// files are not checked, and the `--force` option skips the check.
+///
+/// ### Errors
+///
+/// Returns an [`UnresolvedMarker`] error if a marker is found, or an
+/// [`Error`] if the file can't be read.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`UnresolvedMarker`]: ../error/struct.UnresolvedMarker.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn check_markers(
+    entry: &Entry,
+    source: &Location,
+    collect: bool,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    if collect || common.force || entry.template || entry.encrypted
+        || source.is_dir()
+    {
+        return Ok(());
+    }
+
+    let bytes = match source {
+        Location::File(path) => std::fs::read(path)
+            .with_context(|| format!("Unable to read {:?} to check for \
+                markers", path))?,
+        Location::Archived { archive, member } => archive::read_member(
+            archive,
+            member)?,
+    };
+    if bytes.contains(&0) {
+        trace!("Not checking binary file for markers: {:?}", source);
+        return Ok(());
+    }
+
+    let text = String::from_utf8_lossy(&bytes);
+    for (idx, line) in text.lines().enumerate() {
+        if let Some(kind) = marker_kind(line, context.template_markers) {
+            return Err(UnresolvedMarker {
+                path: source.display_path().into(),
+                line: idx + 1,
+                kind,
+            }.into());
+        }
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the kind of marker found on the line, if any. Template delimiters
+/// are only found if `template_delimiters` is true.
+fn marker_kind(line: &str, template_delimiters: bool) -> Option<&'static str>
+{
+    if line.starts_with("<<<<<<< ") || line.starts_with(">>>>>>> ") {
+        return Some("merge conflict marker");
+    }
+    if !template_delimiters { return None; }
+    match line.find("{{") {
+        Some(start) if line[start + 2..].contains("}}")
+            => Some("template delimiter"),
+        _ => None,
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_delimiters_are_opt_in() {
+        let line = "token: ${{ secrets.GITHUB_TOKEN }}";
+        assert_eq!(marker_kind(line, false), None);
+        assert_eq!(marker_kind(line, true), Some("template delimiter"));
+    }
+
+    #[test]
+    fn merge_conflict_markers_are_always_found() {
+        assert_eq!(marker_kind("<<<<<<< HEAD", false),
+            Some("merge conflict marker"));
+        assert_eq!(marker_kind(">>>>>>> theirs", false),
+            Some("merge conflict marker"));
+        assert_eq!(marker_kind("<<<<<<<", false), None);
+    }
+}
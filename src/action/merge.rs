@@ -0,0 +1,209 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Merging and comparison of stall files.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::format_path;
+use crate::CommonOptions;
+use crate::Config;
+use crate::ConfigFormat;
+use crate::DEFAULT_CONFIG_PATH;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::MergePolicy;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// merge
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall merge' command.
+///
+/// This will add the entries of another stall file to the stall file at
+/// `config_path`, resolving duplicate entries with the given [`MergePolicy`].
+/// The stall file is rewritten in the format it was read in, keeping the
+/// comments above its options and entries, but not its formatting. The other
+/// options of the stall file are kept.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option will print the changes without writing the stall
+/// file.
+///
+/// ### Parameters
+/// + `config_path`: The path of the stall file to merge into.
+/// + `other`: The stall file or stall directory to merge from.
+/// + `policy`: The [`MergePolicy`] for resolving duplicate entries.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns a [`MergeConflict`] error if an entry is duplicated and the
+/// policy is [`MergePolicy::Error`], or an [`Error`] if either stall file
+/// can't be read or the stall file can't be written.
+///
+/// [`MergePolicy`]: ../enum.MergePolicy.html
+/// [`MergePolicy::Error`]: ../enum.MergePolicy.html#variant.Error
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`MergeConflict`]: ../error/struct.MergeConflict.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn merge(
+    config_path: &Path,
+    other: &Path,
+    policy: MergePolicy,
+    common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let (mut config, format) = read_stall_file(config_path)?;
+
+    let other_config = load_other(other)?;
+    let summary = config.merge(other_config, policy)?;
+    config.check_reserved_paths()?;
+    for (label, paths) in [
+        ("added   ", &summary.added),
+        ("replaced", &summary.replaced),
+        ("kept    ", &summary.kept),
+    ] {
+        for path in paths {
+            info!("    {} {}",
+                paint("label", label),
+                format_path(path, common).display());
+        }
+    }
+
+    if !common.dry_run {
+        write_stall_file(&config, config_path, format)?;
+    }
+    let merged = summary.added.len() + summary.replaced.len();
+    info!("{} {} {} into {}.",
+        paint("label", match common.dry_run {
+            true  => "Would merge",
+            false => "Merged",
+        }),
+        merged,
+        if merged == 1 { "entry" } else { "entries" },
+        format_path(config_path, common).display());
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// diff
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall diff' command.
+///
+/// This will print the entries which were added, removed, renamed, or
+/// changed in another stall file relative to the given [`Config`], in order
+/// to review them before merging.
+///
+/// ### Parameters
+/// + `config`: The [`Config`] of the stall file to compare from.
+/// + `other`: The stall file or stall directory to compare to.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the other stall file can't be read.
+///
+/// [`Config`]: ../struct.Config.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn diff(config: &Config, other: &Path, common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let diff = config.diff(&load_other(other)?);
+    for (label, paths) in [
+        ("added  ", &diff.added),
+        ("removed", &diff.removed),
+        ("changed", &diff.changed),
+    ] {
+        for path in paths {
+            info!("    {} {}",
+                paint("label", label),
+                format_path(path, common).display());
+        }
+    }
+    for (from, to) in &diff.renamed {
+        info!("    {} {} -> {}",
+            paint("label", "renamed"),
+            format_path(from, common).display(),
+            format_path(to, common).display());
+    }
+
+    if diff.is_empty() {
+        info!("No differences in the entries of {}.",
+            format_path(other, common).display());
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Reads the stall file at the given path, returning it along with the
+/// format it was written in.
+pub(crate) fn read_stall_file(config_path: &Path)
+    -> Result<(Config, ConfigFormat), Error>
+{
+    let bytes = std::fs::read(config_path)
+        .with_context(|| format!("Unable to read config file: {:?}",
+            config_path))?;
+    Config::parse(&bytes)
+}
+
+/// Rewrites the stall file at the given path in the format it was read in,
+/// keeping its comments. Stall files read in the list format also keep their
+/// blank lines, but are rewritten in the RON format if an entry has options.
+pub(crate) fn write_stall_file(
+    config: &Config,
+    config_path: &Path,
+    format: ConfigFormat)
+    -> Result<(), Error>
+{
+    match format {
+        ConfigFormat::List if config.to_text(ConfigFormat::List).is_err() => {
+            warn!("The stall file has options which can't be written in the \
+                list format. Rewriting it in the RON format.");
+            config.write_to(config_path, ConfigFormat::Ron)
+        },
+        ConfigFormat::Json => config.write_to(config_path, format),
+        ConfigFormat::List | ConfigFormat::Ron => {
+            let original = std::fs::read_to_string(config_path)
+                .unwrap_or_default();
+            let text = match format {
+                ConfigFormat::List => config.update_list_text(&original)?,
+                _                  => config.update_ron_text(&original)?,
+            };
+            std::fs::write(config_path, text)
+                .with_context(|| format!("Unable to write stall file: {:?}",
+                    config_path))?;
+            debug!("Wrote stall file: {:?}", config_path);
+            Ok(())
+        },
+    }
+}
+
+/// Loads the stall file at the given path, or in the given stall directory.
+fn load_other(other: &Path) -> Result<Config, Error> {
+    let other_path = match other.is_dir() {
+        true  => other.join(DEFAULT_CONFIG_PATH),
+        false => other.to_path_buf(),
+    };
+    Config::from_path(&other_path)
+        .with_context(|| format!("Unable to load config file: {:?}",
+            other_path))
+}
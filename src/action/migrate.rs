@@ -0,0 +1,97 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Migration of the stall file to the newest schema version.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::format_path;
+use crate::action::write_stall_file;
+use crate::CommonOptions;
+use crate::Config;
+use crate::CONFIG_VERSION;
+use crate::ConfigFormat;
+use crate::error::Context;
+use crate::error::StallError;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// migrate
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall migrate' command.
+///
+/// This will rewrite the stall file at `config_path` in the newest schema
+/// version, the [`CONFIG_VERSION`], in the format it was written in. Stall
+/// files written with an older version are migrated each time they are
+/// loaded, so this is only needed to record the migration. As with 'stall
+/// merge', the comments above options and entries are kept, but not the
+/// formatting. Stall files in the list format have no schema version, and are
+/// left unchanged.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option will print the versions the stall file would be
+/// migrated between without writing it.
+///
+/// ### Parameters
+/// + `config_path`: The path of the stall file to migrate.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns an [`UnsupportedVersion`] error if the stall file was written with
+/// a newer schema version, or an [`Error`] if it can't be read or written.
+///
+/// [`CONFIG_VERSION`]: ../constant.CONFIG_VERSION.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`UnsupportedVersion`]: ../error/struct.UnsupportedVersion.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn migrate(config_path: &Path, common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let bytes = std::fs::read(config_path)
+        .with_context(|| format!("Unable to read config file: {:?}",
+            config_path))?;
+    let (mut config, format) = Config::parse_unmigrated(&bytes)?;
+    let version = config.version;
+    let path = format_path(config_path, common);
+    match (format, version) {
+        (ConfigFormat::List, _) => {
+            info!("{} is in the list format, which has no schema version.",
+                path.display());
+            return Ok(());
+        },
+        (_, CONFIG_VERSION) => {
+            info!("{} is already at schema version {}.",
+                path.display(),
+                version);
+            return Ok(());
+        },
+        _ => (),
+    }
+
+    config.migrate()?;
+    if !common.dry_run {
+        write_stall_file(&config, config_path, format)?;
+    }
+    info!("{} {} from schema version {} to version {}.",
+        paint("label", match common.dry_run {
+            true  => "Would migrate",
+            false => "Migrated",
+        }),
+        path.display(),
+        version,
+        CONFIG_VERSION);
+    Ok(())
+}
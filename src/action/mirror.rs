@@ -0,0 +1,163 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Mirroring of the distributed layout into a directory tree.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::copy_entry;
+use crate::action::CopyMethod;
+use crate::action::CopyStats;
+use crate::action::format_path;
+use crate::action::indent;
+use crate::action::Location;
+use crate::action::StallStore;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::InvalidFile;
+use crate::error::StallError;
+use crate::RunContext;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// mirror
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall mirror' command.
+///
+/// This will write the files of the given [`Entry`]s as they would be
+/// distributed into the `target` directory, with each remote path placed
+/// beneath it, as in `<target>/home/me/.bashrc`. Templates are rendered, and
+/// the remote files themselves are never read or written. Entries whose
+/// remote files are within archives are not mirrored.
+///
+/// ### Command line options
+///
+/// The `--decrypt` option will write the decrypted contents of encrypted
+/// entries, which are otherwise not mirrored.
+///
+/// The `--dry-run` option will print the files which would be mirrored
+/// without writing them.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to mirror from.
+/// + `target`: The directory to mirror into.
+/// + `files`: The [`Entry`]s to mirror.
+/// + `decrypt`: Whether to mirror encrypted entries.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the target is the root directory, or if a file
+/// can't be rendered, decrypted, or written.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn mirror<'i, I>(
+    stall_dir: &Path,
+    target: &Path,
+    files: I,
+    decrypt: bool,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<CopyStats, StallError>
+    where I: IntoIterator<Item=&'i Entry>
+{
+    let resolved = target.canonicalize()
+        .unwrap_or_else(|_| target.to_path_buf());
+    if resolved.parent().is_none() {
+        return Err(Error::msg(format!("unable to mirror into {:?}: the \
+            mirror would replace the remote files", target)).into());
+    }
+    info!("{}{} {}",
+        indent(context),
+        paint("label", "Mirror directory:"),
+        format_path(target, common).display());
+
+    let method = match common.dry_run {
+        true  => CopyMethod::None,
+        false => context.copy_method,
+    };
+    let store = StallStore::new(stall_dir);
+    let mut stats = CopyStats::default();
+    for entry in files {
+        let remote = Location::remote(entry);
+        if let Location::Archived { .. } = remote {
+            warn!("Not mirroring {}: remote files within archives are not \
+                mirrored.", remote);
+            continue;
+        }
+        let remote = remote.display_path();
+        if entry.encrypted && !decrypt {
+            info!("{}    {} {}",
+                indent(context),
+                paint("label", "skip  "),
+                format_path(&remote, common).display());
+            continue;
+        }
+
+        let file_name = entry.file_name().ok_or(InvalidFile)?;
+        let path = mirror_path(target, &remote);
+        if let (Some(parent), false) = (path.parent(), common.dry_run) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create directory {:?}",
+                    parent))?;
+        }
+        let mirrored = copy_entry(
+                entry,
+                &store.local(file_name),
+                &Location::file(&path),
+                method,
+                common,
+                context)
+            .with_context(|| format!("Unable to mirror {:?}", remote))?;
+        if let Some(mirrored) = mirrored {
+            stats += mirrored;
+        }
+        info!("{}    {} {}",
+            indent(context),
+            paint("label", "mirror"),
+            format_path(&remote, common).display());
+    }
+    Ok(stats)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the path of the remote path `remote` within the mirror directory
+/// `target`. Drive prefixes become directories, as in `<target>/C/Users`, and
+/// parent directory components are ignored.
+fn mirror_path(target: &Path, remote: &Path) -> PathBuf {
+    let mut path = target.to_path_buf();
+    for component in remote.components() {
+        match component {
+            Component::Prefix(prefix) => path.push(prefix.as_os_str()
+                .to_string_lossy()
+                .replace(':', "")),
+            Component::Normal(name) => path.push(name),
+            Component::RootDir
+                | Component::CurDir
+                | Component::ParentDir => (),
+        }
+    }
+    path
+}
@@ -0,0 +1,302 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Run reports and notifications.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::CopyStats;
+use crate::action::State;
+use crate::CommonOptions;
+use crate::error::Context;
+use crate::error::CopyFailureKind;
+use crate::error::Error;
+use crate::Notifier;
+use crate::RunContext;
+use crate::WebhookFormat;
+
+// External library imports.
+use log::*;
+use serde::Serialize;
+
+// Standard library imports.
+use std::io::Write as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// RunReport
+////////////////////////////////////////////////////////////////////////////////
+/// A structured summary of the changes made by a collect or distribute.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Serialize)]
+pub struct RunReport {
+    /// The command which was run: `collect` or `distribute`.
+    pub command: String,
+    /// The stall directory.
+    pub stall_dir: PathBuf,
+    /// Whether the command was a dry run, making no changes.
+    pub dry_run: bool,
+    /// The state of each entry and the action chosen for it, in order.
+    pub entries: Vec<ReportedEntry>,
+    /// The files which were copied.
+    pub changed: Vec<PathBuf>,
+    /// The files which were skipped due to a conflict.
+    pub conflicts: Vec<PathBuf>,
+    /// The files which failed to copy, and their errors.
+    pub errors: Vec<ReportedError>,
+    /// The error which stopped the command, if any.
+    pub error: Option<String>,
+    /// The number of files and bytes copied, and the time taken to copy
+    /// them.
+    #[serde(skip)]
+    pub stats: CopyStats,
+}
+
+impl RunReport {
+    /// Constructs an empty `RunReport` for the given command and stall
+    /// directory.
+    pub fn new(command: &str, stall_dir: &Path, common: &CommonOptions)
+        -> Self
+    {
+        RunReport {
+            command: command.into(),
+            stall_dir: stall_dir.to_path_buf(),
+            dry_run: common.dry_run,
+            .. RunReport::default()
+        }
+    }
+
+    /// Records the state of the file at the given path and the action chosen
+    /// for it.
+    pub fn record_entry(&mut self, path: &Path, state: State, action: Action) {
+        self.entries.push(ReportedEntry {
+            path: path.to_path_buf(),
+            state,
+            action,
+        });
+    }
+
+    /// Records an error for the file at the given path.
+    pub fn record_error(&mut self, path: &Path, error: &Error) {
+        self.errors.push(ReportedError {
+            path: path.to_path_buf(),
+            message: format!("{:#}", error),
+            kind: CopyFailureKind::of(error),
+        });
+    }
+
+    /// Records the result of the command.
+    pub fn finish(&mut self, result: &Result<CopyStats, Error>) {
+        match result {
+            Ok(stats) => self.stats = *stats,
+            Err(e)    => self.error = Some(e.to_string()),
+        }
+    }
+
+    /// Returns true if the command changed nothing and encountered no
+    /// conflicts or errors.
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty()
+            && self.conflicts.is_empty()
+            && self.errors.is_empty()
+            && self.error.is_none()
+    }
+
+    /// Returns a one line summary of the report.
+    pub fn summary(&self) -> String {
+        format!("stall {}{}: {} changed, {} {}, {} {} in {}",
+            self.command,
+            if self.dry_run { " (dry run)" } else { "" },
+            self.changed.len(),
+            self.conflicts.len(),
+            if self.conflicts.len() == 1 { "conflict" } else { "conflicts" },
+            self.errors.len(),
+            if self.errors.len() == 1 { "error" } else { "errors" },
+            self.stall_dir.display())
+    }
+
+    /// Returns the report as plain text, listing each file after the
+    /// summary.
+    pub fn text(&self) -> String {
+        let mut text = self.summary();
+        if let Some(error) = &self.error {
+            text.push_str(&format!("\n\nStopped: {}", error));
+        }
+        let sections = [
+            ("Changed", &self.changed),
+            ("Conflicts", &self.conflicts),
+        ];
+        for (label, paths) in sections {
+            if paths.is_empty() { continue; }
+            text.push_str(&format!("\n\n{}:", label));
+            for path in paths {
+                text.push_str(&format!("\n    {}", path.display()));
+            }
+        }
+        if !self.errors.is_empty() {
+            text.push_str("\n\nErrors:");
+            for error in &self.errors {
+                text.push_str(&format!("\n    {}: {}",
+                    error.path.display(),
+                    error.message));
+            }
+        }
+        text
+    }
+}
+
+/// The state of a file in a [`RunReport`] and the action chosen for it.
+///
+/// [`RunReport`]: struct.RunReport.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize)]
+pub struct ReportedEntry {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The state of the file.
+    pub state: State,
+    /// The action chosen for the file.
+    pub action: Action,
+}
+
+/// An error for a file in a [`RunReport`].
+///
+/// [`RunReport`]: struct.RunReport.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize)]
+pub struct ReportedError {
+    /// The path of the file.
+    pub path: PathBuf,
+    /// The error message, including its causes.
+    pub message: String,
+    /// The kind of copy failure, if the error was caused by a failed copy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<CopyFailureKind>,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// notify
+////////////////////////////////////////////////////////////////////////////////
+/// Sends the [`RunReport`] to each of the [`Notifier`]s of the
+/// [`RunContext`], if the `--notify` option is given. Nothing is sent if the
+/// report is empty.
+///
+/// A failed notification doesn't fail the command, so errors are printed as
+/// warnings.
+///
+/// [`RunReport`]: struct.RunReport.html
+/// [`Notifier`]: ../enum.Notifier.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+pub fn notify(report: &RunReport, common: &CommonOptions, context: &RunContext)
+{
+    if !common.notify { return; }
+    if context.notifiers.is_empty() {
+        warn!("--notify was given, but no notifiers are configured.");
+        return;
+    }
+    if report.is_empty() {
+        debug!("Nothing to notify: {}", report.summary());
+        return;
+    }
+
+    for notifier in &context.notifiers {
+        let result = match notifier {
+            Notifier::Webhook { url, format } => post_webhook(
+                url,
+                *format,
+                report),
+            Notifier::Sendmail { to } => send_mail(to, report),
+        };
+        match result {
+            Ok(()) => debug!("Sent notification: {:?}", notifier),
+            Err(e) => warn!("Unable to send notification: {:#}", e),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Posts the report to a webhook with `curl`.
+fn post_webhook(url: &str, format: WebhookFormat, report: &RunReport)
+    -> Result<(), Error>
+{
+    let body = match format {
+        WebhookFormat::Generic => serde_json::to_value(report)?,
+        WebhookFormat::Slack   => serde_json::json!({
+            "text": report.text(),
+        }),
+        WebhookFormat::Matrix  => serde_json::json!({
+            "msgtype": "m.text",
+            "body": report.text(),
+        }),
+    };
+
+    let mut command = Command::new("curl");
+    let _ = command.args([
+        "--silent",
+        "--show-error",
+        "--fail",
+        "--request", "POST",
+        "--header", "Content-Type: application/json",
+        "--data-binary", "@-",
+        "--url", url,
+    ]);
+    run_with_input("curl", command, body.to_string().as_bytes())
+}
+
+/// Mails the report with `sendmail`.
+///
+/// The recipient is rejected if it contains a line break, which would let it
+/// add headers to the message. Line breaks in the subject are replaced with
+/// spaces.
+fn send_mail(to: &str, report: &RunReport) -> Result<(), Error> {
+    if to.contains(['\r', '\n']) {
+        return Err(Error::msg(format!("invalid mail recipient: {:?}", to)));
+    }
+    let subject = report.summary().replace(['\r', '\n'], " ");
+    let message = format!("To: {}\nSubject: {}\n\n{}\n",
+        to,
+        subject,
+        report.text());
+
+    let mut command = Command::new("sendmail");
+    let _ = command.arg("-t");
+    run_with_input("sendmail", command, message.as_bytes())
+}
+
+/// Runs the command, writing `input` to its stdin.
+fn run_with_input(name: &str, mut command: Command, input: &[u8])
+    -> Result<(), Error>
+{
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("execute {} command", name))?;
+    {
+        let mut stdin = child.stdin.take().expect("piped stdin");
+        stdin.write_all(input)
+            .with_context(|| format!("write {} input", name))?;
+    }
+    let output = child.wait_with_output()
+        .with_context(|| format!("execute {} command", name))?;
+    if !output.status.success() {
+        return Err(Error::msg(format!("{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Generate packaging metadata from the command line interface.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::CommandOptions;
+use crate::error::Context;
+use crate::error::Error;
+
+// External library imports.
+use structopt::clap::AppSettings;
+use structopt::clap::ErrorKind;
+use structopt::clap::Shell;
+use structopt::StructOpt as _;
+
+// Standard library imports.
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// package_metadata
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall package-metadata' command.
+///
+/// This will write the following files into the `out` directory, generated
+/// from the command line interface definitions:
+///
+/// + `completions/`: Shell completion scripts for bash, zsh, fish,
+///   PowerShell, and elvish.
+/// + `man/stall.1`: A man page containing the help for each command.
+/// + `systemd/stall-collect@.service` and `systemd/stall-collect@.timer`:
+///   User units for periodically collecting into the stall directory given as
+///   the (path escaped) instance name.
+///
+/// Stall has no graphical interface, so no desktop entry is generated.
+///
+/// ### Parameters
+/// + `out`: The directory to write the files into.
+/// + `bin_dir`: The directory the `stall` binary will be installed into.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the files can't be written.
+///
+/// [`Error`]: ../error/struct.Error.html
+pub fn package_metadata(out: &Path, bin_dir: &Path) -> Result<(), Error> {
+    let name = env!("CARGO_PKG_NAME");
+
+    // Shell completions.
+    let completions = out.join("completions");
+    create_dir(&completions)?;
+    for (shell, file_name) in &[
+        (Shell::Bash, format!("{}.bash", name)),
+        (Shell::Zsh, format!("_{}", name)),
+        (Shell::Fish, format!("{}.fish", name)),
+        (Shell::PowerShell, format!("_{}.ps1", name)),
+        (Shell::Elvish, format!("{}.elv", name)),
+    ] {
+        let mut buf = Vec::new();
+        CommandOptions::clap().gen_completions_to(name, *shell, &mut buf);
+        write_file(&completions.join(file_name), &buf)?;
+    }
+
+    // Man page.
+    let man = out.join("man");
+    create_dir(&man)?;
+    write_file(&man.join(format!("{}.1", name)), man_page()?.as_bytes())?;
+
+    // Service units.
+    let systemd = out.join("systemd");
+    create_dir(&systemd)?;
+    let exec = bin_dir.join(name);
+    write_file(&systemd.join("stall-collect@.service"), format!("\
+        [Unit]\n\
+        Description=Collect files into the stall directory %I\n\
+        Documentation=man:{name}(1)\n\
+        \n\
+        [Service]\n\
+        Type=oneshot\n\
+        ExecStart={exec} collect --into %I --yes\n",
+        name = name,
+        exec = exec.display()).as_bytes())?;
+    write_file(&systemd.join("stall-collect@.timer"), "\
+        [Unit]\n\
+        Description=Periodically collect files into the stall directory %I\n\
+        \n\
+        [Timer]\n\
+        OnCalendar=daily\n\
+        Persistent=true\n\
+        \n\
+        [Install]\n\
+        WantedBy=timers.target\n".as_bytes())?;
+
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns a man page for the command line interface, in roff format.
+fn man_page() -> Result<String, Error> {
+    let name = env!("CARGO_PKG_NAME");
+    let mut page = format!("\
+        .TH {upper} 1 \"\" \"{name} {version}\" \"User Commands\"\n\
+        .SH NAME\n\
+        {name} \\- {description}\n\
+        .SH SYNOPSIS\n\
+        .B {name}\n\
+        \\fIcommand\\fR [\\fIoptions\\fR]\n\
+        .SH DESCRIPTION\n",
+        upper = name.to_uppercase(),
+        name = name,
+        version = env!("CARGO_PKG_VERSION"),
+        description = env!("CARGO_PKG_DESCRIPTION"));
+    page.push_str(&roff_block(&help_text(&[])?));
+
+    page.push_str(".SH COMMANDS\n");
+    for command in CommandOptions::COMMAND_NAMES {
+        page.push_str(&format!(".SS {}\n", command));
+        let commands: Vec<_> = command.split_whitespace().collect();
+        page.push_str(&roff_block(&help_text(&commands)?));
+    }
+    Ok(page)
+}
+
+/// Returns the help text for the given subcommand path.
+fn help_text(commands: &[&str]) -> Result<String, Error> {
+    let args = std::iter::once(env!("CARGO_PKG_NAME"))
+        .chain(commands.iter().copied())
+        .chain(std::iter::once("--help"));
+    match CommandOptions::clap()
+        .global_setting(AppSettings::ColorNever)
+        .get_matches_from_safe(args)
+    {
+        Err(e) if e.kind == ErrorKind::HelpDisplayed => Ok(e.message),
+        Err(e) => Err(Error::msg(e.message)),
+        Ok(_) => Err(Error::msg("help text was not generated")),
+    }
+}
+
+/// Formats text as a preformatted roff block.
+fn roff_block(text: &str) -> String {
+    let mut block = String::from(".nf\n");
+    for line in text.lines() {
+        let line = line.replace('\\', "\\e");
+        if line.starts_with('.') || line.starts_with('\'') {
+            block.push_str("\\&");
+        }
+        block.push_str(&line);
+        block.push('\n');
+    }
+    block.push_str(".fi\n");
+    block
+}
+
+/// Creates the directory at `path` and its parents.
+fn create_dir(path: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(path)
+        .with_context(|| format!("Unable to create directory {:?}", path))
+}
+
+/// Writes the file at `path`.
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    std::fs::write(path, contents)
+        .with_context(|| format!("Unable to write file {:?}", path))
+}
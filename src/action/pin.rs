@@ -0,0 +1,108 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Checksum pins for immutable entries.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::Location;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::PinMismatch;
+use crate::Pin;
+
+// External library imports.
+use log::*;
+use sha2::Digest as _;
+use sha2::Sha256;
+
+// Standard library imports.
+use std::fs::File;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// check_pin
+////////////////////////////////////////////////////////////////////////////////
+/// Checks the contents of the `source` file of a pinned [`Entry`] against its
+/// pin before it is copied. Entries without a pin are not checked.
+///
+/// When distributing, the source is the stall copy, which must always match.
+/// When collecting, a mismatched source is allowed with the `--repin` option,
+/// and its new pin is printed.
+///
+/// ### Errors
+///
+/// Returns a [`PinMismatch`] error if the contents don't match the pin, or an
+/// [`Error`] if the file can't be read or the entry is encrypted or a
+/// template.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`PinMismatch`]: ../error/struct.PinMismatch.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn check_pin(
+    entry: &Entry,
+    source: &Location,
+    collect: bool,
+    common: &CommonOptions)
+    -> Result<(), Error>
+{
+    let expected = match entry.pin {
+        Some(pin) => pin,
+        None      => return Ok(()),
+    };
+    if entry.encrypted || entry.template {
+        return Err(Error::msg(format!("entry {} can't be pinned: pins are \
+            not supported for encrypted or template entries.",
+            entry.path.display())));
+    }
+
+    let actual = digest(source)?;
+    if actual == expected {
+        debug!("Pin matches for {:?}", source);
+        return Ok(());
+    }
+    let path = source.display_path();
+    if collect && common.repin {
+        warn!("Repinning {}: update its pin to \"{}\"", path.display(),
+            actual);
+        return Ok(());
+    }
+    Err(PinMismatch {
+        path: path.into(),
+        expected,
+        actual,
+        collect,
+    }.into())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// digest
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the [`Pin`] for the contents of the file at the given location.
+///
+/// [`Pin`]: ../struct.Pin.html
+pub fn digest(location: &Location) -> Result<Pin, Error> {
+    let mut hasher = Sha256::new();
+    match location {
+        Location::File(path) => {
+            let mut file = File::open(path)
+                .with_context(|| format!("Unable to open {:?} to compute its \
+                    checksum", path))?;
+            let _ = std::io::copy(&mut file, &mut hasher)
+                .with_context(|| format!("Unable to read {:?} to compute its \
+                    checksum", path))?;
+        },
+        Location::Archived { archive, member } => {
+            hasher.update(archive::read_member(archive, member)?);
+        },
+    }
+    Ok(Pin(hasher.finalize().into()))
+}
@@ -0,0 +1,691 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Planning and execution of copy operations.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::check_markers;
+use crate::action::check_pin;
+use crate::action::copy_entry;
+use crate::action::CopyMethod;
+use crate::action::CommandComparator;
+use crate::action::Comparator;
+use crate::action::Comparison;
+use crate::action::CopyStats;
+use crate::action::format_path;
+use crate::action::HookEnv;
+use crate::action::HookStage;
+use crate::action::keep_known_good;
+use crate::action::Location;
+use crate::action::PipelineComparator;
+use crate::action::plaintext_matches;
+use crate::action::PolicyAction;
+use crate::action::prompt_action;
+use crate::action::Reporter;
+use crate::action::run_hook;
+use crate::action::RunReport;
+use crate::action::StallStore;
+use crate::action::State;
+use crate::action::walk;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::EntryOrder;
+use crate::error::Cancelled;
+use crate::error::CopyFailed;
+use crate::error::Error;
+use crate::error::MissingFile;
+use crate::error::PolicyStop;
+use crate::error::SpecialFile;
+use crate::error::SyncConflict;
+use crate::error::TypeMismatch;
+use crate::logger::EntrySpan;
+use crate::RunContext;
+use crate::state::CachedStatus;
+use crate::state::SyncRecord;
+use crate::state::SyncState;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::sync::mpsc::sync_channel;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// PlanItem
+////////////////////////////////////////////////////////////////////////////////
+/// A planned copy operation for an [`Entry`].
+///
+/// [`Entry`]: ../struct.Entry.html
+#[derive(Debug, Clone)]
+pub struct PlanItem<'e> {
+    /// The entry being copied.
+    pub entry: &'e Entry,
+    /// The location to copy from.
+    pub source: Location,
+    /// The location to copy to.
+    pub target: Location,
+    /// The state of the source file relative to the target file.
+    pub state: State,
+    /// The action to take.
+    pub action: Action,
+    /// Whether to ask the user before copying the file.
+    pub prompt: bool,
+}
+
+impl<'e> PlanItem<'e> {
+    /// Constructs a new `PlanItem` by comparing the `source` and `target`
+    /// files with the [`Comparator`] of the [`RunContext`] to determine the
+    /// [`State`] for the entry. If the entry or the [`RunContext`] have
+    /// `compare_stages`, the files are compared with a [`PipelineComparator`]
+    /// instead, and otherwise entries with a `compare_command` are compared
+    /// with a [`CommandComparator`]. The [`Action`] is then chosen by the
+    /// [`Policy`] of the [`RunContext`].
+    ///
+    /// If a [`SyncRecord`] for the entry is given and both files have been
+    /// modified since it was made, the entry is reported as a conflict.
+    ///
+    /// [`Comparator`]: trait.Comparator.html
+    /// [`RunContext`]: ../command/struct.RunContext.html
+    /// [`State`]: enum.State.html
+    /// [`PipelineComparator`]: struct.PipelineComparator.html
+    /// [`CommandComparator`]: struct.CommandComparator.html
+    /// [`Action`]: enum.Action.html
+    /// [`Policy`]: struct.Policy.html
+    /// [`SyncRecord`]: ../state/struct.SyncRecord.html
+    pub fn new(
+        entry: &'e Entry,
+        source: Location,
+        target: Location,
+        synced: Option<&SyncRecord>,
+        common: &CommonOptions,
+        context: &RunContext)
+        -> Result<Self, Error>
+    {
+        let stages = match entry.compare_stages.is_empty() {
+            true  => &context.compare_stages,
+            false => &entry.compare_stages,
+        };
+        let command_comparator;
+        let pipeline_comparator;
+        let comparator: &dyn Comparator = match (&stages[..],
+            &entry.compare_command)
+        {
+            ([], Some(command)) => {
+                command_comparator = CommandComparator { command };
+                &command_comparator
+            },
+            ([], None) => context.compare.comparator(),
+            (stages, _) => {
+                pipeline_comparator = PipelineComparator { stages };
+                &pipeline_comparator
+            },
+        };
+        PlanItem::with_comparator(
+            entry,
+            source,
+            target,
+            synced,
+            comparator,
+            common,
+            context)
+    }
+
+    /// Constructs a new `PlanItem` as with [`new`], comparing the files with
+    /// the given [`Comparator`] rather than the one chosen by the
+    /// [`RunContext`].
+    ///
+    /// [`new`]: #method.new
+    /// [`Comparator`]: trait.Comparator.html
+    /// [`RunContext`]: ../command/struct.RunContext.html
+    pub fn with_comparator(
+        entry: &'e Entry,
+        source: Location,
+        target: Location,
+        synced: Option<&SyncRecord>,
+        comparator: &dyn Comparator,
+        common: &CommonOptions,
+        context: &RunContext)
+        -> Result<Self, Error>
+    {
+        use State::*;
+        use Action::*;
+        let _span = entry_span(entry, &source, &target);
+
+        // Skip the entry if it may not be copied in this direction.
+        let collect = is_remote(entry, &source);
+        let allowed = match collect {
+            true  => entry.direction.allows_collect(),
+            false => entry.direction.allows_distribute(),
+        };
+        if !allowed {
+            debug!("Entry direction is {:?}.", entry.direction);
+            return Ok(PlanItem { entry, source, target,
+                state: OneWay,
+                action: Skip,
+                prompt: false,
+            });
+        }
+
+        // Never overwrite a template with its rendered output.
+        if entry.template && collect {
+            debug!("Template entries are not collected.");
+            return Ok(PlanItem { entry, source, target,
+                state: OneWay,
+                action: Skip,
+                prompt: false,
+            });
+        }
+
+        // Fail before copying any files if a directory is too large.
+        if let Location::File(path) = &source {
+            if path.is_dir() {
+                walk::check_limits(path, common, context)?;
+            }
+        }
+
+        debug!("Comparing source and target.");
+        let special = source.special_kind();
+        let state = match (source.exists()?, target.exists()?) {
+            // The source is a FIFO, socket, or device.
+            (true,  _) if special.is_some() => {
+                debug!("Source {} is a {}.", source, source.kind());
+                Special
+            },
+
+            // Both files exist, but one is a directory or special file.
+            (true,  true) if source.is_dir() != target.is_dir()
+                || target.special_kind().is_some() =>
+            {
+                warn!("Source {} is a {}, but target {} is a {}.",
+                    source, source.kind(),
+                    target, target.kind());
+                TypeMismatch
+            },
+
+            // Both files exist, and the decrypted stall copy matches.
+            (true,  true) if entry.encrypted && !common.force
+                && plaintext_matches(entry, &source, &target, context)?
+                => Synced,
+
+            // Both files exist, compare them.
+            (true,  true) => comparator.compare(&Comparison {
+                entry,
+                source: &source,
+                target: &target,
+                collect,
+                synced,
+                common,
+                context,
+            })?,
+
+            // Source exists, but not target.
+            (true, false) => Found,
+
+            // Source does not exist, but is not required.
+            (false, _) if entry.optional => Absent,
+
+            // Source does not exist.
+            (false, _) => Error,
+        };
+
+        // Choose the action from the policy, then apply the command line
+        // options. Missing files can never be copied.
+        let policy_action = context.policy.action(collect, state);
+        debug!("Policy action for {:?} state is {:?}.", state, policy_action);
+        let (state, action) = match policy_action {
+            PolicyAction::Copy | PolicyAction::Ask
+                if matches!(state, Absent | Error) => (state, Skip),
+            PolicyAction::Copy | PolicyAction::Ask => (state, Copy),
+            PolicyAction::Stop => (state, Stop),
+            PolicyAction::Skip if common.force
+                && matches!(state, Older | Synced | Conflict | TypeMismatch)
+                => (Force, Copy),
+            PolicyAction::Skip if common.promote_warnings_to_errors
+                && matches!(state, Conflict | TypeMismatch | Error)
+                => (state, Stop),
+            PolicyAction::Skip => (state, Skip),
+        };
+        let prompt = policy_action == PolicyAction::Ask && action == Copy;
+
+        // Skip the file if it exceeds its size budget.
+        let (state, action) = match action {
+            Copy if exceeds_size_budget(entry, &source, common, context)?
+                => (Budget, Skip),
+            _   => (state, action),
+        };
+
+        Ok(PlanItem { entry, source, target, state, action, prompt })
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// PlanInput
+////////////////////////////////////////////////////////////////////////////////
+/// The number of entries planned ahead of the entry being prompted for by
+/// [`execute_prefetched`].
+///
+/// [`execute_prefetched`]: fn.execute_prefetched.html
+const PREFETCH_COUNT: usize = 8;
+
+/// The locations and sync record used to plan the copy operation for an
+/// [`Entry`].
+///
+/// [`Entry`]: ../struct.Entry.html
+#[derive(Debug, Clone)]
+pub struct PlanInput<'e> {
+    /// The entry being copied.
+    pub entry: &'e Entry,
+    /// The location to copy from.
+    pub source: Location,
+    /// The location to copy to.
+    pub target: Location,
+    /// The sync record for the entry, if it has been copied before.
+    pub synced: Option<SyncRecord>,
+}
+
+impl<'e> PlanInput<'e> {
+    /// Constructs the [`PlanItem`] for the input.
+    ///
+    /// [`PlanItem`]: struct.PlanItem.html
+    pub fn plan(self, common: &CommonOptions, context: &RunContext)
+        -> Result<PlanItem<'e>, Error>
+    {
+        PlanItem::new(
+            self.entry,
+            self.source,
+            self.target,
+            self.synced.as_ref(),
+            common,
+            context)
+    }
+}
+
+/// Sorts the given inputs into the [`EntryOrder`] of the [`CommonOptions`].
+/// The sort is stable, so inputs which compare equal keep the order of the
+/// stall file.
+///
+/// [`EntryOrder`]: ../enum.EntryOrder.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+pub fn sort_inputs(
+    inputs: &mut [PlanInput<'_>],
+    common: &CommonOptions,
+    context: &RunContext)
+{
+    let order = common.order.unwrap_or_default();
+    debug!("Entry order: {:?}", order);
+    match order {
+        EntryOrder::StallFile => (),
+        EntryOrder::Alphabetical => inputs
+            .sort_by(|a, b| a.entry.path.cmp(&b.entry.path)),
+        EntryOrder::SmallestFirst => inputs
+            .sort_by_cached_key(|input| input.source.size(common, context)
+                .unwrap_or(u64::MAX)),
+        EntryOrder::DirectoriesFirst => inputs
+            .sort_by_key(|input| !input.source.is_dir()),
+    }
+}
+
+/// Plans the copy operations for all of the given inputs.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if any of the entries can't be planned.
+///
+/// [`Error`]: ../error/struct.Error.html
+pub fn plan_all<'e>(
+    inputs: Vec<PlanInput<'e>>,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<Vec<PlanItem<'e>>, Error>
+{
+    inputs.into_iter()
+        .map(|input| input.plan(common, context))
+        .collect()
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// execute_prefetched
+////////////////////////////////////////////////////////////////////////////////
+/// Plans and executes the copy operations for the given inputs, as with
+/// [`execute_plan`]. Upcoming entries are planned on a background thread
+/// while the user answers the prompt for the current entry, so that the
+/// comparisons for large entries don't delay each prompt.
+///
+/// Unlike [`plan_all`], entries are copied before later entries are planned,
+/// so an error while planning an entry is only returned once the earlier
+/// entries are copied.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if an entry can't be planned, or as with
+/// [`execute_plan`].
+///
+/// [`execute_plan`]: fn.execute_plan.html
+/// [`plan_all`]: fn.plan_all.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn execute_prefetched(
+    inputs: Vec<PlanInput<'_>>,
+    method: CopyMethod,
+    sync_state: &mut SyncState,
+    report: &mut RunReport,
+    reporter: &mut dyn Reporter,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<CopyStats, Error>
+{
+    std::thread::scope(|scope| {
+        let (sender, receiver) = sync_channel(PREFETCH_COUNT);
+        let _planner = scope.spawn(move || {
+            for input in inputs {
+                let item = input.plan(common, context);
+                let failed = item.is_err();
+                // Stop planning once the receiver is done with the plan.
+                if sender.send(item).is_err() || failed { break; }
+            }
+        });
+        execute_plan(receiver, method, sync_state, report, reporter, common,
+            context)
+    })
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// execute_plan
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the planned copy operations in order, printing the status of
+/// each. The [`SyncState`] is updated for each file copied, and records the
+/// error for each file which fails. If the [`SyncState`] is recording the
+/// progress of the run, it is saved as each file is copied. The copies,
+/// conflicts, and errors are also recorded in the [`RunReport`].
+///
+/// The pre-copy and post-copy hooks of each entry are run around its copy.
+/// Each distributed file whose hooks succeed is kept as the entry's last
+/// known good version.
+/// The source of each pinned entry is checked against its pin beforehand, and
+/// the source of each distributed entry is checked for unresolved markers.
+///
+/// Returns the total [`CopyStats`] of the files copied.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if a planned item is to be stopped due to a missing
+/// file or a conflict, if a pin doesn't match, if a distributed file contains
+/// an unresolved marker, if a copy operation or hook fails, or if the plan
+/// yields an error. Returns a [`Cancelled`] error before the next entry is
+/// copied if the [`CancelToken`] of the run context is cancelled, or if the
+/// user quits when asked to confirm a copy.
+///
+/// [`Cancelled`]: ../error/struct.Cancelled.html
+/// [`CancelToken`]: struct.CancelToken.html
+/// [`SyncState`]: ../state/struct.SyncState.html
+/// [`RunReport`]: struct.RunReport.html
+/// [`CopyStats`]: struct.CopyStats.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn execute_plan<'e, I>(
+    plan: I,
+    method: CopyMethod,
+    sync_state: &mut SyncState,
+    report: &mut RunReport,
+    reporter: &mut dyn Reporter,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<CopyStats, Error>
+    where I: IntoIterator<Item=Result<PlanItem<'e>, Error>>
+{
+    let mut total = CopyStats::default();
+    for item in plan {
+        context.cancel.check()?;
+        let item = item?;
+        let _span = entry_span(item.entry, &item.source, &item.target);
+        let display_path = item.source.display_path();
+        reporter.entry_started(item.entry, &display_path);
+
+        // Confirm the copy with the user if requested.
+        let action = match item.action {
+            Action::Copy if (item.prompt || common.interactive)
+                && !common.yes => prompt_action(
+                item.state,
+                &item.source,
+                &item.target,
+                &format_path(&display_path, common),
+                item.entry.is_sensitive())?,
+            _ => item.action,
+        };
+        report.record_entry(&display_path, item.state, action);
+        reporter.action_decided(&display_path, item.state, action);
+
+        match action {
+            Action::Copy => (),
+            Action::Skip if item.state == State::Error => {
+                let error = MissingFile { path: display_path.clone().into() }
+                    .into();
+                sync_state.record_error(item.entry, &error);
+                report.record_error(&display_path, &error);
+                reporter.error(&display_path, &error);
+                continue;
+            },
+            Action::Skip if item.state == State::Conflict => {
+                report.conflicts.push(display_path);
+                continue;
+            },
+            Action::Skip if item.state == State::Synced => {
+                if method != CopyMethod::None {
+                    sync_state.record_synced(item.entry);
+                }
+                continue;
+            },
+            Action::Skip => continue,
+            Action::Stop => {
+                let error: Error = match item.state {
+                    State::Error => MissingFile {
+                        path: display_path.into()
+                    }.into(),
+                    State::Conflict => SyncConflict {
+                        path: display_path.into()
+                    }.into(),
+                    State::TypeMismatch => TypeMismatch {
+                        path: display_path.into()
+                    }.into(),
+                    State::Special => SpecialFile {
+                        path: display_path.into(),
+                        kind: item.source.kind(),
+                    }.into(),
+                    // The policy stopped the command, rather than the user.
+                    state if item.action == Action::Stop => PolicyStop {
+                        path: display_path.into(),
+                        state: state.label().trim_end().into(),
+                    }.into(),
+                    // The user quit when asked to confirm the copy.
+                    _ => return Err(Cancelled.into()),
+                };
+                let display_path = item.source.display_path();
+                sync_state.record_error(item.entry, &error);
+                report.record_error(&display_path, &error);
+                reporter.error(&display_path, &error);
+                return Err(error);
+            },
+        }
+
+        let collect = is_remote(item.entry, &item.source);
+        let hook_env = match collect {
+            true  => HookEnv::new(&item.target, &item.source, true, common,
+                context),
+            false => HookEnv::new(&item.source, &item.target, false, common,
+                context),
+        };
+        let checked = check_pin(item.entry, &item.source, collect, common)
+            .and_then(|()| check_markers(
+                item.entry,
+                &item.source,
+                collect,
+                common,
+                context));
+        if let Err(e) = checked {
+            reporter.action_decided(&display_path, item.state, Action::Stop);
+            sync_state.record_error(item.entry, &e);
+            report.record_error(&display_path, &e);
+            reporter.error(&display_path, &e);
+            return Err(e);
+        }
+        if let Some(hook) = &item.entry.pre_hook {
+            let result = run_hook(hook, HookStage::Pre, &hook_env, common,
+                context);
+            if let Err(e) = result {
+                reporter.action_decided(
+                    &display_path,
+                    item.state,
+                    Action::Stop);
+                sync_state.record_error(item.entry, &e);
+                report.record_error(&display_path, &e);
+                reporter.error(&display_path, &e);
+                return Err(e);
+            }
+        }
+
+        let stats = match copy_entry(
+            item.entry,
+            &item.source,
+            &item.target,
+            method,
+            common,
+            context)
+        {
+            Ok(Some(stats)) => {
+                reporter.copy_finished(&display_path, item.state, &stats);
+                stats
+            },
+            Ok(None) => {
+                reporter.action_decided(
+                    &display_path,
+                    State::Budget,
+                    Action::Skip);
+                continue;
+            },
+            Err(e) if e.is::<Cancelled>() => {
+                reporter.action_decided(&display_path, item.state, Action::Stop);
+                return Err(e);
+            },
+            Err(e) => {
+                reporter.action_decided(&display_path, item.state, Action::Stop);
+                sync_state.record_error(item.entry, &e);
+                report.record_error(&display_path, &e);
+                reporter.error(&display_path, &e);
+                return Err(e.context(CopyFailed {
+                    path: display_path.into(),
+                }));
+            },
+        };
+
+        if let Some(hook) = &item.entry.post_hook {
+            let result = run_hook(hook, HookStage::Post, &hook_env, common,
+                context);
+            if let Err(e) = result {
+                sync_state.record_error(item.entry, &e);
+                report.record_error(&display_path, &e);
+                reporter.error(&display_path, &e);
+                return Err(e);
+            }
+        }
+
+        total += stats;
+        report.changed.push(display_path);
+
+        // Record the modification times of the copied files.
+        if method != CopyMethod::None {
+            let (local, remote) = match collect {
+                true  => (&item.target, &item.source),
+                false => (&item.source, &item.target),
+            };
+            sync_state.record(item.entry, SyncRecord {
+                local_modified: local.modified(common, context)?,
+                remote_modified: remote.modified(common, context)?,
+            });
+            sync_state.cache_status(item.entry, CachedStatus {
+                state: State::Synced,
+                action: Action::Skip,
+                checked: SystemTime::now(),
+            });
+            sync_state.clear_error(item.entry);
+            sync_state.record_synced(item.entry);
+            if !collect {
+                keep_known_good(
+                    &StallStore::new(&report.stall_dir),
+                    item.entry,
+                    &item.source,
+                    sync_state,
+                    common,
+                    context)?;
+            }
+            sync_state.complete(item.entry)?;
+        }
+    }
+    Ok(total)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// entry_span
+////////////////////////////////////////////////////////////////////////////////
+/// Enters an [`EntrySpan`] for the given [`Entry`], using whichever of the
+/// `source` and `target` is not the entry's remote location as the local
+/// location.
+///
+/// [`EntrySpan`]: ../logger/struct.EntrySpan.html
+/// [`Entry`]: ../struct.Entry.html
+fn entry_span(entry: &Entry, source: &Location, target: &Location)
+    -> EntrySpan
+{
+    let (local, remote) = match is_remote(entry, source) {
+        true  => (target, source),
+        false => (source, target),
+    };
+    EntrySpan::enter(local, remote)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// is_remote
+////////////////////////////////////////////////////////////////////////////////
+/// Returns true if the given location is the remote location of the
+/// [`Entry`].
+///
+/// [`Entry`]: ../struct.Entry.html
+fn is_remote(entry: &Entry, location: &Location) -> bool {
+    *location == Location::remote(entry)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// exceeds_size_budget
+////////////////////////////////////////////////////////////////////////////////
+/// Returns true if the file at `source` exceeds the size budget of the given
+/// [`Entry`].
+///
+/// [`Entry`]: ../struct.Entry.html
+fn exceeds_size_budget(
+    entry: &Entry,
+    source: &Location,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<bool, Error>
+{
+    match entry.max_bytes {
+        Some(max_bytes) => {
+            let size = source.size(common, context)?;
+            if size > max_bytes {
+                debug!("{} bytes exceeds budget of {} bytes", size, max_bytes);
+            }
+            Ok(size > max_bytes)
+        },
+        None => Ok(false),
+    }
+}
@@ -0,0 +1,285 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Policies for choosing the action taken for each file state.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::State;
+
+// External library imports.
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// POLICY_STATES
+////////////////////////////////////////////////////////////////////////////////
+/// The states whose action is chosen by a [`Policy`].
+///
+/// [`Policy`]: struct.Policy.html
+pub const POLICY_STATES: &[State] = &[
+    State::Found,
+    State::Newer,
+    State::Older,
+    State::Synced,
+    State::Conflict,
+    State::TypeMismatch,
+    State::Special,
+    State::Absent,
+    State::Error,
+];
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Policy
+////////////////////////////////////////////////////////////////////////////////
+/// A table of the action to take for each file state when collecting or
+/// distributing, loaded from the stall file.
+///
+/// The table of a [`PolicyPreset`] may be overridden for individual states,
+/// keyed by the state names shown in status output:
+///
+/// ```ron
+/// policy: (
+///     preset: "safe",
+///     collect: { "conflict": "ask" },
+///     distribute: { "error": "stop", "older": "copy" },
+/// ),
+/// ```
+///
+/// Special files such as FIFOs and sockets are skipped by every preset. If
+/// the action for the `special` state is `"copy"`, FIFOs are recreated at the
+/// target, and if it is `"stop"`, the command stops with an error. This also
+/// applies to special files within directory entries.
+///
+/// The `--force` option copies files which the policy would skip because they
+/// are older, synced, in conflict, or mismatched, and the `--error` option
+/// stops on files which the policy would skip because they are in conflict,
+/// mismatched, or missing.
+///
+/// [`PolicyPreset`]: enum.PolicyPreset.html
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// The preset providing the action for states which are not overridden.
+    #[serde(default)]
+    pub preset: PolicyPreset,
+
+    /// The actions to take when collecting, keyed by state name.
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_actions")]
+    pub collect: BTreeMap<String, PolicyAction>,
+
+    /// The actions to take when distributing, keyed by state name.
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_actions")]
+    pub distribute: BTreeMap<String, PolicyAction>,
+}
+
+impl Policy {
+    /// Returns the action to take for a file with the given [`State`], when
+    /// collecting if `collect` is true, or distributing otherwise.
+    ///
+    /// [`State`]: enum.State.html
+    pub fn action(&self, collect: bool, state: State) -> PolicyAction {
+        let overrides = match collect {
+            true  => &self.collect,
+            false => &self.distribute,
+        };
+        overrides.get(state.label().trim_end())
+            .copied()
+            .unwrap_or_else(|| self.preset.action(collect, state))
+    }
+}
+
+/// Deserializes the policy actions, rejecting unknown state names.
+fn deserialize_actions<'de, D>(deserializer: D)
+    -> Result<BTreeMap<String, PolicyAction>, D::Error>
+    where D: Deserializer<'de>
+{
+    let actions = BTreeMap::<String, PolicyAction>::deserialize(deserializer)?;
+    for name in actions.keys() {
+        if !POLICY_STATES.iter().any(|state| state.label().trim_end() == name) {
+            return Err(D::Error::custom(format!("unknown policy state {:?}",
+                name)));
+        }
+    }
+    Ok(actions)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// PolicyPreset
+////////////////////////////////////////////////////////////////////////////////
+/// A named [`Policy`] table.
+///
+/// Presets are written in the stall file as strings: `"safe"`,
+/// `"mirror-local"`, `"mirror-remote"`, or `"interactive"`.
+///
+/// [`Policy`]: struct.Policy.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PolicyPreset {
+    /// Copy files which are new or newer than their target, and skip all
+    /// others.
+    #[default]
+    Safe,
+    /// The stall directory is authoritative. Distributing copies every file
+    /// which differs from the stall, and collecting copies nothing.
+    MirrorLocal,
+    /// The files outside the stall directory are authoritative. Collecting
+    /// copies every file which differs from the stall, and distributing
+    /// copies nothing.
+    MirrorRemote,
+    /// Ask before copying any file which is new, newer, older, in conflict,
+    /// or mismatched.
+    Interactive,
+}
+
+impl PolicyPreset {
+    /// The names of the available presets.
+    pub const NAMES: &'static [&'static str] = &[
+        "safe",
+        "mirror-local",
+        "mirror-remote",
+        "interactive",
+    ];
+
+    /// Returns the action to take for a file with the given [`State`], when
+    /// collecting if `collect` is true, or distributing otherwise.
+    ///
+    /// [`State`]: enum.State.html
+    pub fn action(&self, collect: bool, state: State) -> PolicyAction {
+        use PolicyAction::*;
+        use PolicyPreset::*;
+        let differs = matches!(state, State::Found | State::Newer
+            | State::Older | State::Conflict | State::TypeMismatch);
+        match (self, collect) {
+            (Safe, _) => match state {
+                State::Found | State::Newer => Copy,
+                _ => Skip,
+            },
+            (MirrorLocal, false) | (MirrorRemote, true) if differs => Copy,
+            (MirrorLocal, _) | (MirrorRemote, _) => Skip,
+            (Interactive, _) if differs => Ask,
+            (Interactive, _) => Skip,
+        }
+    }
+}
+
+impl std::fmt::Display for PolicyPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            PolicyPreset::Safe         => "safe",
+            PolicyPreset::MirrorLocal  => "mirror-local",
+            PolicyPreset::MirrorRemote => "mirror-remote",
+            PolicyPreset::Interactive  => "interactive",
+        })
+    }
+}
+
+impl std::str::FromStr for PolicyPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "safe"          => Ok(PolicyPreset::Safe),
+            "mirror-local"  => Ok(PolicyPreset::MirrorLocal),
+            "mirror-remote" => Ok(PolicyPreset::MirrorRemote),
+            "interactive"   => Ok(PolicyPreset::Interactive),
+            _ => Err(format!("invalid policy preset: {:?}", s)),
+        }
+    }
+}
+
+impl Serialize for PolicyPreset {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PolicyPreset {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// PolicyAction
+////////////////////////////////////////////////////////////////////////////////
+/// The action a [`Policy`] takes for a file.
+///
+/// Actions are written in the stall file as strings: `"copy"`, `"skip"`,
+/// `"stop"`, or `"ask"`.
+///
+/// [`Policy`]: struct.Policy.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyAction {
+    /// Copy the file.
+    Copy,
+    /// Skip the file.
+    Skip,
+    /// Stop the command with an error.
+    Stop,
+    /// Ask the user whether to copy the file.
+    Ask,
+}
+
+impl std::fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            PolicyAction::Copy => "copy",
+            PolicyAction::Skip => "skip",
+            PolicyAction::Stop => "stop",
+            PolicyAction::Ask  => "ask",
+        })
+    }
+}
+
+impl std::str::FromStr for PolicyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "copy" => Ok(PolicyAction::Copy),
+            "skip" => Ok(PolicyAction::Skip),
+            "stop" => Ok(PolicyAction::Stop),
+            "ask"  => Ok(PolicyAction::Ask),
+            _ => Err(format!("invalid policy action: {:?}", s)),
+        }
+    }
+}
+
+impl Serialize for PolicyAction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PolicyAction {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
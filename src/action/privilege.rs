@@ -0,0 +1,185 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Ownership and privileged copies.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::CopyMethod;
+use crate::action::Location;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::Escalation;
+use crate::RunContext;
+use crate::Timestamps;
+use crate::error::Context;
+use crate::error::Error;
+use crate::logger::ChildStream;
+use crate::logger::log_child_output;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// copy_privileged
+////////////////////////////////////////////////////////////////////////////////
+/// Distributes a privileged [`Entry`] from `source` in the stall directory to
+/// `target`, running each command with the configured [`Escalation`] program.
+///
+/// The file is copied with `cp`, its permissions and timestamps are preserved
+/// with `chmod` and `touch`, and its mode and ownership are applied with
+/// `chmod` and `chown`.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if either location is not a regular file, if the entry
+/// is encrypted or a template, or if any of the commands fail.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`Escalation`]: ../enum.Escalation.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn copy_privileged(
+    entry: &Entry,
+    source: &Location,
+    target: &Location,
+    method: CopyMethod,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    let special = source.special_kind().or_else(|| target.special_kind());
+    let (source, target) = match (source, target) {
+        (Location::File(source), Location::File(target))
+            if !source.is_dir() && !target.is_dir() && special.is_none()
+            => (source, target),
+        _ => return Err(Error::msg(format!("unable to copy privileged entry \
+            {}: privileged entries must be regular files", target))),
+    };
+    if entry.encrypted || entry.template {
+        return Err(Error::msg(format!("unable to copy privileged entry {:?}: \
+            privileged entries can't be encrypted or templates", target)));
+    }
+
+    if method == CopyMethod::None {
+        trace!("no-run flag was specified: Not copying data from {:?} to {:?} \
+            with {}", source, target, context.escalation.program());
+        return Ok(());
+    }
+
+    let escalation = Some(context.escalation);
+    run(&["cp".as_ref(), "--".as_ref(), source.as_os_str(), target.as_os_str()],
+        escalation)?;
+
+    if !common.no_preserve {
+        let mode = source_mode(source)?;
+        run(&["chmod".as_ref(), mode.as_ref(), target.as_os_str()],
+            escalation)?;
+        if common.timestamps == Some(Timestamps::Preserve) {
+            run(&["touch".as_ref(), "-r".as_ref(), source.as_os_str(),
+                target.as_os_str()], escalation)?;
+        }
+    }
+
+    if let Some(mode) = entry.mode {
+        let mode = format!("{:o}", mode.0);
+        run(&["chmod".as_ref(), mode.as_ref(), target.as_os_str()],
+            escalation)?;
+    }
+
+    apply_ownership(target, entry, escalation)
+}
+
+/// Returns the permission bits of the file at `path` in octal.
+#[cfg(unix)]
+fn source_mode(path: &Path) -> Result<String, Error> {
+    use std::os::unix::fs::PermissionsExt as _;
+    let metadata = std::fs::metadata(path)
+        .with_context(|| format!("Unable to read metadata of {:?}", path))?;
+    Ok(format!("{:o}", metadata.permissions().mode() & 0o7777))
+}
+
+/// Returns the permission bits of the file at `path` in octal.
+#[cfg(not(unix))]
+fn source_mode(_path: &Path) -> Result<String, Error> {
+    Err(Error::msg("privileged entries are not supported on this platform"))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// apply_ownership
+////////////////////////////////////////////////////////////////////////////////
+/// Applies the owner and group of an [`Entry`] to the copied file or directory
+/// at `target` with the `chown` command, running it with the given
+/// [`Escalation`] program if one is given.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the `chown` command fails.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`Escalation`]: ../enum.Escalation.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn apply_ownership(
+    target: &Path,
+    entry: &Entry,
+    escalation: Option<Escalation>)
+    -> Result<(), Error>
+{
+    let owner = match (&entry.owner, &entry.group) {
+        (None,        None)        => return Ok(()),
+        (Some(owner), None)        => owner.clone(),
+        (None,        Some(group)) => format!(":{}", group),
+        (Some(owner), Some(group)) => format!("{}:{}", owner, group),
+    };
+    let mut args: Vec<&OsStr> = vec!["chown".as_ref()];
+    if target.is_dir() {
+        args.push("-R".as_ref());
+    }
+    args.push(owner.as_ref());
+    args.push(target.as_os_str());
+    run(&args, escalation)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// run
+////////////////////////////////////////////////////////////////////////////////
+/// Runs the command given by `args`, prefixed by the [`Escalation`] program if
+/// one is given. The output of the command is logged.
+///
+/// [`Escalation`]: ../enum.Escalation.html
+fn run(args: &[&OsStr], escalation: Option<Escalation>) -> Result<(), Error> {
+    let mut command_line: Vec<OsString> = Vec::new();
+    if let Some(escalation) = escalation {
+        command_line.push(escalation.program().into());
+    }
+    command_line.extend(args.iter().map(|arg| arg.to_os_string()));
+    let program = command_line[0].to_string_lossy().into_owned();
+    trace!("Running {:?}", command_line);
+
+    let output = Command::new(&command_line[0])
+        .args(&command_line[1..])
+        .stdin(Stdio::inherit())
+        .output()
+        .with_context(|| format!("execute {} command", program))?;
+    log_child_output(&program, ChildStream::Stdout, &output.stdout);
+    if !output.status.success() {
+        return Err(Error::msg(format!("{} failed: {}",
+            command_line.join(OsStr::new(" ")).to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    log_child_output(&program, ChildStream::Stderr, &output.stderr);
+    Ok(())
+}
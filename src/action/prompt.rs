@@ -0,0 +1,291 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Interactive user prompts.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::Action;
+use crate::action::Location;
+use crate::action::private_temp_dir;
+use crate::action::State;
+use crate::error::Context;
+use crate::error::Error;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Prompter
+////////////////////////////////////////////////////////////////////////////////
+/// The interface through which commands ask the user questions and show them
+/// the answers to their requests, such as diffs. The stall binary installs a
+/// `Prompter` reading from stdin and writing to stdout; programs embedding
+/// stall may install their own with [`install_prompter`].
+///
+/// If no `Prompter` is installed, every prompt is answered as if the input
+/// stream was closed.
+///
+/// [`install_prompter`]: fn.install_prompter.html
+pub trait Prompter: Send + Sync {
+    /// Shows the prompt and returns the user's response without its line
+    /// ending, or `None` if the user can't respond, as when the input stream
+    /// is closed.
+    fn ask(&self, prompt: &str) -> Result<Option<String>, Error>;
+
+    /// Shows a message to the user.
+    fn tell(&self, message: &str);
+}
+
+/// The [`Prompter`] used by the prompt functions.
+///
+/// [`Prompter`]: trait.Prompter.html
+static CURRENT_PROMPTER: RwLock<Option<Arc<dyn Prompter>>> = RwLock::new(None);
+
+/// Installs the [`Prompter`] used for all later prompts.
+///
+/// [`Prompter`]: trait.Prompter.html
+pub fn install_prompter<P>(prompter: P)
+    where P: Prompter + 'static
+{
+    let prompter: Arc<dyn Prompter> = Arc::new(prompter);
+    match CURRENT_PROMPTER.write() {
+        Ok(mut current) => *current = Some(prompter),
+        Err(poisoned) => *poisoned.into_inner() = Some(prompter),
+    }
+}
+
+/// Returns the installed [`Prompter`], if any.
+///
+/// [`Prompter`]: trait.Prompter.html
+fn current_prompter() -> Option<Arc<dyn Prompter>> {
+    match CURRENT_PROMPTER.read() {
+        Ok(current) => current.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// Asks the installed [`Prompter`] the given prompt.
+///
+/// [`Prompter`]: trait.Prompter.html
+fn ask(prompt: &str) -> Result<Option<String>, Error> {
+    match current_prompter() {
+        Some(prompter) => prompter.ask(prompt),
+        None => {
+            debug!("No prompter installed; unable to ask: {}", prompt);
+            Ok(None)
+        },
+    }
+}
+
+/// Shows the message with the installed [`Prompter`].
+///
+/// [`Prompter`]: trait.Prompter.html
+fn tell(message: &str) {
+    if let Some(prompter) = current_prompter() {
+        prompter.tell(message);
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// prompt_action
+////////////////////////////////////////////////////////////////////////////////
+/// Prompts the user to choose whether to copy the file at `source` over
+/// `target`. Returns the chosen [`Action`].
+///
+/// The user may also request a diff of the two files, after which they will
+/// be prompted again. If the file is `sensitive`, the diff only reports
+/// whether the files differ. Closing the input stream is treated as a request
+/// to quit.
+///
+/// [`Action`]: enum.Action.html
+pub(crate) fn prompt_action(
+    state: State,
+    source: &Location,
+    target: &Location,
+    display_path: &Path,
+    sensitive: bool)
+    -> Result<Action, Error>
+{
+    let prompt = format!("    {}{} -- copy, skip, diff, or quit? [c,s,d,q] ",
+        state.colored_string(),
+        display_path.display());
+    loop {
+        let line = match ask(&prompt)? {
+            Some(line) => line,
+            None       => return Ok(Action::Stop),
+        };
+
+        match line.trim().to_lowercase().as_str() {
+            "c" | "copy" => return Ok(Action::Copy),
+            "s" | "skip" => return Ok(Action::Skip),
+            "q" | "quit" => return Ok(Action::Stop),
+            "d" | "diff" => print_file_diff(source, target, sensitive)?,
+            _            => tell("    Please enter one of c, s, d, or q."),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// confirm
+////////////////////////////////////////////////////////////////////////////////
+/// Prompts the user with a yes or no question. Returns true if the user
+/// answers yes. Closing the input stream is treated as answering no.
+pub(crate) fn confirm(message: &str) -> Result<bool, Error> {
+    let prompt = format!("{} [y/N] ", message);
+    loop {
+        let line = match ask(&prompt)? {
+            Some(line) => line,
+            None       => return Ok(false),
+        };
+
+        match line.trim().to_lowercase().as_str() {
+            "y" | "yes"     => return Ok(true),
+            "" | "n" | "no" => return Ok(false),
+            _               => tell("Please enter y or n."),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// prompt_value
+////////////////////////////////////////////////////////////////////////////////
+/// Prompts the user to enter a value. Returns `None` if the input stream is
+/// closed.
+pub(crate) fn prompt_value(message: &str) -> Result<Option<String>, Error> {
+    ask(&format!("{}: ", message))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// print_file_diff
+////////////////////////////////////////////////////////////////////////////////
+/// Shows the differences between `target` and `source` with the installed
+/// [`Prompter`]. If the file is `sensitive`, only whether the files differ is
+/// shown.
+///
+/// [`Prompter`]: trait.Prompter.html
+pub(crate) fn print_file_diff(
+    source: &Location,
+    target: &Location,
+    sensitive: bool)
+    -> Result<(), Error>
+{
+    match sensitive {
+        true  => print_redacted_diff(source, target),
+        false => print_diff(source, target),
+    }
+}
+
+/// Shows the differences between `target` and `source` using the system diff
+/// utility.
+fn print_diff(source: &Location, target: &Location) -> Result<(), Error> {
+    let source_file = DiffFile::new(source)?;
+    let target_file = DiffFile::new(target)?;
+
+    let output = if cfg!(target_os = "windows") {
+        std::process::Command::new("FC")
+            .arg(target_file.path())
+            .arg(source_file.path())
+            .output()
+    } else {
+        std::process::Command::new("diff")
+            .arg("-ruN")
+            .arg(target_file.path())
+            .arg(source_file.path())
+            .output()
+    };
+
+    match output {
+        Ok(output) => {
+            let diff = String::from_utf8_lossy(&output.stdout);
+            if !diff.trim().is_empty() {
+                tell(diff.trim_end());
+            }
+            Ok(())
+        },
+        Err(e) => {
+            warn!("Unable to run diff command: {}", e);
+            Ok(())
+        },
+    }
+}
+
+/// Shows whether `target` and `source` differ, without showing their
+/// contents.
+fn print_redacted_diff(source: &Location, target: &Location)
+    -> Result<(), Error>
+{
+    let source_file = DiffFile::new(source)?;
+    let target_file = DiffFile::new(target)?;
+    let differs = std::fs::read(source_file.path()).ok()
+        != std::fs::read(target_file.path()).ok();
+    match differs {
+        true  => tell("    Sensitive file differs; contents not shown."),
+        false => tell("    Files are identical."),
+    }
+    Ok(())
+}
+
+/// A file to be compared. Archived files are extracted into a private
+/// temporary directory, which is removed when the `DiffFile` is dropped.
+#[derive(Debug)]
+pub(crate) struct DiffFile {
+    /// The path of the file.
+    path: PathBuf,
+    /// The temporary directory containing the file, if it was extracted.
+    temp_dir: Option<PathBuf>,
+}
+
+impl DiffFile {
+    /// Constructs a new `DiffFile` for the given `Location`.
+    pub(crate) fn new(location: &Location) -> Result<Self, Error> {
+        match location {
+            Location::File(path) => Ok(DiffFile {
+                path: path.clone(),
+                temp_dir: None,
+            }),
+
+            Location::Archived { archive, member } => {
+                let temp_dir = private_temp_dir("diff")
+                    .with_context(|| "create diff directory")?;
+                let path = temp_dir.join(member.file_name()
+                    .unwrap_or_else(|| "member".as_ref()));
+                let diff_file = DiffFile { path, temp_dir: Some(temp_dir) };
+                if location.exists()? {
+                    archive::extract_member(archive, member,
+                        &diff_file.path)?;
+                }
+                Ok(diff_file)
+            },
+        }
+    }
+
+    /// Returns the path of the file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for DiffFile {
+    fn drop(&mut self) {
+        if let Some(temp_dir) = &self.temp_dir {
+            let _ = std::fs::remove_dir_all(temp_dir);
+        }
+    }
+}
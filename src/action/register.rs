@@ -0,0 +1,160 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Registration of stall directories in the user's stall registry.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::format_path;
+use crate::action::StallStore;
+use crate::CommonOptions;
+use crate::DEFAULT_CONFIG_PATH;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::MissingConfig;
+use crate::error::StallError;
+use crate::registry::Registry;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// register_stall
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall registry add' command.
+///
+/// This will record the stall directory in the [`Registry`] under the given
+/// name, so that other commands can use it with `--stall-name`. A stall
+/// already registered under the name is replaced.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option will print the stall which would be registered
+/// without writing the registry.
+///
+/// ### Parameters
+/// + `name`: The name to register the stall under.
+/// + `stall_dir`: The 'stall directory' to register.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns a [`MissingConfig`] error if the stall directory has no stall
+/// file, or an [`Error`] if the registry can't be read or written.
+///
+/// [`Registry`]: ../registry/struct.Registry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`MissingConfig`]: ../error/struct.MissingConfig.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn register_stall(name: &str, stall_dir: &Path, common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let stall_dir = stall_dir.canonicalize()
+        .with_context(|| format!("Unable to resolve stall directory {:?}",
+            stall_dir))?;
+    let config_path = stall_dir.join(DEFAULT_CONFIG_PATH);
+    let is_archive = matches!(StallStore::new(&stall_dir),
+        StallStore::Archive(_));
+    if !is_archive && !config_path.exists() {
+        return Err(Error::from(MissingConfig { path: config_path.into() })
+            .into());
+    }
+
+    let mut registry = Registry::load()?;
+    let replaced = registry.register(name, &stall_dir);
+    if let Some(replaced) = replaced.filter(|replaced| *replaced != stall_dir)
+    {
+        warn!("Replacing stall {:?} registered at {}.",
+            name,
+            format_path(&replaced, common).display());
+    }
+    info!("    {} {} {}",
+        paint("label", "register"),
+        name,
+        format_path(&stall_dir, common).display());
+    if !common.dry_run {
+        registry.save()?;
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// unregister_stall
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall registry remove' command.
+///
+/// This will remove the stall registered under the given name from the
+/// [`Registry`]. The stall directory itself is unchanged.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option will print the stall which would be removed
+/// without writing the registry.
+///
+/// ### Parameters
+/// + `name`: The name of the stall to remove.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns an [`UnknownStall`] error if no stall is registered under the
+/// name, or an [`Error`] if the registry can't be read or written.
+///
+/// [`Registry`]: ../registry/struct.Registry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`UnknownStall`]: ../error/struct.UnknownStall.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn unregister_stall(name: &str, common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let mut registry = Registry::load()?;
+    let stall_dir = registry.unregister(name)?;
+    info!("    {} {} {}",
+        paint("label", "remove  "),
+        name,
+        format_path(&stall_dir, common).display());
+    if !common.dry_run {
+        registry.save()?;
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// list_stalls
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall registry list' command.
+///
+/// This will print the name and directory of each stall in the [`Registry`].
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the registry can't be read.
+///
+/// [`Registry`]: ../registry/struct.Registry.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn list_stalls(common: &CommonOptions) -> Result<(), StallError> {
+    let registry = Registry::load()?;
+    if registry.is_empty() {
+        info!("No stalls are registered.");
+        return Ok(());
+    }
+
+    for (name, stall_dir) in registry.iter() {
+        info!("    {} {}{}",
+            paint("label", name),
+            format_path(stall_dir, common).display(),
+            if stall_dir.exists() { "" } else { " (missing)" });
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Progress reporting for copy operations.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::CopyStats;
+use crate::action::print_status_line;
+use crate::action::State;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Error;
+use crate::RunContext;
+
+// Standard library imports.
+use std::path::Path;
+use std::sync::Arc;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Reporter
+////////////////////////////////////////////////////////////////////////////////
+/// An observer of the progress of a collect or distribute, allowing
+/// embedders to drive progress displays.
+///
+/// Each method does nothing by default. The `()` reporter ignores all
+/// progress, and the [`TerminalReporter`] prints it as status lines.
+///
+/// [`TerminalReporter`]: struct.TerminalReporter.html
+pub trait Reporter {
+    /// Called when the planned operation for an [`Entry`] is about to be
+    /// executed. The `path` is the path of the file being copied from.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    fn entry_started(&mut self, _entry: &Entry, _path: &Path) {}
+
+    /// Called when the action for a file is decided. Files to be copied are
+    /// reported with [`Action::Copy`] before the copy begins, and are
+    /// reported again if the copy is then stopped or skipped.
+    ///
+    /// [`Action::Copy`]: enum.Action.html#variant.Copy
+    fn action_decided(&mut self, _path: &Path, _state: State, _action: Action)
+    {}
+
+    /// Called when a file has been copied.
+    fn copy_finished(&mut self, _path: &Path, _state: State, _stats: &CopyStats)
+    {}
+
+    /// Called when an error occurs for a file.
+    fn error(&mut self, _path: &Path, _error: &Error) {}
+}
+
+impl Reporter for () {}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TerminalReporter
+////////////////////////////////////////////////////////////////////////////////
+/// A [`Reporter`] which prints a status line for each file once its action is
+/// final.
+///
+/// [`Reporter`]: trait.Reporter.html
+#[derive(Debug, Clone, Copy)]
+pub struct TerminalReporter<'c> {
+    /// The options used to format the status lines.
+    common: &'c CommonOptions,
+    /// The run context of the stall, used to indent the status lines.
+    context: &'c RunContext,
+}
+
+impl<'c> TerminalReporter<'c> {
+    /// Constructs a new `TerminalReporter` printing with the given
+    /// [`CommonOptions`] and [`RunContext`].
+    ///
+    /// [`CommonOptions`]: ../command/struct.CommonOptions.html
+    /// [`RunContext`]: ../command/struct.RunContext.html
+    pub fn new(common: &'c CommonOptions, context: &'c RunContext) -> Self {
+        TerminalReporter { common, context }
+    }
+}
+
+impl Reporter for TerminalReporter<'_> {
+    fn action_decided(&mut self, path: &Path, state: State, action: Action) {
+        // Copies are printed once they have finished.
+        if action != Action::Copy {
+            print_status_line(state, action, path, self.common,
+                self.context);
+        }
+    }
+
+    fn copy_finished(&mut self, path: &Path, state: State, _stats: &CopyStats)
+    {
+        print_status_line(state, Action::Copy, path, self.common,
+            self.context);
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyProgress
+////////////////////////////////////////////////////////////////////////////////
+/// A callback receiving the byte-level progress of each file as it is copied,
+/// set in the [`RunContext`] of a command.
+///
+/// The callback is given the path of the file being copied from, the number of
+/// bytes copied so far, and the size of the file. It is called after each
+/// block of a native copy, and periodically while a copy subprocess runs, then
+/// once the file is complete. Archived, rendered, and encrypted files are not
+/// reported. The callback may be called from a different thread than the one
+/// which started the command.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::action::CopyProgress;
+/// # use stall::RunContext;
+/// let mut context = RunContext::default();
+/// context.progress = CopyProgress::new(|path, copied, total| {
+///     eprintln!("{}: {}/{} bytes", path.display(), copied, total);
+/// });
+/// assert!(context.progress.is_enabled());
+/// ```
+///
+/// [`RunContext`]: ../command/struct.RunContext.html
+#[derive(Clone, Default)]
+pub struct CopyProgress {
+    /// The callback, or `None` if progress is not reported.
+    callback: Option<Arc<ProgressFn>>,
+}
+
+/// The type of a [`CopyProgress`] callback.
+///
+/// [`CopyProgress`]: struct.CopyProgress.html
+type ProgressFn = dyn Fn(&Path, u64, u64) + Send + Sync;
+
+impl CopyProgress {
+    /// Constructs a new `CopyProgress` calling the given callback.
+    pub fn new<F>(callback: F) -> Self
+        where F: Fn(&Path, u64, u64) + Send + Sync + 'static
+    {
+        CopyProgress { callback: Some(Arc::new(callback)) }
+    }
+
+    /// Returns true if a callback is set.
+    pub fn is_enabled(&self) -> bool {
+        self.callback.is_some()
+    }
+
+    /// Reports that `copied` of the `total` bytes of the file at `path` have
+    /// been copied.
+    pub fn report(&self, path: &Path, copied: u64, total: u64) {
+        if let Some(callback) = &self.callback {
+            callback(path, copied, total);
+        }
+    }
+}
+
+impl std::fmt::Debug for CopyProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CopyProgress")
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
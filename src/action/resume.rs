@@ -0,0 +1,141 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Resumption of interrupted collects and distributes.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::indent;
+use crate::action::Location;
+use crate::action::PlanInput;
+use crate::CommonOptions;
+use crate::RunContext;
+use crate::state::RunProgress;
+use crate::state::SyncState;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::collections::BTreeSet;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// prepare_run
+////////////////////////////////////////////////////////////////////////////////
+/// Prepares the inputs of a collect or distribute, returning the
+/// [`RunProgress`] to record while it runs.
+///
+/// With the `--resume` option, the [`RunProgress`] of an interrupted run of
+/// the same command is continued: the inputs are put back into the order of
+/// that run, and the entries it copied are removed from the inputs if neither
+/// of their files has been modified since. Other entries are processed as
+/// usual.
+///
+/// ### Parameters
+/// + `command`: The name of the command being run.
+/// + `inputs`: The [`PlanInput`]s of the entries, in the chosen order.
+/// + `sync_state`: The [`SyncState`] holding the progress of the interrupted
+///   run.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// [`RunProgress`]: ../state/struct.RunProgress.html
+/// [`PlanInput`]: struct.PlanInput.html
+/// [`SyncState`]: ../state/struct.SyncState.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+pub fn prepare_run(
+    command: &str,
+    inputs: &mut Vec<PlanInput<'_>>,
+    sync_state: &SyncState,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> RunProgress
+{
+    let previous = match (common.resume, sync_state.progress()) {
+        (false, _) => None,
+        (true, Some(progress)) if progress.command == command
+            => Some(progress.clone()),
+        (true, Some(progress)) => {
+            warn!("The interrupted run was a {}; starting a new {}.",
+                progress.command,
+                command);
+            None
+        },
+        (true, None) => {
+            warn!("There is no interrupted {} to resume.", command);
+            None
+        },
+    };
+
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return RunProgress {
+            command: command.into(),
+            order: common.order.unwrap_or_default(),
+            entries: inputs.iter()
+                .map(|input| Location::remote(input.entry).display_path())
+                .collect(),
+            completed: BTreeSet::new(),
+            started: SystemTime::now(),
+        },
+    };
+
+    // Entries added since the interrupted run are processed last.
+    inputs.sort_by_key(|input| {
+        let path = Location::remote(input.entry).display_path();
+        previous.entries.iter()
+            .position(|entry| *entry == path)
+            .unwrap_or(usize::MAX)
+    });
+
+    let count = inputs.len();
+    inputs.retain(|input| !previous.completed
+            .contains(&Location::remote(input.entry).display_path())
+        || !is_unchanged(input, common, context));
+    let skipped = count - inputs.len();
+
+    let age = SystemTime::now()
+        .duration_since(previous.started)
+        .unwrap_or_default();
+    info!("{}Resuming {} started {} seconds ago: skipping {} completed {}.",
+        indent(context),
+        command,
+        age.as_secs(),
+        skipped,
+        if skipped == 1 { "entry" } else { "entries" });
+    previous
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns true if neither file of the input has been modified since it was
+/// last copied.
+fn is_unchanged(
+    input: &PlanInput<'_>,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> bool
+{
+    let record = match &input.synced {
+        Some(record) => record,
+        None         => return false,
+    };
+    let remote = Location::remote(input.entry);
+    let local = match input.source == remote {
+        true  => &input.target,
+        false => &input.source,
+    };
+    match (local.modified(common, context), remote.modified(common, context)) {
+        (Ok(local), Ok(remote)) => record.is_unchanged(local, remote),
+        _ => false,
+    }
+}
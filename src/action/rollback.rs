@@ -0,0 +1,211 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Rollback of entries to their last known good versions.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::confirm;
+use crate::action::copy;
+use crate::action::CopyMethod;
+use crate::action::digest;
+use crate::action::distribute;
+use crate::action::indent;
+use crate::action::Location;
+use crate::action::PolicyAction;
+use crate::action::Reporter;
+use crate::action::RunReport;
+use crate::action::StallStore;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::InvalidFile;
+use crate::error::StallError;
+use crate::RunContext;
+use crate::state::SyncState;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// KNOWN_GOOD_PATH
+////////////////////////////////////////////////////////////////////////////////
+/// The path of the directory holding the last known good version of each
+/// entry, relative to the directory of snapshots. Snapshot names may not
+/// start with '.', so it never holds a snapshot.
+pub const KNOWN_GOOD_PATH: &str = ".known-good";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// keep_known_good
+////////////////////////////////////////////////////////////////////////////////
+/// Keeps a copy of the stall copy of a distributed [`Entry`] as its last
+/// known good version, and records its checksum in the [`SyncState`].
+/// Directory entries have no known good version.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the stall copy can't be read or copied.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`SyncState`]: ../state/struct.SyncState.html
+/// [`Error`]: ../error/struct.Error.html
+pub(crate) fn keep_known_good(
+    store: &StallStore,
+    entry: &Entry,
+    local: &Location,
+    sync_state: &mut SyncState,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    if local.is_dir() {
+        trace!("Not keeping a known good version of directory {}.", local);
+        return Ok(());
+    }
+    let pin = digest(local)?;
+    let known_good_dir = store.known_good_dir();
+    let kept = known_good_dir.join(entry.file_name().ok_or(InvalidFile)?);
+    let unchanged = sync_state.known_good(entry)
+        .is_some_and(|known_good| known_good.pin == pin)
+        && kept.exists();
+    if !unchanged {
+        std::fs::create_dir_all(&known_good_dir)
+            .with_context(|| format!("Unable to create directory {:?}",
+                known_good_dir))?;
+        copy(
+            local,
+            &Location::file(&kept),
+            context.copy_method,
+            PolicyAction::Skip,
+            common,
+            context,
+            None)?;
+        debug!("Kept known good version {} of {}.", pin, local);
+    }
+    sync_state.record_known_good(entry, pin);
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// rollback
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall rollback' command.
+///
+/// This will restore the stall copy of each of the given entries to the last
+/// version which was distributed without error, then distribute it. The last
+/// known good version of an entry is kept each time it is distributed and its
+/// post-copy hook succeeds. Entries without a known good version are skipped
+/// with a warning.
+///
+/// ### Command line options
+///
+/// The `--yes` option will roll back the entries without confirmation.
+///
+/// The `--dry-run` option will print the entries which would be rolled back
+/// without copying them.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to roll back.
+/// + `files`: An iterator over the [`Entry`]s to roll back.
+/// + `reporter`: The [`Reporter`] to report the distribute to.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the kept copy of a known good version is missing
+/// or has been modified, or if an entry can't be restored or distributed.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`Reporter`]: trait.Reporter.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn rollback<'i, P, I>(
+    stall_dir: P,
+    files: I,
+    reporter: &mut dyn Reporter,
+    mut common: CommonOptions,
+    context: RunContext)
+    -> Result<RunReport, StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let stall_dir = stall_dir.as_ref();
+    let store = StallStore::new(stall_dir);
+    let sync_state = SyncState::load_from(&store.state_path())?;
+    let known_good_dir = store.known_good_dir();
+
+    let mut restored = Vec::new();
+    for entry in files {
+        let known_good = match sync_state.known_good(entry) {
+            Some(known_good) => known_good,
+            None => {
+                warn!("{} has no known good version to roll back to.",
+                    Location::remote(entry));
+                continue;
+            },
+        };
+        let kept = Location::file(known_good_dir
+            .join(entry.file_name().ok_or(InvalidFile)?));
+        let matches = kept.exists()?
+            && digest(&kept)? == known_good.pin;
+        if !matches {
+            return Err(Error::msg(format!("the known good version of {} \
+                is missing or has been modified", Location::remote(entry)))
+                .into());
+        }
+        restored.push((entry, kept));
+    }
+    if restored.is_empty() {
+        return Ok(RunReport::new("distribute", stall_dir, &common));
+    }
+
+    if !common.yes && !common.dry_run {
+        let message = format!("Roll back {} {} to the last known good {}, \
+                replacing the stall {} and remote {}?",
+            restored.len(),
+            if restored.len() == 1 { "entry" } else { "entries" },
+            if restored.len() == 1 { "version" } else { "versions" },
+            if restored.len() == 1 { "copy" } else { "copies" },
+            if restored.len() == 1 { "file" } else { "files" });
+        if !confirm(&message)? {
+            info!("Rollback cancelled.");
+            return Ok(RunReport::new("distribute", stall_dir, &common));
+        }
+    }
+
+    let method = match common.dry_run {
+        true  => CopyMethod::None,
+        false => context.copy_method,
+    };
+    for (entry, kept) in &restored {
+        let local = store.local(entry.file_name().ok_or(InvalidFile)?);
+        info!("{}    {} {}",
+            indent(&context),
+            paint("label", "rollback"),
+            Location::remote(entry));
+        copy(kept, &local, method, PolicyAction::Skip, &common, &context,
+                None)
+            .with_context(|| format!("Unable to restore {}", local))?;
+    }
+
+    // The restored stall copies may be older than the remote files.
+    common.force = true;
+    common.yes = true;
+    distribute(stall_dir, restored.iter().map(|(entry, _)| *entry), reporter,
+        common, context)
+}
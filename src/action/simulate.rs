@@ -0,0 +1,129 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Simulation of the status of a stall on another machine.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::format_path;
+use crate::action::indent;
+use crate::action::Location;
+use crate::action::render_file;
+use crate::action::StallStore;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::error::InvalidFile;
+use crate::Platform;
+use crate::RunContext;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// simulate_status
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall status' command with the `--as-host` or `--as-os`
+/// options.
+///
+/// This will print the entries which apply to the given [`Platform`], and
+/// render each template entry as it would be rendered there. No files other
+/// than the stall copies of templates are read, so the results don't depend
+/// on the state of the current machine, apart from environment conditions,
+/// which are evaluated in the current environment.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to simulate the status of.
+/// + `files`: An iterator over the [`Entry`]s which apply to the platform.
+/// + `platform`: The [`Platform`] to simulate.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if any template fails to render.
+///
+/// [`Platform`]: ../struct.Platform.html
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn simulate_status<'i, P, I>(
+    stall_dir: P,
+    files: I,
+    platform: &Platform,
+    common: CommonOptions,
+    context: RunContext)
+    -> Result<(), StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let stall_dir = stall_dir.as_ref();
+    info!("{}{} {}",
+        indent(&context),
+        paint("label", "Stall directory:"),
+        format_path(stall_dir, &common).display());
+    info!("{}{} {} ({})",
+        indent(&context),
+        paint("label", "Simulating host:"),
+        platform.host,
+        platform.os.map_or("unknown os".into(), |os| os.to_string()));
+
+    let store = StallStore::new(stall_dir);
+    let mut options = context.templates.clone();
+    options.os = platform.os;
+
+    info!("{}{}", indent(&context), paint("header", "    RESULT   FILE"));
+    let mut failed = 0;
+    for entry in files {
+        let path = Location::remote(entry).display_path();
+        let path = format_path(&path, &common);
+        if !entry.template {
+            info!("{}    {} {}",
+                indent(&context),
+                paint("found", "applies "),
+                path.display());
+            continue;
+        }
+
+        let file_name = entry.file_name().ok_or(InvalidFile)?;
+        let result = match store.local(file_name) {
+            Location::File(source) => render_file(&source, &options),
+            source => Err(Error::msg(format!("unable to render template {}: \
+                templates must be regular files", source))),
+        };
+        match result {
+            Ok(_) => info!("{}    {} {}",
+                indent(&context),
+                paint("found", "renders "),
+                path.display()),
+            Err(e) => {
+                failed += 1;
+                info!("{}    {} {}: {:#}",
+                    indent(&context),
+                    paint("error", "error   "),
+                    path.display(),
+                    e);
+            },
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(Error::msg(format!("{} {} failed to render for host {}",
+            failed,
+            if failed == 1 { "template" } else { "templates" },
+            platform.host)).into()),
+    }
+}
@@ -0,0 +1,754 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Named snapshots of the stall copies of entries.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::confirm;
+use crate::action::copy;
+use crate::action::CopyMethod;
+use crate::action::digest;
+use crate::action::format_path;
+use crate::action::indent;
+use crate::action::Location;
+use crate::action::PolicyAction;
+use crate::action::print_file_diff;
+use crate::action::StallStore;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::InvalidFile;
+use crate::error::SnapshotExists;
+use crate::error::StallError;
+use crate::error::UnknownSnapshot;
+use crate::RunContext;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// DEFAULT_SNAPSHOTS_PATH
+////////////////////////////////////////////////////////////////////////////////
+/// The path of the directory of snapshots, relative to the stall directory.
+pub const DEFAULT_SNAPSHOTS_PATH: &str = ".stall-snapshots";
+
+/// The name used in place of a snapshot name to refer to the current stall
+/// copies of the entries.
+pub const WORKING_SNAPSHOT: &str = "WORKING";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// AutoSnapshot
+////////////////////////////////////////////////////////////////////////////////
+/// Triggers for automatic snapshots, loaded from the stall file.
+///
+/// Automatic snapshots are taken before a collect or distribute copies any
+/// files, and are named `auto-<command>-<time>`. Only the most recent `keep`
+/// automatic snapshots are kept, while snapshots created with
+/// 'stall snapshot create' are never removed:
+///
+/// ```ron
+/// auto_snapshot: (
+///     before_distribute: true,
+///     before_force: true,
+///     daily: false,
+///     keep: Some(10),
+/// ),
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AutoSnapshot {
+    /// Whether to take a snapshot before every distribute.
+    #[serde(default)]
+    pub before_distribute: bool,
+
+    /// Whether to take a snapshot before every collect or distribute run
+    /// with the `--force` option.
+    #[serde(default)]
+    pub before_force: bool,
+
+    /// Whether to take a snapshot before a collect or distribute if no
+    /// automatic snapshot has been taken in the last day.
+    #[serde(default)]
+    pub daily: bool,
+
+    /// The number of automatic snapshots to keep, or `None` to keep all of
+    /// them.
+    #[serde(default = "AutoSnapshot::default_keep")]
+    pub keep: Option<usize>,
+}
+
+impl AutoSnapshot {
+    /// The default number of automatic snapshots to keep.
+    pub const DEFAULT_KEEP: usize = 10;
+
+    /// Returns the default number of automatic snapshots to keep.
+    fn default_keep() -> Option<usize> {
+        Some(AutoSnapshot::DEFAULT_KEEP)
+    }
+}
+
+impl Default for AutoSnapshot {
+    fn default() -> Self {
+        AutoSnapshot {
+            before_distribute: false,
+            before_force: false,
+            daily: false,
+            keep: AutoSnapshot::default_keep(),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Snapshot
+////////////////////////////////////////////////////////////////////////////////
+/// A named copy of the stall copies of the entries of a stall, taken at one
+/// time so that they can be restored together.
+///
+/// Each snapshot is described by a `<name>.ron` file in the snapshots
+/// directory, and its files are stored in the `<name>` directory beside it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The name of the snapshot.
+    pub name: String,
+    /// The time the snapshot was created.
+    pub created: SystemTime,
+    /// The names of the stall copies in the snapshot.
+    pub files: Vec<PathBuf>,
+    /// Whether the snapshot was taken automatically. Only automatic
+    /// snapshots are removed to keep within [`AutoSnapshot::keep`].
+    ///
+    /// [`AutoSnapshot::keep`]: struct.AutoSnapshot.html#structfield.keep
+    #[serde(default)]
+    pub automatic: bool,
+}
+
+impl Snapshot {
+    /// Loads the named `Snapshot` from the snapshots directory. Returns
+    /// `None` if there is no such snapshot.
+    pub fn load(snapshots_dir: &Path, name: &str)
+        -> Result<Option<Self>, Error>
+    {
+        let path = snapshots_dir.join(format!("{}.ron", name));
+        match std::fs::read_to_string(&path) {
+            Ok(text) => ron::de::from_str(&text)
+                .map(Some)
+                .with_context(|| format!("Unable to parse snapshot file: \
+                    {:?}", path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+                .with_context(|| format!("Unable to read snapshot file: \
+                    {:?}", path)),
+        }
+    }
+
+    /// Loads every `Snapshot` in the snapshots directory, ordered by the time
+    /// they were created.
+    pub fn load_all(snapshots_dir: &Path) -> Result<Vec<Self>, Error> {
+        let dir = match std::fs::read_dir(snapshots_dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e)
+                .with_context(|| format!("Unable to read snapshots directory: \
+                    {:?}", snapshots_dir)),
+        };
+
+        let mut snapshots = Vec::new();
+        for dir_entry in dir {
+            let path = dir_entry
+                .with_context(|| "read snapshots directory entry")?
+                .path();
+            if path.extension().is_none_or(|ext| ext != "ron") { continue; }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name,
+                None       => continue,
+            };
+            snapshots.extend(Snapshot::load(snapshots_dir, name)?);
+        }
+        snapshots.sort_by_key(|snapshot| snapshot.created);
+        Ok(snapshots)
+    }
+
+    /// Writes the description of the `Snapshot` into the snapshots
+    /// directory.
+    pub fn save(&self, snapshots_dir: &Path) -> Result<(), Error> {
+        let path = snapshots_dir.join(format!("{}.ron", self.name));
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .with_context(|| "serialize snapshot")?;
+        std::fs::write(&path, text)
+            .with_context(|| format!("Unable to write snapshot file: {:?}",
+                path))?;
+        debug!("Saved snapshot: {:?}", path);
+        Ok(())
+    }
+
+    /// Returns the directory holding the files of the `Snapshot`.
+    pub fn files_dir(&self, snapshots_dir: &Path) -> PathBuf {
+        snapshots_dir.join(&self.name)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// create_snapshot
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall snapshot create' command.
+///
+/// This will copy the stall copy of each entry into a new snapshot with the
+/// given name. Entries without a stall copy are left out of the snapshot.
+///
+/// ### Command line options
+///
+/// The `--force` option will replace an existing snapshot with the same name.
+///
+/// The `--dry-run` option will print the files without copying them.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to take the snapshot of.
+/// + `name`: The name of the snapshot.
+/// + `files`: An iterator over the [`Entry`]s to include in the snapshot.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns a [`SnapshotExists`] error if the snapshot already exists, or an
+/// [`Error`] if the name is invalid or a file can't be copied.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`SnapshotExists`]: ../error/struct.SnapshotExists.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn create_snapshot<'i, P, I>(
+    stall_dir: P,
+    name: &str,
+    files: I,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let stall_dir = stall_dir.as_ref();
+    check_name(name)?;
+    let store = StallStore::new(stall_dir);
+    let snapshots_dir = store.snapshots_dir();
+    if Snapshot::load(&snapshots_dir, name)?.is_some() {
+        if !common.force {
+            return Err(Error::from(SnapshotExists { name: name.into() })
+                .into());
+        }
+        if !common.dry_run {
+            remove_snapshot(&snapshots_dir, name)?;
+        }
+    }
+
+    let snapshot = take_snapshot(&store, name, false, files, common,
+        context)?;
+
+    info!("{}{} snapshot {:?} of {} {}.",
+        indent(context),
+        paint("label", match common.dry_run {
+            true  => "Would create",
+            false => "Created",
+        }),
+        name,
+        snapshot.files.len(),
+        if snapshot.files.len() == 1 { "file" } else { "files" });
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// auto_snapshot
+////////////////////////////////////////////////////////////////////////////////
+/// Takes an automatic snapshot of the stall copies of the given entries
+/// before a collect or distribute, if one of the [`AutoSnapshot`] triggers
+/// in the [`RunContext`] applies. The oldest automatic snapshots are then
+/// removed to keep within the configured limit.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to take the snapshot of.
+/// + `command`: The name of the command about to be run.
+/// + `files`: An iterator over the [`Entry`]s to include in the snapshot.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if a snapshot can't be read, written, or removed.
+///
+/// [`AutoSnapshot`]: struct.AutoSnapshot.html
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn auto_snapshot<'i, P, I>(
+    stall_dir: P,
+    command: &str,
+    files: I,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let auto = context.auto_snapshot;
+    let store = StallStore::new(stall_dir.as_ref());
+    let snapshots_dir = store.snapshots_dir();
+    let mut automatic: Vec<_> = Snapshot::load_all(&snapshots_dir)?
+        .into_iter()
+        .filter(|snapshot| snapshot.automatic)
+        .collect();
+
+    let now = SystemTime::now();
+    let daily_due = match automatic.last() {
+        Some(latest) => now.duration_since(latest.created)
+            .is_ok_and(|age| age >= Duration::from_secs(24 * 60 * 60)),
+        None => true,
+    };
+    let due = (auto.before_distribute && command == "distribute")
+        || (auto.before_force && common.force)
+        || (auto.daily && daily_due);
+    if !due { return Ok(()); }
+
+    let secs = now.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let name = format!("auto-{}-{}", command, secs);
+    if Snapshot::load(&snapshots_dir, &name)?.is_some() {
+        debug!("Snapshot {:?} already exists.", name);
+        return Ok(());
+    }
+    let snapshot = take_snapshot(&store, &name, true, files, common,
+        context)?;
+    info!("{}{} automatic snapshot {:?} of {} {}.",
+        indent(context),
+        paint("label", match common.dry_run {
+            true  => "Would create",
+            false => "Created",
+        }),
+        name,
+        snapshot.files.len(),
+        if snapshot.files.len() == 1 { "file" } else { "files" });
+
+    if common.dry_run { return Ok(()); }
+    automatic.push(snapshot);
+    let excess = match auto.keep {
+        Some(keep) => automatic.len().saturating_sub(keep),
+        None       => 0,
+    };
+    for snapshot in &automatic[..excess] {
+        remove_snapshot(&snapshots_dir, &snapshot.name)?;
+        debug!("Removed automatic snapshot {:?}.", snapshot.name);
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// list_snapshots
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall snapshot list' command.
+///
+/// This will print the name, number of files, and age of each snapshot of the
+/// stall directory.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if a snapshot file can't be read.
+///
+/// [`Error`]: ../error/struct.Error.html
+pub fn list_snapshots<P>(stall_dir: P, common: &CommonOptions)
+    -> Result<(), StallError>
+    where P: AsRef<Path>
+{
+    let stall_dir = stall_dir.as_ref();
+    let snapshots = Snapshot::load_all(
+        &StallStore::new(stall_dir).snapshots_dir())?;
+    if snapshots.is_empty() {
+        info!("No snapshots of {}.",
+            format_path(stall_dir, common).display());
+        return Ok(());
+    }
+
+    for snapshot in &snapshots {
+        let age = SystemTime::now()
+            .duration_since(snapshot.created)
+            .unwrap_or_default();
+        info!("    {} {} {}, created {} seconds ago{}.",
+            paint("label", &snapshot.name),
+            snapshot.files.len(),
+            if snapshot.files.len() == 1 { "file" } else { "files" },
+            age.as_secs(),
+            if snapshot.automatic { " automatically" } else { "" });
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// restore_snapshot
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall restore' command.
+///
+/// This will copy the files of the named snapshot over the stall copies of
+/// the given entries. Entries which are not in the snapshot are left
+/// unchanged. The files outside of the stall directory are not modified; run
+/// 'stall distribute' afterwards to apply the restored files.
+///
+/// ### Command line options
+///
+/// The `--yes` option will restore the files without confirmation.
+///
+/// The `--dry-run` option will print the files without copying them.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to restore into.
+/// + `name`: The name of the snapshot.
+/// + `files`: An iterator over the [`Entry`]s to restore.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`UnknownSnapshot`] error if the snapshot doesn't exist, or an
+/// [`Error`] if a file can't be copied.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`UnknownSnapshot`]: ../error/struct.UnknownSnapshot.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn restore_snapshot<'i, P, I>(
+    stall_dir: P,
+    name: &str,
+    files: I,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let stall_dir = stall_dir.as_ref();
+    let store = StallStore::new(stall_dir);
+    let snapshots_dir = store.snapshots_dir();
+    let snapshot = Snapshot::load(&snapshots_dir, name)?
+        .ok_or_else(|| Error::from(UnknownSnapshot { name: name.into() }))?;
+    let files_dir = snapshot.files_dir(&snapshots_dir);
+
+    let mut restored = Vec::new();
+    for entry in files {
+        let file_name = PathBuf::from(entry.file_name().ok_or(InvalidFile)?);
+        match snapshot.files.contains(&file_name) {
+            true  => restored.push(file_name),
+            false => warn!("{} is not in snapshot {:?}.",
+                Location::remote(entry),
+                name),
+        }
+    }
+
+    if !restored.is_empty() && !common.yes && !common.dry_run {
+        let message = format!("Restore {} {} from snapshot {:?}, replacing \
+                the stall {}?",
+            restored.len(),
+            if restored.len() == 1 { "file" } else { "files" },
+            name,
+            if restored.len() == 1 { "copy" } else { "copies" });
+        if !confirm(&message)? {
+            info!("Restore cancelled.");
+            return Ok(());
+        }
+    }
+
+    let method = match common.dry_run {
+        true  => CopyMethod::None,
+        false => context.copy_method,
+    };
+    for file_name in &restored {
+        let local = store.local(file_name);
+        debug!("{}    {}", indent(context), local);
+        copy(
+            &Location::file(files_dir.join(file_name)),
+            &local,
+            method,
+            PolicyAction::Skip,
+            common,
+            context,
+            None)?;
+    }
+
+    info!("{}{} {} {} from snapshot {:?}.",
+        indent(context),
+        paint("label", match common.dry_run {
+            true  => "Would restore",
+            false => "Restored",
+        }),
+        restored.len(),
+        if restored.len() == 1 { "file" } else { "files" },
+        name);
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// diff_snapshots
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall snapshot diff' command.
+///
+/// This will print the files of the given entries which were added, removed,
+/// or changed between two snapshots. The name [`WORKING_SNAPSHOT`] may be
+/// used for either snapshot to refer to the current stall copies. Files are
+/// compared by content, and directories by their total size.
+///
+/// ### Command line options
+///
+/// The `--content` option will also print the differences between the
+/// contents of each changed file. The contents of sensitive entries are never
+/// shown.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' holding the snapshots.
+/// + `from`: The name of the snapshot to compare from.
+/// + `to`: The name of the snapshot to compare to.
+/// + `files`: An iterator over the [`Entry`]s to compare.
+/// + `content`: Whether to print the differences between file contents.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`UnknownSnapshot`] error if either snapshot doesn't exist, or
+/// an [`Error`] if a file can't be read.
+///
+/// [`WORKING_SNAPSHOT`]: constant.WORKING_SNAPSHOT.html
+/// [`Entry`]: ../struct.Entry.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`UnknownSnapshot`]: ../error/struct.UnknownSnapshot.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn diff_snapshots<'i, P, I>(
+    stall_dir: P,
+    from: &str,
+    to: &str,
+    files: I,
+    content: bool,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    let store = StallStore::new(stall_dir.as_ref());
+    let from_snapshot = load_named(&store, from)?;
+    let to_snapshot = load_named(&store, to)?;
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for entry in files {
+        let file_name = PathBuf::from(entry.file_name().ok_or(InvalidFile)?);
+        let (old, old_exists) = snapshot_file(
+            &store,
+            from_snapshot.as_ref(),
+            &file_name)?;
+        let (new, new_exists) = snapshot_file(
+            &store,
+            to_snapshot.as_ref(),
+            &file_name)?;
+        let label = match (old_exists, new_exists) {
+            (false, false) => continue,
+            (false, true)  => { added += 1; "added  " },
+            (true,  false) => { removed += 1; "removed" },
+            (true,  true) if same_content(&old, &new, common, context)? => {
+                debug!("{}    unchanged {}",
+                    indent(context),
+                    file_name.display());
+                continue;
+            },
+            (true,  true)  => { changed += 1; "changed" },
+        };
+        info!("{}    {} {}",
+            indent(context),
+            paint("label", label),
+            file_name.display());
+        if content {
+            print_file_diff(&new, &old, entry.is_sensitive())?;
+        }
+    }
+
+    info!("{}{} {:?} and {:?}: {} added, {} removed, {} changed.",
+        indent(context),
+        paint("label", "Compared"),
+        from,
+        to,
+        added,
+        removed,
+        changed);
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Checks that the snapshot name can be used as a file name.
+fn check_name(name: &str) -> Result<(), Error> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains(['/', '\\']);
+    match valid {
+        true  => Ok(()),
+        false => Err(Error::msg(format!("invalid snapshot name {:?}: names \
+            may not be empty, start with '.', or contain path separators",
+            name))),
+    }
+}
+
+/// Copies the stall copy of each entry into a new snapshot with the given
+/// name, returning the [`Snapshot`]. Entries without a stall copy are left out
+/// of the snapshot.
+///
+/// [`Snapshot`]: struct.Snapshot.html
+fn take_snapshot<'i, I>(
+    store: &StallStore,
+    name: &str,
+    automatic: bool,
+    files: I,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<Snapshot, Error>
+    where I: IntoIterator<Item=&'i Entry>,
+{
+    let snapshots_dir = store.snapshots_dir();
+    let mut snapshot = Snapshot {
+        name: name.into(),
+        created: SystemTime::now(),
+        files: Vec::new(),
+        automatic,
+    };
+    let files_dir = snapshot.files_dir(&snapshots_dir);
+    let method = match common.dry_run {
+        true  => CopyMethod::None,
+        false => context.copy_method,
+    };
+    if !common.dry_run {
+        std::fs::create_dir_all(&files_dir)
+            .with_context(|| format!("Unable to create snapshot directory: \
+                {:?}", files_dir))?;
+    }
+    for entry in files {
+        let file_name = PathBuf::from(entry.file_name().ok_or(InvalidFile)?);
+        let local = store.local(&file_name);
+        if !local.exists()? {
+            debug!("No stall copy to snapshot: {}", local);
+            continue;
+        }
+        debug!("{}    {}", indent(context), local);
+        copy(
+            &local,
+            &Location::file(files_dir.join(&file_name)),
+            method,
+            PolicyAction::Skip,
+            common,
+            context,
+            None)?;
+        snapshot.files.push(file_name);
+    }
+    if !common.dry_run {
+        snapshot.save(&snapshots_dir)?;
+    }
+    Ok(snapshot)
+}
+
+/// Removes the files and description of the named snapshot.
+fn remove_snapshot(snapshots_dir: &Path, name: &str) -> Result<(), Error> {
+    let files_dir = snapshots_dir.join(name);
+    if files_dir.exists() {
+        std::fs::remove_dir_all(&files_dir)
+            .with_context(|| format!("Unable to remove snapshot directory: \
+                {:?}", files_dir))?;
+    }
+    let path = snapshots_dir.join(format!("{}.ron", name));
+    std::fs::remove_file(&path)
+        .with_context(|| format!("Unable to remove snapshot file: {:?}",
+            path))
+}
+
+/// Loads the named snapshot, returning `None` for the
+/// [`WORKING_SNAPSHOT`].
+///
+/// [`WORKING_SNAPSHOT`]: constant.WORKING_SNAPSHOT.html
+fn load_named(store: &StallStore, name: &str)
+    -> Result<Option<Snapshot>, Error>
+{
+    if name == WORKING_SNAPSHOT { return Ok(None); }
+    match Snapshot::load(&store.snapshots_dir(), name)? {
+        Some(snapshot) => Ok(Some(snapshot)),
+        None           => Err(UnknownSnapshot { name: name.into() }.into()),
+    }
+}
+
+/// Returns the location of the named file in the given snapshot, or the stall
+/// copy if the snapshot is `None`, and whether the file exists.
+fn snapshot_file(
+    store: &StallStore,
+    snapshot: Option<&Snapshot>,
+    file_name: &Path)
+    -> Result<(Location, bool), Error>
+{
+    match snapshot {
+        Some(snapshot) => Ok((
+            Location::file(snapshot
+                .files_dir(&store.snapshots_dir())
+                .join(file_name)),
+            snapshot.files.iter().any(|file| file == file_name),
+        )),
+        None => {
+            let local = store.local(file_name);
+            let exists = local.exists()?;
+            Ok((local, exists))
+        },
+    }
+}
+
+/// Returns true if the files have the same content. Directories are compared
+/// by their total size.
+fn same_content(
+    a: &Location,
+    b: &Location,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<bool, Error>
+{
+    if a.size(common, context)? != b.size(common, context)? {
+        return Ok(false);
+    }
+    match a.is_dir() || b.is_dir() {
+        true  => Ok(a.is_dir() == b.is_dir()),
+        false => Ok(digest(a)? == digest(b)?),
+    }
+}
@@ -0,0 +1,187 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Free space checks for planned copy operations.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::format_bytes;
+use crate::action::Location;
+use crate::action::PlanItem;
+use crate::action::walk;
+use crate::CommonOptions;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::InsufficientSpace;
+use crate::RunContext;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// check_free_space
+////////////////////////////////////////////////////////////////////////////////
+/// Checks that each file system copied to has enough free space for the
+/// files to be copied by the plan.
+///
+/// The space required for each file is its size less the size of the file it
+/// replaces. Files which are only copied after confirmation are included.
+/// With the `--dry-run` option, a warning is printed instead.
+///
+/// ### Errors
+///
+/// Returns an [`InsufficientSpace`] error if a file system does not have
+/// enough free space, or an [`Error`] if the sizes of the files or the free
+/// space can't be determined.
+///
+/// [`InsufficientSpace`]: ../error/struct.InsufficientSpace.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn check_free_space(
+    plan: &[PlanItem<'_>],
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    // The directory and required space, keyed by file system.
+    let mut required: BTreeMap<FileSystem, (PathBuf, u64)> = BTreeMap::new();
+    for item in plan.iter().filter(|item| item.action == Action::Copy) {
+        let path = match &item.target {
+            Location::File(path)               => path,
+            Location::Archived { archive, .. } => archive,
+        };
+        let replaced = match (&item.target, item.target.exists()?) {
+            (Location::File(_), true) => item.target.size(common, context)?,
+            _                         => 0,
+        };
+        let size = item.source.size(common, context)?.saturating_sub(replaced);
+
+        let dir = existing_ancestor(path)?;
+        let entry = required.entry(file_system(&dir)?)
+            .or_insert_with(|| (dir, 0));
+        entry.1 += size;
+    }
+
+    for (dir, required) in required.into_values() {
+        let available = available_space(&dir)?;
+        debug!("{} required on file system of {:?}, {} available.",
+            format_bytes(required), dir, format_bytes(available));
+        if required <= available { continue; }
+
+        let error = InsufficientSpace {
+            path: dir.into(),
+            required,
+            available,
+        };
+        match common.dry_run {
+            true  => warn!("{}", error),
+            false => return Err(error.into()),
+        }
+    }
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// An identifier for a file system: its device id where available, otherwise
+/// the root of its paths.
+type FileSystem = (Option<u64>, PathBuf);
+
+/// Returns the nearest existing ancestor directory of `path`. The parent of a
+/// relative path with a single component is the current directory.
+fn existing_ancestor(path: &Path) -> Result<PathBuf, Error> {
+    path.ancestors()
+        .skip(1)
+        .map(|dir| match dir.as_os_str().is_empty() {
+            true  => Path::new("."),
+            false => dir,
+        })
+        .find(|dir| dir.is_dir())
+        .map(Path::to_path_buf)
+        .ok_or_else(|| Error::msg(format!("unable to find the file system \
+            for {:?}", path)))
+}
+
+/// Returns the [`FileSystem`] containing the directory `dir`.
+///
+/// [`FileSystem`]: type.FileSystem.html
+fn file_system(dir: &Path) -> Result<FileSystem, Error> {
+    let metadata = dir.metadata()
+        .with_context(|| format!("Unable to read metadata of {:?}", dir))?;
+    Ok(match walk::device_id(&metadata) {
+        Some(device) => (Some(device), PathBuf::new()),
+        None         => (None, dir.components().take(2).collect()),
+    })
+}
+
+/// Returns the number of bytes available to the current user on the file
+/// system containing the directory `dir`.
+#[cfg(unix)]
+fn available_space(dir: &Path) -> Result<u64, Error> {
+    use std::os::unix::ffi::OsStrExt as _;
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path {:?}", dir))?;
+    // The path is a valid C string, and the buffer is only read if the call
+    // succeeds.
+    let stat = unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Unable to read free space of {:?}",
+                    dir));
+        }
+        stat
+    };
+    // The field types differ between platforms.
+    #[allow(trivial_numeric_casts, clippy::unnecessary_cast)]
+    let available = stat.f_bavail as u64 * stat.f_frsize as u64;
+    Ok(available)
+}
+
+/// Returns the number of bytes available to the current user on the file
+/// system containing the directory `dir`.
+#[cfg(windows)]
+fn available_space(dir: &Path) -> Result<u64, Error> {
+    use std::os::windows::ffi::OsStrExt as _;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path: Vec<u16> = dir.as_os_str()
+        .encode_wide()
+        .chain(std::iter::once(0))
+        .collect();
+    let mut available = 0u64;
+    // The path is null-terminated, and the unused outputs may be null.
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut())
+    };
+    if ok == 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Unable to read free space of {:?}",
+                dir));
+    }
+    Ok(available)
+}
+
+/// Returns the number of bytes available to the current user on the file
+/// system containing the directory `dir`. The free space can't be determined
+/// on this platform, so it is assumed to be sufficient.
+#[cfg(not(any(unix, windows)))]
+fn available_space(_dir: &Path) -> Result<u64, Error> {
+    Ok(u64::MAX)
+}
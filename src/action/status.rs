@@ -0,0 +1,536 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Report the status of files in a stall.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::Action;
+use crate::action::format_path;
+use crate::action::indent;
+use crate::action::Location;
+use crate::action::mode_drift;
+use crate::action::ModeDrift;
+use crate::action::PlanItem;
+use crate::action::publish_host_status;
+use crate::action::print_status_header;
+use crate::action::print_status_legend;
+use crate::action::print_status_line;
+use crate::action::StallStore;
+use crate::action::State;
+use crate::CommonOptions;
+use crate::Entry;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::error::InvalidFile;
+use crate::RunContext;
+use crate::state::CachedStatus;
+use crate::state::LastError;
+use crate::state::SyncState;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// StatusMode
+////////////////////////////////////////////////////////////////////////////////
+/// The method used to determine the status of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusMode {
+    /// Read the status recorded by the last accurate query. Only the state
+    /// file is read, so the status may be stale.
+    Cached,
+    /// Compare the files on disk, and record the result for cached queries.
+    Accurate,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// GroupBy
+////////////////////////////////////////////////////////////////////////////////
+/// The key used to group entries in status output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub enum GroupBy {
+    /// Group entries by their tags. Entries with several tags are listed
+    /// under each of them.
+    Tag,
+    /// Group entries by the directory containing their remote file.
+    Dir,
+}
+
+impl GroupBy {
+    /// The names of the available grouping keys.
+    pub const NAMES: &'static [&'static str] = &["tag", "dir"];
+
+    /// Returns the names of the groups the status of an [`Entry`] belongs to.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    fn groups(&self, entry: &Entry, common: &CommonOptions) -> Vec<String> {
+        match self {
+            GroupBy::Tag if entry.tags.is_empty() => vec!["untagged".into()],
+            GroupBy::Tag => entry.tags.clone(),
+            GroupBy::Dir => {
+                let path = Location::remote(entry).display_path();
+                let dir = path.parent().unwrap_or(&path);
+                vec![format_path(dir, common).display().to_string()]
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tag" => Ok(GroupBy::Tag),
+            "dir" => Ok(GroupBy::Dir),
+            _     => Err(format!("invalid grouping key: {}", s)),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EntryStatus
+////////////////////////////////////////////////////////////////////////////////
+/// The status of an [`Entry`], as it would be collected.
+///
+/// [`Entry`]: ../struct.Entry.html
+#[derive(Debug, Clone)]
+pub struct EntryStatus<'e> {
+    /// The entry.
+    pub entry: &'e Entry,
+    /// The state of the entry.
+    pub state: State,
+    /// The action which would be taken to collect the entry.
+    pub action: Action,
+    /// The time at which the status was computed, or `None` if it is unknown.
+    pub checked: Option<SystemTime>,
+    /// The error encountered the last time the entry failed to be processed,
+    /// if it has not been processed successfully since.
+    pub last_error: Option<LastError>,
+    /// The mode of the remote file, if it differs from the mode set by the
+    /// entry. Modes are not checked for cached statuses.
+    pub mode_drift: Option<ModeDrift>,
+    /// The time the entry was last copied or found to be in sync by a
+    /// collect or distribute, or `None` if it never has been.
+    pub last_synced: Option<SystemTime>,
+}
+
+impl<'e> EntryStatus<'e> {
+    /// Returns how long ago the status was computed, or `None` if it is
+    /// unknown.
+    pub fn age(&self) -> Option<Duration> {
+        self.checked.map(|checked| SystemTime::now()
+            .duration_since(checked)
+            .unwrap_or_default())
+    }
+
+    /// Returns true if the entry is enabled, has an `expect_sync` cadence,
+    /// and has not been synced within it.
+    pub fn is_overdue(&self) -> bool {
+        match (self.entry.expect_sync, self.last_synced) {
+            _ if !self.entry.enabled => false,
+            (None, _)       => false,
+            (Some(_), None) => true,
+            (Some(cadence), Some(synced)) => SystemTime::now()
+                .duration_since(synced)
+                .is_ok_and(|age| age > cadence.0),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// entry_status
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the status of each of the given [`Entry`]s, in the order they are
+/// given.
+///
+/// Disabled entries are reported with the `Disabled` state, and their files
+/// are not compared.
+///
+/// With [`StatusMode::Cached`], no files other than the state file are read,
+/// making this suitable for shell prompts and completions. Entries whose
+/// status has never been computed are reported with the `Unknown` state. The
+/// [`EntryStatus::age`] of each result indicates how stale it may be.
+///
+/// With [`StatusMode::Accurate`], the files are compared and the results are
+/// recorded in the state file, unless the `--dry-run` option is given. If the
+/// stall file enables `fleet`, they are also recorded in the status file of
+/// the current host.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`StatusMode::Cached`]: enum.StatusMode.html#variant.Cached
+/// [`StatusMode::Accurate`]: enum.StatusMode.html#variant.Accurate
+/// [`EntryStatus::age`]: struct.EntryStatus.html#method.age
+pub fn entry_status<'e, P, I>(
+    stall_dir: P,
+    files: I,
+    mode: StatusMode,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<Vec<EntryStatus<'e>>, Error>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'e Entry>,
+{
+    let store = StallStore::new(stall_dir);
+    let mut sync_state = SyncState::load_from(&store.state_path())?;
+
+    if mode == StatusMode::Cached {
+        return Ok(files.into_iter()
+            .map(|entry| match sync_state.cached_status(entry) {
+                _ if !entry.enabled => disabled_status(entry, &sync_state),
+                Some(cached) => EntryStatus {
+                    entry,
+                    state: cached.state,
+                    action: cached.action,
+                    checked: Some(cached.checked),
+                    last_error: sync_state.last_error(entry).cloned(),
+                    mode_drift: None,
+                    last_synced: sync_state.last_synced(entry),
+                },
+                None => EntryStatus {
+                    entry,
+                    state: State::Unknown,
+                    action: Action::Skip,
+                    checked: None,
+                    last_error: sync_state.last_error(entry).cloned(),
+                    mode_drift: None,
+                    last_synced: sync_state.last_synced(entry),
+                },
+            })
+            .collect());
+    }
+
+    let mut statuses = Vec::new();
+    for entry in files {
+        if !entry.enabled {
+            statuses.push(disabled_status(entry, &sync_state));
+            continue;
+        }
+        let file_name = entry.file_name().ok_or(InvalidFile)?;
+        let source = Location::remote(entry);
+        let target = store.local(file_name);
+        let synced = sync_state.get(entry);
+        let item = PlanItem::new(entry, source, target, synced, common,
+            context)?;
+        let checked = SystemTime::now();
+
+        sync_state.cache_status(entry, CachedStatus {
+            state: item.state,
+            action: item.action,
+            checked,
+        });
+        statuses.push(EntryStatus {
+            entry,
+            state: item.state,
+            action: item.action,
+            checked: Some(checked),
+            last_error: sync_state.last_error(entry).cloned(),
+            mode_drift: mode_drift(entry)?,
+            last_synced: sync_state.last_synced(entry),
+        });
+    }
+
+    if !common.dry_run {
+        sync_state.save_to(&store.state_path())?;
+        if context.fleet {
+            publish_host_status(&store, &statuses)?;
+        }
+    }
+    Ok(statuses)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// status
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall status' command.
+///
+/// This will determine the state of each file and the action which would be
+/// taken by the 'stall collect' command, without copying any files. Nothing
+/// is printed; the statuses are rendered with [`print_status`].
+///
+/// ### Command line options
+///
+/// The `--cached` option will return the status recorded by the last status
+/// check instead of comparing the files.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' to report the status of.
+/// + `files`: An iterator over the [`Entry`]s of the files to check.
+/// + `mode`: The [`StatusMode`] to use.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the state file can't be read or written, or if
+/// the metadata of a file can't be read.
+///
+/// [`print_status`]: fn.print_status.html
+/// [`Entry`]: ../struct.Entry.html
+/// [`StatusMode`]: enum.StatusMode.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn status<'i, P, I>(
+    stall_dir: P,
+    files: I,
+    mode: StatusMode,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<Vec<EntryStatus<'i>>, StallError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item=&'i Entry>,
+{
+    Ok(entry_status(stall_dir, files, mode, common, context)?)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// print_status
+////////////////////////////////////////////////////////////////////////////////
+/// Prints the statuses returned by the 'stall status' command.
+///
+/// ### Command line options
+///
+/// The `--group-by` option will print the entries under a header for each tag
+/// or directory, followed by a count of each state in the group.
+///
+/// The `--verbose`, `--quiet`, `--xtrace`, `--short-names`, `--absolute`, and
+/// `--icons` options will change which outputs are produced.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' the status is of.
+/// + `statuses`: The [`EntryStatus`] of each entry.
+/// + `mode`: The [`StatusMode`] the statuses were determined with.
+/// + `group_by`: The [`GroupBy`] key to group the entries by, if any.
+/// + `common`: The [`CommonOptions`] to use for the command.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// [`EntryStatus`]: struct.EntryStatus.html
+/// [`StatusMode`]: enum.StatusMode.html
+/// [`GroupBy`]: enum.GroupBy.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+pub fn print_status(
+    stall_dir: &Path,
+    statuses: &[EntryStatus<'_>],
+    mode: StatusMode,
+    group_by: Option<GroupBy>,
+    common: &CommonOptions,
+    context: &RunContext)
+{
+    info!("{}{} {}",
+        indent(context),
+        paint("label", "Stall directory:"),
+        format_path(stall_dir, common).display());
+
+    print_status_header(common, context);
+    match group_by {
+        Some(group_by) => {
+            let mut groups: BTreeMap<String, Vec<&EntryStatus<'_>>>
+                = BTreeMap::new();
+            for status in statuses {
+                for group in group_by.groups(status.entry, common) {
+                    groups.entry(group).or_default().push(status);
+                }
+            }
+            for (group, statuses) in groups {
+                info!("{}{}",
+                    indent(context),
+                    paint("label", &format!("{}:", group)));
+                print_status_lines(statuses.iter().copied(), common,
+                    context);
+                print_subtotal(&statuses, context);
+            }
+        },
+        None => print_status_lines(statuses, common, context),
+    }
+    print_status_legend(common, context);
+
+    if mode == StatusMode::Cached {
+        match statuses.iter().filter_map(EntryStatus::age).max() {
+            Some(age) => info!("{}Status last checked {} seconds ago.",
+                indent(context),
+                age.as_secs()),
+            None => info!("{}Status has not been checked. Run 'stall status' \
+                without --cached to check it.",
+                indent(context)),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// StatusTotals
+////////////////////////////////////////////////////////////////////////////////
+/// The number of entries with each state across the stalls reported by a
+/// recursive 'stall status' command.
+#[derive(Debug, Clone, Default)]
+pub struct StatusTotals {
+    /// The number of stalls reported.
+    pub stalls: usize,
+    /// The number of entries with each state, in the order the states were
+    /// first reported.
+    pub counts: Vec<(State, usize)>,
+}
+
+impl StatusTotals {
+    /// Constructs a new, empty `StatusTotals`.
+    pub fn new() -> Self {
+        StatusTotals::default()
+    }
+
+    /// Adds the statuses of the entries of a stall to the totals.
+    pub fn add(&mut self, statuses: &[EntryStatus<'_>]) {
+        self.stalls += 1;
+        for status in statuses {
+            count_state(&mut self.counts, status.state);
+        }
+    }
+
+    /// Returns the total number of entries.
+    pub fn entries(&self) -> usize {
+        self.counts.iter().map(|(_, count)| count).sum()
+    }
+
+    /// Prints the totals.
+    pub fn print(&self) {
+        let entries = self.entries();
+        info!("{} {} {} in {} {}{}{}",
+            paint("label", "Total:"),
+            entries,
+            if entries == 1 { "entry" } else { "entries" },
+            self.stalls,
+            if self.stalls == 1 { "stall" } else { "stalls" },
+            if entries == 0 { "" } else { ": " },
+            format_counts(&self.counts));
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the status of a disabled entry, which is never compared.
+fn disabled_status<'e>(entry: &'e Entry, sync_state: &SyncState)
+    -> EntryStatus<'e>
+{
+    EntryStatus {
+        entry,
+        state: State::Disabled,
+        action: Action::Skip,
+        checked: None,
+        last_error: sync_state.last_error(entry).cloned(),
+        mode_drift: None,
+        last_synced: sync_state.last_synced(entry),
+    }
+}
+
+/// Prints the status line for each entry, followed by its description, its
+/// last error, its mode drift, and a warning if it is overdue to be synced.
+fn print_status_lines<'s, 'e: 's, I>(
+    statuses: I,
+    common: &CommonOptions,
+    context: &RunContext)
+    where I: IntoIterator<Item=&'s EntryStatus<'e>>
+{
+    for status in statuses {
+        let path = Location::remote(status.entry).display_path();
+        print_status_line(status.state, status.action, &path, common,
+            context);
+        if let Some(description) = &status.entry.description {
+            info!("{}        {}",
+                indent(context),
+                paint("description", description));
+        }
+        if let Some(error) = &status.last_error {
+            let age = SystemTime::now()
+                .duration_since(error.time)
+                .unwrap_or_default();
+            info!("{}        {} {}",
+                indent(context),
+                paint("error", &format!("failed {} seconds ago:",
+                    age.as_secs())),
+                error.message);
+        }
+        if let Some(drift) = &status.mode_drift {
+            info!("{}        {}",
+                indent(context),
+                paint("drift", &format!("mode is {}, expected {}",
+                    drift.actual,
+                    drift.expected)));
+        }
+        let overdue = match (status.entry.expect_sync, status.last_synced) {
+            _ if !status.is_overdue() => None,
+            (Some(cadence), Some(synced)) => {
+                let age = SystemTime::now()
+                    .duration_since(synced)
+                    .unwrap_or_default()
+                    .as_secs();
+                Some(format!("last synced {} ago, expected every {}",
+                    match age {
+                        0..=86_399 => format!("{}h", age / 3_600),
+                        _          => format!("{}d", age / 86_400),
+                    },
+                    cadence))
+            },
+            (Some(cadence), None) => Some(format!("never synced, expected \
+                every {}", cadence)),
+            (None, _) => None,
+        };
+        if let Some(message) = overdue {
+            info!("{}        {}", indent(context), paint("drift", &message));
+        }
+    }
+}
+
+/// Prints the number of entries in a group with each state.
+fn print_subtotal(statuses: &[&EntryStatus<'_>], context: &RunContext) {
+    let mut counts: Vec<(State, usize)> = Vec::new();
+    for status in statuses {
+        count_state(&mut counts, status.state);
+    }
+    info!("{}    {} {}: {}",
+        indent(context),
+        statuses.len(),
+        if statuses.len() == 1 { "entry" } else { "entries" },
+        format_counts(&counts));
+}
+
+/// Adds an entry with the given state to the counts of each state.
+fn count_state(counts: &mut Vec<(State, usize)>, state: State) {
+    match counts.iter_mut().find(|(counted, _)| *counted == state) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((state, 1)),
+    }
+}
+
+/// Formats the counts of each state as a comma separated list.
+fn format_counts(counts: &[(State, usize)]) -> String {
+    counts.iter()
+        .map(|(state, count)| format!("{} {}", count, state.label().trim_end()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
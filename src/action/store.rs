@@ -0,0 +1,178 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Stall storage.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::DEFAULT_HOSTS_PATH;
+use crate::action::DEFAULT_SNAPSHOTS_PATH;
+use crate::action::KNOWN_GOOD_PATH;
+use crate::action::Location;
+use crate::Config;
+use crate::DEFAULT_CONFIG_PATH;
+use crate::error::Error;
+use crate::lock::DEFAULT_LOCK_PATH;
+use crate::state::DEFAULT_STATE_PATH;
+
+// Standard library imports.
+use std::path::Path;
+use std::path::PathBuf;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// StallStore
+////////////////////////////////////////////////////////////////////////////////
+/// The storage holding the stall-local copies of the stall entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StallStore {
+    /// A stall directory.
+    Dir(PathBuf),
+    /// A single-file stall: an archive holding the stall file and the entries.
+    ///
+    /// The state and lock files are specific to the host, so they are kept
+    /// alongside the archive rather than within it.
+    Archive(PathBuf),
+}
+
+impl StallStore {
+    /// Returns the `StallStore` at the given path. Existing files are treated
+    /// as single-file stalls.
+    pub fn new<P>(path: P) -> Self
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        match path.is_file() {
+            true  => StallStore::Archive(path.to_path_buf()),
+            false => StallStore::Dir(path.to_path_buf()),
+        }
+    }
+
+    /// Returns the path of the stall directory or archive.
+    pub fn path(&self) -> &Path {
+        match self {
+            StallStore::Dir(path)     |
+            StallStore::Archive(path) => path,
+        }
+    }
+
+    /// Returns true if the store is a single-file stall.
+    pub fn is_archive(&self) -> bool {
+        matches!(self, StallStore::Archive(_))
+    }
+
+    /// Returns the directory relative paths in the stall file are resolved
+    /// against.
+    pub fn base_dir(&self) -> PathBuf {
+        match self {
+            StallStore::Dir(dir)         => dir.clone(),
+            StallStore::Archive(archive) => archive.parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the [`Location`] of the stall-local copy of the file with the
+    /// given name.
+    ///
+    /// [`Location`]: enum.Location.html
+    pub fn local<P>(&self, file_name: P) -> Location
+        where P: AsRef<Path>
+    {
+        let file_name = file_name.as_ref();
+        match self {
+            StallStore::Dir(dir) => Location::file(dir.join(file_name)),
+            StallStore::Archive(archive) => Location::Archived {
+                archive: archive.clone(),
+                member: file_name.to_path_buf(),
+            },
+        }
+    }
+
+    /// Returns the path of the state file.
+    pub fn state_path(&self) -> PathBuf {
+        match self {
+            StallStore::Dir(dir) => dir.join(DEFAULT_STATE_PATH),
+            StallStore::Archive(archive) => sidecar(
+                archive,
+                DEFAULT_STATE_PATH),
+        }
+    }
+
+    /// Returns the path of the lock file.
+    pub fn lock_path(&self) -> PathBuf {
+        match self {
+            StallStore::Dir(dir) => dir.join(DEFAULT_LOCK_PATH),
+            StallStore::Archive(archive) => sidecar(
+                archive,
+                DEFAULT_LOCK_PATH),
+        }
+    }
+
+    /// Returns the path of the directory of host status files.
+    pub fn hosts_dir(&self) -> PathBuf {
+        match self {
+            StallStore::Dir(dir) => dir.join(DEFAULT_HOSTS_PATH),
+            StallStore::Archive(archive) => sidecar(
+                archive,
+                DEFAULT_HOSTS_PATH),
+        }
+    }
+
+    /// Returns the path of the directory of snapshots.
+    pub fn snapshots_dir(&self) -> PathBuf {
+        match self {
+            StallStore::Dir(dir) => dir.join(DEFAULT_SNAPSHOTS_PATH),
+            StallStore::Archive(archive) => sidecar(
+                archive,
+                DEFAULT_SNAPSHOTS_PATH),
+        }
+    }
+
+    /// Returns the path of the directory holding the last known good version
+    /// of each entry, within the directory of snapshots.
+    pub fn known_good_dir(&self) -> PathBuf {
+        self.snapshots_dir().join(KNOWN_GOOD_PATH)
+    }
+
+    /// Reads the stall file of the store.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the stall file can't be read or parsed, or a
+    /// [`FeatureDisabled`] error for single-file stalls if the `archive`
+    /// feature is not enabled.
+    ///
+    /// [`Error`]: ../error/struct.Error.html
+    /// [`FeatureDisabled`]: ../error/struct.FeatureDisabled.html
+    pub fn read_config(&self) -> Result<Config, Error> {
+        match self {
+            StallStore::Dir(dir) => Config::from_path(
+                dir.join(DEFAULT_CONFIG_PATH)),
+            StallStore::Archive(archive) => {
+                let bytes = archive::read_member(
+                    archive,
+                    Path::new(DEFAULT_CONFIG_PATH))?;
+                Config::from_bytes(&bytes)
+            },
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the path alongside `archive` for a file that would be named
+/// `file_name` in a stall directory. (`.stall-state` becomes
+/// `archive.zip.stall-state`.)
+fn sidecar(archive: &Path, file_name: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_os_string();
+    path.push(file_name);
+    path.into()
+}
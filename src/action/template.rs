@@ -0,0 +1,502 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Template rendering.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::archive;
+use crate::action::CopyMethod;
+use crate::action::Location;
+use crate::action::StallStore;
+#[cfg(feature = "templates")]
+use crate::action::walk;
+#[cfg(feature = "templates")]
+use crate::action::WalkLimits;
+use crate::Entry;
+use crate::error::Context;
+use crate::error::Error;
+#[cfg(not(feature = "templates"))]
+use crate::error::FeatureDisabled;
+use crate::error::InvalidFile;
+#[cfg(feature = "templates")]
+use crate::error::MissingSecret;
+use crate::error::StallError;
+use crate::error::UndefinedVariable;
+use crate::Os;
+use crate::RunContext;
+use crate::TemplateEngine;
+
+// External library imports.
+#[cfg(feature = "templates")]
+use handlebars::Handlebars;
+#[cfg(feature = "templates")]
+use handlebars::Helper;
+#[cfg(feature = "templates")]
+use handlebars::HelperResult;
+#[cfg(feature = "templates")]
+use handlebars::Output;
+#[cfg(feature = "templates")]
+use handlebars::RenderContext;
+#[cfg(feature = "templates")]
+use handlebars::RenderError;
+use log::*;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SECRET_SERVICE
+////////////////////////////////////////////////////////////////////////////////
+/// The keyring service name under which template secrets are stored.
+pub const SECRET_SERVICE: &str = "stall";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TemplateOptions
+////////////////////////////////////////////////////////////////////////////////
+/// Options for rendering template entries, loaded from the stall file.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateOptions {
+    /// The engine used to render templates.
+    pub engine: TemplateEngine,
+    /// The variables to substitute into templates.
+    pub variables: BTreeMap<String, String>,
+    /// A directory of templates which may be included by other templates.
+    pub includes: Option<PathBuf>,
+    /// The operating system to render templates for. Default is the current
+    /// operating system.
+    pub os: Option<Os>,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// render_template
+////////////////////////////////////////////////////////////////////////////////
+/// Renders the template file at `source` into `target`, using the template
+/// options of the [`RunContext`].
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if either location is not a regular file, if the
+/// template can't be read or written, or if it refers to an undefined
+/// variable.
+///
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn render_template(
+    source: &Location,
+    target: &Location,
+    method: CopyMethod,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    let (source, target) = match (source, target) {
+        (Location::File(source), Location::File(target))
+            if !source.is_dir() && !target.is_dir() => (source, target),
+        _ => return Err(Error::msg(format!("unable to render template {}: \
+            templates must be regular files", source))),
+    };
+
+    let rendered = render_file(source, &context.templates)?;
+
+    if method == CopyMethod::None {
+        trace!("no-run flag was specified: Not rendering {:?} to {:?}",
+            source, target);
+        return Ok(());
+    }
+    trace!("Rendering {:?} to {:?}", source, target);
+    std::fs::write(target, rendered)
+        .with_context(|| format!("Unable to write {:?}", target))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// render_file
+////////////////////////////////////////////////////////////////////////////////
+/// Renders the template file at `source` with the given [`TemplateOptions`],
+/// returning the rendered text.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the template can't be read, or if it fails to
+/// render.
+///
+/// [`TemplateOptions`]: struct.TemplateOptions.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn render_file(source: &Path, options: &TemplateOptions)
+    -> Result<String, Error>
+{
+    let text = std::fs::read_to_string(source)
+        .with_context(|| format!("Unable to read template {:?}", source))?;
+    render_text(&text, options)
+        .with_context(|| format!("Unable to render template {:?}", source))
+}
+
+/// Renders `template` with the engine of the given [`TemplateOptions`].
+///
+/// [`TemplateOptions`]: struct.TemplateOptions.html
+fn render_text(template: &str, options: &TemplateOptions)
+    -> Result<String, Error>
+{
+    match options.engine {
+        TemplateEngine::Simple => render(template, &options.variables),
+        TemplateEngine::Handlebars => render_handlebars(template, options),
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// render_entry
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall render' command.
+///
+/// This will render the stall copy of a template [`Entry`] with the template
+/// options of the [`RunContext`], returning the rendered text without
+/// writing it anywhere.
+///
+/// ### Parameters
+/// + `stall_dir`: The 'stall directory' holding the template.
+/// + `entry`: The [`Entry`] to render.
+/// + `vars_file`: A RON file of variables, which override the stall file's
+///   variables of the same name.
+/// + `context`: The [`RunContext`] of the stall.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if the entry is not a template, if the template or
+/// variables can't be read, or if the template refers to an undefined
+/// variable.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn render_entry(
+    stall_dir: &Path,
+    entry: &Entry,
+    vars_file: Option<&Path>,
+    context: &RunContext)
+    -> Result<String, StallError>
+{
+    if !entry.template {
+        return Err(Error::msg(format!("entry {} is not a template.",
+            entry.path.display())).into());
+    }
+
+    let mut options = context.templates.clone();
+    if let Some(vars_file) = vars_file {
+        let text = std::fs::read_to_string(vars_file)
+            .with_context(|| format!("Unable to read variables file {:?}",
+                vars_file))?;
+        let variables: BTreeMap<String, String> = ron::de::from_str(&text)
+            .with_context(|| format!("Unable to parse variables file {:?}",
+                vars_file))?;
+        options.variables.extend(variables);
+    }
+
+    let file_name = entry.file_name().ok_or(InvalidFile)?;
+    let source = StallStore::new(stall_dir).local(file_name);
+    let text = read_template(&source)?;
+    Ok(render_text(&text, &options)
+        .with_context(|| format!("Unable to render template {}", source))?)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// render
+////////////////////////////////////////////////////////////////////////////////
+/// Substitutes each `{{ name }}` placeholder in `template` with the value of
+/// the named variable. Whitespace around the name is ignored.
+///
+/// Placeholders of the form `{{ secret "name" }}` are substituted with the
+/// named secret from the system keyring.
+///
+/// ### Errors
+///
+/// Returns an [`UndefinedVariable`] error if a placeholder names a variable
+/// which is not defined, a [`MissingSecret`] error if a secret is not in the
+/// keyring, or an [`Error`] if a placeholder is malformed.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::action::render;
+/// # use std::collections::BTreeMap;
+/// let mut variables = BTreeMap::new();
+/// let _ = variables.insert("email".to_string(), "me@example.com".to_string());
+///
+/// let rendered = render("email = {{ email }}", &variables).unwrap();
+/// assert_eq!(rendered, "email = me@example.com");
+///
+/// assert!(render("name = {{ name }}", &variables).is_err());
+/// ```
+///
+/// [`UndefinedVariable`]: ../error/struct.UndefinedVariable.html
+/// [`MissingSecret`]: ../error/struct.MissingSecret.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn render(template: &str, variables: &BTreeMap<String, String>)
+    -> Result<String, Error>
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}")
+            .ok_or_else(|| Error::msg("unclosed template placeholder"))?;
+        let name = after[..end].trim();
+        match name.strip_prefix("secret ") {
+            Some(secret) => {
+                let secret = secret.trim()
+                    .strip_prefix('"')
+                    .and_then(|secret| secret.strip_suffix('"'))
+                    .ok_or_else(|| Error::msg(format!("invalid secret \
+                        placeholder: {{{{ {} }}}}", name)))?;
+                rendered.push_str(&lookup_secret(secret)?);
+            },
+            None => rendered.push_str(variables.get(name)
+                .ok_or_else(|| UndefinedVariable { name: name.into() })?),
+        }
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// template_variables
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the names of the variables referred to by `template`, in the order
+/// they first appear.
+///
+/// Secrets, partials, and the helpers and block expressions of Handlebars
+/// templates are not variables, and are not returned.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::action::template_variables;
+/// let template = "{{ name }} <{{email}}>{{#if work}} {{ name }}{{/if}}";
+///
+/// assert_eq!(template_variables(template), vec!["name", "email"]);
+/// ```
+pub fn template_variables(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let after = &rest[start + 2..];
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None      => break,
+        };
+        let name = after[..end].trim_matches(|c: char| c == '{' || c == '~')
+            .trim();
+        let is_variable = !name.is_empty()
+            && name != "else"
+            && name != "this"
+            && name.chars().all(|c| c.is_alphanumeric()
+                || c == '_'
+                || c == '-'
+                || c == '.');
+        if is_variable && !names.iter().any(|known| known == name) {
+            names.push(name.into());
+        }
+        rest = &after[end + 2..];
+    }
+    names
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// undefined_variables
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the names of the variables referred to by the stall copies of the
+/// given template [`Entry`]s which are not defined in the
+/// [`TemplateOptions`]. Entries which are not templates are ignored.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if a template can't be read.
+///
+/// [`Entry`]: ../struct.Entry.html
+/// [`TemplateOptions`]: struct.TemplateOptions.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn undefined_variables<'i, I>(
+    stall_dir: &Path,
+    entries: I,
+    options: &TemplateOptions)
+    -> Result<Vec<String>, Error>
+    where I: IntoIterator<Item=&'i Entry>
+{
+    let store = StallStore::new(stall_dir);
+    let mut undefined: Vec<String> = Vec::new();
+    for entry in entries.into_iter().filter(|entry| entry.template) {
+        let file_name = entry.file_name().ok_or(InvalidFile)?;
+        let text = read_template(&store.local(file_name))?;
+        for name in template_variables(&text) {
+            // Handlebars templates are given the `os` variable.
+            let provided = name == "os"
+                && options.engine == TemplateEngine::Handlebars;
+            if !provided
+                && !options.variables.contains_key(&name)
+                && !undefined.contains(&name)
+            {
+                undefined.push(name);
+            }
+        }
+    }
+    Ok(undefined)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// render_handlebars
+////////////////////////////////////////////////////////////////////////////////
+/// Renders `template` with Handlebars, using the given [`TemplateOptions`].
+///
+/// Each file in the includes directory is available as a partial named by its
+/// path relative to that directory, such as `{{> shell/aliases}}`. In
+/// addition to the stall file's variables, the `os` variable is set to the
+/// operating system of the options, or the current operating system (`linux`,
+/// `macos`, or `windows`), unless the stall file defines it. The `secret`
+/// helper, as in `{{ secret "name" }}`, inserts the named secret from the
+/// system keyring. Output is not HTML-escaped, and undefined variables are
+/// reported as errors.
+///
+/// ### Errors
+///
+/// Returns an [`Error`] if an include can't be loaded or the template fails to
+/// render.
+///
+/// [`TemplateOptions`]: struct.TemplateOptions.html
+/// [`Error`]: ../error/struct.Error.html
+#[cfg(feature = "templates")]
+pub fn render_handlebars(template: &str, options: &TemplateOptions)
+    -> Result<String, Error>
+{
+    let mut registry = Handlebars::new();
+    registry.set_strict_mode(true);
+    registry.register_escape_fn(handlebars::no_escape);
+    registry.register_helper("secret", Box::new(secret_helper));
+
+    if let Some(includes) = &options.includes {
+        let limits = WalkLimits::unlimited();
+        walk::walk_files(includes, false, limits, |rel, _| {
+            let name = rel.to_string_lossy().replace('\\', "/");
+            trace!("Registering template include {:?}", name);
+            registry.register_template_file(&name, includes.join(rel))
+                .with_context(|| format!("Unable to load template include \
+                    {:?}", rel))
+        })?;
+    }
+
+    let mut data = serde_json::Map::new();
+    if let Some(os) = options.os.or_else(Os::current) {
+        let _ = data.insert("os".into(), serde_json::to_value(os)?);
+    }
+    for (name, value) in &options.variables {
+        let _ = data.insert(name.clone(), value.clone().into());
+    }
+
+    Ok(registry.render_template(template, &data)?)
+}
+
+/// Renders `template` with Handlebars. The Handlebars engine is not supported
+/// without the `templates` feature.
+#[cfg(not(feature = "templates"))]
+pub fn render_handlebars(_template: &str, _options: &TemplateOptions)
+    -> Result<String, Error>
+{
+    Err(FeatureDisabled {
+        feature: "templates",
+        operation: "the handlebars template engine",
+    }.into())
+}
+
+/// Handlebars helper which writes the secret named by its first parameter.
+#[cfg(feature = "templates")]
+fn secret_helper(
+    helper: &Helper<'_, '_>,
+    _: &Handlebars<'_>,
+    _: &handlebars::Context,
+    _: &mut RenderContext<'_, '_>,
+    out: &mut dyn Output)
+    -> HelperResult
+{
+    let name = helper.param(0)
+        .and_then(|param| param.value().as_str())
+        .ok_or_else(|| RenderError::new("secret helper requires a name"))?;
+    let secret = lookup_secret(name)
+        .map_err(|e| match e.downcast::<MissingSecret>() {
+            Ok(missing) => RenderError::from_error("secret", missing),
+            Err(e) => RenderError::new(format!("{:#}", e)),
+        })?;
+    out.write(&secret)?;
+    Ok(())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// lookup_secret
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the secret with the given name from the system keyring. Secrets are
+/// stored under the [`SECRET_SERVICE`] service, using the name as the user.
+///
+/// ### Errors
+///
+/// Returns a [`MissingSecret`] error if the secret is not in the keyring, or
+/// an [`Error`] if the keyring can't be accessed.
+///
+/// [`SECRET_SERVICE`]: constant.SECRET_SERVICE.html
+/// [`MissingSecret`]: ../error/struct.MissingSecret.html
+/// [`Error`]: ../error/struct.Error.html
+#[cfg(feature = "templates")]
+pub fn lookup_secret(name: &str) -> Result<String, Error> {
+    trace!("Looking up secret {:?}", name);
+    let entry = keyring::Entry::new(SECRET_SERVICE, name)
+        .with_context(|| format!("Unable to access keyring for secret {:?}",
+            name))?;
+    match entry.get_password() {
+        Ok(secret) => Ok(secret),
+        Err(keyring::Error::NoEntry) => Err(MissingSecret {
+            name: name.into(),
+        }.into()),
+        Err(e) => Err(e).with_context(|| format!("Unable to read secret {:?} \
+            from keyring", name)),
+    }
+}
+
+/// Returns the secret with the given name from the system keyring. Secrets
+/// are not supported without the `templates` feature.
+#[cfg(not(feature = "templates"))]
+pub fn lookup_secret(_name: &str) -> Result<String, Error> {
+    Err(FeatureDisabled {
+        feature: "templates",
+        operation: "looking up template secrets",
+    }.into())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Reads the text of the template at the given [`Location`].
+///
+/// [`Location`]: enum.Location.html
+fn read_template(source: &Location) -> Result<String, Error> {
+    match source {
+        Location::File(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read template {:?}", path)),
+        Location::Archived { archive, member } => String::from_utf8(
+                archive::read_member(archive, member)?)
+            .with_context(|| format!("Unable to read template {}", source)),
+    }
+}
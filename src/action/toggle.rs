@@ -0,0 +1,118 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Enabling and disabling of entries in the stall file.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::action::format_path;
+use crate::action::read_stall_file;
+use crate::action::write_stall_file;
+use crate::CommonOptions;
+use crate::entry_candidates;
+use crate::error::Error;
+use crate::error::StallError;
+use crate::error::UnknownEntry;
+use crate::normalize_key;
+use crate::theme::paint;
+
+// External library imports.
+use log::*;
+
+// Standard library imports.
+use std::path::Path;
+use std::path::PathBuf;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// set_enabled
+////////////////////////////////////////////////////////////////////////////////
+/// Executes the 'stall enable' and 'stall disable' commands.
+///
+/// This will set the `enabled` option of the entries matching the given
+/// paths in the stall file at `config_path`. Disabled entries are skipped by
+/// every command, but keep their options and recorded state, so they can be
+/// enabled again later. As with 'stall merge', the stall file is rewritten in
+/// the format it was read in, keeping the comments above its options and
+/// entries, but not its formatting.
+///
+/// ### Command line options
+///
+/// The `--dry-run` option will print the entries which would be changed
+/// without writing the stall file.
+///
+/// ### Parameters
+/// + `config_path`: The path of the stall file to change.
+/// + `entries`: The paths or file names of the entries to change.
+/// + `enabled`: Whether to enable or disable the entries.
+/// + `common`: The [`CommonOptions`] to use for the command.
+///
+/// ### Errors
+///
+/// Returns an [`UnknownEntry`] error if a path matches no entries, or an
+/// [`Error`] if the stall file can't be read or written.
+///
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`UnknownEntry`]: ../error/struct.UnknownEntry.html
+/// [`Error`]: ../error/struct.Error.html
+pub fn set_enabled(
+    config_path: &Path,
+    entries: &[PathBuf],
+    enabled: bool,
+    common: &CommonOptions)
+    -> Result<(), StallError>
+{
+    let (mut config, format) = read_stall_file(config_path)?;
+    let label = match enabled {
+        true  => "enable  ",
+        false => "disable ",
+    };
+
+    let mut changed = 0;
+    for path in entries {
+        let key = normalize_key(path);
+        let mut matched = false;
+        for entry in config.files.iter_mut()
+            .filter(|entry| *normalize_key(&entry.path) == *key
+                || entry.file_name() == Some(key.as_os_str()))
+        {
+            matched = true;
+            if entry.enabled == enabled {
+                debug!("Entry {:?} is already {}.",
+                    entry.path,
+                    if enabled { "enabled" } else { "disabled" });
+                continue;
+            }
+            entry.enabled = enabled;
+            changed += 1;
+            info!("    {} {}",
+                paint("label", label),
+                format_path(&entry.path, common).display());
+        }
+        if !matched {
+            return Err(Error::from(UnknownEntry {
+                path: path.clone().into(),
+                candidates: entry_candidates(&config.files),
+            }).into());
+        }
+    }
+
+    if !common.dry_run && changed > 0 {
+        write_stall_file(&config, config_path, format)?;
+    }
+    info!("{} {} {} in {}.",
+        paint("label", match (common.dry_run, enabled) {
+            (true, true)   => "Would enable",
+            (true, false)  => "Would disable",
+            (false, true)  => "Enabled",
+            (false, false) => "Disabled",
+        }),
+        changed,
+        if changed == 1 { "entry" } else { "entries" },
+        format_path(config_path, common).display());
+    Ok(())
+}
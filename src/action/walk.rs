@@ -0,0 +1,297 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licenced using the MIT or Apache 2 license.
+// See licence-mit.md and licence-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Directory traversal for directory entries.
+////////////////////////////////////////////////////////////////////////////////
+
+// Local imports.
+use crate::CommonOptions;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::WalkLimitExceeded;
+use crate::RunContext;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::fs::FileType;
+use std::fs::Metadata;
+use std::path::Path;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// WalkLimits
+////////////////////////////////////////////////////////////////////////////////
+/// Limits on the size of directory entries, loaded from the stall file.
+///
+/// Walking a directory which exceeds a limit fails immediately, so that an
+/// entry for a much larger directory than intended (such as the home directory
+/// itself) is reported before any files are copied:
+///
+/// ```ron
+/// limits: (
+///     max_depth: Some(8),
+///     max_files: None,
+/// ),
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WalkLimits {
+    /// The maximum depth of nested directories within a directory entry, or
+    /// `None` for no limit.
+    #[serde(default = "WalkLimits::default_max_depth")]
+    pub max_depth: Option<usize>,
+
+    /// The maximum number of files within a directory entry, or `None` for no
+    /// limit.
+    #[serde(default = "WalkLimits::default_max_files")]
+    pub max_files: Option<usize>,
+}
+
+impl WalkLimits {
+    /// The default maximum depth of nested directories.
+    pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+    /// The default maximum number of files.
+    pub const DEFAULT_MAX_FILES: usize = 100_000;
+
+    /// Returns `WalkLimits` with no limits.
+    pub fn unlimited() -> Self {
+        WalkLimits { max_depth: None, max_files: None }
+    }
+
+    /// Returns the default maximum depth of nested directories.
+    fn default_max_depth() -> Option<usize> {
+        Some(WalkLimits::DEFAULT_MAX_DEPTH)
+    }
+
+    /// Returns the default maximum number of files.
+    fn default_max_files() -> Option<usize> {
+        Some(WalkLimits::DEFAULT_MAX_FILES)
+    }
+}
+
+impl Default for WalkLimits {
+    fn default() -> Self {
+        WalkLimits {
+            max_depth: WalkLimits::default_max_depth(),
+            max_files: WalkLimits::default_max_files(),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// walk_files
+////////////////////////////////////////////////////////////////////////////////
+/// Calls `f` for each file found under the directory `root`, passing the path
+/// of the file relative to `root` along with its metadata.
+///
+/// Symbolic links to directories are not followed. If `one_file_system` is
+/// true, directories on a different device than `root` (i.e., mount points)
+/// are skipped.
+///
+/// ### Errors
+///
+/// Returns a [`WalkLimitExceeded`] error if the directory exceeds the given
+/// [`WalkLimits`].
+///
+/// [`WalkLimitExceeded`]: ../error/struct.WalkLimitExceeded.html
+/// [`WalkLimits`]: struct.WalkLimits.html
+pub(crate) fn walk_files<F>(
+    root: &Path,
+    one_file_system: bool,
+    limits: WalkLimits,
+    mut f: F)
+    -> Result<(), Error>
+    where F: FnMut(&Path, &Metadata) -> Result<(), Error>
+{
+    let mut walk = Walk {
+        root,
+        root_device: device_id(&root.metadata()
+            .with_context(|| "load directory metadata")?),
+        one_file_system,
+        limits,
+        files: 0,
+    };
+    walk.walk_dir(Path::new(""), 0, &mut f)
+}
+
+/// Calls `f` for each file found under the directory entry at `root`, using
+/// the `--one-file-system` option of the [`CommonOptions`] and the
+/// [`WalkLimits`] of the [`RunContext`].
+///
+/// [`WalkLimits`]: struct.WalkLimits.html
+/// [`CommonOptions`]: ../command/struct.CommonOptions.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+pub(crate) fn walk_entry_files<F>(
+    root: &Path,
+    common: &CommonOptions,
+    context: &RunContext,
+    f: F)
+    -> Result<(), Error>
+    where F: FnMut(&Path, &Metadata) -> Result<(), Error>
+{
+    walk_files(root, common.one_file_system, context.limits, f)
+}
+
+/// Returns an error if the directory entry at `root` exceeds the
+/// [`WalkLimits`] of the [`RunContext`].
+///
+/// [`WalkLimits`]: struct.WalkLimits.html
+/// [`RunContext`]: ../command/struct.RunContext.html
+pub(crate) fn check_limits(
+    root: &Path,
+    common: &CommonOptions,
+    context: &RunContext)
+    -> Result<(), Error>
+{
+    walk_entry_files(root, common, context, |_, _| Ok(()))
+}
+
+/// The state of a directory walk.
+struct Walk<'r> {
+    /// The directory being walked.
+    root: &'r Path,
+    /// The id of the device containing the root directory.
+    root_device: Option<u64>,
+    /// Whether to skip directories on other devices.
+    one_file_system: bool,
+    /// The limits on the walk.
+    limits: WalkLimits,
+    /// The number of files found so far.
+    files: usize,
+}
+
+impl Walk<'_> {
+    /// Counts a file found by the walk, failing if there are too many.
+    fn count_file(&mut self) -> Result<(), Error> {
+        self.files += 1;
+        match self.limits.max_files {
+            Some(max_files) if self.files > max_files => Err(
+                WalkLimitExceeded {
+                    path: self.root.into(),
+                    option: "max_files",
+                    limit: max_files,
+                }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Recursively walks the directory at `root.join(rel)`, which is nested
+    /// `depth` directories below the root.
+    fn walk_dir<F>(&mut self, rel: &Path, depth: usize, f: &mut F)
+        -> Result<(), Error>
+        where F: FnMut(&Path, &Metadata) -> Result<(), Error>
+    {
+        if let Some(max_depth) = self.limits.max_depth {
+            if depth > max_depth {
+                return Err(WalkLimitExceeded {
+                    path: self.root.into(),
+                    option: "max_depth",
+                    limit: max_depth,
+                }.into());
+            }
+        }
+
+        let dir = self.root.join(rel);
+        for dir_entry in dir.read_dir().with_context(|| "read directory")? {
+            let dir_entry = dir_entry
+                .with_context(|| "read directory entry")?;
+            let rel_path = rel.join(dir_entry.file_name());
+            let link_meta = dir_entry.metadata()
+                .with_context(|| "load directory entry metadata")?;
+
+            if link_meta.file_type().is_symlink() {
+                // Follow file links, but not directory links.
+                let meta = match dir_entry.path().metadata() {
+                    Ok(meta) => meta,
+                    Err(_)   => {
+                        debug!("Skipping broken link: {:?}", dir_entry.path());
+                        continue;
+                    },
+                };
+                if meta.is_dir() {
+                    debug!("Skipping directory link: {:?}", dir_entry.path());
+                } else {
+                    self.count_file()?;
+                    f(&rel_path, &meta)?;
+                }
+
+            } else if link_meta.is_dir() {
+                if self.one_file_system
+                    && device_id(&link_meta) != self.root_device
+                {
+                    debug!("Skipping directory on another file system: {:?}",
+                        dir_entry.path());
+                    continue;
+                }
+                self.walk_dir(&rel_path, depth + 1, f)?;
+
+            } else {
+                self.count_file()?;
+                f(&rel_path, &link_meta)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the id of the device containing the file with the given metadata.
+#[cfg(unix)]
+pub(crate) fn device_id(meta: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt as _;
+    Some(meta.dev())
+}
+
+/// Returns the id of the device containing the file with the given metadata.
+#[cfg(not(unix))]
+pub(crate) fn device_id(_meta: &Metadata) -> Option<u64> {
+    None
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// special_kind
+////////////////////////////////////////////////////////////////////////////////
+/// The description of a FIFO returned by [`special_kind`].
+///
+/// [`special_kind`]: fn.special_kind.html
+pub(crate) const FIFO: &str = "FIFO";
+
+/// Returns a description of the kind of special file with the given type, or
+/// `None` if it is a regular file, directory, or symbolic link. Special files
+/// such as FIFOs and sockets can't be copied by reading their contents.
+#[cfg(unix)]
+pub(crate) fn special_kind(file_type: &FileType) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt as _;
+    if file_type.is_fifo() {
+        Some(FIFO)
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else if file_type.is_char_device() {
+        Some("character device")
+    } else {
+        None
+    }
+}
+
+/// Returns a description of the kind of special file with the given type, or
+/// `None` if it is a regular file, directory, or symbolic link.
+#[cfg(not(unix))]
+pub(crate) fn special_kind(file_type: &FileType) -> Option<&'static str> {
+    match file_type.is_file() || file_type.is_dir() || file_type.is_symlink() {
+        true  => None,
+        false => Some("special file"),
+    }
+}
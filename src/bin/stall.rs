@@ -10,18 +10,50 @@
 
 // Local imports.
 use stall::action;
+use stall::action::CancelToken;
+use stall::action::Prompter;
+use stall::action::StatusMode;
+use stall::action::StatusTotals;
+use stall::action::StallStore;
+use stall::action::TerminalReporter;
 use stall::CommandOptions;
+use stall::CommonOptions;
+use stall::ConfigCommand;
+use stall::FleetCommand;
+use stall::MergePolicy;
 use stall::Config;
+use stall::Platform;
+use stall::registry::Registry;
+use stall::RegistryCommand;
+use stall::RunContext;
+use stall::SnapshotCommand;
 use stall::DEFAULT_CONFIG_PATH;
+use stall::Entry;
 use stall::error::Context;
 use stall::error::Error;
+use stall::error::ErrorReport;
+use stall::error::MissingConfig;
+use stall::error::StallError;
+use stall::error::ValidationFailed;
+use stall::lock::StallLock;
+use stall::logger::LevelSource;
 use stall::logger::Logger;
+use stall::logger::RecentEvent;
+use stall::logger::TraceFilter;
 
 // External library imports.
 use structopt::StructOpt;
 use log::*;
+use signal_hook::consts::SIGINT;
 pub use log::LevelFilter;
 
+// Standard library imports.
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::sync::OnceLock;
+
 
 ////////////////////////////////////////////////////////////////////////////////
 // main
@@ -30,7 +62,16 @@ pub use log::LevelFilter;
 pub fn main() {
     if let Err(err) = main_facade() {
         // Print errors to stderr and exit with error code.
-        eprintln!("{}", err);
+        eprintln!("{}", ErrorReport::new(&err));
+
+        // Print the recent log records for context.
+        let events = RecentEvent::failure_context();
+        if !events.is_empty() {
+            eprintln!("\nRecent events:");
+            for event in events {
+                eprintln!("    {}", event);
+            }
+        }
         std::process::exit(1);
     }
 }
@@ -42,34 +83,99 @@ pub fn main() {
 pub fn main_facade() -> Result<(), Error> {
     // Parse command line options.
     let opts = CommandOptions::from_args();
+    action::install_prompter(TerminalPrompter);
+
+    // Generate packaging metadata without loading a stall file.
+    if let CommandOptions::PackageMetadata { out, bin_dir, .. } = &opts {
+        action::package_metadata(out, bin_dir)?;
+        println!("Wrote package metadata to {}", out.display());
+        return Ok(());
+    }
 
     // Find the path for the config file.
     // We do this up front because current_dir might fail due to access
     // problems, and we only want to error out if we really need to use it.
-    let stall_dir = opts.stall_dir()?;
-    let config_path = match &opts.common().use_config {
-        Some(path) => path.clone(),
-        None       => stall_dir.join(DEFAULT_CONFIG_PATH),
+    let common = opts.common();
+    let stall_dir = match &common.stall_name {
+        Some(name) => Registry::load()?.stall_dir(name)?.to_path_buf(),
+        None       => opts.stall_dir()?,
+    };
+    let (config_path, overlays) = match (&common.use_config,
+        common.stall_files.split_first())
+    {
+        (Some(path), _)            => (path.clone(), Vec::new()),
+        (None, Some((path, rest))) => (path.clone(), rest.to_vec()),
+        (None, None) => (stall_dir.join(DEFAULT_CONFIG_PATH), Vec::new()),
     };
+    let rewriting = matches!(opts,
+        CommandOptions::Merge { .. } |
+        CommandOptions::Disable { .. } |
+        CommandOptions::Enable { .. } |
+        CommandOptions::Migrate { .. });
+    if rewriting && !overlays.is_empty() {
+        return Err(Error::msg(format!("unable to rewrite {} stall files at \
+            once: give a single --stall file", overlays.len() + 1)));
+    }
 
-    // Load the config file.
-    let mut config = Config::from_path(&config_path)
-        .with_context(|| format!("Unable to load config file: {:?}",
-            config_path))?;
-    config.normalize_paths(&stall_dir);
+    // Load the config file. An import or bootstrap may provide the stall
+    // file, so the default config is used for them if it doesn't exist yet.
+    // The registry commands don't use the stall file. Single-file stalls keep
+    // the stall file within the archive.
+    let importing = matches!(opts,
+        CommandOptions::Import { .. } | CommandOptions::Bootstrap { .. });
+    let registry = matches!(opts, CommandOptions::Registry { .. });
+    let store = StallStore::new(&stall_dir);
+    let config = match &store {
+        StallStore::Archive(archive) if common.use_config.is_none()
+            && common.stall_files.is_empty() => {
+            let config = store.read_config()
+                .with_context(|| format!("Unable to load config file from \
+                    {:?}", archive))?;
+            prepare_config(config, &store.base_dir())?
+        },
+        _ if registry => Config::default(),
+        _ if importing && !config_path.exists() => {
+            std::fs::create_dir_all(&stall_dir)
+                .with_context(|| format!("Unable to create stall directory \
+                    {:?}", stall_dir))?;
+            Config::default()
+        },
+        _ => load_overlaid_config(&config_path, &overlays, &stall_dir)?,
+    };
+    config.theme.clone().install();
 
-    // Setup and start the global logger.
-    let mut logger =  Logger::from_config(config.logger_config.clone());
+    // Determine the log levels. Command line options take precedence over
+    // environment variables, which take precedence over the config file.
+    let mut trace_filter = TraceFilter::new();
+    trace_filter.set("stall", LevelFilter::Info, LevelSource::Default);
     for (context, level) in &config.log_levels {
-        logger = logger.level_for(context.clone(), *level);
+        trace_filter.set(context.clone(), *level, LevelSource::Config);
     }
-    let common = opts.common();
-    match (common.verbose, common.quiet, common.trace) {
-        (_, _, true) => logger.level_for("stall", LevelFilter::Trace).start(),
-        (_, true, _) => (),
-        (true, _, _) => logger.level_for("stall", LevelFilter::Debug).start(),
-        _            => logger.level_for("stall", LevelFilter::Info).start(),
+    if config.logger_config.allow_env_override {
+        trace_filter.apply_env();
     }
+    let level = match (common.verbose, common.quiet, common.trace) {
+        (_, _, true) => Some(LevelFilter::Trace),
+        (_, true, _) => Some(LevelFilter::Off),
+        (true, _, _) => Some(LevelFilter::Debug),
+        _            => None,
+    };
+    if let Some(level) = level {
+        trace_filter.set_all("stall", level, LevelSource::CommandLine);
+    }
+    for preset in &common.debug {
+        for target in preset.targets() {
+            trace_filter.set_all(*target, LevelFilter::Trace,
+                LevelSource::CommandLine);
+        }
+    }
+
+    // Setup and start the global logger.
+    let mut logger_config = config.logger_config.clone();
+    logger_config.color_mode = Some(common.color);
+    Logger::from_config(logger_config)
+        .trace_filter(&trace_filter)
+        .start();
 
     // Print version information.
     debug!("Stall version: {}", env!("CARGO_PKG_VERSION"));
@@ -78,20 +184,641 @@ pub fn main_facade() -> Result<(), Error> {
     if let Some(hash) = rustc_meta.commit_hash {
         trace!("Rustc git commit: {}", hash);
     }
+    let features: Vec<_> = stall::FEATURES.iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    debug!("Enabled features: {}", match features.is_empty() {
+        true  => "none".into(),
+        false => features.join(", "),
+    });
     trace!("Options: {:?}", opts);
     trace!("Config: {:?}", config); 
+    debug!("Effective trace filter:\n{}", trace_filter);
+
+    // Commands which don't modify the stall directory.
+    if let CommandOptions::Config { command, .. } = &opts {
+        match command {
+            ConfigCommand::ShowEffectiveTrace { .. } => {
+                print!("{}", trace_filter);
+            },
+        }
+        return Ok(());
+    }
+
+    // Lock the stall directory for the duration of the command. Bootstrap
+    // locks it once the stall is fetched, as git only clones into empty
+    // directories.
+    let bootstrapping = matches!(opts, CommandOptions::Bootstrap { .. });
+    let lock = !opts.is_read_only() && !bootstrapping;
+    let _lock = match lock {
+        true  => Some(StallLock::acquire_file(&store.lock_path(),
+            common.break_lock)?),
+        false => None,
+    };
 
     // Dispatch to appropriate commands.
-    use CommandOptions::*;
     match opts {
-        Collect { common, .. } => action::collect(
-            stall_dir,
-            config.files.iter().map(|p| &**p),
-            common),
-
-        Distribute { common, .. } => action::distribute(
-            stall_dir,
-            config.files.iter().map(|p| &**p),
-            common),
+        CommandOptions::Collect { common, entries, recurse_stalls, .. } => {
+            let mut walk = StallWalk::new(&stall_dir, recurse_stalls, lock)?
+                .with_cancel(interrupt_token());
+            walk.run(&stall_dir, &config, &entries, &common,
+                &mut |stall_dir, files, common, context| {
+                    action::auto_snapshot(stall_dir, "collect",
+                        files.iter().copied(), &common, &context)?;
+                    let mut reporter = TerminalReporter::new(&common,
+                        &context);
+                    let report = action::collect(stall_dir, files,
+                            &mut reporter, common.clone(), context.clone())
+                        .map_err(StallError::into_error)?;
+                    action::print_copy_summary(&report.stats, &common,
+                        &context);
+                    Ok(())
+                })
+        },
+
+        CommandOptions::Distribute { common, entries, recurse_stalls, .. }
+            => {
+            let mut walk = StallWalk::new(&stall_dir, recurse_stalls, lock)?
+                .with_cancel(interrupt_token());
+            walk.run(&stall_dir, &config, &entries, &common,
+                &mut |stall_dir, files, common, context| {
+                    action::auto_snapshot(stall_dir, "distribute",
+                        files.iter().copied(), &common, &context)?;
+                    let mut reporter = TerminalReporter::new(&common,
+                        &context);
+                    let report = action::distribute(stall_dir, files,
+                            &mut reporter, common.clone(), context.clone())
+                        .map_err(StallError::into_error)?;
+                    action::print_copy_summary(&report.stats, &common,
+                        &context);
+                    Ok(())
+                })
+        },
+
+        CommandOptions::Status { common, as_host, as_os, entries, .. }
+            if as_host.is_some() || as_os.is_some() =>
+        {
+            let current = Platform::current();
+            let platform = Platform {
+                host: as_host.unwrap_or(current.host),
+                os: as_os.or(current.os),
+            };
+            let mut configured = common.clone();
+            configure_common(&mut configured, &config);
+            let context = RunContext::new(&config, &stall_dir, &configured);
+            let files = config.select_entries_on(&entries, &platform)?
+                .into_iter()
+                .filter(|entry| !entry.is_stall());
+            action::simulate_status(&stall_dir, files, &platform, configured,
+                    context)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Status {
+            common,
+            cached,
+            recursive,
+            group_by,
+            entries,
+            ..
+        } => {
+            let mode = match cached {
+                true  => StatusMode::Cached,
+                false => StatusMode::Accurate,
+            };
+            let mut walk = StallWalk::new(&stall_dir, true, lock)?
+                .with_disabled();
+            if recursive {
+                walk = walk.with_discovered();
+            }
+            let mut totals = StatusTotals::new();
+            walk.run(&stall_dir, &config, &entries, &common,
+                &mut |stall_dir, files, common, context| {
+                    let statuses = action::status(stall_dir, files, mode,
+                            &common, &context)
+                        .map_err(StallError::into_error)?;
+                    action::print_status(stall_dir, &statuses, mode, group_by,
+                        &common, &context);
+                    totals.add(&statuses);
+                    Ok(())
+                })?;
+            if recursive {
+                totals.print();
+            }
+            Ok(())
+        },
+
+        CommandOptions::Render { common, vars_file, output, entry, .. } => {
+            let context = RunContext::new(&config, &stall_dir, &common);
+            let selected = config.select_entries(std::slice::from_ref(&entry))?;
+            let entry = match selected.as_slice() {
+                [entry] => *entry,
+                _ => return Err(Error::msg(format!("{} matches {} entries; \
+                    specify the entry's full path.",
+                    entry.display(),
+                    selected.len()))),
+            };
+            let rendered = action::render_entry(
+                    &stall_dir,
+                    entry,
+                    vars_file.as_deref(),
+                    &context)
+                .map_err(StallError::into_error)?;
+            match output {
+                Some(output) => std::fs::write(&output, rendered)
+                    .with_context(|| format!("Unable to write {:?}",
+                        output)),
+                None => {
+                    print!("{}", rendered);
+                    Ok(())
+                },
+            }
+        },
+
+        CommandOptions::Export { common, format, since, archive, .. } => {
+            action::export(&stall_dir, &archive, format, since.as_deref(),
+                    &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Import {
+            common,
+            format,
+            apply_delta,
+            archive,
+            ..
+        } => {
+            action::import(&stall_dir, &archive, format, apply_delta,
+                    &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Bootstrap {
+            common,
+            source,
+            vars,
+            register,
+            ..
+        } => {
+            action::fetch_stall(&source, &stall_dir, &common)
+                .map_err(StallError::into_error)?;
+            if let Some(name) = &register {
+                action::register_stall(name, &stall_dir, &common)
+                    .map_err(StallError::into_error)?;
+            }
+            let _lock = match common.dry_run {
+                true  => None,
+                false => Some(StallLock::acquire_file(&store.lock_path(),
+                    common.break_lock)?),
+            };
+            let config = load_overlaid_config(&config_path, &overlays,
+                &stall_dir)?;
+            let mut configured = common.clone();
+            configure_common(&mut configured, &config);
+            let mut context = RunContext::new(&config, &stall_dir,
+                &configured);
+            context.cancel = interrupt_token();
+            let files = action::bootstrap_entries(&stall_dir, &config, &vars,
+                    &configured, &mut context)
+                .map_err(StallError::into_error)?;
+            let mut reporter = TerminalReporter::new(&configured, &context);
+            let report = action::distribute(&stall_dir, files, &mut reporter,
+                    configured.clone(), context.clone())
+                .map_err(StallError::into_error)?;
+            action::print_copy_summary(&report.stats, &configured, &context);
+            Ok(())
+        },
+
+        CommandOptions::Mirror { common, decrypt, target, entries, .. } => {
+            let mut configured = common.clone();
+            configure_common(&mut configured, &config);
+            let context = RunContext::new(&config, &stall_dir, &configured);
+            let files = config.select_entries(&entries)?
+                .into_iter()
+                .filter(|entry| !entry.is_stall());
+            let stats = action::mirror(&stall_dir, &target, files, decrypt,
+                    &configured, &context)
+                .map_err(StallError::into_error)?;
+            action::print_copy_summary(&stats, &configured, &context);
+            Ok(())
+        },
+
+        CommandOptions::Fleet { command, .. } => match command {
+            FleetCommand::Status { common, entries } => {
+                let mut configured = common.clone();
+                configure_common(&mut configured, &config);
+                let files = config.select_entries(&entries)?
+                    .into_iter()
+                    .filter(|entry| !entry.is_stall());
+                action::fleet_status(&stall_dir, files, configured)
+                    .map_err(StallError::into_error)
+            },
+        },
+
+        CommandOptions::Merge { common, policy, other, .. } => {
+            action::merge(&config_path, &other, policy, &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Diff { common, stall_file, .. } => {
+            action::diff(&config, &stall_file, &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Check { common, json, .. } => {
+            let result = action::check(&config, json, &common)
+                .map_err(StallError::into_error);
+            let failed = result.as_ref()
+                .err()
+                .and_then(|e| e.downcast_ref::<ValidationFailed>());
+            if let Some(failed) = failed.filter(|_| json) {
+                for finding in &failed.findings {
+                    println!("{}", serde_json::to_string(finding)
+                        .with_context(|| "serialize finding")?);
+                }
+            }
+            result
+        },
+
+        CommandOptions::Snapshot { command, .. } => match command {
+            SnapshotCommand::Create { common, name } => {
+                let mut configured = common.clone();
+                configure_common(&mut configured, &config);
+                let context = RunContext::new(&config, &stall_dir,
+                    &configured);
+                let files = config.iter().filter(|entry| !entry.is_stall());
+                action::create_snapshot(&stall_dir, &name, files, &configured,
+                        &context)
+                    .map_err(StallError::into_error)
+            },
+            SnapshotCommand::List { common } => {
+                action::list_snapshots(&stall_dir, &common)
+                    .map_err(StallError::into_error)
+            },
+            SnapshotCommand::Diff { common, from, to, content } => {
+                let mut configured = common.clone();
+                configure_common(&mut configured, &config);
+                let context = RunContext::new(&config, &stall_dir,
+                    &configured);
+                let files = config.iter().filter(|entry| !entry.is_stall());
+                action::diff_snapshots(&stall_dir, &from, &to, files, content,
+                        &configured, &context)
+                    .map_err(StallError::into_error)
+            },
+        },
+
+        CommandOptions::Restore { common, snapshot, entries, .. } => {
+            let mut configured = common.clone();
+            configure_common(&mut configured, &config);
+            let context = RunContext::new(&config, &stall_dir, &configured);
+            let files = config.select_entries(&entries)?
+                .into_iter()
+                .filter(|entry| !entry.is_stall());
+            action::restore_snapshot(&stall_dir, &snapshot, files,
+                    &configured, &context)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Rollback { common, entries, .. } => {
+            let mut configured = common.clone();
+            configure_common(&mut configured, &config);
+            let mut context = RunContext::new(&config, &stall_dir,
+                &configured);
+            context.cancel = interrupt_token();
+            let files = config.select_entries(&entries)?
+                .into_iter()
+                .filter(|entry| !entry.is_stall());
+            let mut reporter = TerminalReporter::new(&configured, &context);
+            let report = action::rollback(&stall_dir, files, &mut reporter,
+                    configured.clone(), context.clone())
+                .map_err(StallError::into_error)?;
+            action::print_copy_summary(&report.stats, &configured, &context);
+            Ok(())
+        },
+
+        CommandOptions::Disable { common, entries, .. } => {
+            action::set_enabled(&config_path, &entries, false, &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Enable { common, entries, .. } => {
+            action::set_enabled(&config_path, &entries, true, &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Migrate { common, .. } => {
+            action::migrate(&config_path, &common)
+                .map_err(StallError::into_error)
+        },
+
+        CommandOptions::Registry { command, .. } => match command {
+            RegistryCommand::Add { common, name } => {
+                action::register_stall(&name, &stall_dir, &common)
+                    .map_err(StallError::into_error)
+            },
+            RegistryCommand::Remove { common, name } => {
+                action::unregister_stall(&name, &common)
+                    .map_err(StallError::into_error)
+            },
+            RegistryCommand::List { common } => {
+                action::list_stalls(&common)
+                    .map_err(StallError::into_error)
+            },
+        },
+
+        CommandOptions::PackageMetadata { .. } |
+        CommandOptions::Config { .. } => unreachable!(),
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// load_config
+////////////////////////////////////////////////////////////////////////////////
+/// Loads the stall file at `config_path` for the given stall directory.
+fn load_config(config_path: &Path, stall_dir: &PathBuf)
+    -> Result<Config, Error>
+{
+    if !config_path.exists() {
+        return Err(MissingConfig { path: config_path.into() }.into());
+    }
+    let config = Config::from_path(config_path)
+        .with_context(|| format!("Unable to load config file: {:?}",
+            config_path))?;
+    prepare_config(config, stall_dir)
+}
+
+/// Loads the stall file at `config_path` for the given stall directory, then
+/// overlays the entries of each of the `overlays` stall files in order. An
+/// overlaid entry replaces an earlier entry for the same remote file, or
+/// stored at the same path in the stall directory. The other options of the
+/// overlaid stall files are ignored.
+fn load_overlaid_config(
+    config_path: &Path,
+    overlays: &[PathBuf],
+    stall_dir: &PathBuf)
+    -> Result<Config, Error>
+{
+    if overlays.is_empty() {
+        return load_config(config_path, stall_dir);
+    }
+    if !config_path.exists() {
+        return Err(MissingConfig { path: config_path.into() }.into());
+    }
+    let mut config = Config::from_path(config_path)
+        .with_context(|| format!("Unable to load config file: {:?}",
+            config_path))?;
+    for overlay in overlays {
+        if !overlay.exists() {
+            return Err(MissingConfig { path: overlay.as_path().into() }
+                .into());
+        }
+        let overlay_config = Config::from_path(overlay)
+            .with_context(|| format!("Unable to load config file: {:?}",
+                overlay))?;
+        let summary = config.merge(overlay_config, MergePolicy::Theirs)?;
+        debug!("Overlaid {:?}: {} added, {} replaced.",
+            overlay,
+            summary.added.len(),
+            summary.replaced.len());
+    }
+    prepare_config(config, stall_dir)
+}
+
+/// Returns the [`CancelToken`] which is cancelled by the first interrupt
+/// signal, so that the file being copied is finished before the command
+/// stops. A second interrupt exits immediately.
+///
+/// [`CancelToken`]: ../stall/action/struct.CancelToken.html
+fn interrupt_token() -> CancelToken {
+    static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+    TOKEN.get_or_init(|| {
+        let flag = Arc::new(AtomicBool::new(false));
+        // If the handlers can't be registered, interrupts exit immediately.
+        let registered = signal_hook::flag::register_conditional_shutdown(
+                SIGINT, 130, flag.clone())
+            .and_then(|_| signal_hook::flag::register(SIGINT, flag.clone()));
+        if let Err(e) = registered {
+            debug!("Unable to register interrupt handler: {}", e);
+        }
+        CancelToken::from_flag(flag)
+    }).clone()
+}
+
+/// Normalizes and checks a loaded stall file, resolving relative paths against
+/// `base`.
+fn prepare_config(mut config: Config, base: &PathBuf)
+    -> Result<Config, Error>
+{
+    config.normalize_paths(base);
+    config.apply_default_modes();
+    config.check_reserved_paths()?;
+    Ok(config)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// StallWalk
+////////////////////////////////////////////////////////////////////////////////
+/// Runs a command on a stall and, if requested, on its nested stalls.
+struct StallWalk {
+    /// Whether to run the command on nested stalls.
+    recurse: bool,
+    /// Whether to lock nested stall directories.
+    lock: bool,
+    /// Whether to pass disabled file entries to the command, so that their
+    /// status can be shown.
+    disabled: bool,
+    /// Whether to run the command on stalls in subdirectories which are not
+    /// declared as nested stalls.
+    discover: bool,
+    /// The canonical paths of the stalls currently being processed, used to
+    /// detect cycles.
+    visited: Vec<PathBuf>,
+    /// The token used to cancel the command on each stall.
+    cancel: CancelToken,
+}
+
+impl StallWalk {
+    /// Constructs a new `StallWalk` starting at the given stall directory.
+    fn new(stall_dir: &Path, recurse: bool, lock: bool) -> Result<Self, Error> {
+        let root = stall_dir.canonicalize()
+            .with_context(|| format!("Unable to resolve stall directory {:?}",
+                stall_dir))?;
+        Ok(StallWalk {
+            recurse,
+            lock,
+            disabled: false,
+            discover: false,
+            visited: vec![root],
+            cancel: CancelToken::default(),
+        })
+    }
+
+    /// Includes the disabled file entries of each stall after its selected
+    /// entries.
+    fn with_disabled(mut self) -> Self {
+        self.disabled = true;
+        self
+    }
+
+    /// Runs the command on the stalls in subdirectories of each stall which
+    /// are not declared as nested stalls, after its nested stalls.
+    fn with_discovered(mut self) -> Self {
+        self.discover = true;
+        self
+    }
+
+    /// Cancels the command on every stall with the given [`CancelToken`].
+    ///
+    /// [`CancelToken`]: ../stall/action/struct.CancelToken.html
+    fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Runs `command` on the selected file entries of the stall, then on all
+    /// of the entries of each selected nested stall, and of each discovered
+    /// stall if requested. The given
+    /// [`CommonOptions`] are those from the command line, and are configured
+    /// separately for each stall, along with its [`RunContext`].
+    ///
+    /// [`CommonOptions`]: ../stall/struct.CommonOptions.html
+    /// [`RunContext`]: ../stall/struct.RunContext.html
+    fn run<F>(
+        &mut self,
+        stall_dir: &Path,
+        config: &Config,
+        entries: &[PathBuf],
+        common: &CommonOptions,
+        command: &mut F)
+        -> Result<(), Error>
+        where F: FnMut(&Path, Vec<&Entry>, CommonOptions, RunContext)
+            -> Result<(), Error>
+    {
+        let mut configured = common.clone();
+        configure_common(&mut configured, config);
+        let mut context = RunContext::new(config, stall_dir, &configured);
+        context.cancel = self.cancel.clone();
+        // The stalls being processed include this one and its parents.
+        context.depth = self.visited.len() - 1;
+        let (stalls, mut files): (Vec<&Entry>, Vec<&Entry>) = config
+            .select_entries(entries)?
+            .into_iter()
+            .partition(|entry| entry.is_stall());
+        if self.disabled {
+            files.extend(config.disabled_entries(entries)
+                .into_iter()
+                .filter(|entry| !entry.is_stall()));
+        }
+        command(stall_dir, files, configured, context)?;
+
+        for entry in stalls {
+            let nested_dir = entry.stall_dir(stall_dir);
+            if !self.recurse {
+                debug!("Skipping nested stall {:?}: --recurse-stalls was not \
+                    specified.", nested_dir);
+                continue;
+            }
+            if entry.optional && !nested_dir.exists() {
+                debug!("Skipping absent optional stall {:?}.", nested_dir);
+                continue;
+            }
+            self.run_nested(&nested_dir, common, command)?;
+        }
+
+        if self.discover {
+            for nested_dir in config.discovered_stalls(stall_dir)? {
+                debug!("Found undeclared stall {:?}.", nested_dir);
+                self.run_nested(&nested_dir, common, command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `command` on all of the entries of the nested stall in
+    /// `nested_dir`, unless it is already being processed.
+    fn run_nested<F>(
+        &mut self,
+        nested_dir: &Path,
+        common: &CommonOptions,
+        command: &mut F)
+        -> Result<(), Error>
+        where F: FnMut(&Path, Vec<&Entry>, CommonOptions, RunContext)
+            -> Result<(), Error>
+    {
+        let canonical = nested_dir.canonicalize()
+            .with_context(|| format!("Unable to resolve nested stall {:?}",
+                nested_dir))?;
+        if self.visited.contains(&canonical) {
+            warn!("Skipping nested stall {:?}: it is already being \
+                processed.", nested_dir);
+            return Ok(());
+        }
+
+        let nested_config = load_config(
+            &nested_dir.join(DEFAULT_CONFIG_PATH),
+            &nested_dir.to_path_buf())?;
+        let _lock = match self.lock {
+            true  => Some(StallLock::acquire(nested_dir, common.break_lock)?),
+            false => None,
+        };
+
+        self.visited.push(canonical);
+        let result = self.run(nested_dir, &nested_config, &[], common,
+            command);
+        let _ = self.visited.pop();
+        result
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// configure_common
+////////////////////////////////////////////////////////////////////////////////
+/// Applies the display and ordering settings of the stall file to the
+/// [`CommonOptions`] of a command. Settings given on the command line take
+/// precedence. The remaining settings are resolved into a [`RunContext`].
+///
+/// [`CommonOptions`]: ../stall/struct.CommonOptions.html
+/// [`RunContext`]: ../stall/struct.RunContext.html
+fn configure_common(common: &mut CommonOptions, config: &Config) {
+    let _ = common.timestamps.get_or_insert(config.timestamps);
+    let _ = common.order.get_or_insert(config.order);
+    common.icons |= config.icons;
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TerminalPrompter
+////////////////////////////////////////////////////////////////////////////////
+/// A [`Prompter`] which writes prompts to stdout and reads responses from
+/// stdin.
+///
+/// [`Prompter`]: ../stall/action/trait.Prompter.html
+#[derive(Debug, Clone, Copy)]
+struct TerminalPrompter;
+
+impl Prompter for TerminalPrompter {
+    fn ask(&self, prompt: &str) -> Result<Option<String>, Error> {
+        use std::io::BufRead as _;
+        use std::io::Write as _;
+        print!("{}", prompt);
+        std::io::stdout().flush()
+            .with_context(|| "write prompt")?;
+
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line)
+            .with_context(|| "read response")? == 0
+        {
+            println!();
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).into()))
+    }
+
+    fn tell(&self, message: &str) {
+        println!("{}", message);
     }
 }
@@ -9,67 +9,336 @@
 ////////////////////////////////////////////////////////////////////////////////
 
 
+// Local imports.
+use crate::action::AutoSnapshot;
+use crate::action::CancelToken;
+use crate::action::CapabilityCache;
+use crate::action::CopyMethod;
+use crate::action::CopyProgress;
+use crate::action::CustomBackend;
+use crate::action::EncryptionOptions;
+use crate::action::ExportFormat;
+use crate::action::GroupBy;
+use crate::action::LargePlan;
+use crate::action::Policy;
+use crate::action::PolicyPreset;
+use crate::action::TemplateOptions;
+use crate::action::WalkLimits;
+use crate::CompareMode;
+use crate::CompareStage;
+use crate::Config;
+use crate::EntryOrder;
+use crate::Escalation;
+use crate::logger::ColorMode;
+use crate::logger::TracePreset;
+use crate::MergePolicy;
+use crate::Notifier;
+use crate::Os;
+use crate::Timestamps;
+
 // External library imports.
 use serde::Deserialize;
 use serde::Serialize;
 
+#[cfg(feature = "cli")]
 use structopt::StructOpt;
 
 // Standard library imports.
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
 
 ////////////////////////////////////////////////////////////////////////////////
 // CommonOptions
 ////////////////////////////////////////////////////////////////////////////////
 /// Command line options shared between subcommands.
-#[derive(Debug, Clone)]
+///
+/// Programs embedding stall without the `cli` feature can construct these
+/// from their `Default`, which matches running a command with no options.
+#[derive(Debug, Clone, Default)]
 #[derive(Serialize, Deserialize)]
-#[derive(StructOpt)]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
 pub struct CommonOptions {
     /// The stall file to use.
-    #[structopt(
+    #[cfg_attr(feature = "cli", structopt(
         short = "u",
         long = "use-config",
-        parse(from_os_str))]
+        parse(from_os_str)))]
     pub use_config: Option<PathBuf>,
 
+    /// A stall file to use, given once for each file. The entries of later
+    /// files override those of earlier files for the same remote file.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "stall",
+        value_name = "file",
+        number_of_values = 1,
+        conflicts_with = "use-config",
+        parse(from_os_str)))]
+    pub stall_files: Vec<PathBuf>,
+
+    /// Use the stall directory registered under the given name instead of
+    /// the stall directory option.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "stall-name",
+        value_name = "name"))]
+    pub stall_name: Option<String>,
+
     /// The format of the stall file.
-    #[structopt(
+    #[cfg_attr(feature = "cli", structopt(
         short = "c",
         long = "config-format",
-        possible_values(&["ron","list"]))]
+        possible_values(&["ron","list","json"])))]
     pub config_format: Option<String>,
 
     /// Print copy operations instead of running them.
-    #[structopt(short = "n", long = "dry-run")]
+    #[cfg_attr(feature = "cli", structopt(short = "n", long = "dry-run"))]
     pub dry_run: bool,
     
     /// Shorten filenames by omitting path prefixes.
-    #[structopt(short = "s", long = "short-names")]
+    #[cfg_attr(feature = "cli", structopt(short = "s", long = "short-names"))]
     pub short_names: bool,
 
+    /// When to use colored output: auto, always, or never.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "color",
+        value_name = "when",
+        default_value = "auto",
+        possible_values(ColorMode::NAMES)))]
+    pub color: ColorMode,
+
+    /// Print full paths instead of showing paths relative to the home
+    /// directory.
+    #[cfg_attr(feature = "cli", structopt(long = "absolute"))]
+    pub absolute: bool,
+
+    /// Print statuses as compact glyphs, followed by a legend.
+    #[cfg_attr(feature = "cli", structopt(long = "icons"))]
+    pub icons: bool,
+
     /// Force copy even if files are unmodified.
-    #[structopt(short = "f", long = "force")]
+    #[cfg_attr(feature = "cli", structopt(short = "f", long = "force"))]
     pub force: bool,
     
+    /// Answer yes to all prompts.
+    #[cfg_attr(feature = "cli", structopt(short = "y", long = "yes"))]
+    pub yes: bool,
+
+    /// Prompt for confirmation before copying each file.
+    #[cfg_attr(feature = "cli", structopt(short = "i", long = "interactive"))]
+    pub interactive: bool,
+
+    /// Whether copied files keep their source modification time: preserve or
+    /// touch. Overrides the stall file setting.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "timestamps",
+        value_name = "strategy",
+        possible_values(Timestamps::NAMES)))]
+    pub timestamps: Option<Timestamps>,
+
+    /// The order in which entries are processed: stall-file, alphabetical,
+    /// smallest-first, or directories-first. Overrides the stall file setting.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "order",
+        value_name = "order",
+        possible_values(EntryOrder::NAMES)))]
+    pub order: Option<EntryOrder>,
+
+    /// The policy preset for choosing the action for each file: safe,
+    /// mirror-local, mirror-remote, or interactive. Overrides the stall file
+    /// preset.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "policy",
+        value_name = "preset",
+        possible_values(PolicyPreset::NAMES)))]
+    pub policy_preset: Option<PolicyPreset>,
+
+    /// Print the commands and environment of entry hooks instead of running
+    /// them.
+    #[cfg_attr(feature = "cli", structopt(long = "simulate-hooks"))]
+    pub simulate_hooks: bool,
+
+    /// Collect pinned entries even if their contents no longer match their
+    /// pins. The new pin of each such entry is printed.
+    #[cfg_attr(feature = "cli", structopt(long = "repin"))]
+    pub repin: bool,
+
+    /// Send a summary of the changes to the notifiers configured in the stall
+    /// file.
+    #[cfg_attr(feature = "cli", structopt(long = "notify"))]
+    pub notify: bool,
+
+    /// Resume an interrupted collect or distribute, skipping the entries it
+    /// copied which are unchanged since.
+    #[cfg_attr(feature = "cli", structopt(long = "resume"))]
+    pub resume: bool,
+
+    /// Do not show progress bars while copying large files.
+    #[cfg_attr(feature = "cli", structopt(long = "no-progress"))]
+    pub no_progress: bool,
+
+    /// Do not copy the permissions or modification times of copied files.
+    #[cfg_attr(feature = "cli", structopt(long = "no-preserve"))]
+    pub no_preserve: bool,
+
+    /// Do not cross file system boundaries when copying directories.
+    #[cfg_attr(feature = "cli",
+        structopt(short = "x", long = "one-file-system"))]
+    pub one_file_system: bool,
+
     /// Promote file access warnings into errors.
-    #[structopt(short = "e", long = "error")]
+    #[cfg_attr(feature = "cli", structopt(short = "e", long = "error"))]
     pub promote_warnings_to_errors: bool,
     
+    /// Remove the stall directory lock, even if it is held by another process.
+    #[cfg_attr(feature = "cli", structopt(long = "break-lock"))]
+    pub break_lock: bool,
+
     /// Provides more detailed messages.
-    #[structopt(short = "v", long = "verbose")]
+    #[cfg_attr(feature = "cli", structopt(short = "v", long = "verbose"))]
     pub verbose: bool,
 
     /// Silences all program output. This override --verbose if both are provided.
-    #[structopt(short = "q", long = "quiet", alias = "silent")]
+    #[cfg_attr(feature = "cli",
+        structopt(short = "q", long = "quiet", alias = "silent"))]
     pub quiet: bool,
 
     /// Print trace messages. This override --quiet if both are provided.
-    #[structopt(long = "ztrace", hidden(true))]
+    #[cfg_attr(feature = "cli", structopt(long = "ztrace", hidden(true)))]
     pub trace: bool,
+
+    /// Print trace messages for a subsystem: copy, status, io, or all.
+    #[cfg_attr(feature = "cli", structopt(
+        long = "debug",
+        value_name = "subsystem",
+        number_of_values = 1,
+        possible_values(TracePreset::NAMES)))]
+    pub debug: Vec<TracePreset>,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// RunContext
+////////////////////////////////////////////////////////////////////////////////
+/// The settings used to run a command on a stall, resolved from its stall
+/// file, and the state shared by the command while it runs. These are passed
+/// alongside the [`CommonOptions`] of the command.
+///
+/// [`CommonOptions`]: struct.CommonOptions.html
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    /// The template options.
+    pub templates: TemplateOptions,
+
+    /// The encryption options.
+    pub encryption: EncryptionOptions,
+
+    /// The program used to distribute privileged entries.
+    pub escalation: Escalation,
+
+    /// The policy for choosing the action for each file.
+    pub policy: Policy,
+
+    /// Whether status checks are recorded for 'stall fleet status'.
+    pub fleet: bool,
+
+    /// The notifiers to send run summaries to.
+    pub notifiers: Vec<Notifier>,
+
+    /// The strategy used to compare files.
+    pub compare: CompareMode,
+
+    /// The stages used to compare files.
+    pub compare_stages: Vec<CompareStage>,
+
+    /// The largest difference between modification times for which files
+    /// are treated as synced.
+    pub mtime_tolerance: Duration,
+
+    /// The limits on the depth and number of files of directory entries.
+    pub limits: WalkLimits,
+
+    /// The triggers for automatic snapshots.
+    pub auto_snapshot: AutoSnapshot,
+
+    /// The size above which plans are confirmed.
+    pub large_plan: LargePlan,
+
+    /// Whether distributed files are checked for template delimiters.
+    pub template_markers: bool,
+
+    /// The token used to cancel the command.
+    pub cancel: CancelToken,
+
+    /// The method used to copy files.
+    pub copy_method: CopyMethod,
+
+    /// The backend used to copy files in place of the copy method, if one is
+    /// registered.
+    pub copy_backend: CustomBackend,
+
+    /// The capabilities of the file systems copied to, probed by the `Auto`
+    /// copy method.
+    pub capabilities: CapabilityCache,
+
+    /// The callback receiving the progress of each file copied.
+    pub progress: CopyProgress,
+
+    /// The directory of the stall being processed.
+    pub stall_dir: PathBuf,
+
+    /// The nesting depth of the stall being processed. Output for nested
+    /// stalls is indented by one level for each parent stall.
+    pub depth: usize,
+}
+
+impl RunContext {
+    /// Constructs a new `RunContext` from the settings of the stall file of
+    /// the stall in `stall_dir`. The policy preset given in the
+    /// [`CommonOptions`] takes precedence over the stall file.
+    ///
+    /// [`CommonOptions`]: struct.CommonOptions.html
+    pub fn new(config: &Config, stall_dir: &Path, common: &CommonOptions)
+        -> Self
+    {
+        let mut policy = config.policy.clone();
+        if let Some(preset) = common.policy_preset {
+            policy.preset = preset;
+        }
+        RunContext {
+            templates: TemplateOptions {
+                engine: config.template_engine,
+                variables: config.variables.clone(),
+                includes: config.template_includes.as_ref()
+                    .map(|includes| stall_dir.join(includes)),
+                os: None,
+            },
+            encryption: EncryptionOptions {
+                backend: config.encryption_backend,
+                identity: config.age_identity.as_ref()
+                    .map(|identity| stall_dir.join(identity)),
+                recipients: config.age_recipients.clone(),
+                gpg_recipients: config.gpg_recipients.clone(),
+            },
+            escalation: config.escalation,
+            policy,
+            fleet: config.fleet,
+            notifiers: config.notifiers.clone(),
+            compare: config.compare,
+            compare_stages: config.compare_stages.clone(),
+            mtime_tolerance: config.mtime_tolerance,
+            limits: config.limits,
+            auto_snapshot: config.auto_snapshot,
+            large_plan: config.large_plan,
+            template_markers: config.template_markers,
+            copy_method: config.copy_method,
+            stall_dir: stall_dir.to_path_buf(),
+            ..RunContext::default()
+        }
+    }
 }
 
+
 ////////////////////////////////////////////////////////////////////////////////
 // CommandOptions
 ////////////////////////////////////////////////////////////////////////////////
@@ -77,37 +346,506 @@ pub struct CommonOptions {
 #[allow(missing_docs)]
 #[derive(Debug, Clone)]
 #[derive(Serialize, Deserialize)]
-#[derive(StructOpt)]
-#[structopt(name = "stall")]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
+#[cfg_attr(feature = "cli", structopt(name = "stall"))]
 pub enum CommandOptions {
     /// Copies files into the stall directory.
     Collect {
         /// The stall directory to copy into. Default is the current directory.
-        #[structopt(long = "into", parse(from_os_str))]
+        #[cfg_attr(feature = "cli",
+            structopt(long = "into", parse(from_os_str)))]
         into: Option<PathBuf>,
 
-        #[structopt(flatten)]
+        /// Also collect the entries of nested stalls.
+        #[cfg_attr(feature = "cli", structopt(long = "recurse-stalls"))]
+        recurse_stalls: bool,
+
+        /// The entries to collect. Default is all entries.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
         common: CommonOptions,
     },
 
     /// Copies files from the stall directory to their sources.
     Distribute {
         /// The stall directory to copy from. Default is the current directory.
-        #[structopt(long = "from", parse(from_os_str))]
+        #[cfg_attr(feature = "cli",
+            structopt(long = "from", parse(from_os_str)))]
+        from: Option<PathBuf>,
+
+        /// Also distribute the entries of nested stalls.
+        #[cfg_attr(feature = "cli", structopt(long = "recurse-stalls"))]
+        recurse_stalls: bool,
+
+        /// The entries to distribute. Default is all entries.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Prints the status of files in the stall directory.
+    Status {
+        /// The stall directory to check. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// Print the status recorded by the last check instead of comparing
+        /// the files.
+        #[cfg_attr(feature = "cli", structopt(long = "cached"))]
+        cached: bool,
+
+        /// Also check the stalls in subdirectories of the stall directory
+        /// which are not declared as nested stalls, and print the totals
+        /// across all stalls.
+        #[cfg_attr(feature = "cli", structopt(short = "r", long = "recursive"))]
+        recursive: bool,
+
+        /// Group the entries under section headers by tag or directory.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "group-by",
+            value_name = "key",
+            possible_values(GroupBy::NAMES)))]
+        group_by: Option<GroupBy>,
+
+        /// Evaluate the entry conditions and templates as if running on the
+        /// named host, without comparing any files.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "as-host", value_name = "host"))]
+        as_host: Option<String>,
+
+        /// Evaluate the entry conditions and templates as if running on the
+        /// given operating system, without comparing any files.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "as-os",
+            value_name = "os",
+            possible_values(Os::NAMES)))]
+        as_os: Option<Os>,
+
+        /// The entries to check. Default is all entries.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Renders a template entry with the stall file's variables.
+    Render {
+        /// The stall directory holding the template. Default is the current
+        /// directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// A RON file of variables which override the stall file's
+        /// variables.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "vars-file", parse(from_os_str)))]
+        vars_file: Option<PathBuf>,
+
+        /// Write the rendered template to the given file instead of stdout.
+        #[cfg_attr(feature = "cli",
+            structopt(short = "o", long = "output", parse(from_os_str)))]
+        output: Option<PathBuf>,
+
+        /// The template entry to render.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entry: PathBuf,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Writes the stall directory into an archive.
+    Export {
+        /// The stall directory to export. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "from", parse(from_os_str)))]
         from: Option<PathBuf>,
 
-        #[structopt(flatten)]
+        /// The archive format: tar.gz, tar.zst, or zip. Default is determined
+        /// by the extension of the archive.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "format",
+            value_name = "format",
+            possible_values(ExportFormat::NAMES)))]
+        format: Option<ExportFormat>,
+
+        /// Export only the files changed since the given snapshot or UTC
+        /// date, as in 2024-05-01 or 2024-05-01T18:30.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "since", value_name = "snapshot|date"))]
+        since: Option<String>,
+
+        /// The archive to write.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        archive: PathBuf,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Extracts an exported archive into the stall directory.
+    Import {
+        /// The stall directory to import into. Default is the current
+        /// directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "into", parse(from_os_str)))]
+        into: Option<PathBuf>,
+
+        /// The archive format: tar.gz, tar.zst, or zip. Default is determined
+        /// by the extension of the archive.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "format",
+            value_name = "format",
+            possible_values(ExportFormat::NAMES)))]
+        format: Option<ExportFormat>,
+
+        /// Merge an archive written with `export --since`, keeping files
+        /// which are newer in the stall directory.
+        #[cfg_attr(feature = "cli", structopt(long = "apply-delta"))]
+        apply_delta: bool,
+
+        /// The archive to read.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        archive: PathBuf,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Sets up a stall on a new machine from a git repository or an exported
+    /// archive, then distributes it.
+    Bootstrap {
+        /// The stall directory to fetch the stall into. Default is the
+        /// current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "into", parse(from_os_str)))]
+        into: Option<PathBuf>,
+
+        /// A template variable to define, as name=value. Undefined variables
+        /// are otherwise prompted for.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "var",
+            value_name = "name=value",
+            number_of_values = 1))]
+        vars: Vec<String>,
+
+        /// Register the fetched stall under the given name.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "register", value_name = "name"))]
+        register: Option<String>,
+
+        /// The git URL, archive URL, or archive to fetch the stall from.
+        source: String,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Writes the files of the stall as they would be distributed into a
+    /// directory, without changing the remote files.
+    Mirror {
+        /// The stall directory to mirror from. Default is the current
+        /// directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "from", parse(from_os_str)))]
+        from: Option<PathBuf>,
+
+        /// Write the decrypted contents of encrypted entries, which are
+        /// otherwise not mirrored.
+        #[cfg_attr(feature = "cli", structopt(long = "decrypt"))]
+        decrypt: bool,
+
+        /// The directory to mirror into.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        target: PathBuf,
+
+        /// The entries to mirror. Default is all entries.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Writes shell completions, a man page, and service units for packaging.
+    PackageMetadata {
+        /// The directory to write the files into.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "out", parse(from_os_str)))]
+        out: PathBuf,
+
+        /// The directory the stall binary will be installed into.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "bin-dir",
+            default_value = "/usr/bin",
+            parse(from_os_str)))]
+        bin_dir: PathBuf,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Inspects the status of the stall on each host sharing it.
+    Fleet {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(subcommand))]
+        command: FleetCommand,
+    },
+
+    /// Adds the entries of another stall file to the stall file.
+    Merge {
+        /// The stall directory to merge into. Default is the current
+        /// directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// How to resolve entries found in both stall files: error, ours, or
+        /// theirs.
+        #[cfg_attr(feature = "cli", structopt(
+            long = "policy",
+            value_name = "policy",
+            default_value = "error",
+            possible_values(MergePolicy::NAMES)))]
+        policy: MergePolicy,
+
+        /// The stall file or stall directory to merge from.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        other: PathBuf,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Checks the stall file for problems.
+    Check {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// Print each problem as a line of JSON.
+        #[cfg_attr(feature = "cli", structopt(long = "json"))]
+        json: bool,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Prints the changes to the entries made by another stall file.
+    Diff {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// The stall file or stall directory to compare with.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "stall-file", parse(from_os_str)))]
+        stall_file: PathBuf,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Records and lists named snapshots of the stall copies of entries.
+    Snapshot {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(subcommand))]
+        command: SnapshotCommand,
+    },
+
+    /// Restores the stall copies of entries from a snapshot.
+    Restore {
+        /// The stall directory to restore into. Default is the current
+        /// directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// The name of the snapshot to restore from.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "snapshot", value_name = "name"))]
+        snapshot: String,
+
+        /// The entries to restore. Default is all entries.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Restores entries to the last version distributed without error.
+    Rollback {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// The entries to roll back.
+        #[cfg_attr(feature = "cli",
+            structopt(parse(from_os_str), required = true))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Disables entries in the stall file, excluding them from every command
+    /// without removing them.
+    Disable {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// The entries to disable.
+        #[cfg_attr(feature = "cli",
+            structopt(parse(from_os_str), required = true))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Enables entries disabled in the stall file.
+    Enable {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        /// The entries to enable.
+        #[cfg_attr(feature = "cli",
+            structopt(parse(from_os_str), required = true))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Rewrites the stall file in the newest schema version.
+    Migrate {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
         common: CommonOptions,
     },
+
+    /// Manages the registry of named stall directories.
+    Registry {
+        /// The stall directory to register. Default is the current
+        /// directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(subcommand))]
+        command: RegistryCommand,
+    },
+
+    /// Inspects the stall configuration.
+    Config {
+        /// The stall directory to use. Default is the current directory.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "dir", parse(from_os_str)))]
+        dir: Option<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(subcommand))]
+        command: ConfigCommand,
+    },
 }
 
 impl CommandOptions {
+    /// The names of the subcommands.
+    pub const COMMAND_NAMES: &'static [&'static str] = &[
+        "collect",
+        "distribute",
+        "status",
+        "render",
+        "export",
+        "import",
+        "bootstrap",
+        "mirror",
+        "package-metadata",
+        "fleet status",
+        "merge",
+        "diff",
+        "check",
+        "snapshot create",
+        "snapshot list",
+        "snapshot diff",
+        "restore",
+        "rollback",
+        "disable",
+        "enable",
+        "migrate",
+        "registry add",
+        "registry remove",
+        "registry list",
+        "config show-effective-trace",
+    ];
+
     /// Returns the `CommonOptions`.
     pub fn common(&self) -> &CommonOptions {
         use CommandOptions::*;
         match self {
             Collect { common, .. } => common,
             Distribute { common, .. } => common,
+            Status { common, .. } => common,
+            Render { common, .. } => common,
+            Export { common, .. } => common,
+            Import { common, .. } => common,
+            Bootstrap { common, .. } => common,
+            Mirror { common, .. } => common,
+            PackageMetadata { common, .. } => common,
+            Fleet { command, .. } => command.common(),
+            Merge { common, .. } => common,
+            Diff { common, .. } => common,
+            Check { common, .. } => common,
+            Snapshot { command, .. } => command.common(),
+            Restore { common, .. } => common,
+            Rollback { common, .. } => common,
+            Disable { common, .. } => common,
+            Enable { common, .. } => common,
+            Migrate { common, .. } => common,
+            Registry { command, .. } => command.common(),
+            Config { command, .. } => command.common(),
+        }
+    }
+
+    /// Returns true if the command does not modify the stall directory.
+    pub fn is_read_only(&self) -> bool {
+        use CommandOptions::*;
+        match self {
+            Status { cached, as_host, as_os, .. } => *cached
+                || as_host.is_some()
+                || as_os.is_some(),
+            Render { .. } | Export { .. } | PackageMetadata { .. } |
+            Fleet { .. } | Diff { .. } | Check { .. } | Mirror { .. } |
+            Registry { .. } | Config { .. } => true,
+            Snapshot { command: SnapshotCommand::List { .. }, .. } |
+            Snapshot { command: SnapshotCommand::Diff { .. }, .. } => true,
+            _ => self.common().dry_run,
         }
     }
 
@@ -122,7 +860,243 @@ impl CommandOptions {
             Distribute { from, .. } => match from {
                 Some(path) => Ok(path.clone()),
                 None       => std::env::current_dir(),
-            }
+            },
+            Status { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Render { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Export { from, .. } => match from {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Import { into, .. } => match into {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Bootstrap { into, .. } => match into {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Mirror { from, .. } => match from {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            PackageMetadata { .. } => std::env::current_dir(),
+            Fleet { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Merge { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Diff { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Check { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Snapshot { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Restore { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Rollback { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Disable { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Enable { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Migrate { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Registry { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+            Config { dir, .. } => match dir {
+                Some(path) => Ok(path.clone()),
+                None       => std::env::current_dir(),
+            },
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// FleetCommand
+////////////////////////////////////////////////////////////////////////////////
+/// Subcommands of the 'stall fleet' command.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
+pub enum FleetCommand {
+    /// Prints the state of each entry on each host which has recorded its
+    /// status.
+    Status {
+        /// The entries to show. Default is all entries.
+        #[cfg_attr(feature = "cli", structopt(parse(from_os_str)))]
+        entries: Vec<PathBuf>,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+}
+
+impl FleetCommand {
+    /// Returns the `CommonOptions`.
+    pub fn common(&self) -> &CommonOptions {
+        use FleetCommand::*;
+        match self {
+            Status { common, .. } => common,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SnapshotCommand
+////////////////////////////////////////////////////////////////////////////////
+/// Subcommands of the 'stall snapshot' command.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
+pub enum SnapshotCommand {
+    /// Copies the stall copy of every entry into a named snapshot.
+    Create {
+        /// The name of the snapshot.
+        #[cfg_attr(feature = "cli",
+            structopt(long = "name", value_name = "name"))]
+        name: String,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Prints the snapshots of the stall directory.
+    List {
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Prints the files which changed between two snapshots.
+    Diff {
+        /// The name of the snapshot to compare from.
+        from: String,
+
+        /// The name of the snapshot to compare to, or WORKING for the current
+        /// stall copies.
+        to: String,
+
+        /// Print the differences between the contents of changed files.
+        #[cfg_attr(feature = "cli", structopt(long = "content"))]
+        content: bool,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+}
+
+impl SnapshotCommand {
+    /// Returns the `CommonOptions`.
+    pub fn common(&self) -> &CommonOptions {
+        use SnapshotCommand::*;
+        match self {
+            Create { common, .. } => common,
+            List { common } => common,
+            Diff { common, .. } => common,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// RegistryCommand
+////////////////////////////////////////////////////////////////////////////////
+/// Subcommands of the 'stall registry' command.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
+pub enum RegistryCommand {
+    /// Registers the stall directory under a name.
+    Add {
+        /// The name to register the stall under.
+        name: String,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Removes a stall from the registry.
+    Remove {
+        /// The name of the stall to remove.
+        name: String,
+
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+
+    /// Prints the registered stalls.
+    List {
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+}
+
+impl RegistryCommand {
+    /// Returns the `CommonOptions`.
+    pub fn common(&self) -> &CommonOptions {
+        use RegistryCommand::*;
+        match self {
+            Add { common, .. } => common,
+            Remove { common, .. } => common,
+            List { common } => common,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ConfigCommand
+////////////////////////////////////////////////////////////////////////////////
+/// Subcommands of the 'stall config' command.
+#[allow(missing_docs)]
+#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(StructOpt))]
+pub enum ConfigCommand {
+    /// Prints the log level of each module and where it was set.
+    ShowEffectiveTrace {
+        #[cfg_attr(feature = "cli", structopt(flatten))]
+        common: CommonOptions,
+    },
+}
+
+impl ConfigCommand {
+    /// Returns the `CommonOptions`.
+    pub fn common(&self) -> &CommonOptions {
+        use ConfigCommand::*;
+        match self {
+            ShowEffectiveTrace { common } => common,
         }
     }
 }
@@ -10,15 +10,35 @@
 #![warn(missing_docs)]
 
 // Local imports.
+use crate::action::AutoSnapshot;
+use crate::action::CopyMethod;
+use crate::action::DEFAULT_HOSTS_PATH;
+use crate::action::DEFAULT_SNAPSHOTS_PATH;
+use crate::action::host_name;
+use crate::action::LargePlan;
+use crate::action::Location;
+use crate::action::Policy;
+use crate::action::umask;
+use crate::action::WalkLimits;
 use crate::logger::LevelFilter;
 use crate::logger::LoggerConfig;
 use crate::logger::StdoutLogOutput;
 use crate::error::Error;
 use crate::error::Context;
+use crate::error::MergeConflict;
+use crate::error::ReservedPath;
+use crate::error::UnknownEntry;
+use crate::error::UnsupportedVersion;
+use crate::lock::DEFAULT_LOCK_PATH;
+use crate::state::DEFAULT_STATE_PATH;
+use crate::theme::Theme;
 
 // External library imports.
+use ron::ser::PrettyConfig;
 use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
+use serde::Serializer;
 
 use log::*;
 
@@ -26,13 +46,13 @@ use log::*;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
-use std::io::BufReader;
 use std::io::BufRead;
-use std::io::Seek;
-use std::io::SeekFrom;
 use std::path::PathBuf;
 use std::borrow::Cow;
 use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::path::Component;
+use std::time::Duration;
 
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -43,24 +63,207 @@ use std::collections::BTreeMap;
 /// [`Config`]: struct.Config.html
 pub const DEFAULT_CONFIG_PATH: &'static str = ".stall";
 
+////////////////////////////////////////////////////////////////////////////////
+// CONFIG_VERSION
+////////////////////////////////////////////////////////////////////////////////
+/// The newest schema version of the stall file. Stall files written with an
+/// older version are migrated when they are loaded, and the 'stall migrate'
+/// command rewrites them in the newest version.
+pub const CONFIG_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// The migrations upgrading a [`Config`] from each earlier schema version.
+/// The migration at index `n` upgrades a `Config` from version `n` to version
+/// `n + 1`. Options which are renamed in a new version should also be given a
+/// serde alias, so that older stall files can still be parsed.
+///
+/// [`Config`]: struct.Config.html
+const MIGRATIONS: &[fn(&mut Config)] = &[
+    // Version 1 added the `version` option. Stall files without it are
+    // version 0, and otherwise unchanged.
+    |_| (),
+];
+
+////////////////////////////////////////////////////////////////////////////////
+// RESERVED_PATHS
+////////////////////////////////////////////////////////////////////////////////
+/// The paths in the stall directory which are used by stall itself, and may
+/// never be used to store an [`Entry`]. See [`Config::reserved_paths`] for the
+/// full list, including paths set in the stall file.
+///
+/// [`Entry`]: struct.Entry.html
+/// [`Config::reserved_paths`]: struct.Config.html#method.reserved_paths
+pub const RESERVED_PATHS: &[&str] = &[
+    DEFAULT_CONFIG_PATH,
+    DEFAULT_STATE_PATH,
+    DEFAULT_LOCK_PATH,
+    DEFAULT_HOSTS_PATH,
+    DEFAULT_SNAPSHOTS_PATH,
+];
+
 ////////////////////////////////////////////////////////////////////////////////
 // Config
 ////////////////////////////////////////////////////////////////////////////////
 /// Application configuration data (stall file). Configures the logger and
 /// defines files.
+///
+/// A `Config` is `Send` and `Sync`, and has no interior mutability, so a
+/// shared reference may be held by several threads running commands at once.
+/// Commands which modify the same stall directory are serialized by its
+/// [`StallLock`], and commands never modify the `Config` they are given.
+///
+/// [`StallLock`]: lock/struct.StallLock.html
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Config {
+    /// The schema version the stall file was written with. Stall files
+    /// without a version were written before versioning was added, and are
+    /// version 0.
+    #[serde(default)]
+    pub version: u32,
+
     /// The logger configuration.
     #[serde(default = "Config::default_logger_config")]
+    #[serde(skip_serializing_if = "Config::is_default_logger_config")]
     pub logger_config: LoggerConfig,
 
     /// Module specific log levels.
     #[serde(default = "Config::default_log_levels")]
+    #[serde(skip_serializing_if = "is_default")]
     pub log_levels: BTreeMap<Cow<'static, str>, LevelFilter>,
 
+    /// The mode to apply to files created by stall commands. If not
+    /// provided, files are created according to the umask.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub default_file_mode: Option<Mode>,
+
+    /// The mode to apply to directories created by stall commands. If not
+    /// provided, directories are created according to the umask.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub default_dir_mode: Option<Mode>,
+
+    /// Whether copied files keep the modification time of their source.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub timestamps: Timestamps,
+
+    /// The order in which entries are collected and distributed.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub order: EntryOrder,
+
+    /// The program used to distribute privileged entries with elevated
+    /// privileges.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub escalation: Escalation,
+
+    /// The policy for choosing the action taken for each file state.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub policy: Policy,
+
+    /// The strategy used to compare files which exist both in and out of the
+    /// stall.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub compare: CompareMode,
+
+    /// The stages of the pipeline used to compare files, in place of the
+    /// `compare` strategy. The pipeline is not used if no stages are given.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub compare_stages: Vec<CompareStage>,
+
+    /// The method used to copy files. Files are read and written directly by
+    /// default. The `Auto` method chooses the best available method for each
+    /// file.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub copy_method: CopyMethod,
+
+    /// The largest difference in seconds between the modification times of
+    /// two files for which they are treated as synced. This allows for file
+    /// systems which store coarse timestamps, such as FAT32, which rounds
+    /// them to 2 seconds.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[serde(deserialize_with = "deserialize_seconds")]
+    #[serde(serialize_with = "serialize_seconds")]
+    pub mtime_tolerance: Duration,
+
+    /// The limits on the depth and number of files of directory entries.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub limits: WalkLimits,
+
+    /// The triggers for automatic snapshots of the stall copies.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub auto_snapshot: AutoSnapshot,
+
+    /// The size above which a collect or distribute is confirmed.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub large_plan: LargePlan,
+
+    /// The color theme for command output.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub theme: Theme,
+
+    /// The backend used to encrypt and decrypt encrypted entries.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub encryption_backend: EncryptionBackend,
+
+    /// The age identity file used to encrypt and decrypt encrypted entries,
+    /// relative to the stall directory.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub age_identity: Option<PathBuf>,
+
+    /// Additional age public keys to encrypt encrypted entries to, such as
+    /// those of other machines sharing the stall.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub age_recipients: Vec<String>,
+
+    /// The GnuPG key IDs or user IDs to encrypt encrypted entries to when
+    /// using the `Gpg` backend. Entries are decrypted with any available
+    /// secret key.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub gpg_recipients: Vec<String>,
+
+    /// Whether statuses are printed as compact glyphs, as with the `--icons`
+    /// option.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub icons: bool,
+
+    /// The variables to substitute into template entries.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub variables: BTreeMap<String, String>,
+
+    /// The engine used to render template entries.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub template_engine: TemplateEngine,
+
+    /// A directory of templates which may be included by template entries,
+    /// relative to the stall directory. Only used by the `Handlebars` engine.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub template_includes: Option<PathBuf>,
+
+    /// Whether distributed files which are not templates are refused if they
+    /// contain template delimiters, as well as merge conflict markers.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub template_markers: bool,
+
+    /// Whether each host records the results of its status checks in the
+    /// stall directory, to be shown by the 'stall fleet status' command on
+    /// the other hosts sharing the stall.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub fleet: bool,
+
+    /// The notifiers sent a summary of each collect or distribute run with
+    /// the `--notify` option.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub notifiers: Vec<Notifier>,
+
+    /// Whether entries are written in order of their paths in the stall
+    /// directory when the stall file is rewritten by stall commands, so that
+    /// changes to the stall file produce small diffs. Entries are processed
+    /// in the order they are written unless another `order` is chosen.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub sort_entries: bool,
+
     /// The list of files to apply stall commands to.
-    pub files: Vec<Box<Path>>,
+    #[serde(deserialize_with = "deserialize_entries")]
+    #[serde(serialize_with = "serialize_entries")]
+    pub files: Vec<Entry>,
 }
 
 
@@ -81,27 +284,133 @@ impl Config {
 
     /// Constructs a new `Config` with options parsed from the given file.
     fn from_file(mut file: File) -> Result<Self, Error>  {
-        match Config::parse_ron_file(&mut file) {
-            Ok(config) => Ok(config),
-            Err(e)     => {
-                debug!("Error in RON, switching to list format.\n{:?}", e);
-                let _ = file.seek(SeekFrom::Start(0))?;
-                Config::parse_list_file(&mut file)
-            },
-        }
-    }
-
-    /// Parses a `Config` from a file using the RON format.
-    fn parse_ron_file(file: &mut File) -> Result<Self, Error> {
         let len = file.metadata()
             .with_context(|| "Failed to recover file metadata.")?
             .len();
         let mut buf = Vec::with_capacity(len as usize);
         let _ = file.read_to_end(&mut buf)
             .with_context(|| "Failed to read config file")?;
+        Config::from_bytes(&buf)
+    }
+
+    /// Constructs a new `Config` with options parsed from the given stall
+    /// file contents.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error>  {
+        Config::parse(bytes).map(|(config, _)| config)
+    }
+
+    /// Constructs a new `Config` with options parsed from the given stall
+    /// file contents, returning it along with the [`ConfigFormat`] it was
+    /// written in. Stall files written with an older schema version are
+    /// migrated to the [`CONFIG_VERSION`].
+    ///
+    /// Stall files whose contents begin with `{` are parsed as JSON. Others
+    /// are parsed as RON, or in the list format if they are not valid RON.
+    ///
+    /// ### Example
+    ///
+    /// ```rust
+    /// # use stall::Config;
+    /// # use stall::ConfigFormat;
+    /// let (config, format) = Config::parse(br#"{
+    ///     "copy_method": "Native",
+    ///     "files": ["~/.bashrc", { "path": "~/.vimrc", "tags": ["vim"] }]
+    /// }"#).unwrap();
+    ///
+    /// assert_eq!(format, ConfigFormat::Json);
+    /// assert_eq!(config.files.len(), 2);
+    /// assert_eq!(config.files[1].tags, ["vim"]);
+    /// ```
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`UnsupportedVersion`] error if the stall file was written
+    /// with a newer schema version, or an [`Error`] if the contents can't be
+    /// parsed.
+    ///
+    /// [`ConfigFormat`]: enum.ConfigFormat.html
+    /// [`CONFIG_VERSION`]: constant.CONFIG_VERSION.html
+    /// [`UnsupportedVersion`]: ../error/struct.UnsupportedVersion.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn parse(bytes: &[u8]) -> Result<(Self, ConfigFormat), Error> {
+        let (mut config, format) = Config::parse_unmigrated(bytes)?;
+        config.migrate()?;
+        Ok((config, format))
+    }
+
+    /// Constructs a new `Config` with options parsed from the given stall
+    /// file contents as with [`parse`], without migrating it. The `version`
+    /// of the `Config` is the schema version the stall file was written with.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`UnsupportedVersion`] error if the stall file was written
+    /// with a newer schema version, or an [`Error`] if the contents can't be
+    /// parsed.
+    ///
+    /// [`parse`]: #method.parse
+    /// [`UnsupportedVersion`]: ../error/struct.UnsupportedVersion.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn parse_unmigrated(bytes: &[u8])
+        -> Result<(Self, ConfigFormat), Error>
+    {
+        /// The schema version of a stall file, read before its other options
+        /// so that options added by newer versions aren't reported as
+        /// unknown.
+        #[derive(Deserialize)]
+        struct Versioned {
+            /// The schema version.
+            #[serde(default)]
+            version: u32,
+        }
+
+        if bytes.trim_ascii_start().starts_with(b"{") {
+            let versioned: Versioned = serde_json::from_slice(bytes)
+                .with_context(|| "Failed parsing JSON file")?;
+            check_version(versioned.version)?;
+            return Ok((Config::parse_json(bytes)?, ConfigFormat::Json));
+        }
+        if let Ok(versioned) = ron::de::from_bytes::<Versioned>(bytes) {
+            check_version(versioned.version)?;
+        }
+        match Config::parse_ron(bytes) {
+            Ok(config) => Ok((config, ConfigFormat::Ron)),
+            Err(e)     => {
+                debug!("Error in RON, switching to list format.\n{:?}", e);
+                Ok((Config::parse_list(bytes)?, ConfigFormat::List))
+            },
+        }
+    }
+
+    /// Migrates the `Config` from the schema version it was written with to
+    /// the [`CONFIG_VERSION`].
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`UnsupportedVersion`] error if the `Config` was written
+    /// with a newer schema version.
+    ///
+    /// [`CONFIG_VERSION`]: constant.CONFIG_VERSION.html
+    /// [`UnsupportedVersion`]: ../error/struct.UnsupportedVersion.html
+    pub fn migrate(&mut self) -> Result<(), Error> {
+        check_version(self.version)?;
+        for (version, migration) in MIGRATIONS.iter()
+            .enumerate()
+            .skip(self.version as usize)
+        {
+            debug!("Migrating stall file from version {} to version {}.",
+                version,
+                version + 1);
+            migration(self);
+        }
+        self.version = CONFIG_VERSION;
+        Ok(())
+    }
 
+    /// Parses a `Config` from the RON format.
+    fn parse_ron(bytes: &[u8]) -> Result<Self, Error> {
         use ron::de::Deserializer;
-        let mut d = Deserializer::from_bytes(&buf)
+        let mut d = Deserializer::from_bytes(bytes)
             .with_context(|| "Failed deserializing RON file")?;
         let config = Config::deserialize(&mut d)
             .with_context(|| "Failed parsing Ron file")?;
@@ -111,12 +420,16 @@ impl Config {
         Ok(config) 
     }
     
-    /// Parses a `Config` from a file using a newline-delimited file list
-    /// format.
-    fn parse_list_file(file: &mut File) -> Result<Self, Error> {
+    /// Parses a `Config` from the JSON format.
+    fn parse_json(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes)
+            .with_context(|| "Failed parsing JSON file")
+    }
+
+    /// Parses a `Config` from a newline-delimited file list format.
+    fn parse_list(bytes: &[u8]) -> Result<Self, Error> {
         let mut config = Config::default();
-        let buf_reader = BufReader::new(file);
-        for line in buf_reader.lines() {
+        for line in bytes.lines() {
             let line = line
                 .with_context(|| "Failed to read config file")?;
             
@@ -129,12 +442,262 @@ impl Config {
             if line.starts_with("#") { continue }
 
             let path: PathBuf = line.into();
-            config.files.push(path.into());
+            config.files.push(Entry::from(path));
         }
 
         Ok(config) 
     }
 
+    /// Returns a [`ConfigBuilder`] for constructing a `Config`.
+    ///
+    /// [`ConfigBuilder`]: struct.ConfigBuilder.html
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    /// Returns the text of the stall file for the `Config` in the given
+    /// [`ConfigFormat`].
+    ///
+    /// The text is the same on every platform, with `\n` line endings and a
+    /// trailing newline. If `sort_entries` is set, the entries are written in
+    /// order of their paths in the stall directory.
+    ///
+    /// ### Example
+    ///
+    /// ```rust
+    /// # use stall::Config;
+    /// # use stall::ConfigFormat;
+    /// let mut config = Config::builder()
+    ///     .file("~/.vimrc")
+    ///     .file("~/.bashrc")
+    ///     .build()
+    ///     .unwrap();
+    /// config.sort_entries = true;
+    ///
+    /// let text = config.to_text(ConfigFormat::Ron).unwrap();
+    /// assert!(text.ends_with("    files: [\n        \"~/.bashrc\",\n        \
+    ///     \"~/.vimrc\",\n    ],\n)\n"));
+    /// ```
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the `Config` can't be serialized, or if the
+    /// list format is requested for a `Config` with options other than the
+    /// paths of its entries.
+    ///
+    /// [`ConfigFormat`]: enum.ConfigFormat.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn to_text(&self, format: ConfigFormat) -> Result<String, Error> {
+        let to_ron = |config: &Config| ron::ser::to_string_pretty(
+                config,
+                PrettyConfig::new()
+                    .with_new_line("\n".into())
+                    .with_indentor("    ".into()))
+            .map(|text| text + "\n")
+            .with_context(|| "serialize stall file");
+        let config = match self.sort_entries {
+            true  => Cow::Owned(self.sorted()),
+            false => Cow::Borrowed(self),
+        };
+        match format {
+            ConfigFormat::Ron => to_ron(&config),
+            ConfigFormat::Json => serde_json::to_string_pretty(&config)
+                .map(|text| text + "\n")
+                .with_context(|| "serialize stall file"),
+            ConfigFormat::List => {
+                // The list format only holds paths, so every other option
+                // must be left at its default.
+                let options = Config { files: Vec::new(), .. self.clone() };
+                let plain = !self.files.iter().any(Entry::has_options);
+                if !plain || to_ron(&options)? != to_ron(&Config::default())? {
+                    return Err(Error::msg("the stall file has options which \
+                        can't be written in the list format"));
+                }
+                let mut text = String::new();
+                for entry in &self.files {
+                    text.push_str(&entry.path.to_string_lossy());
+                    text.push('\n');
+                }
+                Ok(text)
+            },
+        }
+    }
+
+    /// Returns a copy of the `Config` with its entries in order of their
+    /// paths in the stall directory, then of their remote paths.
+    fn sorted(&self) -> Config {
+        let mut config = self.clone();
+        config.files.sort_by(|a, b| a.file_name()
+            .cmp(&b.file_name())
+            .then_with(|| a.path.cmp(&b.path)));
+        config
+    }
+
+    /// Returns the text of the stall file for the `Config` in the list
+    /// format, updating the `original` text of a stall file in that format.
+    ///
+    /// The comments, blank lines, and order of the original lines are
+    /// preserved. The lines of entries which are no longer in the `Config` are
+    /// removed, and entries which were not in the original are added at the
+    /// end.
+    ///
+    /// ### Example
+    ///
+    /// ```rust
+    /// # use stall::Config;
+    /// let config = Config::builder()
+    ///     .file("~/.vimrc")
+    ///     .file("~/.gitconfig")
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let original = "# Shell\n~/.bashrc\n\n# Editor\n~/.vimrc\n";
+    /// assert_eq!(config.update_list_text(original).unwrap(),
+    ///     "# Shell\n\n# Editor\n~/.vimrc\n~/.gitconfig\n");
+    /// ```
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the `Config` has options other than the paths
+    /// of its entries, which can't be written in the list format.
+    ///
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn update_list_text(&self, original: &str) -> Result<String, Error> {
+        let _ = self.to_text(ConfigFormat::List)?;
+        let mut remaining: Vec<&Entry> = self.files.iter().collect();
+        let mut text = String::with_capacity(original.len());
+        for line in original.lines() {
+            let keep = match line.trim() {
+                "" => true,
+                comment if comment.starts_with("//")
+                    || comment.starts_with('#') => true,
+                path => {
+                    let key = normalize_key(Path::new(path));
+                    let index = remaining.iter()
+                        .position(|entry| *entry.path == key);
+                    if let Some(index) = index {
+                        let _ = remaining.remove(index);
+                    }
+                    index.is_some()
+                },
+            };
+            if keep {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+        for entry in remaining {
+            text.push_str(&entry.path.to_string_lossy());
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    /// Returns the text of the stall file for the `Config` in the RON format,
+    /// keeping the line comments of the `original` text of a stall file in
+    /// that format.
+    ///
+    /// Comments before the stall file's options are kept at its start, and
+    /// comments after them at its end. Comments directly above a top-level
+    /// option or an entry are kept above it, even if the entries are
+    /// reordered. Comments within other options, and those above entries
+    /// which are no longer in the `Config`, are dropped.
+    ///
+    /// ### Example
+    ///
+    /// ```rust
+    /// # use stall::Config;
+    /// # use stall::ConfigFormat;
+    /// let original = "// My stall.\n(\n    files: [\n        \
+    ///     // Shell configuration.\n        \"~/.bashrc\",\n    ],\n)\n";
+    /// let config = Config::from_bytes(original.as_bytes()).unwrap();
+    ///
+    /// let text = config.update_ron_text(original).unwrap();
+    /// let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    /// assert_eq!(lines[0], "// My stall.");
+    /// let comment = lines.iter()
+    ///     .position(|line| *line == "// Shell configuration.")
+    ///     .unwrap();
+    /// assert_eq!(lines[comment + 1], "\"~/.bashrc\",");
+    /// ```
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the `Config` can't be serialized.
+    ///
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn update_ron_text(&self, original: &str) -> Result<String, Error> {
+        let mut header = Vec::new();
+        let mut comments: BTreeMap<CommentAnchor, Vec<&str>> = BTreeMap::new();
+        let mut pending = Vec::new();
+        let mut code_seen = false;
+        let original: Vec<&str> = original.lines().collect();
+        for (line, anchor) in original.iter().zip(ron_anchors(&original)) {
+            let trimmed = line.trim();
+            match (trimmed.starts_with("//"), code_seen) {
+                (true, false) => header.push(trimmed),
+                (true, true)  => pending.push(trimmed),
+                _ if trimmed.is_empty() => (),
+                _ => {
+                    code_seen = true;
+                    if let Some(anchor) = anchor {
+                        comments.entry(anchor)
+                            .or_default()
+                            .append(&mut pending);
+                    }
+                    pending.clear();
+                },
+            }
+        }
+
+        let generated = self.to_text(ConfigFormat::Ron)?;
+        let lines: Vec<&str> = generated.lines().collect();
+        let mut text = String::with_capacity(generated.len());
+        for comment in header {
+            text.push_str(comment);
+            text.push('\n');
+        }
+        for (line, anchor) in lines.iter().zip(ron_anchors(&lines)) {
+            let kept = anchor.and_then(|anchor| comments.get(&anchor));
+            for comment in kept.into_iter().flatten() {
+                let indent = line.len() - line.trim_start().len();
+                text.push_str(&line[..indent]);
+                text.push_str(comment);
+                text.push('\n');
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        for comment in pending {
+            text.push_str(comment);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    /// Writes the `Config` to the stall file at the given path in the given
+    /// [`ConfigFormat`].
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the `Config` can't be written in the format or
+    /// the file can't be written.
+    ///
+    /// [`ConfigFormat`]: enum.ConfigFormat.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn write_to<P>(&self, path: P, format: ConfigFormat)
+        -> Result<(), Error>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let text = self.to_text(format)?;
+        std::fs::write(path, text)
+            .with_context(|| format!("Unable to write stall file: {:?}",
+                path))?;
+        debug!("Wrote stall file: {:?}", path);
+        Ok(())
+    }
+
     /// Normalizes paths in the config by expanding them relative to the given
     /// root path.
     pub fn normalize_paths(&mut self, base: &PathBuf) {
@@ -148,6 +711,439 @@ impl Config {
         }
     }
 
+    /// Returns the entries matching the given paths, or all entries if no
+    /// paths are given. Paths match an entry if they are equal to its path or
+    /// its file name in the stall directory after [`normalize_key`] is
+    /// applied.
+    ///
+    /// Entries which are disabled, restricted to another host or operating
+    /// system, or whose environment conditions are not met are never
+    /// returned.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`UnknownEntry`] error if a path matches no entries.
+    ///
+    /// [`normalize_key`]: fn.normalize_key.html
+    /// [`UnknownEntry`]: ../error/struct.UnknownEntry.html
+    pub fn select_entries<'c>(&'c self, paths: &[PathBuf])
+        -> Result<Vec<&'c Entry>, Error>
+    {
+        self.select_entries_on(paths, &Platform::current())
+    }
+
+    /// Returns the entries matching the given paths as with
+    /// [`select_entries`], but selecting those which apply to the given
+    /// [`Platform`] rather than the current one.
+    ///
+    /// [`select_entries`]: #method.select_entries
+    /// [`Platform`]: struct.Platform.html
+    pub fn select_entries_on<'c>(
+        &'c self,
+        paths: &[PathBuf],
+        platform: &Platform)
+        -> Result<Vec<&'c Entry>, Error>
+    {
+        if paths.is_empty() {
+            return Ok(self.files.iter()
+                .filter(|entry| entry.is_enabled_on(platform))
+                .collect());
+        }
+
+        let mut selected = Vec::new();
+        for path in paths {
+            let key = normalize_key(path);
+            let matches: Vec<_> = self.files.iter()
+                .filter(|entry| *entry.path == *key
+                    || entry.file_name() == Some(key.as_os_str()))
+                .collect();
+            if matches.is_empty() {
+                return Err(UnknownEntry {
+                    path: path.clone().into(),
+                    candidates: entry_candidates(&self.files),
+                }.into());
+            }
+            for entry in matches {
+                if !entry.is_enabled_on(platform) {
+                    debug!("Skipping disabled entry: {:?}", entry.path);
+                } else if !selected.contains(&entry) {
+                    selected.push(entry);
+                }
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Returns the disabled entries matching the given paths, or all disabled
+    /// entries if no paths are given, which would otherwise apply to the
+    /// current host, operating system, and environment.
+    pub fn disabled_entries<'c>(&'c self, paths: &[PathBuf]) -> Vec<&'c Entry> {
+        let platform = Platform::current();
+        let keys: Vec<_> = paths.iter()
+            .map(|path| normalize_key(path))
+            .collect();
+        self.files.iter()
+            .filter(|entry| !entry.enabled && entry.applies_to(&platform))
+            .filter(|entry| keys.is_empty() || keys.iter()
+                .any(|key| *entry.path == **key
+                    || entry.file_name() == Some(key.as_os_str())))
+            .collect()
+    }
+
+    /// Returns an iterator over the entries of the stall file, in the order
+    /// they are given, regardless of whether they apply to the current host.
+    pub fn iter(&self) -> std::slice::Iter<'_, Entry> {
+        self.files.iter()
+    }
+
+    /// Returns the entry at the given index in the stall file, if there is
+    /// one.
+    pub fn get(&self, index: usize) -> Option<&Entry> {
+        self.files.get(index)
+    }
+
+    /// Returns an iterator over the entries whose paths are equal to or
+    /// within the given path, or whose file name in the stall directory is
+    /// equal to it, after [`normalize_key`] is applied. Unlike
+    /// [`select_entries`], every entry is considered regardless of whether
+    /// it applies to the current host, and no error is returned if none
+    /// match.
+    ///
+    /// ### Example
+    ///
+    /// ```rust
+    /// # use stall::Config;
+    /// let config = Config::builder()
+    ///     .file("~/.config/fish/config.fish")
+    ///     .file("~/.config/git/config")
+    ///     .file("~/.bashrc")
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(config.entries_matching("~/.config").count(), 2);
+    /// assert_eq!(config.entries_matching(".bashrc").count(), 1);
+    /// assert_eq!(*config[2].path, config.entry_data()[2].remote);
+    /// ```
+    ///
+    /// [`normalize_key`]: fn.normalize_key.html
+    /// [`select_entries`]: #method.select_entries
+    pub fn entries_matching<'c, P>(&'c self, path: P)
+        -> impl Iterator<Item=&'c Entry> + 'c
+        where P: AsRef<Path>
+    {
+        let key = normalize_key(path.as_ref());
+        self.files.iter()
+            .filter(move |entry| entry.path.starts_with(&key)
+                || entry.file_name() == Some(key.as_os_str()))
+    }
+
+    /// Returns the [`EntryData`] of each entry of the stall file, in the
+    /// order they are given.
+    ///
+    /// [`EntryData`]: struct.EntryData.html
+    pub fn entry_data(&self) -> Vec<EntryData> {
+        self.files.iter()
+            .enumerate()
+            .map(|(index, entry)| EntryData::new(index, entry))
+            .collect()
+    }
+
+    /// Merges the entries of another `Config` into this one. Entries of
+    /// `other` which refer to the same remote file as an existing entry, or
+    /// which would be stored at the same path in the stall directory, are
+    /// resolved by the given [`MergePolicy`]. The other options of this
+    /// `Config` are kept.
+    ///
+    /// ### Errors
+    ///
+    /// Returns a [`MergeConflict`] error for the first duplicate entry if the
+    /// policy is [`MergePolicy::Error`]. The `Config` is unchanged in that
+    /// case.
+    ///
+    /// [`MergePolicy`]: enum.MergePolicy.html
+    /// [`MergeConflict`]: ../error/struct.MergeConflict.html
+    /// [`MergePolicy::Error`]: enum.MergePolicy.html#variant.Error
+    pub fn merge(&mut self, other: Config, policy: MergePolicy)
+        -> Result<MergeSummary, Error>
+    {
+        let mut files = self.files.clone();
+        let mut summary = MergeSummary::default();
+        for entry in other.files {
+            let remote = Location::remote(&entry).display_path();
+            let duplicate = files.iter().position(|existing| {
+                Location::remote(existing).display_path() == remote
+                    || (!entry.is_stall() && !existing.is_stall()
+                        && existing.file_name() == entry.file_name())
+            });
+            let index = match duplicate {
+                Some(index) => index,
+                None => {
+                    summary.added.push(remote);
+                    files.push(entry);
+                    continue;
+                },
+            };
+            let existing = Location::remote(&files[index]).display_path();
+            match policy {
+                MergePolicy::Error => return Err(MergeConflict {
+                    path: remote.into(),
+                    existing: existing.into(),
+                }.into()),
+                MergePolicy::Ours => summary.kept.push(existing),
+                MergePolicy::Theirs => {
+                    summary.replaced.push(existing);
+                    files[index] = entry;
+                },
+            }
+        }
+        self.files = files;
+        Ok(summary)
+    }
+
+    /// Compares the entries of this `Config` with those of another, returning
+    /// a [`ConfigDiff`] describing the changes made by `other`.
+    ///
+    /// Entries are matched by their remote paths. Unmatched entries which
+    /// would be stored at the same path in the stall directory are reported
+    /// as renamed.
+    ///
+    /// ```rust
+    /// # use stall::Config;
+    /// # use stall::Entry;
+    /// # use std::path::PathBuf;
+    /// let old = Config::builder()
+    ///     .entry(Entry::from(PathBuf::from("/home/a/.bashrc")))
+    ///     .entry(Entry::from(PathBuf::from("/home/a/.vimrc")))
+    ///     .build()?;
+    /// let new = Config::builder()
+    ///     .entry(Entry::from(PathBuf::from("/home/b/.bashrc")))
+    ///     .entry(Entry::from(PathBuf::from("/home/a/.gitconfig")))
+    ///     .build()?;
+    ///
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.added, vec![PathBuf::from("/home/a/.gitconfig")]);
+    /// assert_eq!(diff.removed, vec![PathBuf::from("/home/a/.vimrc")]);
+    /// assert_eq!(diff.renamed, vec![(
+    ///     PathBuf::from("/home/a/.bashrc"),
+    ///     PathBuf::from("/home/b/.bashrc"))]);
+    /// # Ok::<(), stall::error::Error>(())
+    /// ```
+    ///
+    /// [`ConfigDiff`]: struct.ConfigDiff.html
+    pub fn diff(&self, other: &Config) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        let mut matched = vec![false; self.files.len()];
+        let mut unmatched = Vec::new();
+        for entry in &other.files {
+            let remote = Location::remote(entry).display_path();
+            let index = self.files.iter().position(|existing|
+                Location::remote(existing).display_path() == remote);
+            match index {
+                Some(index) => {
+                    matched[index] = true;
+                    if self.files[index] != *entry {
+                        diff.changed.push(remote);
+                    }
+                },
+                None => unmatched.push(entry),
+            }
+        }
+
+        for entry in unmatched {
+            let remote = Location::remote(entry).display_path();
+            let index = self.files.iter()
+                .enumerate()
+                .position(|(index, existing)| !matched[index]
+                    && !entry.is_stall() && !existing.is_stall()
+                    && existing.file_name() == entry.file_name());
+            match index {
+                Some(index) => {
+                    matched[index] = true;
+                    diff.renamed.push((
+                        Location::remote(&self.files[index]).display_path(),
+                        remote));
+                },
+                None => diff.added.push(remote),
+            }
+        }
+
+        diff.removed = self.files.iter()
+            .zip(matched)
+            .filter(|(_, matched)| !matched)
+            .map(|(entry, _)| Location::remote(entry).display_path())
+            .collect();
+        diff
+    }
+
+    /// Checks the stall file for problems which loading it doesn't detect,
+    /// returning a [`Finding`] for each:
+    ///
+    /// + Entries for the same remote file.
+    /// + Entries which would be stored at the same path in the stall
+    ///   directory.
+    /// + Paths in the stall directory which are absolute or which escape it
+    ///   with `..`.
+    /// + Remote files which exist but can't be read.
+    ///
+    /// Entries are only reported as duplicates if their host and operating
+    /// system conditions allow both to apply on the same machine. Remote files
+    /// are only checked for entries which apply to the current machine and
+    /// which aren't privileged.
+    ///
+    /// [`Finding`]: struct.Finding.html
+    pub fn validate(&self) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (index, entry) in self.files.iter().enumerate() {
+            let remote = Location::remote(entry);
+            let path = remote.display_path();
+            let earlier = self.files[..index].iter()
+                .filter(|other| may_overlap(entry, other));
+            for other in earlier {
+                let other_path = Location::remote(other).display_path();
+                if other_path == path {
+                    findings.push(Finding::new(
+                        FindingKind::DuplicateRemote,
+                        &path,
+                        "the remote file has more than one entry"));
+                    break;
+                }
+                if !entry.is_stall() && !other.is_stall()
+                    && other.file_name() == entry.file_name()
+                {
+                    findings.push(Finding::new(
+                        FindingKind::DuplicateLocal,
+                        &path,
+                        format!("the entry would be stored at the same path \
+                            as the entry for {}", other_path.display())));
+                    break;
+                }
+            }
+
+            if entry.is_stall() {
+                findings.extend(check_local_path(&entry.path, "nested stall"));
+            } else if entry.is_enabled() && !entry.privileged {
+                let file = match &remote {
+                    Location::File(file)              => file,
+                    Location::Archived { archive, .. } => archive,
+                };
+                if is_unreadable(file) {
+                    findings.push(Finding::new(
+                        FindingKind::UnreadableRemote,
+                        &path,
+                        "the remote file exists but can't be read"));
+                }
+            }
+        }
+
+        findings.extend(self.age_identity.iter()
+            .filter_map(|path| check_local_path(path, "age identity")));
+        findings.extend(self.template_includes.iter()
+            .filter_map(|path| check_local_path(path, "template includes")));
+        findings
+    }
+
+    /// Returns the paths in the stall directory which may not be used to store
+    /// an [`Entry`], relative to the stall directory. These are the
+    /// [`RESERVED_PATHS`], the age identity file, the template includes
+    /// directory, and the directories of nested stalls.
+    ///
+    /// [`Entry`]: struct.Entry.html
+    /// [`RESERVED_PATHS`]: constant.RESERVED_PATHS.html
+    pub fn reserved_paths(&self) -> Vec<PathBuf> {
+        RESERVED_PATHS.iter()
+            .map(PathBuf::from)
+            .chain(self.age_identity.iter().cloned())
+            .chain(self.template_includes.iter().cloned())
+            .chain(self.files.iter()
+                .filter(|entry| entry.is_stall())
+                .map(|entry| entry.path.to_path_buf()))
+            .map(|path| normalize_key(&path))
+            .collect()
+    }
+
+    /// Returns the subdirectories of the stall directory which contain a
+    /// stall file but are not declared as nested stalls, in order of their
+    /// names. Hidden directories, [`reserved_paths`], and the stall copies of
+    /// entries are not searched.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the stall directory can't be read.
+    ///
+    /// [`reserved_paths`]: #method.reserved_paths
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn discovered_stalls(&self, stall_dir: &Path)
+        -> Result<Vec<PathBuf>, Error>
+    {
+        let reserved = self.reserved_paths();
+        let mut stalls = Vec::new();
+        let dir_entries = std::fs::read_dir(stall_dir)
+            .with_context(|| format!("Unable to read stall directory {:?}",
+                stall_dir))?;
+        for dir_entry in dir_entries {
+            let dir_entry = dir_entry
+                .with_context(|| format!("Unable to read stall directory \
+                    {:?}", stall_dir))?;
+            let name = dir_entry.file_name();
+            let skipped = name.to_string_lossy().starts_with('.')
+                || reserved.iter().any(|path| path.as_os_str() == name)
+                || self.files.iter()
+                    .any(|entry| entry.file_name() == Some(name.as_os_str()));
+            let path = dir_entry.path();
+            let declared = self.files.iter()
+                .any(|entry| entry.is_stall()
+                    && entry.stall_dir(stall_dir) == path);
+            if skipped || declared { continue }
+            if path.join(DEFAULT_CONFIG_PATH).is_file() {
+                stalls.push(path);
+            }
+        }
+        stalls.sort();
+        Ok(stalls)
+    }
+
+    /// Checks that no entry would be stored at one of the
+    /// [`reserved_paths`].
+    ///
+    /// ### Errors
+    ///
+    /// Returns a [`ReservedPath`] error for the first entry which would be
+    /// stored at a reserved path.
+    ///
+    /// [`reserved_paths`]: #method.reserved_paths
+    /// [`ReservedPath`]: ../error/struct.ReservedPath.html
+    pub fn check_reserved_paths(&self) -> Result<(), Error> {
+        let reserved = self.reserved_paths();
+        for entry in self.files.iter().filter(|entry| !entry.is_stall()) {
+            let name = match entry.file_name() {
+                Some(name) => Path::new(name),
+                None       => continue,
+            };
+            if let Some(path) = reserved.iter().find(|path| *path == name) {
+                return Err(ReservedPath {
+                    path: entry.path.clone(),
+                    reserved: path.clone().into(),
+                }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the modes of each entry which does not provide its own to the
+    /// default modes, masked by the umask. Modes provided by entries are
+    /// applied as written.
+    pub fn apply_default_modes(&mut self) {
+        let umask = umask();
+        let masked = |mode: Option<Mode>| mode
+            .map(|mode| Mode(mode.0 & !umask));
+        let file_mode = masked(self.default_file_mode);
+        let dir_mode = masked(self.default_dir_mode);
+        for entry in &mut self.files {
+            entry.mode = entry.mode.or(file_mode);
+            entry.dir_mode = entry.dir_mode.or(dir_mode);
+        }
+    }
+
     /// Returns the default [`LoggerConfig`].
     ///
     /// [`LoggerConfig`]: ../logger/struct.LoggerConfig.html
@@ -165,13 +1161,45 @@ impl Config {
         Default::default()
     }
 
+    /// Returns true if the logger configuration is the default.
+    fn is_default_logger_config(logger_config: &LoggerConfig) -> bool {
+        *logger_config == Config::default_logger_config()
+    }
+
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            version: CONFIG_VERSION,
             logger_config: Config::default_logger_config(),
             log_levels: Config::default_log_levels(),
+            default_file_mode: None,
+            default_dir_mode: None,
+            timestamps: Timestamps::default(),
+            order: EntryOrder::default(),
+            escalation: Escalation::default(),
+            policy: Policy::default(),
+            compare: CompareMode::default(),
+            compare_stages: Vec::new(),
+            copy_method: CopyMethod::default(),
+            mtime_tolerance: Duration::ZERO,
+            limits: WalkLimits::default(),
+            auto_snapshot: AutoSnapshot::default(),
+            large_plan: LargePlan::default(),
+            theme: Theme::default(),
+            icons: false,
+            encryption_backend: EncryptionBackend::default(),
+            age_identity: None,
+            age_recipients: Vec::new(),
+            gpg_recipients: Vec::new(),
+            variables: BTreeMap::new(),
+            template_engine: TemplateEngine::default(),
+            template_includes: None,
+            template_markers: false,
+            fleet: false,
+            notifiers: Vec::new(),
+            sort_entries: false,
             files: Vec::new(),
         }
     }
@@ -186,3 +1214,1926 @@ impl std::fmt::Display for Config {
         writeln!(fmt, "\tfiles: {:?}", self.files)
     }
 }
+
+impl std::ops::Index<usize> for Config {
+    type Output = Entry;
+
+    fn index(&self, index: usize) -> &Entry {
+        &self.files[index]
+    }
+}
+
+impl<'c> IntoIterator for &'c Config {
+    type Item = &'c Entry;
+    type IntoIter = std::slice::Iter<'c, Entry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.files.iter()
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MergePolicy
+////////////////////////////////////////////////////////////////////////////////
+/// The policy for resolving duplicate entries when merging stall files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum MergePolicy {
+    /// Duplicate entries are reported as errors.
+    #[default]
+    Error,
+    /// The existing entry is kept.
+    Ours,
+    /// The existing entry is replaced by the merged one.
+    Theirs,
+}
+
+impl MergePolicy {
+    /// The names of the available policies.
+    pub const NAMES: &'static [&'static str] = &["error", "ours", "theirs"];
+}
+
+impl std::str::FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error"  => Ok(MergePolicy::Error),
+            "ours"   => Ok(MergePolicy::Ours),
+            "theirs" => Ok(MergePolicy::Theirs),
+            _        => Err(format!("invalid merge policy: {}", s)),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MergeSummary
+////////////////////////////////////////////////////////////////////////////////
+/// The changes made by [`Config::merge`], listed by the remote paths of the
+/// entries.
+///
+/// [`Config::merge`]: struct.Config.html#method.merge
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeSummary {
+    /// The entries which were added.
+    pub added: Vec<PathBuf>,
+    /// The existing entries which were replaced by duplicates.
+    pub replaced: Vec<PathBuf>,
+    /// The existing entries which were kept in place of duplicates.
+    pub kept: Vec<PathBuf>,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ConfigDiff
+////////////////////////////////////////////////////////////////////////////////
+/// The differences between the entries of two stall files returned by
+/// [`Config::diff`], listed by the remote paths of the entries.
+///
+/// [`Config::diff`]: struct.Config.html#method.diff
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// The entries which were added.
+    pub added: Vec<PathBuf>,
+    /// The entries which were removed.
+    pub removed: Vec<PathBuf>,
+    /// The entries whose remote paths changed, given as the old and new
+    /// remote paths.
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+    /// The entries whose options changed.
+    pub changed: Vec<PathBuf>,
+}
+
+impl ConfigDiff {
+    /// Returns true if there are no differences.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Finding
+////////////////////////////////////////////////////////////////////////////////
+/// A problem with a stall file found by [`Config::validate`].
+///
+/// [`Config::validate`]: struct.Config.html#method.validate
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize)]
+pub struct Finding {
+    /// The kind of problem.
+    pub kind: FindingKind,
+    /// The remote path of the entry, or the path of the setting, with the
+    /// problem.
+    pub path: PathBuf,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl Finding {
+    /// Constructs a new `Finding`.
+    fn new<M>(kind: FindingKind, path: &Path, message: M) -> Self
+        where M: Into<String>
+    {
+        Finding { kind, path: path.to_path_buf(), message: message.into() }
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+/// The kinds of problems found by [`Config::validate`].
+///
+/// [`Config::validate`]: struct.Config.html#method.validate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FindingKind {
+    /// Two entries would be stored at the same path in the stall directory.
+    DuplicateLocal,
+    /// Two entries refer to the same remote file.
+    DuplicateRemote,
+    /// A path in the stall directory escapes it with `..`.
+    EscapesStall,
+    /// A path in the stall directory is absolute.
+    AbsoluteLocal,
+    /// A remote file exists but can't be read.
+    UnreadableRemote,
+}
+
+impl FindingKind {
+    /// Returns the name of the kind of problem.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FindingKind::DuplicateLocal   => "duplicate-local",
+            FindingKind::DuplicateRemote  => "duplicate-remote",
+            FindingKind::EscapesStall     => "escapes-stall",
+            FindingKind::AbsoluteLocal    => "absolute-local",
+            FindingKind::UnreadableRemote => "unreadable-remote",
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ConfigFormat
+////////////////////////////////////////////////////////////////////////////////
+/// The formats in which a stall file may be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConfigFormat {
+    /// The RON format, supporting all options.
+    #[default]
+    Ron,
+    /// The newline-delimited file list format, supporting only the paths of
+    /// entries without any other options.
+    List,
+    /// The JSON format, supporting all options, for stall files generated or
+    /// read by other tools.
+    Json,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ConfigBuilder
+////////////////////////////////////////////////////////////////////////////////
+/// A builder for constructing a [`Config`] programmatically, for tools which
+/// generate stall files.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::Config;
+/// # use stall::ConfigFormat;
+/// let config = Config::builder()
+///     .file("~/.bashrc")
+///     .file("~/.config/fish/config.fish")
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(config.to_text(ConfigFormat::List).unwrap(),
+///     "~/.bashrc\n~/.config/fish/config.fish\n");
+///
+/// assert!(Config::builder().file(".stall").build().is_err());
+/// ```
+///
+/// [`Config`]: struct.Config.html
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder {
+    /// The config being built.
+    config: Config,
+    /// The path the stall file is written to.
+    load_path: Option<PathBuf>,
+    /// The format the stall file is written in.
+    format: ConfigFormat,
+}
+
+impl ConfigBuilder {
+    /// Constructs a new `ConfigBuilder` for a [`Config`] with the default
+    /// options and no entries.
+    ///
+    /// [`Config`]: struct.Config.html
+    pub fn new() -> Self {
+        ConfigBuilder::default()
+    }
+
+    /// Adds an entry for the given path with no other options.
+    pub fn file<P>(self, path: P) -> Self
+        where P: Into<PathBuf>
+    {
+        self.entry(Entry::from(path.into()))
+    }
+
+    /// Adds the given [`Entry`].
+    ///
+    /// [`Entry`]: struct.Entry.html
+    pub fn entry(mut self, entry: Entry) -> Self {
+        self.config.files.push(entry);
+        self
+    }
+
+    /// Adds each of the given [`Entry`]s.
+    ///
+    /// [`Entry`]: struct.Entry.html
+    pub fn entries<I>(mut self, entries: I) -> Self
+        where I: IntoIterator<Item=Entry>
+    {
+        self.config.files.extend(entries);
+        self
+    }
+
+    /// Sets the path the stall file is written to by [`write`]. This is
+    /// usually the [`DEFAULT_CONFIG_PATH`] within a stall directory.
+    ///
+    /// [`write`]: #method.write
+    /// [`DEFAULT_CONFIG_PATH`]: constant.DEFAULT_CONFIG_PATH.html
+    pub fn load_path<P>(mut self, path: P) -> Self
+        where P: Into<PathBuf>
+    {
+        self.load_path = Some(path.into());
+        self
+    }
+
+    /// Sets the [`ConfigFormat`] the stall file is written in by [`write`].
+    ///
+    /// [`ConfigFormat`]: enum.ConfigFormat.html
+    /// [`write`]: #method.write
+    pub fn format(mut self, format: ConfigFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Returns the built [`Config`].
+    ///
+    /// ### Errors
+    ///
+    /// Returns a [`ReservedPath`] error if an entry would be stored at a
+    /// reserved path.
+    ///
+    /// [`Config`]: struct.Config.html
+    /// [`ReservedPath`]: ../error/struct.ReservedPath.html
+    pub fn build(self) -> Result<Config, Error> {
+        self.config.check_reserved_paths()?;
+        Ok(self.config)
+    }
+
+    /// Writes the built [`Config`] to the load path in the chosen format, and
+    /// returns it.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if no load path is set, if the [`Config`] can't be
+    /// built or expressed in the chosen format, or if the file can't be
+    /// written.
+    ///
+    /// [`Config`]: struct.Config.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn write(self) -> Result<Config, Error> {
+        let path = self.load_path.clone()
+            .ok_or_else(|| Error::msg("no load path set for stall file"))?;
+        let format = self.format;
+        let config = self.build()?;
+        config.write_to(&path, format)?;
+        Ok(config)
+    }
+}
+
+impl From<Config> for ConfigBuilder {
+    fn from(config: Config) -> Self {
+        ConfigBuilder {
+            config,
+            .. Default::default()
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Entry
+////////////////////////////////////////////////////////////////////////////////
+/// A file entry in the stall file.
+///
+/// Entries may be written in the stall file as a plain path, or as a struct
+/// with additional options:
+///
+/// ```ron
+/// files: [
+///     "~/.bashrc",
+///     (path: "~/.themes/bundle.zip", member: "bundle/colors.conf"),
+///     (path: "~/.cache/thumbnails", max_bytes: 10000000, max_seconds: 30),
+///     (path: "~/.cache/history", direction: "collect-only"),
+///     (path: "~/.config/host.conf", optional: true),
+///     (path: "~/.config/old-editor", enabled: false),
+///     (path: "~/.local/share/notes", expect_sync: "7d"),
+///     (path: "~/.ssh/config", mode: "600"),
+///     (path: "~/AppData/Roaming/Code/User/settings.json", os: "windows"),
+///     (path: "~/.config/wsl.conf", env: ["WSL_DISTRO_NAME", "!CI"]),
+///     (path: "apps/editor", kind: "stall"),
+///     (path: "/etc/hosts", privileged: true, owner: "root", group: "root"),
+///     (path: "~/.config/fish/functions", post_hook: "fish -c fish_update"),
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    /// The path of the file outside of the stall directory. For nested
+    /// stalls, the path of the nested stall directory, relative to the stall
+    /// directory.
+    pub path: Box<Path>,
+
+    /// The kind of the entry.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub kind: EntryKind,
+
+    /// Whether the entry is enabled. Disabled entries are skipped by every
+    /// command without losing their options or recorded state, and are shown
+    /// as `disabled` in status output.
+    #[serde(default = "default_enabled", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+
+    /// The path of a file within the archive at `path`. If provided, the
+    /// entry refers to the archived file rather than the archive itself.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub member: Option<Box<Path>>,
+
+    /// The maximum number of bytes to copy for the entry. Entries exceeding
+    /// this budget are skipped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_bytes: Option<u64>,
+
+    /// The maximum number of seconds to spend copying the entry. Entries
+    /// exceeding this budget are abandoned and skipped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub max_seconds: Option<u64>,
+
+    /// The directions in which the entry may be copied.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub direction: Direction,
+
+    /// Whether the entry may be missing. Missing optional entries are skipped
+    /// without error, even if warnings are promoted to errors.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub optional: bool,
+
+    /// The longest the entry is expected to go without being collected or
+    /// distributed, such as `"7d"`. The status of entries which have not been
+    /// synced within this time includes a warning, catching entries which
+    /// have silently stopped syncing.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub expect_sync: Option<Cadence>,
+
+    /// The mode to apply to files created for the entry.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mode: Option<Mode>,
+
+    /// The mode to apply to directories created for the entry.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub dir_mode: Option<Mode>,
+
+    /// The operating system the entry applies to. If not provided, the entry
+    /// applies to all operating systems.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub os: Option<Os>,
+
+    /// The names of the hosts the entry applies to. If empty, the entry
+    /// applies to all hosts.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub hosts: Vec<String>,
+
+    /// Environment variable conditions which must all hold for the entry to
+    /// apply.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub env: Vec<EnvPredicate>,
+
+    /// Tags used to group the entry in status output.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub tags: Vec<String>,
+
+    /// A description of the entry, shown below it in status output.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub description: Option<String>,
+
+    /// The user to own the files distributed for the entry.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub owner: Option<String>,
+
+    /// The group to own the files distributed for the entry.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub group: Option<String>,
+
+    /// Whether the entry requires elevated privileges to distribute. The
+    /// commands which copy the file and apply its mode and ownership are run
+    /// with the configured escalation program.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub privileged: bool,
+
+    /// Whether the contents of the entry are sensitive. The contents of
+    /// sensitive entries are never shown in diffs.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub sensitive: bool,
+
+    /// Whether the stall copy is encrypted. Encrypted entries are encrypted
+    /// with the configured backend when collected and decrypted when
+    /// distributed.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub encrypted: bool,
+
+    /// Whether the stall copy is a template. Templates are rendered with the
+    /// stall file's variables when distributed, and are never collected.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub template: bool,
+
+    /// The checksum the contents of the entry must match, for files which
+    /// should never change. The stall copy is checked against the pin before
+    /// it is distributed, and collect refuses to overwrite it with different
+    /// contents unless the `--repin` option is given.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub pin: Option<Pin>,
+
+    /// A shell command used to decide whether the source and target of the
+    /// entry differ, for files whose formatting changes are irrelevant. The
+    /// paths of the files are passed in the `STALL_COMPARE_SOURCE` and
+    /// `STALL_COMPARE_TARGET` environment variables. An exit code of 0 means
+    /// the files are the same, and 1 means they differ.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub compare_command: Option<String>,
+
+    /// The stages of the pipeline used to compare the files of the entry, in
+    /// place of the stages or strategy of the stall file.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub compare_stages: Vec<CompareStage>,
+
+    /// A shell command to run before the entry is copied. The entry is not
+    /// copied if the command fails.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub pre_hook: Option<String>,
+
+    /// A shell command to run after the entry is copied.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub post_hook: Option<String>,
+}
+
+impl Entry {
+    /// Returns the file name to use for the entry in the stall directory.
+    pub fn file_name(&self) -> Option<&OsStr> {
+        match &self.member {
+            Some(member) => member.file_name(),
+            None         => self.path.file_name(),
+        }
+    }
+
+    /// Returns true if any option of the entry other than its path differs
+    /// from the default.
+    pub fn has_options(&self) -> bool {
+        *self != Entry {
+            path: self.path.clone(),
+            .. Entry::from(PathBuf::new())
+        }
+    }
+
+    /// Returns true if the entry refers to a nested stall.
+    pub fn is_stall(&self) -> bool {
+        self.kind == EntryKind::Stall
+    }
+
+    /// Returns the directory of a nested stall entry, given the directory of
+    /// the stall containing it.
+    pub fn stall_dir(&self, parent: &Path) -> PathBuf {
+        parent.join(&self.path)
+    }
+
+    /// Returns true if the contents of the entry should not be shown. This is
+    /// true for entries marked sensitive and for encrypted entries.
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitive || self.encrypted
+    }
+
+    /// Returns true if the entry is enabled and applies to the current host,
+    /// operating system, and environment.
+    pub fn is_enabled(&self) -> bool {
+        self.is_enabled_on(&Platform::current())
+    }
+
+    /// Returns true if the entry is enabled and applies to the given
+    /// [`Platform`]. Environment conditions are always evaluated in the
+    /// current environment.
+    ///
+    /// [`Platform`]: struct.Platform.html
+    pub fn is_enabled_on(&self, platform: &Platform) -> bool {
+        self.enabled && self.applies_to(platform)
+    }
+
+    /// Returns true if the entry applies to the given [`Platform`], whether
+    /// or not it is enabled.
+    ///
+    /// [`Platform`]: struct.Platform.html
+    pub fn applies_to(&self, platform: &Platform) -> bool {
+        self.os.is_none_or(|os| Some(os) == platform.os)
+            && (self.hosts.is_empty() || self.hosts.contains(&platform.host))
+            && self.env.iter().all(EnvPredicate::holds)
+    }
+}
+
+impl From<PathBuf> for Entry {
+    fn from(path: PathBuf) -> Self {
+        Entry {
+            path: normalize_key(&path).into(),
+            kind: EntryKind::default(),
+            enabled: true,
+            member: None,
+            max_bytes: None,
+            max_seconds: None,
+            direction: Direction::default(),
+            optional: false,
+            expect_sync: None,
+            mode: None,
+            dir_mode: None,
+            os: None,
+            hosts: Vec::new(),
+            env: Vec::new(),
+            tags: Vec::new(),
+            description: None,
+            owner: None,
+            group: None,
+            privileged: false,
+            sensitive: false,
+            encrypted: false,
+            template: false,
+            pin: None,
+            compare_command: None,
+            compare_stages: Vec::new(),
+            pre_hook: None,
+            post_hook: None,
+        }
+    }
+}
+
+/// Deserializes a non-negative number of seconds as a `Duration`.
+fn deserialize_seconds<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where D: Deserializer<'de>
+{
+    use serde::de::Error as _;
+    let seconds = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|_| D::Error::custom(format!("invalid number of seconds: \
+            {}", seconds)))
+}
+
+/// Serializes a `Duration` as a number of seconds.
+fn serialize_seconds<S>(duration: &Duration, serializer: S)
+    -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Returns true if the value is the default for its type, so that options
+/// left at their defaults are omitted from serialized stall files.
+fn is_default<T>(value: &T) -> bool
+    where T: Default + PartialEq
+{
+    *value == T::default()
+}
+
+/// Returns true, the default for the `enabled` option of entries.
+fn default_enabled() -> bool {
+    true
+}
+
+/// Returns true if an entry is enabled, so that the `enabled` option is
+/// omitted from serialized entries unless it is false.
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
+}
+
+/// Returns true if both entries may apply on the same machine, given their
+/// host and operating system conditions.
+fn may_overlap(a: &Entry, b: &Entry) -> bool {
+    let os = a.os.is_none() || b.os.is_none() || a.os == b.os;
+    let hosts = a.hosts.is_empty() || b.hosts.is_empty()
+        || a.hosts.iter().any(|host| b.hosts.contains(host));
+    os && hosts
+}
+
+/// Returns a [`Finding`] if the given path, relative to the stall directory,
+/// is absolute or escapes the stall directory.
+///
+/// [`Finding`]: struct.Finding.html
+fn check_local_path(path: &Path, description: &str) -> Option<Finding> {
+    if path.is_absolute() {
+        return Some(Finding::new(
+            FindingKind::AbsoluteLocal,
+            path,
+            format!("the {} path is absolute, but should be relative to the \
+                stall directory", description)));
+    }
+    match path.components().any(|c| c == Component::ParentDir) {
+        true  => Some(Finding::new(
+            FindingKind::EscapesStall,
+            path,
+            format!("the {} path is outside of the stall directory",
+                description))),
+        false => None,
+    }
+}
+
+/// Returns true if the file at the given path exists but can't be read.
+fn is_unreadable(path: &Path) -> bool {
+    match path.metadata() {
+        Err(e) => e.kind() == std::io::ErrorKind::PermissionDenied,
+        Ok(meta) if meta.is_dir() => std::fs::read_dir(path).is_err(),
+        Ok(_) => File::open(path).is_err(),
+    }
+}
+
+/// Serializes a list of [`Entry`]s, writing those without options as bare
+/// paths.
+///
+/// [`Entry`]: struct.Entry.html
+fn serialize_entries<S>(entries: &[Entry], serializer: S)
+    -> Result<S::Ok, S::Error>
+    where S: Serializer
+{
+    /// The serialized representations of an [`Entry`].
+    ///
+    /// [`Entry`]: struct.Entry.html
+    #[derive(Serialize)]
+    #[serde(untagged)]
+    enum EntryRepr<'e> {
+        /// An entry written as a bare path.
+        Path(&'e Path),
+        /// An entry written with options.
+        Entry(&'e Entry),
+    }
+
+    serializer.collect_seq(entries.iter()
+        .map(|entry| match entry.has_options() {
+            true  => EntryRepr::Entry(entry),
+            false => EntryRepr::Path(&entry.path),
+        }))
+}
+
+/// A line of a stall file in the RON format which comments may be kept
+/// above when it is rewritten.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum CommentAnchor {
+    /// The line setting the top-level option with the given name.
+    Option(String),
+    /// The first line of the entry with the given path.
+    Entry(PathBuf),
+}
+
+/// Returns the [`CommentAnchor`] of each of the lines of a stall file in the
+/// RON format, if it sets a top-level option or begins an entry.
+///
+/// [`CommentAnchor`]: enum.CommentAnchor.html
+fn ron_anchors(lines: &[&str]) -> Vec<Option<CommentAnchor>> {
+    let mut anchors = Vec::with_capacity(lines.len());
+    let mut depths = Vec::with_capacity(lines.len());
+    let mut depth = 0;
+    for line in lines {
+        depths.push(depth);
+        depth += bracket_depth_change(line);
+    }
+
+    let mut in_files = false;
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let anchor = match depths[index] {
+            1 => field_name(trimmed).map(|name| {
+                in_files = name == "files";
+                CommentAnchor::Option(name.into())
+            }),
+            2 if in_files && trimmed.starts_with('"') => first_string(trimmed)
+                .map(|path| CommentAnchor::Entry(
+                    normalize_key(Path::new(&path)))),
+            // The path of an entry with options may be on a later line.
+            2 if in_files && trimmed.starts_with('(') => {
+                let end = (index + 1..lines.len())
+                    .find(|later| depths[*later] <= 2)
+                    .unwrap_or(lines.len());
+                lines[index..end].iter()
+                    .map(|line| line.trim().trim_start_matches('('))
+                    .find(|line| field_name(line) == Some("path"))
+                    .and_then(first_string)
+                    .map(|path| CommentAnchor::Entry(
+                        normalize_key(Path::new(&path))))
+            },
+            _ => None,
+        };
+        anchors.push(anchor);
+    }
+    anchors
+}
+
+/// Returns the change in the depth of brackets over a line of RON text,
+/// ignoring brackets in strings and comments.
+fn bracket_depth_change(line: &str) -> i32 {
+    let mut change = 0;
+    let mut in_string = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_string) {
+            ('\\', true) => { let _ = chars.next(); },
+            ('"', _) => in_string = !in_string,
+            ('/', false) if chars.peek() == Some(&'/') => break,
+            ('(' | '[' | '{', false) => change += 1,
+            (')' | ']' | '}', false) => change -= 1,
+            _ => (),
+        }
+    }
+    change
+}
+
+/// Returns the name of the option set by a line of RON text, if it begins
+/// with one.
+fn field_name(line: &str) -> Option<&str> {
+    let (name, _) = line.split_once(':')?;
+    let identifier = !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    identifier.then_some(name)
+}
+
+/// Returns the contents of the first string in a line of RON text.
+fn first_string(line: &str) -> Option<String> {
+    let mut chars = line.chars().skip_while(|c| *c != '"').skip(1);
+    let mut string = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => string.push(chars.next()?),
+            '"'  => return Some(string),
+            c    => string.push(c),
+        }
+    }
+    None
+}
+
+/// Returns an [`UnsupportedVersion`] error if the given schema version is
+/// newer than the [`CONFIG_VERSION`].
+///
+/// [`UnsupportedVersion`]: ../error/struct.UnsupportedVersion.html
+/// [`CONFIG_VERSION`]: constant.CONFIG_VERSION.html
+fn check_version(version: u32) -> Result<(), Error> {
+    match version > CONFIG_VERSION {
+        true  => Err(UnsupportedVersion {
+            version,
+            supported: CONFIG_VERSION,
+        }.into()),
+        false => Ok(()),
+    }
+}
+
+/// Deserializes a list of [`Entry`]s, each of which may be given as a bare
+/// path or as a struct.
+///
+/// [`Entry`]: struct.Entry.html
+fn deserialize_entries<'de, D>(deserializer: D) -> Result<Vec<Entry>, D::Error>
+    where D: Deserializer<'de>
+{
+    /// The serialized representations of an [`Entry`].
+    ///
+    /// [`Entry`]: struct.Entry.html
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum EntryRepr {
+        /// An entry given as a bare path.
+        Path(PathBuf),
+        /// An entry given with options.
+        Entry(Box<Entry>),
+    }
+
+    let entries = Vec::<EntryRepr>::deserialize(deserializer)?;
+    Ok(entries.into_iter()
+        .map(|repr| match repr {
+            EntryRepr::Path(path)   => Entry::from(path),
+            EntryRepr::Entry(mut entry) => {
+                entry.path = normalize_key(&entry.path).into();
+                entry.member = entry.member
+                    .map(|member| normalize_key(&member).into());
+                *entry
+            },
+        })
+        .collect())
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EntryData
+////////////////////////////////////////////////////////////////////////////////
+/// An owned description of an [`Entry`] of a stall file, with the locations
+/// of its files resolved, for library users which keep entries apart from the
+/// [`Config`] they were read from.
+///
+/// [`Entry`]: struct.Entry.html
+/// [`Config`]: struct.Config.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub struct EntryData {
+    /// The index of the entry in the stall file.
+    pub index: usize,
+    /// The path of the stall copy of the entry, relative to the stall
+    /// directory. This is `None` for entries whose path has no file name.
+    pub local: Option<PathBuf>,
+    /// The path of the file outside of the stall directory. For archive
+    /// members, this is the path of the member within the archive.
+    pub remote: PathBuf,
+    /// The entry, holding its flags and other options.
+    pub entry: Entry,
+}
+
+impl EntryData {
+    /// Constructs the `EntryData` of the given [`Entry`], found at the given
+    /// index in the stall file.
+    ///
+    /// [`Entry`]: struct.Entry.html
+    pub fn new(index: usize, entry: &Entry) -> Self {
+        let local = match entry.is_stall() {
+            true  => Some(entry.path.to_path_buf()),
+            false => entry.file_name().map(PathBuf::from),
+        };
+        EntryData {
+            index,
+            local,
+            remote: Location::remote(entry).display_path(),
+            entry: entry.clone(),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// normalize_key
+////////////////////////////////////////////////////////////////////////////////
+/// Normalizes an entry path so that equivalent spellings of the same path
+/// compare equal. `.` components, repeated separators, and trailing
+/// separators are removed. On Windows, `/` and `\` separators are treated
+/// equally.
+///
+/// `..` components are kept, as they can't be resolved without accessing the
+/// file system.
+///
+/// ```rust
+/// # use stall::normalize_key;
+/// # use std::path::Path;
+/// assert_eq!(normalize_key(Path::new("./foo")), Path::new("foo"));
+/// assert_eq!(normalize_key(Path::new("foo/")), Path::new("foo"));
+/// assert_eq!(normalize_key(Path::new("foo//bar/./baz")),
+///     Path::new("foo/bar/baz"));
+/// assert_eq!(normalize_key(Path::new("/foo/../bar")),
+///     Path::new("/foo/../bar"));
+/// assert_eq!(normalize_key(Path::new("./")), Path::new("."));
+/// ```
+///
+/// ```rust
+/// # use stall::normalize_key;
+/// # use std::path::Path;
+/// if cfg!(windows) {
+///     assert_eq!(normalize_key(Path::new(r"foo\bar\")),
+///         normalize_key(Path::new("foo/bar")));
+/// }
+/// ```
+pub fn normalize_key(path: &Path) -> PathBuf {
+    let key: PathBuf = path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect();
+    match key.as_os_str().is_empty() {
+        true  => PathBuf::from("."),
+        false => key,
+    }
+}
+
+
+/// Returns the names by which the given entries may be selected, for
+/// suggesting the intended entry when a path matches none of them. These are
+/// the file name of each entry in the stall directory, its normalized path,
+/// and its path relative to the home directory, as in `~/.bashrc`.
+pub(crate) fn entry_candidates(entries: &[Entry]) -> Vec<String> {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from);
+    let mut candidates = Vec::new();
+    for entry in entries {
+        let key = normalize_key(&entry.path);
+        if let Some(name) = entry.file_name() {
+            candidates.push(name.to_string_lossy().into_owned());
+        }
+        if let Some(rel) = home.as_ref()
+            .and_then(|home| key.strip_prefix(home).ok())
+        {
+            candidates.push(Path::new("~").join(rel).display().to_string());
+        }
+        candidates.push(key.display().to_string());
+    }
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EntryKind
+////////////////////////////////////////////////////////////////////////////////
+/// The kind of an [`Entry`].
+///
+/// Kinds are written in the stall file as strings: `"file"` or `"stall"`.
+///
+/// [`Entry`]: struct.Entry.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EntryKind {
+    /// The entry is a file or directory to be copied.
+    #[default]
+    File,
+    /// The entry is another stall directory, whose entries are processed
+    /// along with those of this stall.
+    Stall,
+}
+
+impl EntryKind {
+    /// Returns the name of the kind as written in the stall file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::File  => "file",
+            EntryKind::Stall => "stall",
+        }
+    }
+}
+
+impl Serialize for EntryKind {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Direction
+////////////////////////////////////////////////////////////////////////////////
+/// The directions in which an [`Entry`] may be copied.
+///
+/// Directions are written in the stall file as strings: `"both"`,
+/// `"collect-only"`, or `"distribute-only"`.
+///
+/// [`Entry`]: struct.Entry.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    /// The entry may be collected and distributed.
+    #[default]
+    Both,
+    /// The entry may only be collected into the stall directory.
+    CollectOnly,
+    /// The entry may only be distributed from the stall directory.
+    DistributeOnly,
+}
+
+impl Direction {
+    /// Returns the name of the direction as written in the stall file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Both           => "both",
+            Direction::CollectOnly    => "collect-only",
+            Direction::DistributeOnly => "distribute-only",
+        }
+    }
+
+    /// Returns true if the entry may be collected.
+    pub fn allows_collect(&self) -> bool {
+        *self != Direction::DistributeOnly
+    }
+
+    /// Returns true if the entry may be distributed.
+    pub fn allows_distribute(&self) -> bool {
+        *self != Direction::CollectOnly
+    }
+}
+
+impl Serialize for Direction {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(self.name())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Os
+////////////////////////////////////////////////////////////////////////////////
+/// An operating system an [`Entry`] may be restricted to.
+///
+/// Operating systems are written in the stall file as strings: `"linux"`,
+/// `"macos"`, or `"windows"`.
+///
+/// [`Entry`]: struct.Entry.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Os {
+    /// Linux.
+    Linux,
+    /// macOS.
+    MacOs,
+    /// Windows.
+    Windows,
+}
+
+impl Os {
+    /// The names of the operating systems.
+    pub const NAMES: &'static [&'static str] = &["linux", "macos", "windows"];
+
+    /// Returns the current operating system, or `None` if it is not one of
+    /// the supported operating systems.
+    pub fn current() -> Option<Self> {
+        if cfg!(target_os = "linux") {
+            Some(Os::Linux)
+        } else if cfg!(target_os = "macos") {
+            Some(Os::MacOs)
+        } else if cfg!(target_os = "windows") {
+            Some(Os::Windows)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for Os {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Os::Linux   => write!(f, "linux"),
+            Os::MacOs   => write!(f, "macos"),
+            Os::Windows => write!(f, "windows"),
+        }
+    }
+}
+
+impl Serialize for Os {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl std::str::FromStr for Os {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linux"   => Ok(Os::Linux),
+            "macos"   => Ok(Os::MacOs),
+            "windows" => Ok(Os::Windows),
+            _         => Err(format!("invalid operating system: {}", s)),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Platform
+////////////////////////////////////////////////////////////////////////////////
+/// A machine whose host and operating system conditions an [`Entry`] may be
+/// evaluated for.
+///
+/// [`Entry`]: struct.Entry.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Platform {
+    /// The name of the host.
+    pub host: String,
+    /// The operating system, or `None` if it is not one of the supported
+    /// operating systems.
+    pub os: Option<Os>,
+}
+
+impl Platform {
+    /// Returns the `Platform` of the current machine.
+    pub fn current() -> Self {
+        Platform {
+            host: host_name(),
+            os: Os::current(),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EnvPredicate
+////////////////////////////////////////////////////////////////////////////////
+/// A condition on an environment variable which an [`Entry`] may depend on.
+///
+/// Predicates are written in the stall file as strings:
+///
+/// + `"NAME"`: The variable is set.
+/// + `"!NAME"`: The variable is not set.
+/// + `"NAME=value"`: The variable is set to `value`.
+/// + `"NAME!=value"`: The variable is not set to `value`.
+///
+/// [`Entry`]: struct.Entry.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvPredicate {
+    /// The variable is set.
+    Exists(String),
+    /// The variable is not set.
+    Missing(String),
+    /// The variable is set to the given value.
+    Equals(String, String),
+    /// The variable is not set to the given value.
+    NotEquals(String, String),
+}
+
+impl EnvPredicate {
+    /// Returns true if the predicate holds in the current environment.
+    pub fn holds(&self) -> bool {
+        use EnvPredicate::*;
+        match self {
+            Exists(name)           => std::env::var_os(name).is_some(),
+            Missing(name)          => std::env::var_os(name).is_none(),
+            Equals(name, value)    => std::env::var(name)
+                .is_ok_and(|v| v == *value),
+            NotEquals(name, value) => std::env::var(name)
+                .map_or(true, |v| v != *value),
+        }
+    }
+}
+
+impl std::fmt::Display for EnvPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use EnvPredicate::*;
+        match self {
+            Exists(name)           => write!(f, "{}", name),
+            Missing(name)          => write!(f, "!{}", name),
+            Equals(name, value)    => write!(f, "{}={}", name, value),
+            NotEquals(name, value) => write!(f, "{}!={}", name, value),
+        }
+    }
+}
+
+impl std::str::FromStr for EnvPredicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let predicate = if let Some(idx) = s.find("!=") {
+            EnvPredicate::NotEquals(s[..idx].into(), s[idx + 2..].into())
+        } else if let Some(idx) = s.find('=') {
+            EnvPredicate::Equals(s[..idx].into(), s[idx + 1..].into())
+        } else if let Some(name) = s.strip_prefix('!') {
+            EnvPredicate::Missing(name.into())
+        } else {
+            EnvPredicate::Exists(s.into())
+        };
+
+        match predicate {
+            EnvPredicate::Exists(name)
+                | EnvPredicate::Missing(name)
+                | EnvPredicate::Equals(name, _)
+                | EnvPredicate::NotEquals(name, _) if name.is_empty()
+                => Err(format!("invalid environment condition: {:?}", s)),
+            _ => Ok(predicate),
+        }
+    }
+}
+
+impl Serialize for EnvPredicate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for EnvPredicate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Timestamps
+////////////////////////////////////////////////////////////////////////////////
+/// The strategy for setting the modification time of copied files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum Timestamps {
+    /// Copied files are given the modification time of their source.
+    Preserve,
+    /// Copied files are given the time of the copy as their modification time.
+    #[default]
+    Touch,
+}
+
+impl Timestamps {
+    /// The names of the available strategies.
+    pub const NAMES: &'static [&'static str] = &["preserve", "touch"];
+}
+
+impl std::str::FromStr for Timestamps {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Timestamps::Preserve),
+            "touch"    => Ok(Timestamps::Touch),
+            _          => Err(format!("invalid timestamp strategy: {}", s)),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EntryOrder
+////////////////////////////////////////////////////////////////////////////////
+/// The order in which entries are collected and distributed.
+///
+/// Each order is deterministic, so an interrupted run which is restarted
+/// processes the remaining entries in the same sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum EntryOrder {
+    /// Entries are processed in the order they appear in the stall file.
+    #[default]
+    StallFile,
+    /// Entries are processed in order of their paths.
+    Alphabetical,
+    /// Entries are processed in order of the size of the files being copied,
+    /// smallest first. Entries whose size can't be read are processed last.
+    SmallestFirst,
+    /// Directory entries are processed before file entries, otherwise in
+    /// stall file order.
+    DirectoriesFirst,
+}
+
+impl EntryOrder {
+    /// The names of the available orders.
+    pub const NAMES: &'static [&'static str] = &[
+        "stall-file",
+        "alphabetical",
+        "smallest-first",
+        "directories-first",
+    ];
+}
+
+impl std::str::FromStr for EntryOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stall-file"        => Ok(EntryOrder::StallFile),
+            "alphabetical"      => Ok(EntryOrder::Alphabetical),
+            "smallest-first"    => Ok(EntryOrder::SmallestFirst),
+            "directories-first" => Ok(EntryOrder::DirectoriesFirst),
+            _                   => Err(format!("invalid entry order: {}", s)),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TemplateEngine
+////////////////////////////////////////////////////////////////////////////////
+/// The engine used to render template entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum TemplateEngine {
+    /// `{{ name }}` placeholders are substituted with variables.
+    #[default]
+    Simple,
+    /// Templates are rendered with Handlebars, supporting conditionals such
+    /// as `{{#if}}` and includes such as `{{> name}}`.
+    Handlebars,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EncryptionBackend
+////////////////////////////////////////////////////////////////////////////////
+/// The backend used to encrypt and decrypt encrypted entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum EncryptionBackend {
+    /// Entries are encrypted with age, using the configured identity file.
+    #[default]
+    Age,
+    /// Entries are encrypted by the `gpg` command, using the configured
+    /// recipients' keys.
+    Gpg,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CompareMode
+////////////////////////////////////////////////////////////////////////////////
+/// The built-in strategies for comparing the files of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum CompareMode {
+    /// Files are compared by modification time.
+    #[default]
+    Mtime,
+    /// Files are compared by size and modification time. Files of different
+    /// sizes are never treated as synced.
+    SizeMtime,
+    /// Files are compared by content, and modification times are only used to
+    /// determine which file is newer.
+    Hash,
+    /// Files are always copied.
+    Always,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CompareStage
+////////////////////////////////////////////////////////////////////////////////
+/// A stage of a comparison pipeline, set with the `compare_stages` option of
+/// the stall file or of an entry:
+///
+/// ```ron
+/// compare_stages: ["size", "mtime", "hash"],
+/// ```
+///
+/// Files are compared only if both exist. Each stage in turn may decide that
+/// the files are the same or that they differ, ending the comparison, or
+/// leave the decision to the next stage. Files which no stage decides are
+/// treated as differing, and differing files are ordered by modification
+/// time. Cheap stages may be listed alone to trade accuracy for speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareStage {
+    /// Files of different sizes differ. Directories are passed on.
+    Size,
+    /// Files which are unchanged since they were last copied, or whose
+    /// modification times are equal or within the `mtime_tolerance`, are the
+    /// same.
+    Mtime,
+    /// Files are the same if their contents match, and differ otherwise.
+    /// Directories, encrypted entries, and templates are passed on.
+    Hash,
+    /// Files are compared with the `compare_command` of the entry. Entries
+    /// without a command, directories, encrypted entries, and templates are
+    /// passed on.
+    Command,
+}
+
+impl std::fmt::Display for CompareStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            CompareStage::Size    => "size",
+            CompareStage::Mtime   => "mtime",
+            CompareStage::Hash    => "hash",
+            CompareStage::Command => "command",
+        })
+    }
+}
+
+impl std::str::FromStr for CompareStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "size"    => Ok(CompareStage::Size),
+            "mtime"   => Ok(CompareStage::Mtime),
+            "hash"    => Ok(CompareStage::Hash),
+            "command" => Ok(CompareStage::Command),
+            _ => Err(format!("invalid compare stage: {:?}; expected size, \
+                mtime, hash, or command", s)),
+        }
+    }
+}
+
+impl Serialize for CompareStage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for CompareStage {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Notifier
+////////////////////////////////////////////////////////////////////////////////
+/// A destination for the summary of a collect or distribute run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize)]
+pub enum Notifier {
+    /// The summary is posted to a webhook URL with `curl`.
+    Webhook {
+        /// The URL to post to.
+        url: String,
+        /// The format of the posted message.
+        #[serde(default)]
+        format: WebhookFormat,
+    },
+    /// The summary is mailed with `sendmail`.
+    Sendmail {
+        /// The address to mail to.
+        to: String,
+    },
+}
+
+/// The format of a message posted to a webhook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum WebhookFormat {
+    /// The full report, as a JSON object.
+    #[default]
+    Generic,
+    /// A Slack incoming webhook message.
+    Slack,
+    /// A Matrix text message event.
+    Matrix,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Escalation
+////////////////////////////////////////////////////////////////////////////////
+/// The program used to run commands with elevated privileges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum Escalation {
+    /// Commands are run with `sudo`.
+    #[default]
+    Sudo,
+    /// Commands are run with `doas`.
+    Doas,
+    /// Commands are run with `pkexec`, using polkit to authorize them.
+    Pkexec,
+}
+
+impl Escalation {
+    /// Returns the name of the program.
+    pub fn program(&self) -> &'static str {
+        match self {
+            Escalation::Sudo   => "sudo",
+            Escalation::Doas   => "doas",
+            Escalation::Pkexec => "pkexec",
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Pin
+////////////////////////////////////////////////////////////////////////////////
+/// A SHA-256 checksum pinning the contents of an entry, written in the stall
+/// file as `"sha256:"` followed by the hexadecimal digest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin(pub [u8; 32]);
+
+impl std::fmt::Display for Pin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256:")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Pin {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::msg(format!("invalid pin: {:?}", s));
+        let digits = s.strip_prefix("sha256:").ok_or_else(invalid)?;
+        if digits.len() != 64 || !digits.is_ascii() {
+            return Err(invalid());
+        }
+        let mut digest = [0; 32];
+        for (i, byte) in digest.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| invalid())?;
+        }
+        Ok(Pin(digest))
+    }
+}
+
+impl Serialize for Pin {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pin {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Mode
+////////////////////////////////////////////////////////////////////////////////
+/// A unix file mode, written in the stall file as an octal string such as
+/// `"644"`. The modes of entries are applied as written, while the default
+/// modes are masked by the umask. Modes are ignored on platforms without unix
+/// permissions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode(pub u32);
+
+impl std::fmt::Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:o}", self.0)
+    }
+}
+
+impl std::str::FromStr for Mode {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.strip_prefix("0o").unwrap_or(s);
+        u32::from_str_radix(digits, 8).map(Mode)
+    }
+}
+
+impl Serialize for Mode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| serde::de::Error::custom(
+            format!("invalid file mode: {:?}", s)))
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Cadence
+////////////////////////////////////////////////////////////////////////////////
+/// A length of time, written in the stall file as a number followed by a unit
+/// of `s`, `m`, `h`, `d`, or `w`, such as `"7d"`.
+///
+/// ### Example
+///
+/// ```rust
+/// # use stall::Cadence;
+/// # use std::time::Duration;
+/// let cadence: Cadence = "2w".parse().unwrap();
+/// assert_eq!(cadence.0, Duration::from_secs(14 * 24 * 60 * 60));
+/// assert_eq!(cadence.to_string(), "2w");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cadence(pub Duration);
+
+impl Cadence {
+    /// The units of a cadence and their lengths in seconds, from largest to
+    /// smallest.
+    const UNITS: &'static [(char, u64)] = &[
+        ('w', 7 * 24 * 60 * 60),
+        ('d', 24 * 60 * 60),
+        ('h', 60 * 60),
+        ('m', 60),
+        ('s', 1),
+    ];
+}
+
+impl std::fmt::Display for Cadence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        let (unit, len) = Cadence::UNITS.iter()
+            .find(|(_, len)| secs >= *len && secs % *len == 0)
+            .unwrap_or(&('s', 1));
+        write!(f, "{}{}", secs / len, unit)
+    }
+}
+
+impl std::str::FromStr for Cadence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let invalid = || format!("invalid sync cadence: {:?}; expected a \
+            number followed by s, m, h, d, or w", s);
+        let (count, unit) = s.char_indices()
+            .last()
+            .map(|(i, unit)| (&s[..i], unit))
+            .ok_or_else(invalid)?;
+        let len = Cadence::UNITS.iter()
+            .find(|(u, _)| *u == unit)
+            .map(|(_, len)| *len)
+            .ok_or_else(invalid)?;
+        let count: u64 = count.trim().parse().map_err(|_| invalid())?;
+        Ok(Cadence(Duration::from_secs(count.saturating_mul(len))))
+    }
+}
+
+impl Serialize for Cadence {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cadence {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a `Config` with entries at the given paths.
+    fn config_of(paths: &[&str]) -> Config {
+        Config {
+            files: paths.iter()
+                .map(|path| Entry::from(PathBuf::from(path)))
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn normalize_key_strips_cur_dir_and_trailing_separators() {
+        assert_eq!(normalize_key(Path::new("./foo")), Path::new("foo"));
+        assert_eq!(normalize_key(Path::new("foo/")), Path::new("foo"));
+        assert_eq!(normalize_key(Path::new("./foo/./bar/")),
+            Path::new("foo/bar"));
+        assert_eq!(normalize_key(Path::new("foo//bar")), Path::new("foo/bar"));
+        assert_eq!(normalize_key(Path::new(".")), Path::new("."));
+        assert_eq!(normalize_key(Path::new("")), Path::new("."));
+    }
+
+    #[test]
+    fn normalize_key_keeps_parent_dirs() {
+        assert_eq!(normalize_key(Path::new("/foo/../bar")),
+            Path::new("/foo/../bar"));
+        assert_eq!(normalize_key(Path::new("../foo/")), Path::new("../foo"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn normalize_key_unifies_separators() {
+        assert_eq!(normalize_key(Path::new(r".\foo\bar\")),
+            normalize_key(Path::new("foo/bar")));
+    }
+
+    #[test]
+    fn entries_are_normalized_on_insert() {
+        let config = config_of(&["./foo/", "bar//baz"]);
+        assert_eq!(*config.files[0].path, *Path::new("foo"));
+        assert_eq!(*config.files[1].path, *Path::new("bar/baz"));
+    }
+
+    #[test]
+    fn entries_are_normalized_on_deserialize() {
+        let config = Config::from_bytes(
+            br#"(files: ["./foo/", (path: "bar/./baz/")])"#)
+            .unwrap();
+        assert_eq!(*config.files[0].path, *Path::new("foo"));
+        assert_eq!(*config.files[1].path, *Path::new("bar/baz"));
+    }
+
+    #[test]
+    fn select_entries_matches_normalized_paths() {
+        let config = config_of(&["foo", "dir/bar"]);
+        for path in &["foo", "foo/", "./foo", "./foo/"] {
+            let selected = config.select_entries(&[PathBuf::from(path)])
+                .unwrap();
+            assert_eq!(selected, vec![&config.files[0]], "{:?}", path);
+        }
+        let selected = config.select_entries(&[PathBuf::from("./dir/bar/")])
+            .unwrap();
+        assert_eq!(selected, vec![&config.files[1]]);
+    }
+
+    #[test]
+    fn select_entries_matches_file_names() {
+        let config = config_of(&["/home/a/.bashrc"]);
+        let selected = config.select_entries(&[PathBuf::from(".bashrc/")])
+            .unwrap();
+        assert_eq!(selected, vec![&config.files[0]]);
+    }
+
+    #[test]
+    fn select_entries_deduplicates_matches() {
+        let config = config_of(&["foo"]);
+        let selected = config
+            .select_entries(&[PathBuf::from("foo"), PathBuf::from("./foo")])
+            .unwrap();
+        assert_eq!(selected.len(), 1);
+    }
+
+    #[test]
+    fn select_entries_rejects_unknown_paths() {
+        let config = config_of(&["foo"]);
+        let e = config.select_entries(&[PathBuf::from("fo")]).unwrap_err();
+        let unknown = e.downcast_ref::<UnknownEntry>().unwrap();
+        assert_eq!(&*unknown.path, Path::new("fo"));
+        assert!(unknown.candidates.contains(&"foo".to_string()));
+    }
+
+    #[test]
+    fn select_entries_skips_disabled_entries() {
+        let mut config = config_of(&["foo", "bar"]);
+        config.files[0].enabled = false;
+        let selected = config.select_entries(&[PathBuf::from("foo")])
+            .unwrap();
+        assert!(selected.is_empty());
+        assert_eq!(config.select_entries(&[]).unwrap(),
+            vec![&config.files[1]]);
+    }
+
+    #[test]
+    fn parse_detects_formats() {
+        let (_, format) = Config::parse(br#"(files: ["foo"])"#).unwrap();
+        assert_eq!(format, ConfigFormat::Ron);
+        let (_, format) = Config::parse(br#"  {"files": ["foo"]}"#).unwrap();
+        assert_eq!(format, ConfigFormat::Json);
+        let (_, format) = Config::parse(b"foo\nbar\n").unwrap();
+        assert_eq!(format, ConfigFormat::List);
+    }
+
+    #[test]
+    fn parse_list_skips_blank_lines_and_comments() {
+        let (config, _) = Config::parse(
+            b"# comment\n\n  // comment\n  ./foo/  \r\nbar\n").unwrap();
+        let paths: Vec<_> = config.files.iter()
+            .map(|entry| entry.path.to_path_buf())
+            .collect();
+        assert_eq!(paths, [PathBuf::from("foo"), PathBuf::from("bar")]);
+    }
+
+    #[test]
+    fn parse_rejects_newer_versions() {
+        let newer = CONFIG_VERSION + 1;
+        let ron = format!("(version: {}, files: [])", newer);
+        let json = format!(r#"{{"version": {}, "files": []}}"#, newer);
+        for text in &[ron, json] {
+            let e = Config::parse(text.as_bytes()).unwrap_err();
+            let unsupported = e.downcast_ref::<UnsupportedVersion>()
+                .unwrap();
+            assert_eq!(unsupported.version, newer);
+        }
+    }
+
+    #[test]
+    fn parse_rejects_invalid_json() {
+        assert!(Config::parse(br#"{"files": ["foo""#).is_err());
+    }
+
+    #[test]
+    fn to_text_omits_default_options() {
+        let mut config = config_of(&["~/a"]);
+        let text = config.to_text(ConfigFormat::Ron).unwrap();
+        assert_eq!(text, format!("(\n    version: {},\n    files: [\n        \
+            \"~/a\",\n    ],\n)\n", CONFIG_VERSION));
+
+        config.icons = true;
+        config.files[0].tags = vec!["x".into()];
+        let text = config.to_text(ConfigFormat::Ron).unwrap();
+        assert!(text.contains("    icons: true,\n"));
+        assert!(!text.contains("logger_config"));
+        assert!(!text.contains("enabled"));
+        let (parsed, _) = Config::parse(text.as_bytes()).unwrap();
+        assert!(parsed.icons);
+        assert_eq!(parsed.files, config.files);
+    }
+
+    /// Returns the line following the given comment in the text, with
+    /// surrounding whitespace removed.
+    fn line_after<'t>(text: &'t str, comment: &str) -> &'t str {
+        let mut lines = text.lines().map(str::trim);
+        assert!(lines.any(|line| line == comment),
+            "missing {:?} in {}", comment, text);
+        lines.next().unwrap()
+    }
+
+    #[test]
+    fn update_ron_text_keeps_comments_with_reordered_entries() {
+        let original = "// Header.\n(\n    // Icons.\n    icons: true,\n    \
+            // Sorted.\n    sort_entries: true,\n    files: [\n        \
+            // Vim.\n        \"~/.vimrc\",\n        // Bash.\n        \
+            (path: \"~/.bashrc\", tags: [\"shell\"]),\n        \
+            // Removed.\n        \"~/.zshrc\",\n    ],\n)\n// Footer.\n";
+        let mut config = Config::from_bytes(original.as_bytes()).unwrap();
+        config.files.retain(|entry| entry.file_name()
+            != Some(OsStr::new(".zshrc")));
+        config.files.push(Entry::from(PathBuf::from("~/.gitconfig")));
+
+        let text = config.update_ron_text(original).unwrap();
+        assert_eq!(text.lines().next(), Some("// Header."));
+        assert_eq!(text.lines().last(), Some("// Footer."));
+        assert_eq!(line_after(&text, "// Icons."), "icons: true,");
+        assert_eq!(line_after(&text, "// Sorted."), "sort_entries: true,");
+        assert_eq!(line_after(&text, "// Vim."), "\"~/.vimrc\",");
+        assert!(!text.contains("// Removed."));
+
+        // The entries are sorted, with the comments moved along with them.
+        let position = |needle: &str| text.find(needle).unwrap();
+        assert!(line_after(&text, "// Bash.").starts_with('('));
+        assert!(position("// Bash.") < position("~/.bashrc"));
+        assert!(position("~/.bashrc") < position("~/.gitconfig"));
+        assert!(position("~/.gitconfig") < position("// Vim."));
+        let (parsed, _) = Config::parse(text.as_bytes()).unwrap();
+        assert_eq!(parsed.files.len(), 3);
+    }
+
+    #[test]
+    fn merge_adds_new_entries() {
+        let mut config = config_of(&["/a/foo"]);
+        let summary = config.merge(config_of(&["/a/bar"]), MergePolicy::Error)
+            .unwrap();
+        assert_eq!(summary.added, [PathBuf::from("/a/bar")]);
+        assert_eq!(config.files.len(), 2);
+    }
+
+    #[test]
+    fn merge_error_policy_rejects_duplicates() {
+        let mut config = config_of(&["/a/foo"]);
+        let e = config
+            .merge(config_of(&["/a/bar", "/b/foo"]), MergePolicy::Error)
+            .unwrap_err();
+        let conflict = e.downcast_ref::<MergeConflict>().unwrap();
+        assert_eq!(&*conflict.path, Path::new("/b/foo"));
+        assert_eq!(&*conflict.existing, Path::new("/a/foo"));
+        // A failed merge leaves the config unchanged.
+        assert_eq!(config.files, config_of(&["/a/foo"]).files);
+    }
+
+    #[test]
+    fn merge_ours_policy_keeps_existing_entries() {
+        let mut config = config_of(&["/a/foo"]);
+        let summary = config.merge(config_of(&["/b/foo/"]), MergePolicy::Ours)
+            .unwrap();
+        assert_eq!(summary.kept, [PathBuf::from("/a/foo")]);
+        assert_eq!(config.files, config_of(&["/a/foo"]).files);
+    }
+
+    #[test]
+    fn merge_theirs_policy_replaces_existing_entries() {
+        let mut config = config_of(&["/a/foo", "/a/bar"]);
+        let summary = config
+            .merge(config_of(&["/b/foo"]), MergePolicy::Theirs)
+            .unwrap();
+        assert_eq!(summary.replaced, [PathBuf::from("/a/foo")]);
+        assert_eq!(config.files, config_of(&["/b/foo", "/a/bar"]).files);
+    }
+}
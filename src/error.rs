@@ -12,6 +12,15 @@
 pub use anyhow::Error;
 pub use anyhow::Context;
 
+// Local imports.
+use crate::action::format_bytes;
+use crate::action::SECRET_SERVICE;
+use crate::Finding;
+use crate::Pin;
+
+// External library imports.
+use serde::Serialize;
+
 // Standard library imports.
 use std::path::Path;
 
@@ -55,3 +64,1122 @@ impl std::fmt::Display for MissingFile {
 		write!(f, "missing file: {}.", self.path.display())
 	}
 }
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SyncConflict
+////////////////////////////////////////////////////////////////////////////////
+/// Both copies of a file were modified since they were last copied.
+#[derive(Debug, Clone)]
+pub struct SyncConflict {
+	/// The path of the conflicting file.
+	pub path: Box<Path>,
+}
+
+impl std::error::Error for SyncConflict {}
+
+impl std::fmt::Display for SyncConflict {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "conflicting changes: {}. Use --force to overwrite.",
+			self.path.display())
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TypeMismatch
+////////////////////////////////////////////////////////////////////////////////
+/// One copy of a file is a directory and the other is not.
+#[derive(Debug, Clone)]
+pub struct TypeMismatch {
+	/// The path of the mismatched file.
+	pub path: Box<Path>,
+}
+
+impl std::error::Error for TypeMismatch {}
+
+impl std::fmt::Display for TypeMismatch {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "file type mismatch: {}. Use --force to replace it.",
+			self.path.display())
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SpecialFile
+////////////////////////////////////////////////////////////////////////////////
+/// A file is a special file, such as a FIFO or socket, which the policy does
+/// not allow to be skipped.
+#[derive(Debug, Clone)]
+pub struct SpecialFile {
+	/// The path of the special file.
+	pub path: Box<Path>,
+	/// The kind of special file.
+	pub kind: &'static str,
+}
+
+impl std::error::Error for SpecialFile {}
+
+impl std::fmt::Display for SpecialFile {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "special file: {} is a {}.",
+			self.path.display(),
+			self.kind)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// WalkLimitExceeded
+////////////////////////////////////////////////////////////////////////////////
+/// A directory entry exceeds one of the configured walk limits.
+#[derive(Debug, Clone)]
+pub struct WalkLimitExceeded {
+	/// The path of the directory.
+	pub path: Box<Path>,
+	/// The name of the exceeded limit option.
+	pub option: &'static str,
+	/// The value of the exceeded limit.
+	pub limit: usize,
+}
+
+impl std::error::Error for WalkLimitExceeded {}
+
+impl std::fmt::Display for WalkLimitExceeded {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		match self.option {
+			"max_depth" => write!(f, "directory too deep: {} has more than \
+				{} levels of nested directories.",
+				self.path.display(),
+				self.limit),
+			_ => write!(f, "directory too large: {} has more than {} files.",
+				self.path.display(),
+				self.limit),
+		}
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// InsufficientSpace
+////////////////////////////////////////////////////////////////////////////////
+/// A file system does not have enough free space for the files to be copied.
+#[derive(Debug, Clone)]
+pub struct InsufficientSpace {
+	/// The path of a directory on the file system.
+	pub path: Box<Path>,
+	/// The number of bytes required.
+	pub required: u64,
+	/// The number of bytes available.
+	pub available: u64,
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+impl std::fmt::Display for InsufficientSpace {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "not enough free space: {} has {} available, but {} is \
+			required.",
+			self.path.display(),
+			format_bytes(self.available),
+			format_bytes(self.required))
+	}
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// FeatureDisabled
+////////////////////////////////////////////////////////////////////////////////
+/// An operation requires a cargo feature which is not enabled in this build.
+#[derive(Debug, Clone, Copy)]
+pub struct FeatureDisabled {
+	/// The name of the feature.
+	pub feature: &'static str,
+	/// A description of the operation.
+	pub operation: &'static str,
+}
+
+impl std::error::Error for FeatureDisabled {}
+
+impl std::fmt::Display for FeatureDisabled {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "{} requires the `{}` feature, which is not enabled in \
+			this build.",
+			self.operation,
+			self.feature)
+	}
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// PinMismatch
+////////////////////////////////////////////////////////////////////////////////
+/// The contents of a pinned entry do not match its pin.
+#[derive(Debug, Clone)]
+pub struct PinMismatch {
+	/// The path of the mismatched file.
+	pub path: Box<Path>,
+	/// The pin given in the stall file.
+	pub expected: Pin,
+	/// The checksum of the file.
+	pub actual: Pin,
+	/// Whether the mismatch was found while collecting.
+	pub collect: bool,
+}
+
+impl std::error::Error for PinMismatch {}
+
+impl std::fmt::Display for PinMismatch {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		match self.collect {
+			true  => write!(f, "refusing to overwrite pinned entry: {} \
+				has checksum {}, but is pinned to {}.",
+				self.path.display(),
+				self.actual,
+				self.expected),
+			false => write!(f, "pinned file {} has checksum {}, but is \
+				pinned to {}.",
+				self.path.display(),
+				self.actual,
+				self.expected),
+		}
+	}
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UnresolvedMarker
+////////////////////////////////////////////////////////////////////////////////
+/// A file to be distributed contains a template delimiter or a merge conflict
+/// marker.
+#[derive(Debug, Clone)]
+pub struct UnresolvedMarker {
+	/// The path of the file.
+	pub path: Box<Path>,
+	/// The number of the line containing the marker.
+	pub line: usize,
+	/// The kind of marker found.
+	pub kind: &'static str,
+}
+
+impl std::error::Error for UnresolvedMarker {}
+
+impl std::fmt::Display for UnresolvedMarker {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "refusing to distribute {}: line {} contains a {}.",
+			self.path.display(),
+			self.line,
+			self.kind)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// PolicyStop
+////////////////////////////////////////////////////////////////////////////////
+/// The policy for the state of a file is to stop the command.
+#[derive(Debug, Clone)]
+pub struct PolicyStop {
+	/// The path of the file.
+	pub path: Box<Path>,
+	/// The name of the file's state.
+	pub state: String,
+}
+
+impl std::error::Error for PolicyStop {}
+
+impl std::fmt::Display for PolicyStop {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "stopped by policy: {} is {}.",
+			self.path.display(),
+			self.state)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// BudgetExceeded
+////////////////////////////////////////////////////////////////////////////////
+/// The time budget for copying a file was exceeded.
+#[allow(missing_copy_implementations)]
+#[derive(Debug, Clone)]
+pub struct BudgetExceeded;
+
+impl std::error::Error for BudgetExceeded {}
+
+impl std::fmt::Display for BudgetExceeded {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "time budget exceeded.")
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Cancelled
+////////////////////////////////////////////////////////////////////////////////
+/// A command was cancelled by its [`CancelToken`], or by the user when asked
+/// to confirm a copy.
+///
+/// [`CancelToken`]: ../action/struct.CancelToken.html
+#[allow(missing_copy_implementations)]
+#[derive(Debug, Clone)]
+pub struct Cancelled;
+
+impl std::error::Error for Cancelled {}
+
+impl std::fmt::Display for Cancelled {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "the command was cancelled.")
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Locked
+////////////////////////////////////////////////////////////////////////////////
+/// The stall directory is locked by another process.
+#[derive(Debug, Clone)]
+pub struct Locked {
+	/// The path of the lock file.
+	pub path: Box<Path>,
+	/// The process id of the lock holder.
+	pub pid: u32,
+	/// The command line of the lock holder.
+	pub command: String,
+	/// The time the lock was acquired, in seconds since the unix epoch.
+	pub acquired: u64,
+}
+
+impl std::error::Error for Locked {}
+
+impl std::fmt::Display for Locked {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		let now = std::time::SystemTime::now()
+			.duration_since(std::time::UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		write!(f, "stall is locked by process {} (`{}`), acquired {} seconds \
+			ago. If that process is no longer running, remove the lock with \
+			--break-lock or delete {}.",
+			self.pid,
+			self.command,
+			now.saturating_sub(self.acquired),
+			self.path.display())
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MissingConfig
+////////////////////////////////////////////////////////////////////////////////
+/// The stall file was not found.
+#[derive(Debug, Clone)]
+pub struct MissingConfig {
+	/// The path of the missing stall file.
+	pub path: Box<Path>,
+}
+
+impl std::error::Error for MissingConfig {}
+
+impl std::fmt::Display for MissingConfig {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "no stall file found at {}.", self.path.display())
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UnknownEntry
+////////////////////////////////////////////////////////////////////////////////
+/// The specified path does not match any entry in the stall file.
+#[derive(Debug, Clone)]
+pub struct UnknownEntry {
+	/// The unmatched path.
+	pub path: Box<Path>,
+	/// The paths of the entries in the stall file.
+	pub candidates: Vec<String>,
+}
+
+impl std::error::Error for UnknownEntry {}
+
+impl std::fmt::Display for UnknownEntry {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "no entry for {} in the stall file.", self.path.display())
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UnknownStall
+////////////////////////////////////////////////////////////////////////////////
+/// The specified name does not match any stall in the registry.
+#[derive(Debug, Clone)]
+pub struct UnknownStall {
+	/// The unmatched name.
+	pub name: String,
+	/// The names of the registered stalls.
+	pub candidates: Vec<String>,
+}
+
+impl std::error::Error for UnknownStall {}
+
+impl std::fmt::Display for UnknownStall {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "no stall named {:?} in the registry.", self.name)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ReservedPath
+////////////////////////////////////////////////////////////////////////////////
+/// An entry would be stored at a path in the stall directory which is
+/// reserved for stall's own files.
+#[derive(Debug, Clone)]
+pub struct ReservedPath {
+	/// The path of the entry.
+	pub path: Box<Path>,
+	/// The reserved path in the stall directory.
+	pub reserved: Box<Path>,
+}
+
+impl std::error::Error for ReservedPath {}
+
+impl std::fmt::Display for ReservedPath {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "entry {} would be stored at {}, which is reserved in the \
+			stall directory.",
+			self.path.display(),
+			self.reserved.display())
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UnsupportedVersion
+////////////////////////////////////////////////////////////////////////////////
+/// The stall file was written with a newer schema version than is supported.
+#[derive(Debug, Clone, Copy)]
+pub struct UnsupportedVersion {
+	/// The schema version of the stall file.
+	pub version: u32,
+	/// The newest supported schema version.
+	pub supported: u32,
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+impl std::fmt::Display for UnsupportedVersion {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "the stall file has schema version {}, but only versions up \
+			to {} are supported.",
+			self.version,
+			self.supported)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UndefinedVariable
+////////////////////////////////////////////////////////////////////////////////
+/// A template refers to a variable which is not defined in the stall file.
+#[derive(Debug, Clone)]
+pub struct UndefinedVariable {
+	/// The name of the undefined variable.
+	pub name: String,
+}
+
+impl std::error::Error for UndefinedVariable {}
+
+impl std::fmt::Display for UndefinedVariable {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "undefined template variable: {}.", self.name)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MissingSecret
+////////////////////////////////////////////////////////////////////////////////
+/// A template refers to a secret which is not in the system keyring.
+#[derive(Debug, Clone)]
+pub struct MissingSecret {
+	/// The name of the missing secret.
+	pub name: String,
+}
+
+impl std::error::Error for MissingSecret {}
+
+impl std::fmt::Display for MissingSecret {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "secret not found in keyring: {}.", self.name)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyFailed
+////////////////////////////////////////////////////////////////////////////////
+/// A file could not be copied.
+#[derive(Debug, Clone)]
+pub struct CopyFailed {
+	/// The path of the file being copied.
+	pub path: Box<Path>,
+}
+
+impl std::error::Error for CopyFailed {}
+
+impl std::fmt::Display for CopyFailed {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "unable to copy {}.", self.path.display())
+	}
+}
+
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyCommandFailed
+////////////////////////////////////////////////////////////////////////////////
+/// The copy command used by the subprocess copy backend failed.
+#[derive(Debug, Clone)]
+pub struct CopyCommandFailed {
+	/// The name of the copy command.
+	pub program: String,
+	/// The exit code of the command, or `None` if it was terminated by a
+	/// signal.
+	pub code: Option<i32>,
+	/// The error output of the command.
+	pub stderr: String,
+	/// The kind of failure, determined from the error output.
+	pub kind: CopyFailureKind,
+}
+
+impl std::error::Error for CopyCommandFailed {}
+
+impl std::fmt::Display for CopyCommandFailed {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		match self.code {
+			Some(code) => write!(f, "{} exited with code {}", self.program,
+				code)?,
+			None       => write!(f, "{} was terminated by a signal",
+				self.program)?,
+		}
+		match self.stderr.is_empty() {
+			true  => write!(f, " ({}).", self.kind),
+			false => write!(f, " ({}): {}", self.kind, self.stderr),
+		}
+	}
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CopyFailureKind
+////////////////////////////////////////////////////////////////////////////////
+/// The kind of failure which prevented a file from being copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CopyFailureKind {
+	/// Access to the source or target was denied.
+	PermissionDenied,
+	/// The source or the target's directory does not exist.
+	NotFound,
+	/// The target's file system is full or over its quota.
+	NoSpace,
+	/// The target's file system is read-only.
+	ReadOnly,
+	/// Any other failure.
+	Other,
+}
+
+impl CopyFailureKind {
+	/// Classifies the error output of a copy command.
+	pub fn from_stderr(stderr: &str) -> Self {
+		let stderr = stderr.to_lowercase();
+		let matches = |patterns: &[&str]| patterns.iter()
+			.any(|pattern| stderr.contains(pattern));
+		if matches(&["permission denied", "operation not permitted",
+			"access is denied"])
+		{
+			CopyFailureKind::PermissionDenied
+		} else if matches(&["no such file", "not found",
+			"cannot find the"])
+		{
+			CopyFailureKind::NotFound
+		} else if matches(&["no space left", "quota exceeded",
+			"not enough space"])
+		{
+			CopyFailureKind::NoSpace
+		} else if matches(&["read-only file system"]) {
+			CopyFailureKind::ReadOnly
+		} else {
+			CopyFailureKind::Other
+		}
+	}
+
+	/// Returns the kind of copy failure which caused the given error, or
+	/// `None` if it was not caused by a failed copy command or an IO error.
+	pub fn of(error: &Error) -> Option<Self> {
+		use std::io::ErrorKind;
+		for cause in error.chain() {
+			if let Some(e) = cause.downcast_ref::<CopyCommandFailed>() {
+				return Some(e.kind);
+			}
+			if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+				return Some(match e.kind() {
+					ErrorKind::PermissionDenied   => {
+						CopyFailureKind::PermissionDenied
+					},
+					ErrorKind::NotFound           => CopyFailureKind::NotFound,
+					ErrorKind::StorageFull
+						| ErrorKind::QuotaExceeded => CopyFailureKind::NoSpace,
+					ErrorKind::ReadOnlyFilesystem => CopyFailureKind::ReadOnly,
+					_                             => CopyFailureKind::Other,
+				});
+			}
+		}
+		None
+	}
+}
+
+impl std::fmt::Display for CopyFailureKind {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "{}", match self {
+			CopyFailureKind::PermissionDenied => "permission denied",
+			CopyFailureKind::NotFound         => "file not found",
+			CopyFailureKind::NoSpace          => "no space left",
+			CopyFailureKind::ReadOnly         => "read-only file system",
+			CopyFailureKind::Other            => "copy failed",
+		})
+	}
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UnknownSnapshot
+////////////////////////////////////////////////////////////////////////////////
+/// The named snapshot does not exist.
+#[derive(Debug, Clone)]
+pub struct UnknownSnapshot {
+	/// The name of the snapshot.
+	pub name: String,
+}
+
+impl std::error::Error for UnknownSnapshot {}
+
+impl std::fmt::Display for UnknownSnapshot {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "no snapshot named {:?}.", self.name)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SnapshotExists
+////////////////////////////////////////////////////////////////////////////////
+/// A snapshot with the given name already exists.
+#[derive(Debug, Clone)]
+pub struct SnapshotExists {
+	/// The name of the snapshot.
+	pub name: String,
+}
+
+impl std::error::Error for SnapshotExists {}
+
+impl std::fmt::Display for SnapshotExists {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "a snapshot named {:?} already exists.", self.name)
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// MergeConflict
+////////////////////////////////////////////////////////////////////////////////
+/// An entry being merged into a stall file duplicates an existing entry.
+#[derive(Debug, Clone)]
+pub struct MergeConflict {
+	/// The remote path of the entry being merged.
+	pub path: Box<Path>,
+	/// The remote path of the existing entry.
+	pub existing: Box<Path>,
+}
+
+impl std::error::Error for MergeConflict {}
+
+impl std::fmt::Display for MergeConflict {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		match self.path == self.existing {
+			true  => write!(f, "both stall files have an entry for {}.",
+				self.path.display()),
+			false => write!(f, "the entry for {} would be stored at the same \
+				path as the entry for {}.",
+				self.path.display(),
+				self.existing.display()),
+		}
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ValidationFailed
+////////////////////////////////////////////////////////////////////////////////
+/// Problems were found when checking a stall file.
+#[derive(Debug, Clone)]
+pub struct ValidationFailed {
+	/// The problems found.
+	pub findings: Vec<Finding>,
+}
+
+impl std::error::Error for ValidationFailed {}
+
+impl std::fmt::Display for ValidationFailed {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		let count = self.findings.len();
+		write!(f, "found {} {} in the stall file.",
+			count,
+			if count == 1 { "problem" } else { "problems" })
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// StallError
+////////////////////////////////////////////////////////////////////////////////
+/// An error returned by a stall command, classified by the kind of failure.
+///
+/// Each variant holds the underlying [`Error`], whose causes and downcasts are
+/// unchanged. An [`Error`] is classified by the first of its causes which
+/// identifies a kind of failure, so a [`CopyFailed`] error caused by an IO
+/// error is a `Copy` failure.
+///
+/// [`Error`]: type.Error.html
+/// [`CopyFailed`]: struct.CopyFailed.html
+#[derive(Debug, thiserror::Error)]
+pub enum StallError {
+	/// A file could not be read or written.
+	#[error(transparent)]
+	Io(Error),
+	/// The stall file or another of stall's files could not be parsed.
+	#[error(transparent)]
+	Parse(Error),
+	/// A requested entry, file, snapshot, or stall file does not exist.
+	#[error(transparent)]
+	MissingEntry(Error),
+	/// The copies of a file conflict, a file does not match its pin, or merged
+	/// entries duplicate existing entries.
+	#[error(transparent)]
+	Conflict(Error),
+	/// A file could not be copied.
+	#[error(transparent)]
+	Copy(Error),
+	/// Any other failure.
+	#[error(transparent)]
+	Other(Error),
+}
+
+impl StallError {
+	/// Returns the underlying [`Error`].
+	///
+	/// [`Error`]: type.Error.html
+	pub fn error(&self) -> &Error {
+		use StallError::*;
+		match self {
+			Io(e) | Parse(e) | MissingEntry(e) | Conflict(e) | Copy(e) |
+			Other(e) => e,
+		}
+	}
+
+	/// Converts the `StallError` into the underlying [`Error`].
+	///
+	/// [`Error`]: type.Error.html
+	pub fn into_error(self) -> Error {
+		use StallError::*;
+		match self {
+			Io(e) | Parse(e) | MissingEntry(e) | Conflict(e) | Copy(e) |
+			Other(e) => e,
+		}
+	}
+}
+
+impl From<Error> for StallError {
+	fn from(error: Error) -> Self {
+		for cause in error.chain() {
+			if cause.is::<MissingFile>()
+				|| cause.is::<MissingConfig>()
+				|| cause.is::<UnknownEntry>()
+				|| cause.is::<UnknownStall>()
+				|| cause.is::<UnknownSnapshot>()
+			{
+				return StallError::MissingEntry(error);
+			}
+			if cause.is::<SyncConflict>()
+				|| cause.is::<TypeMismatch>()
+				|| cause.is::<PinMismatch>()
+				|| cause.is::<MergeConflict>()
+			{
+				return StallError::Conflict(error);
+			}
+			if cause.is::<CopyFailed>() || cause.is::<CopyCommandFailed>() {
+				return StallError::Copy(error);
+			}
+			if cause.is::<ron::Error>()
+				|| cause.is::<serde_json::Error>()
+				|| cause.is::<UnsupportedVersion>()
+			{
+				return StallError::Parse(error);
+			}
+			if cause.is::<std::io::Error>() {
+				return StallError::Io(error);
+			}
+		}
+		StallError::Other(error)
+	}
+}
+
+impl From<InvalidFile> for StallError {
+	fn from(error: InvalidFile) -> Self {
+		StallError::from(Error::from(error))
+	}
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ErrorReport
+////////////////////////////////////////////////////////////////////////////////
+/// A user-facing rendering of an [`Error`], listing its causes and a
+/// suggested next step for common failures.
+///
+/// [`Error`]: type.Error.html
+#[derive(Debug)]
+pub struct ErrorReport<'e> {
+	/// The error to report.
+	error: &'e Error,
+}
+
+impl<'e> ErrorReport<'e> {
+	/// Constructs a new `ErrorReport` for the given error. A [`StallError`]
+	/// is reported as its underlying error.
+	///
+	/// [`StallError`]: enum.StallError.html
+	pub fn new(error: &'e Error) -> Self {
+		let error = error.downcast_ref::<StallError>()
+			.map_or(error, StallError::error);
+		ErrorReport { error }
+	}
+
+	/// Returns the first error of the given type in the chain of causes.
+	fn find<E>(&self) -> Option<&E>
+		where E: std::error::Error + 'static
+	{
+		self.error.chain().find_map(|cause| cause.downcast_ref::<E>())
+	}
+
+	/// Returns a suggested next step for resolving the error, if one is
+	/// known. Each cause of the error is checked.
+	pub fn hint(&self) -> Option<String> {
+		if let Some(e) = self.find::<MissingConfig>() {
+			return Some(format!("create a stall file at {}, or specify one \
+				with --use-config.", e.path.display()));
+		}
+
+		if let Some(e) = self.find::<MissingFile>() {
+			let matches = closest_matches(&e.path);
+			return Some(match matches.is_empty() {
+				true  => "check the entry's path in the stall file, or run \
+					without --error to skip missing files.".into(),
+				false => format!("did you mean {}?", matches.join(" or ")),
+			});
+		}
+
+		if let Some(e) = self.find::<UnknownEntry>() {
+			let matches = similar(&e.path.to_string_lossy(), &e.candidates);
+			return Some(match matches.is_empty() {
+				true  => "check the entries listed in the stall file.".into(),
+				false => format!("did you mean {}?", matches.join(" or ")),
+			});
+		}
+
+		if let Some(e) = self.find::<UnknownStall>() {
+			let matches = similar(&e.name, &e.candidates);
+			return Some(match matches.is_empty() {
+				true  => "run 'stall registry list' to see the registered \
+					stalls, or register one with 'stall registry add'.".into(),
+				false => format!("did you mean {}?", matches.join(" or ")),
+			});
+		}
+
+		if self.find::<Cancelled>().is_some() {
+			return Some("run the command again with --resume to skip the \
+				entries which were copied.".into());
+		}
+
+		if self.find::<MergeConflict>().is_some() {
+			return Some("use --policy ours to keep the existing entry, or \
+				--policy theirs to replace it.".into());
+		}
+
+		if self.find::<UnknownSnapshot>().is_some() {
+			return Some("run 'stall snapshot list' to see the available \
+				snapshots.".into());
+		}
+
+		if self.find::<SnapshotExists>().is_some() {
+			return Some("choose another name, or use --force to replace the \
+				snapshot.".into());
+		}
+
+		if self.find::<ValidationFailed>().is_some() {
+			return Some("fix the problems listed above, then run 'stall \
+				check' again.".into());
+		}
+
+		if self.find::<UnsupportedVersion>().is_some() {
+			return Some("upgrade stall to use this stall file.".into());
+		}
+
+		if let Some(e) = self.find::<ReservedPath>() {
+			return Some(format!("remove the entry for {} from the stall \
+				file.", e.path.display()));
+		}
+
+		if let Some(e) = self.find::<SpecialFile>() {
+			return Some(format!("remove the {} or set the action for \
+				`special` files to `skip` in the `policy` section of the \
+				stall file. Only FIFOs can be recreated with `copy`.",
+				e.kind));
+		}
+
+		if let Some(e) = self.find::<WalkLimitExceeded>() {
+			return Some(format!("check that the entry for {} is the \
+				intended directory, or raise `{}` in the `limits` section of \
+				the stall file.", e.path.display(), e.option));
+		}
+
+		if let Some(e) = self.find::<InsufficientSpace>() {
+			return Some(format!("free up space on the file system containing \
+				{}, or select fewer entries to copy.", e.path.display()));
+		}
+
+		if let Some(e) = self.find::<FeatureDisabled>() {
+			return Some(format!("reinstall stall with the feature enabled: \
+				`cargo install stall --features {}`.", e.feature));
+		}
+
+		if let Some(e) = self.find::<PinMismatch>() {
+			return Some(match e.collect {
+				true  => "re-run with --repin to collect the new contents, \
+					then update the entry's pin.".into(),
+				false => format!("restore the stall copy of {}, or update \
+					the entry's pin to `{}` if the change is intended.",
+					e.path.display(), e.actual),
+			});
+		}
+
+		if let Some(e) = self.find::<UnresolvedMarker>() {
+			return Some(format!("edit the stall copy of {} to remove the \
+				{}, mark the entry as a `template`, or re-run with --force \
+				to distribute it anyway.", e.path.display(), e.kind));
+		}
+
+		if let Some(e) = self.find::<PolicyStop>() {
+			return Some(format!("change the action for `{}` files in the \
+				`policy` section of the stall file.", e.state));
+		}
+
+		if let Some(e) = self.find::<UndefinedVariable>() {
+			return Some(format!("define `{}` in the `variables` section of \
+				the stall file.", e.name));
+		}
+
+		if let Some(e) = self.find::<MissingSecret>() {
+			return Some(format!("store the secret in the system keyring \
+				with service `{}` and user `{}`.", SECRET_SERVICE, e.name));
+		}
+
+		if let Some(e) = self.find::<CopyCommandFailed>() {
+			return Some(match e.kind {
+				CopyFailureKind::PermissionDenied => "check the permissions \
+					of the file and the target's directory, or re-run the \
+					command as a user with access to them.".into(),
+				CopyFailureKind::NotFound => "check that the file and the \
+					target's directory exist.".into(),
+				CopyFailureKind::NoSpace => "free up space on the target's \
+					file system, or select fewer entries to copy.".into(),
+				CopyFailureKind::ReadOnly => "remount the target's file \
+					system as writable, or exclude its entries from the \
+					command.".into(),
+				CopyFailureKind::Other => format!("check the error output \
+					of {}, or use the native copy method.", e.program),
+			});
+		}
+
+		let permission_denied = self.error.chain()
+			.filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+			.any(|e| e.kind() == std::io::ErrorKind::PermissionDenied);
+		if permission_denied {
+			return Some("check the file's permissions, or re-run the command \
+				as a user with access to it.".into());
+		}
+
+		None
+	}
+}
+
+impl<'e> std::fmt::Display for ErrorReport<'e> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>)
+		-> Result<(), std::fmt::Error> 
+	{
+		write!(f, "error: {}", self.error)?;
+		for cause in self.error.chain().skip(1) {
+			write!(f, "\n    caused by: {}", cause)?;
+		}
+		if let Some(hint) = self.hint() {
+			write!(f, "\nhint: {}", hint)?;
+		}
+		Ok(())
+	}
+}
+
+/// Returns the names of files next to the given path with similar names, most
+/// similar first.
+fn closest_matches(path: &Path) -> Vec<String> {
+	let (parent, name) = match (path.parent(), path.file_name()) {
+		(Some(parent), Some(name)) => (parent, name.to_string_lossy()),
+		_ => return Vec::new(),
+	};
+	let dir = match std::fs::read_dir(parent) {
+		Ok(dir) => dir,
+		Err(_)  => return Vec::new(),
+	};
+
+	let candidates: Vec<_> = dir
+		.filter_map(|entry| entry.ok())
+		.map(|entry| entry.file_name().to_string_lossy().into_owned())
+		.collect();
+	closest(&name, &candidates)
+		.into_iter()
+		.map(|candidate| format!("`{}`", parent.join(candidate).display()))
+		.collect()
+}
+
+/// Returns the candidates similar to the given name, quoted for a hint, most
+/// similar first.
+fn similar(name: &str, candidates: &[String]) -> Vec<String> {
+	closest(name, candidates)
+		.into_iter()
+		.map(|candidate| format!("`{}`", candidate))
+		.collect()
+}
+
+/// Returns up to three of the candidates within a third of the name's length
+/// of the name by edit distance, most similar first.
+fn closest<'c>(name: &str, candidates: &'c [String]) -> Vec<&'c str> {
+	let max_distance = (name.chars().count() / 3).max(2);
+	let mut matches: Vec<_> = candidates.iter()
+		.map(|candidate| (edit_distance(name, candidate), candidate.as_str()))
+		.filter(|(distance, _)| *distance <= max_distance)
+		.collect();
+	matches.sort();
+	matches.dedup();
+	matches.into_iter()
+		.take(3)
+		.map(|(_, candidate)| candidate)
+		.collect()
+}
+
+/// Returns the Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+		for (j, cb) in b.iter().enumerate() {
+			let above = row[j + 1];
+			row[j + 1] = if ca == *cb {
+				diagonal
+			} else {
+				1 + diagonal.min(above).min(row[j])
+			};
+			diagonal = above;
+		}
+	}
+	row[b.len()]
+}
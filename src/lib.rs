@@ -47,8 +47,47 @@ mod config;
 // Public modules.
 pub mod action;
 pub mod error;
+pub mod lock;
 pub mod logger;
+pub mod registry;
+pub mod state;
+pub mod theme;
 
 // Exports.
 pub use command::*;
 pub use config::*;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// FEATURES
+////////////////////////////////////////////////////////////////////////////////
+/// The optional cargo features of the crate, and whether each is enabled in
+/// this build.
+pub const FEATURES: &[(&str, bool)] = &[
+    ("archive", cfg!(feature = "archive")),
+    ("async", cfg!(feature = "async")),
+    ("cli", cfg!(feature = "cli")),
+    ("color", cfg!(feature = "color")),
+    ("encryption", cfg!(feature = "encryption")),
+    ("progress", cfg!(feature = "progress")),
+    ("templates", cfg!(feature = "templates")),
+];
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Thread safety
+////////////////////////////////////////////////////////////////////////////////
+// The stall file model, command options, and results are shared by reference
+// between the planning and copying threads, and may be held by programs
+// running many stalls concurrently, so they must remain `Send` and `Sync`.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Config>();
+    assert_send_sync::<Entry>();
+    assert_send_sync::<CommonOptions>();
+    assert_send_sync::<RunContext>();
+    assert_send_sync::<action::StallStore>();
+    assert_send_sync::<action::RunReport>();
+    assert_send_sync::<state::SyncState>();
+    assert_send_sync::<error::StallError>();
+};
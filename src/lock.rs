@@ -0,0 +1,456 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licensed using the MIT or Apache 2 license.
+// See license-mit.md and license-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Stall directory locking.
+////////////////////////////////////////////////////////////////////////////////
+#![warn(missing_docs)]
+
+// Local imports.
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::Locked;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::io::Write as _;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// DEFAULT_LOCK_PATH
+////////////////////////////////////////////////////////////////////////////////
+/// The path of the lock file, relative to the stall directory.
+pub const DEFAULT_LOCK_PATH: &str = ".stall.lock";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// UNREADABLE_LOCK_GRACE
+////////////////////////////////////////////////////////////////////////////////
+/// How long a lock file which can't be read is treated as held before it is
+/// removed. Lock files are written before they are linked into place, so an
+/// unreadable lock file was left by an older version or was corrupted.
+pub const UNREADABLE_LOCK_GRACE: Duration = Duration::from_secs(10);
+
+/// How long to wait before checking an unreadable lock file again.
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+
+////////////////////////////////////////////////////////////////////////////////
+// LockHolder
+////////////////////////////////////////////////////////////////////////////////
+/// Information about the process holding a [`StallLock`].
+///
+/// [`StallLock`]: struct.StallLock.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    /// The process id of the holder.
+    pub pid: u32,
+    /// The command line of the holder.
+    pub command: Vec<String>,
+    /// The time the lock was acquired, in seconds since the unix epoch.
+    pub acquired: u64,
+}
+
+impl LockHolder {
+    /// Returns a `LockHolder` describing the current process.
+    fn current() -> Self {
+        LockHolder {
+            pid: std::process::id(),
+            command: std::env::args().collect(),
+            acquired: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    /// Returns true if the holding process is still running.
+    pub fn is_running(&self) -> bool {
+        process_exists(self.pid)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// StallLock
+////////////////////////////////////////////////////////////////////////////////
+/// An exclusive lock on a stall directory, preventing concurrent stall
+/// commands from modifying the same files. The lock is released when the
+/// `StallLock` is dropped.
+#[derive(Debug)]
+pub struct StallLock {
+    /// The path of the lock file.
+    path: PathBuf,
+}
+
+impl StallLock {
+    /// Acquires the lock for the given stall directory.
+    ///
+    /// Locks held by processes which are no longer running are considered
+    /// stale and are removed automatically.
+    ///
+    /// ### Parameters
+    /// + `stall_dir`: The stall directory to lock.
+    /// + `break_lock`: Whether to remove an existing lock, even if its holder
+    ///   is still running.
+    ///
+    /// ### Errors
+    ///
+    /// Returns a [`Locked`] error if the lock is held by another running
+    /// process.
+    ///
+    /// [`Locked`]: ../error/struct.Locked.html
+    pub fn acquire(stall_dir: &Path, break_lock: bool) -> Result<Self, Error> {
+        StallLock::acquire_file(&stall_dir.join(DEFAULT_LOCK_PATH), break_lock)
+    }
+
+    /// Acquires the lock file at the given path, as with [`acquire`].
+    ///
+    /// The lock holder is written to a temporary file which is then hard
+    /// linked into place, so the lock file is never seen without its holder.
+    /// A lock file which can't be read is treated as held until it is older
+    /// than the [`UNREADABLE_LOCK_GRACE`] period.
+    ///
+    /// [`acquire`]: #method.acquire
+    /// [`UNREADABLE_LOCK_GRACE`]: constant.UNREADABLE_LOCK_GRACE.html
+    pub fn acquire_file(path: &Path, break_lock: bool) -> Result<Self, Error> {
+        let path = path.to_path_buf();
+        loop {
+            match create_lock_file(&path) {
+                Ok(()) => {
+                    debug!("Acquired lock: {:?}", path);
+                    return Ok(StallLock { path });
+                },
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+                Err(e) => return Err(e).with_context(|| "create lock file"),
+            }
+
+            let observed = LockObservation::read(&path);
+            match &observed {
+                // The lock was released since it was found.
+                None => continue,
+                _ if break_lock => {
+                    warn!("Breaking lock held by {}",
+                        describe(&observed.as_ref().and_then(|o| o.holder())));
+                },
+                Some(LockObservation::Held(holder))
+                    if !holder.is_running() =>
+                {
+                    warn!("Removing stale lock held by {}",
+                        describe(&Some(holder.clone())));
+                },
+                Some(LockObservation::Unreadable(modified))
+                    if is_expired(*modified) =>
+                {
+                    warn!("Removing unreadable lock file: {}", path.display());
+                },
+                Some(LockObservation::Unreadable(_)) => {
+                    trace!("Waiting for unreadable lock file: {}",
+                        path.display());
+                    std::thread::sleep(LOCK_RETRY_INTERVAL);
+                    continue;
+                },
+                Some(LockObservation::Held(holder)) => return Err(Locked {
+                    path: path.into(),
+                    pid: holder.pid,
+                    command: holder.command.join(" "),
+                    acquired: holder.acquired,
+                }.into()),
+            }
+
+            // Another process may have replaced the lock since it was read,
+            // so only remove it if it is unchanged.
+            if LockObservation::read(&path) != observed {
+                debug!("Lock file changed before removal: {}", path.display());
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(()) => (),
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(e).with_context(|| "remove lock file"),
+            }
+        }
+    }
+}
+
+impl Drop for StallLock {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => debug!("Released lock: {:?}", self.path),
+            Err(e) => warn!("Unable to remove lock file {}: {}",
+                self.path.display(), e),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// LockObservation
+////////////////////////////////////////////////////////////////////////////////
+/// The contents of an existing lock file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LockObservation {
+    /// The lock file names its holder.
+    Held(LockHolder),
+    /// The lock file can't be read or parsed. Holds its modification time,
+    /// if known.
+    Unreadable(Option<SystemTime>),
+}
+
+impl LockObservation {
+    /// Reads the lock file at the given path. Returns `None` if it does not
+    /// exist.
+    fn read(path: &Path) -> Option<Self> {
+        let modified = match std::fs::metadata(path) {
+            Ok(metadata) => metadata.modified().ok(),
+            Err(e) if e.kind() == ErrorKind::NotFound => return None,
+            Err(_) => None,
+        };
+        let holder = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| ron::de::from_str(&text).ok());
+        match holder {
+            Some(holder) => Some(LockObservation::Held(holder)),
+            None => match path.exists() {
+                true  => Some(LockObservation::Unreadable(modified)),
+                false => None,
+            },
+        }
+    }
+
+    /// Returns the [`LockHolder`] named by the lock file, if it is readable.
+    ///
+    /// [`LockHolder`]: struct.LockHolder.html
+    fn holder(&self) -> Option<LockHolder> {
+        match self {
+            LockObservation::Held(holder) => Some(holder.clone()),
+            LockObservation::Unreadable(_) => None,
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Creates the lock file at the given path, naming the current process as
+/// its holder. The holder is written to a temporary file first, which is
+/// then hard linked to the lock path, failing with `AlreadyExists` if the
+/// lock file exists. File systems without hard links create the lock file
+/// directly.
+fn create_lock_file(path: &Path) -> std::io::Result<()> {
+    let holder = ron::ser::to_string(&LockHolder::current())
+        .map_err(std::io::Error::other)?;
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+    let temp = path.with_extension(format!("lock-{}-{}.tmp",
+        std::process::id(),
+        nanos));
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp)?;
+    let written = file.write_all(holder.as_bytes())
+        .and_then(|()| file.sync_all());
+    drop(file);
+    let linked = written.and_then(|()| std::fs::hard_link(&temp, path));
+    let _ = std::fs::remove_file(&temp);
+    match linked {
+        Err(e) if e.kind() == ErrorKind::Unsupported => {
+            debug!("Hard links are unsupported; creating lock file directly.");
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?
+                .write_all(holder.as_bytes())
+        },
+        result => result,
+    }
+}
+
+/// Returns true if an unreadable lock file with the given modification time
+/// is older than the [`UNREADABLE_LOCK_GRACE`] period. Lock files whose age
+/// can't be determined are treated as expired, so they can't block every
+/// command.
+///
+/// [`UNREADABLE_LOCK_GRACE`]: constant.UNREADABLE_LOCK_GRACE.html
+fn is_expired(modified: Option<SystemTime>) -> bool {
+    modified
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_none_or(|age| age > UNREADABLE_LOCK_GRACE)
+}
+
+/// Returns a description of a lock holder for log messages.
+fn describe(holder: &Option<LockHolder>) -> String {
+    match holder {
+        Some(holder) => format!("process {} ({})",
+            holder.pid,
+            holder.command.join(" ")),
+        None => "an unknown process".into(),
+    }
+}
+
+/// Returns true if a process with the given id is running.
+#[cfg(target_os = "linux")]
+fn process_exists(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Returns true if a process with the given id is running.
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_exists(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+/// Returns true if a process with the given id is running.
+#[cfg(windows)]
+fn process_exists(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .arg("/FI")
+        .arg(format!("PID eq {}", pid))
+        .arg("/NH")
+        .output()
+        .map(|out| String::from_utf8_lossy(&out.stdout)
+            .contains(&pid.to_string()))
+        .unwrap_or(true)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests
+////////////////////////////////////////////////////////////////////////////////
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a new empty directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("stall-lock-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns the id of a process which is not running.
+    fn exited_pid() -> u32 {
+        let mut child = std::process::Command::new("true")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        assert!(child.wait().unwrap().success());
+        pid
+    }
+
+    #[test]
+    fn lock_is_exclusive_and_released_on_drop() {
+        let dir = test_dir("exclusive");
+        let lock = StallLock::acquire(&dir, false).unwrap();
+        let path = dir.join(DEFAULT_LOCK_PATH);
+        match LockObservation::read(&path) {
+            Some(LockObservation::Held(holder)) =>
+                assert_eq!(holder.pid, std::process::id()),
+            observed => panic!("unexpected lock file: {:?}", observed),
+        }
+
+        let e = StallLock::acquire(&dir, false).unwrap_err();
+        let locked = e.downcast_ref::<Locked>().unwrap();
+        assert_eq!(locked.pid, std::process::id());
+
+        drop(lock);
+        assert!(!path.exists());
+        drop(StallLock::acquire(&dir, false).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lock_can_be_broken() {
+        let dir = test_dir("break");
+        let lock = StallLock::acquire(&dir, false).unwrap();
+        let broken = StallLock::acquire(&dir, true).unwrap();
+        // Forget the first lock so it doesn't remove the second's file.
+        std::mem::forget(lock);
+        drop(broken);
+        assert!(!dir.join(DEFAULT_LOCK_PATH).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stale_lock_is_removed() {
+        let dir = test_dir("stale");
+        let path = dir.join(DEFAULT_LOCK_PATH);
+        let holder = LockHolder {
+            pid: exited_pid(),
+            command: vec!["stall".into()],
+            acquired: 0,
+        };
+        std::fs::write(&path, ron::ser::to_string(&holder).unwrap())
+            .unwrap();
+
+        let lock = StallLock::acquire(&dir, false).unwrap();
+        match LockObservation::read(&path) {
+            Some(LockObservation::Held(holder)) =>
+                assert_eq!(holder.pid, std::process::id()),
+            observed => panic!("unexpected lock file: {:?}", observed),
+        }
+        drop(lock);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_lock_is_observed() {
+        let dir = test_dir("unreadable");
+        let path = dir.join(DEFAULT_LOCK_PATH);
+        assert_eq!(LockObservation::read(&path), None);
+        std::fs::write(&path, "").unwrap();
+        match LockObservation::read(&path) {
+            Some(LockObservation::Unreadable(Some(_))) => (),
+            observed => panic!("unexpected lock file: {:?}", observed),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unreadable_lock_expires_after_grace_period() {
+        let now = SystemTime::now();
+        assert!(!is_expired(Some(now)));
+        assert!(is_expired(Some(now - UNREADABLE_LOCK_GRACE * 2)));
+        assert!(is_expired(None));
+    }
+
+    #[test]
+    fn create_lock_file_fails_if_locked() {
+        let dir = test_dir("create");
+        let path = dir.join(DEFAULT_LOCK_PATH);
+        create_lock_file(&path).unwrap();
+        let e = create_lock_file(&path).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+        // The temporary file is removed either way.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -10,6 +10,7 @@
 #![warn(missing_docs)]
 
 // External library imports.
+#[cfg(feature = "cli")]
 use fern::colors::Color;
 
 use log::*;
@@ -18,11 +19,19 @@ use serde::Deserialize;
 use serde::Serialize;
 
 // Standard library imports.
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
 use std::env;
 use std::fmt;
 use std::io;
 use std::path::PathBuf;
 use std::str::FromStr;
+#[cfg(feature = "cli")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
 
 // Exports.
 pub use log::LevelFilter;
@@ -32,7 +41,7 @@ pub use log::LevelFilter;
 // LoggerConfig
 ////////////////////////////////////////////////////////////////////////////////
 /// Logger configuration parameters.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct LoggerConfig {
     /// Determines how to log to the terminal.
     #[serde(default = "LoggerConfig::default_stdout_log_output")]
@@ -46,9 +55,23 @@ pub struct LoggerConfig {
     /// Enables logging to the file at the given path.
     pub log_path: Option<PathBuf>,
 
+    #[serde(default = "LoggerConfig::default_log_format")]
+    /// Sets the format of the log file.
+    pub log_format: LogFormat,
+
     #[serde(default = "LoggerConfig::default_allow_env_override")]
     /// Enables config values to be overriden by environment variables.
     pub allow_env_override: bool,
+
+    #[serde(default = "LoggerConfig::default_recent_events")]
+    /// The number of recent log records to keep for display if the command
+    /// fails. Records are kept regardless of the level filter.
+    pub recent_events: usize,
+
+    #[serde(skip)]
+    /// When to use colored output, as given by the `--color` option. Takes
+    /// precedence over environment variables.
+    pub color_mode: Option<ColorMode>,
 }
 
 impl LoggerConfig {
@@ -70,11 +93,23 @@ impl LoggerConfig {
         None
     }
 
+    /// Returns the default log file format.
+    #[inline(always)]
+    fn default_log_format() -> LogFormat {
+        LogFormat::Text
+    }
+
     /// Returns the default setting for allowing environment variable overrides.
     #[inline(always)]
     fn default_allow_env_override() -> bool {
         true
     }
+
+    /// Returns the default number of recent log records to keep.
+    #[inline(always)]
+    fn default_recent_events() -> usize {
+        64
+    }
 }
 
 impl Default for LoggerConfig {
@@ -83,7 +118,10 @@ impl Default for LoggerConfig {
             stdout_log_output: LoggerConfig::default_stdout_log_output(),
             level_filter: LoggerConfig::default_level_filter(),
             log_path: LoggerConfig::default_log_path(),
+            log_format: LoggerConfig::default_log_format(),
             allow_env_override: LoggerConfig::default_allow_env_override(),
+            recent_events: LoggerConfig::default_recent_events(),
+            color_mode: None,
         }
     }
 }
@@ -105,17 +143,464 @@ pub enum StdoutLogOutput {
 }
 
 
+////////////////////////////////////////////////////////////////////////////////
+// LogFormat
+////////////////////////////////////////////////////////////////////////////////
+/// Options for formatting the log file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum LogFormat {
+    /// Formats log records as lines of text.
+    Text,
+    /// Formats log records as lines of JSON objects, with a field for each
+    /// [`EntrySpan`] value.
+    ///
+    /// [`EntrySpan`]: struct.EntrySpan.html
+    Json,
+}
+
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TracePreset
+////////////////////////////////////////////////////////////////////////////////
+/// Named groups of modules to enable trace messages for, so that useful debug
+/// traces can be produced without knowing the module structure.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TracePreset {
+    /// Traces file copy operations.
+    Copy,
+    /// Traces file status comparisons.
+    Status,
+    /// Traces file system and archive access.
+    Io,
+    /// Traces everything.
+    All,
+}
+
+impl TracePreset {
+    /// The names of the available presets.
+    pub const NAMES: &'static [&'static str] = &["copy", "status", "io", "all"];
+
+    /// Returns the log targets enabled by the preset.
+    pub fn targets(&self) -> &'static [&'static str] {
+        match self {
+            TracePreset::Copy => &[
+                "stall::action::copy",
+                "stall::action::backend",
+                "stall::action::archive",
+            ],
+            TracePreset::Status => &[
+                "stall::action::plan",
+                "stall::action::location",
+            ],
+            TracePreset::Io => &[
+                "stall::action::archive",
+                "stall::action::walk",
+                "stall::config",
+                "stall::lock",
+            ],
+            TracePreset::All => &["stall"],
+        }
+    }
+}
+
+impl FromStr for TracePreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "copy"   => Ok(TracePreset::Copy),
+            "status" => Ok(TracePreset::Status),
+            "io"     => Ok(TracePreset::Io),
+            "all"    => Ok(TracePreset::All),
+            _        => Err(format!("unknown trace preset '{}', expected one \
+                of: {}", s, TracePreset::NAMES.join(", "))),
+        }
+    }
+}
+
+impl fmt::Display for TracePreset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TracePreset::Copy   => write!(f, "copy"),
+            TracePreset::Status => write!(f, "status"),
+            TracePreset::Io     => write!(f, "io"),
+            TracePreset::All    => write!(f, "all"),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ColorMode
+////////////////////////////////////////////////////////////////////////////////
+/// When to use colored output on the terminal.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[derive(Serialize, Deserialize)]
+pub enum ColorMode {
+    /// Use colored output if stdout is a terminal.
+    #[default]
+    Auto,
+    /// Always use colored output.
+    Always,
+    /// Never use colored output.
+    Never,
+}
+
+impl ColorMode {
+    /// The names of the available modes.
+    pub const NAMES: &'static [&'static str] = &["auto", "always", "never"];
+
+    /// Applies the color mode to the terminal output of the given
+    /// [`LoggerConfig`] and to the styles of the [`theme`] module.
+    ///
+    /// [`LoggerConfig`]: struct.LoggerConfig.html
+    /// [`theme`]: ../theme/index.html
+    pub fn apply(&self, config: &mut LoggerConfig) {
+        use std::io::IsTerminal as _;
+        let colored = match self {
+            ColorMode::Auto   => io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never  => false,
+        };
+        config.stdout_log_output = match (config.stdout_log_output, colored) {
+            (StdoutLogOutput::Off, _)   => StdoutLogOutput::Off,
+            (StdoutLogOutput::Plain, _) if *self != ColorMode::Always
+                => StdoutLogOutput::Plain,
+            (_, true)  => StdoutLogOutput::Colored,
+            (_, false) => StdoutLogOutput::Plain,
+        };
+        #[cfg(feature = "color")]
+        colored::control::set_override(
+            config.stdout_log_output == StdoutLogOutput::Colored);
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto"   => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never"  => Ok(ColorMode::Never),
+            _        => Err(format!("unknown color mode '{}', expected one \
+                of: {}", s, ColorMode::NAMES.join(", "))),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// LevelSource
+////////////////////////////////////////////////////////////////////////////////
+/// The source of a log level setting, in order of increasing precedence.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum LevelSource {
+    /// The level is the application default.
+    Default,
+    /// The level was set in the config file.
+    Config,
+    /// The level was set by an environment variable.
+    Environment,
+    /// The level was set by a command line option.
+    CommandLine,
+}
+
+impl fmt::Display for LevelSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LevelSource::Default     => write!(f, "default"),
+            LevelSource::Config      => write!(f, "config"),
+            LevelSource::Environment => write!(f, "environment"),
+            LevelSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// TraceFilter
+////////////////////////////////////////////////////////////////////////////////
+/// The module specific log levels collected from each [`LevelSource`].
+///
+/// Settings from a source only replace settings from sources of equal or lower
+/// precedence, so command line options always override environment variables,
+/// which always override the config file, regardless of the order in which
+/// they are applied.
+///
+/// [`LevelSource`]: enum.LevelSource.html
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    /// The level and source for each log target.
+    levels: BTreeMap<Cow<'static, str>, (LevelFilter, LevelSource)>,
+}
+
+impl TraceFilter {
+    /// The environment variable used to set module specific log levels.
+    ///
+    /// The variable holds a comma separated list of `target=level` settings. A
+    /// bare `level` sets the level for the `stall` target.
+    pub const ENV_VAR: &'static str = "STALL_LOG";
+
+    /// Constructs a new `TraceFilter` with no settings.
+    pub fn new() -> Self {
+        TraceFilter::default()
+    }
+
+    /// Sets the level for the given log target, unless it was set by a
+    /// source with higher precedence.
+    pub fn set<T>(&mut self, target: T, level: LevelFilter, source: LevelSource)
+        where T: Into<Cow<'static, str>>
+    {
+        let target = target.into();
+        match self.levels.get(&target) {
+            Some((_, current)) if *current > source => (),
+            _ => { let _ = self.levels.insert(target, (level, source)); },
+        }
+    }
+
+    /// Sets the level for the given log target and all of its submodules,
+    /// discarding any submodule settings from sources with lower precedence.
+    pub fn set_all<T>(
+        &mut self,
+        target: T,
+        level: LevelFilter,
+        source: LevelSource)
+        where T: Into<Cow<'static, str>>
+    {
+        let target = target.into();
+        let prefix = format!("{}::", target);
+        self.levels.retain(|name, (_, current)|
+            !name.starts_with(&prefix) || *current >= source);
+        self.set(target, level, source);
+    }
+
+    /// Applies the settings from the [`ENV_VAR`] environment variable.
+    ///
+    /// [`ENV_VAR`]: #associatedconstant.ENV_VAR
+    pub fn apply_env(&mut self) {
+        let var = match env::var(TraceFilter::ENV_VAR) {
+            Ok(var) => var,
+            Err(_)  => return,
+        };
+
+        for setting in var.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (target, level) = match setting.find('=') {
+                Some(idx) => (&setting[..idx], &setting[idx + 1..]),
+                None      => ("stall", setting),
+            };
+            match LevelFilter::from_str(level) {
+                Ok(level) => self.set_all(
+                    target.to_owned(),
+                    level,
+                    LevelSource::Environment),
+                Err(_) => eprintln!("Ignoring invalid log level in {}: {}",
+                    TraceFilter::ENV_VAR, setting),
+            }
+        }
+    }
+
+    /// Returns an iterator over the log targets with their levels and
+    /// sources.
+    pub fn levels(&self)
+        -> impl Iterator<Item=(&str, LevelFilter, LevelSource)> + '_
+    {
+        self.levels.iter()
+            .map(|(target, (level, source))| (&**target, *level, *source))
+    }
+}
+
+impl fmt::Display for TraceFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (target, level, source) in self.levels() {
+            writeln!(f, "{} = {} ({})", target, level, source)?;
+        }
+        Ok(())
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// EntrySpan
+////////////////////////////////////////////////////////////////////////////////
+thread_local! {
+    /// The fields of the innermost entered `EntrySpan` on the current thread.
+    static ENTRY_SPAN: RefCell<Option<SpanFields>>
+        = const { RefCell::new(None) };
+}
+
+/// The fields recorded by an [`EntrySpan`].
+///
+/// [`EntrySpan`]: struct.EntrySpan.html
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct SpanFields {
+    /// The location of the entry within the stall directory.
+    local: String,
+    /// The location of the entry outside the stall directory.
+    remote: String,
+}
+
+/// A guard which attaches the local and remote locations of a stall entry to
+/// every log record emitted on the current thread until it is dropped.
+///
+/// This allows the log output for a single entry to be filtered without
+/// parsing paths out of log messages.
+#[derive(Debug)]
+pub struct EntrySpan {
+    /// The fields of the enclosing span, restored when this span is dropped.
+    previous: Option<SpanFields>,
+}
+
+impl EntrySpan {
+    /// Enters a new `EntrySpan` for the given entry locations.
+    ///
+    /// ### Parameters
+    /// + `local`: The location of the entry within the stall directory.
+    /// + `remote`: The location of the entry outside the stall directory.
+    pub fn enter<L, R>(local: L, remote: R) -> Self
+        where
+            L: fmt::Display,
+            R: fmt::Display,
+    {
+        let fields = SpanFields {
+            local: local.to_string(),
+            remote: remote.to_string(),
+        };
+        let previous = ENTRY_SPAN.with(|span| span.replace(Some(fields)));
+        EntrySpan { previous }
+    }
+}
+
+impl Drop for EntrySpan {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        ENTRY_SPAN.with(|span| *span.borrow_mut() = previous);
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Child process output
+////////////////////////////////////////////////////////////////////////////////
+thread_local! {
+    /// The fields of the child process output currently being logged on the
+    /// current thread.
+    static CHILD_OUTPUT: RefCell<Option<ChildFields>>
+        = const { RefCell::new(None) };
+}
+
+/// The fields recorded for a line of child process output.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ChildFields {
+    /// The name of the program which wrote the output.
+    program: String,
+    /// The stream the output was written to.
+    stream: ChildStream,
+}
+
+/// An output stream of a child process.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ChildStream {
+    /// The standard output stream. Lines are logged as info messages.
+    Stdout,
+    /// The standard error stream. Lines are logged as warnings.
+    Stderr,
+}
+
+impl ChildStream {
+    /// Returns the name of the stream.
+    #[cfg(feature = "cli")]
+    fn name(&self) -> &'static str {
+        match self {
+            ChildStream::Stdout => "stdout",
+            ChildStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Logs the captured output of a child process, one record per line, so that
+/// it is prefixed with the program name, colored, and suppressed by `--quiet`
+/// like other output. In the JSON log format, the program and stream are
+/// recorded as the `program` and `stream` fields.
+pub fn log_child_output(program: &str, stream: ChildStream, output: &[u8]) {
+    let output = String::from_utf8_lossy(output);
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let fields = ChildFields { program: program.into(), stream };
+        let _ = CHILD_OUTPUT.with(|child| child.replace(Some(fields)));
+        match stream {
+            ChildStream::Stdout => info!("{}", line),
+            ChildStream::Stderr => warn!("{}", line),
+        }
+        let _ = CHILD_OUTPUT.with(|child| child.replace(None));
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Transfer statistics
+////////////////////////////////////////////////////////////////////////////////
+thread_local! {
+    /// The transfer statistics of the record currently being logged on the
+    /// current thread.
+    static TRANSFER: RefCell<Option<Transfer>>
+        = const { RefCell::new(None) };
+}
+
+/// The number of files and bytes copied, and the time taken to copy them,
+/// recorded by [`log_transfer`].
+///
+/// [`log_transfer`]: fn.log_transfer.html
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    /// The number of files copied.
+    pub files: usize,
+    /// The number of bytes copied.
+    pub bytes: u64,
+    /// The time taken to copy the files.
+    pub elapsed: Duration,
+    /// The name of the copy backend which copied the files, if known.
+    pub backend: Option<&'static str>,
+}
+
+/// Logs a message describing a file transfer at the given level. In the JSON
+/// log format, the transfer is recorded as the `files`, `bytes`, `seconds`,
+/// and `bytes_per_second` fields, and the `backend` field if it is known.
+pub fn log_transfer(
+    level: Level,
+    transfer: &Transfer,
+    message: fmt::Arguments<'_>)
+{
+    let _ = TRANSFER.with(|fields| fields.replace(Some(*transfer)));
+    log!(level, "{}", message);
+    let _ = TRANSFER.with(|fields| fields.replace(None));
+}
+
 
 ////////////////////////////////////////////////////////////////////////////////
 // Logger
 ////////////////////////////////////////////////////////////////////////////////
 /// Logger interface for creating and setting up the global logger.
+#[cfg(feature = "cli")]
 #[allow(missing_debug_implementations)]
 pub struct Logger {
     /// The logging dispatcher.
     dispatch: fern::Dispatch,
+    /// The formatter used for text output.
+    formatter: Arc<Formatter>,
+    /// The number of recent log records to keep.
+    recent_events: usize,
 }
 
+/// The type of a log output formatter function.
+#[cfg(feature = "cli")]
+type Formatter = dyn Fn(
+        fern::FormatCallback<'_>,
+        &fmt::Arguments<'_>,
+        &Record<'_>)
+    + Sync + Send;
+
+#[cfg(feature = "cli")]
 impl Logger {
     
     ////////////////////////////////////////////////////////////////////////////
@@ -124,18 +609,7 @@ impl Logger {
     
     /// Constructs a new Logger with the default settings.
     fn new() -> Self {
-        let dispatch = fern::Dispatch::new().format(|out, message, record| {
-            match record.level() {
-                Level::Info => out.finish(*message),
-                _ => out.finish(format_args!(
-                    "[{level}][{target}] {message}",
-                    level = record.level(),
-                    target = record.target(),
-                    message = message)),
-            }
-        });
-
-        Self { dispatch }
+        Logger::new_with_formatter(text_format)
     }
 
     /// Constructs a new Logger from a [`LoggerConfig`].
@@ -160,8 +634,11 @@ impl Logger {
                 &Record<'_>)
             + Sync + Send + 'static,
     {
-        let dispatch = fern::Dispatch::new().format(formatter);
-        Self { dispatch }
+        Self {
+            dispatch: fern::Dispatch::new(),
+            formatter: Arc::new(formatter),
+            recent_events: 0,
+        }
     }
     
     /// Constructs a new Logger from a [`LoggerConfig`] and an output formatter.
@@ -192,32 +669,41 @@ impl Logger {
         if config.allow_env_override {
             env_var_override(&mut config);
         }
+        if let Some(color_mode) = config.color_mode {
+            color_mode.apply(&mut config);
+        }
 
         self.dispatch = self.dispatch.level(config.level_filter);
+        self.recent_events = config.recent_events;
 
         match config.stdout_log_output {
             StdoutLogOutput::Plain => {
-                self.dispatch = self.dispatch.chain(io::stdout())
+                let output = self.text_output().chain(io::stdout());
+                self.dispatch = self.dispatch.chain(output)
             },
 
             StdoutLogOutput::Colored => {
-                self.dispatch = self
-                    .dispatch
+                let output = self.text_output()
                     .chain(colored_stdout(fern::colors::ColoredLevelConfig {
                         error: Color::BrightRed,
                         warn: Color::Yellow,
                         debug: Color::White,
                         info: Color::Green,
                         trace: Color::Cyan,
-                    }))
+                    }));
+                self.dispatch = self.dispatch.chain(output)
             },
             
             _ => ()
         }
 
         if let Some(path) = config.log_path {
-            if let Ok(log_path) = fern::log_file(path) {
-                self.dispatch = self.dispatch.chain(log_path)
+            if let Ok(log_file) = fern::log_file(path) {
+                let output = match config.log_format {
+                    LogFormat::Text => self.text_output(),
+                    LogFormat::Json => fern::Dispatch::new().format(json_format),
+                };
+                self.dispatch = self.dispatch.chain(output.chain(log_file))
             } else {
                 eprintln!("Unable to access the log file, as such it will not \
                     be used")
@@ -227,6 +713,13 @@ impl Logger {
         self
     }
 
+    /// Returns a dispatcher which formats log records with the text formatter.
+    fn text_output(&self) -> fern::Dispatch {
+        let formatter = self.formatter.clone();
+        fern::Dispatch::new()
+            .format(move |out, message, record| formatter(out, message, record))
+    }
+
     /// Sets the log level for a module.
     ///
     /// ### Parameters
@@ -234,7 +727,7 @@ impl Logger {
     /// + `level`: The [`LevelFilter`] to set.
     ///
     /// [`LevelFilter`]: https://docs.rs/log/0.4.10/log/enum.LevelFilter.html
-    pub fn level_for<T: Into<std::borrow::Cow<'static, str>>>(
+    pub fn level_for<T: Into<Cow<'static, str>>>(
         mut self,
         module: T,
         level: LevelFilter) 
@@ -244,17 +737,111 @@ impl Logger {
         self
     }
 
+    /// Sets the log levels for each target in a [`TraceFilter`].
+    ///
+    /// ### Parameters
+    /// + `filter`: The `TraceFilter` to apply.
+    ///
+    /// [`TraceFilter`]: struct.TraceFilter.html
+    pub fn trace_filter(mut self, filter: &TraceFilter) -> Self {
+        for (target, level, _) in filter.levels() {
+            self = self.level_for(target.to_owned(), level);
+        }
+        self
+    }
+
     /// Starts the `Logger`, enabling the use of [`log macros`].
     ///
     /// [`log macros`]: https://docs.rs/log/0.4.10/log/#macros
     pub fn start(self) {
-        self.dispatch.apply().unwrap_or_else(|_|
+        let mut root = fern::Dispatch::new().chain(self.dispatch);
+        if self.recent_events > 0 {
+            root = root.chain(recent_events_output(self.recent_events));
+        }
+
+        root.apply().unwrap_or_else(|_|
             warn!("Logger already set, SUNFLOWER logger will not be used")
         );
     }
 }
 
 
+////////////////////////////////////////////////////////////////////////////////
+// RecentEvent
+////////////////////////////////////////////////////////////////////////////////
+/// The recently emitted log records, oldest first.
+static RECENT_EVENTS: Mutex<VecDeque<RecentEvent>> = Mutex::new(VecDeque::new());
+
+/// A log record kept for display if the command fails.
+#[derive(Clone, Debug)]
+pub struct RecentEvent {
+    /// The level of the record.
+    pub level: Level,
+    /// The target of the record.
+    pub target: String,
+    /// The formatted message of the record.
+    pub message: String,
+    /// The [`EntrySpan`] fields at the time the record was emitted.
+    ///
+    /// [`EntrySpan`]: struct.EntrySpan.html
+    span: Option<SpanFields>,
+}
+
+impl RecentEvent {
+    /// Returns the recent log records relevant to a failure: those emitted
+    /// while processing the most recent entry, or the recent warnings and
+    /// errors if no entry was being processed.
+    pub fn failure_context() -> Vec<RecentEvent> {
+        let events = match RECENT_EVENTS.lock() {
+            Ok(events) => events,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let last_span = events.iter().rev().find_map(|e| e.span.as_ref());
+        match last_span {
+            Some(span) => events.iter()
+                .filter(|e| e.span.as_ref() == Some(span))
+                .cloned()
+                .collect(),
+            None => events.iter()
+                .filter(|e| e.level <= Level::Warn)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for RecentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}][{}] {}", self.level, self.target, self.message)
+    }
+}
+
+/// Returns a dispatcher which stores records from the `stall` crate in the
+/// recent event buffer.
+///
+/// ### Parameters
+/// + `capacity`: The number of recent records to keep.
+#[cfg(feature = "cli")]
+fn recent_events_output(capacity: usize) -> fern::Dispatch {
+    fern::Dispatch::new()
+        .filter(|metadata| metadata.target().starts_with("stall"))
+        .chain(fern::Output::call(move |record| {
+            let event = RecentEvent {
+                level: record.level(),
+                target: record.target().to_owned(),
+                message: record.args().to_string(),
+                span: ENTRY_SPAN.with(|span| span.borrow().clone()),
+            };
+            if let Ok(mut events) = RECENT_EVENTS.lock() {
+                while events.len() >= capacity {
+                    let _ = events.pop_front();
+                }
+                events.push_back(event);
+            }
+        }))
+}
+
+
 ////////////////////////////////////////////////////////////////////////////////
 // env_var_override
 ////////////////////////////////////////////////////////////////////////////////
@@ -264,6 +851,7 @@ impl Logger {
 /// + `LoggerConfig`: The logger configuration to override.
 ///
 /// [`LoggerConfig`]: struct.LoggerConfig.html
+#[cfg(feature = "cli")]
 fn env_var_override(config: &mut LoggerConfig) {
     if let Ok(var) = env::var("SUNFLOWER_LOG_STDOUT") {
         match var.to_lowercase().as_ref() {
@@ -289,6 +877,82 @@ fn env_var_override(config: &mut LoggerConfig) {
 }
 
 
+////////////////////////////////////////////////////////////////////////////////
+// text_format
+////////////////////////////////////////////////////////////////////////////////
+/// Formats a log record as a line of text. Info messages are printed without
+/// decoration, as they make up the normal program output.
+#[cfg(feature = "cli")]
+fn text_format(
+    out: fern::FormatCallback<'_>,
+    message: &fmt::Arguments<'_>,
+    record: &Record<'_>)
+{
+    let child = CHILD_OUTPUT.with(|child| child.borrow().clone());
+    if let Some(child) = child {
+        return out.finish(format_args!("    [{}] {}", child.program, message));
+    }
+
+    if record.level() == Level::Info {
+        return out.finish(*message);
+    }
+
+    ENTRY_SPAN.with(|span| match &*span.borrow() {
+        Some(fields) => out.finish(format_args!(
+            "[{level}][{target}][local={local} remote={remote}] {message}",
+            level = record.level(),
+            target = record.target(),
+            local = fields.local,
+            remote = fields.remote,
+            message = message)),
+        None => out.finish(format_args!(
+            "[{level}][{target}] {message}",
+            level = record.level(),
+            target = record.target(),
+            message = message)),
+    })
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// json_format
+////////////////////////////////////////////////////////////////////////////////
+/// Formats a log record as a line of JSON.
+#[cfg(feature = "cli")]
+fn json_format(
+    out: fern::FormatCallback<'_>,
+    message: &fmt::Arguments<'_>,
+    record: &Record<'_>)
+{
+    let mut object = serde_json::Map::new();
+    let _ = object.insert("level".into(), record.level().as_str().into());
+    let _ = object.insert("target".into(), record.target().into());
+    let _ = object.insert("message".into(), message.to_string().into());
+    ENTRY_SPAN.with(|span| if let Some(fields) = &*span.borrow() {
+        let _ = object.insert("local".into(), fields.local.clone().into());
+        let _ = object.insert("remote".into(), fields.remote.clone().into());
+    });
+    CHILD_OUTPUT.with(|child| if let Some(fields) = &*child.borrow() {
+        let _ = object.insert("program".into(), fields.program.clone().into());
+        let _ = object.insert("stream".into(), fields.stream.name().into());
+    });
+    TRANSFER.with(|transfer| if let Some(transfer) = &*transfer.borrow() {
+        let seconds = transfer.elapsed.as_secs_f64();
+        let _ = object.insert("files".into(), transfer.files.into());
+        let _ = object.insert("bytes".into(), transfer.bytes.into());
+        let _ = object.insert("seconds".into(), seconds.into());
+        if seconds > 0.0 {
+            let _ = object.insert("bytes_per_second".into(),
+                (transfer.bytes as f64 / seconds).into());
+        }
+        if let Some(backend) = transfer.backend {
+            let _ = object.insert("backend".into(), backend.into());
+        }
+    });
+    out.finish(format_args!("{}", serde_json::Value::Object(object)))
+}
+
+
 ////////////////////////////////////////////////////////////////////////////////
 // colored_stdout
 ////////////////////////////////////////////////////////////////////////////////
@@ -298,6 +962,7 @@ fn env_var_override(config: &mut LoggerConfig) {
 /// + `color_config`: The [`ColoredLevelConfig`] specifying output colors.
 ///
 /// [`ColoredLevelConfig`]: https://docs.rs/fern/0.5.9/fern/colors/struct.ColoredLevelConfig.html
+#[cfg(feature = "cli")]
 fn colored_stdout(color_config: fern::colors::ColoredLevelConfig)
     -> fern::Dispatch
 {
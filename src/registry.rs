@@ -0,0 +1,216 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licensed using the MIT or Apache 2 license.
+// See license-mit.md and license-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Registry of the stall directories known to the current user.
+////////////////////////////////////////////////////////////////////////////////
+#![warn(missing_docs)]
+
+// Local imports.
+use crate::action::replace_file;
+use crate::error::Context;
+use crate::error::Error;
+use crate::error::UnknownStall;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// DEFAULT_REGISTRY_PATH
+////////////////////////////////////////////////////////////////////////////////
+/// The path of the registry file, relative to the user's configuration
+/// directory.
+pub const DEFAULT_REGISTRY_PATH: &str = "stall/registry.ron";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// registry_path
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the path of the registry file.
+///
+/// This is the `STALL_REGISTRY` environment variable if it is set. Otherwise
+/// it is the [`DEFAULT_REGISTRY_PATH`] within `$XDG_CONFIG_HOME`, `%APPDATA%`
+/// on Windows, or `~/.config`. Returns `None` if none of these are set.
+///
+/// [`DEFAULT_REGISTRY_PATH`]: constant.DEFAULT_REGISTRY_PATH.html
+pub fn registry_path() -> Option<PathBuf> {
+    if let Some(path) = non_empty_var("STALL_REGISTRY") {
+        return Some(path.into());
+    }
+    let config_dir = non_empty_var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| match cfg!(windows) {
+            true  => non_empty_var("APPDATA").map(PathBuf::from),
+            false => None,
+        })
+        .or_else(|| non_empty_var("HOME")
+            .map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join(DEFAULT_REGISTRY_PATH))
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Registry
+////////////////////////////////////////////////////////////////////////////////
+/// The stall directories known to the current user, by name, so that
+/// commands can address a stall by its name instead of its path.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Registry {
+    /// The stall directory registered under each name.
+    #[serde(default)]
+    stalls: BTreeMap<String, PathBuf>,
+}
+
+impl Registry {
+    /// Loads the `Registry` from the [`registry_path`]. Returns an empty
+    /// `Registry` if the registry file does not exist.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the registry path can't be determined, or if
+    /// the registry file can't be read or parsed.
+    ///
+    /// [`registry_path`]: fn.registry_path.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn load() -> Result<Self, Error> {
+        Registry::load_from(&default_path()?)
+    }
+
+    /// Loads the `Registry` from the registry file at the given path. Returns
+    /// an empty `Registry` if the registry file does not exist.
+    pub fn load_from(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => ron::de::from_str(&text)
+                .with_context(|| format!("Unable to parse registry file: \
+                    {:?}", path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!("No registry file found: {:?}", path);
+                Ok(Registry::default())
+            },
+            Err(e) => Err(e)
+                .with_context(|| format!("Unable to read registry file: {:?}",
+                    path)),
+        }
+    }
+
+    /// Writes the `Registry` to the [`registry_path`].
+    ///
+    /// [`registry_path`]: fn.registry_path.html
+    pub fn save(&self) -> Result<(), Error> {
+        self.save_to(&default_path()?)
+    }
+
+    /// Writes the `Registry` to the registry file at the given path, creating
+    /// its parent directory if needed.
+    pub fn save_to(&self, path: &Path) -> Result<(), Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create directory {:?}",
+                    parent))?;
+        }
+        let temp = path.with_extension("stall-tmp");
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .with_context(|| "serialize registry")?;
+        std::fs::write(&temp, text)
+            .with_context(|| format!("Unable to write registry file: {:?}",
+                temp))?;
+        replace_file(&temp, path)
+            .with_context(|| format!("Unable to write registry file: {:?}",
+                path))?;
+        debug!("Saved registry: {:?}", path);
+        Ok(())
+    }
+
+    /// Registers the stall directory under the given name, returning the
+    /// directory previously registered under it, if any.
+    pub fn register<S, P>(&mut self, name: S, stall_dir: P) -> Option<PathBuf>
+        where
+            S: Into<String>,
+            P: Into<PathBuf>,
+    {
+        self.stalls.insert(name.into(), stall_dir.into())
+    }
+
+    /// Removes the stall directory registered under the given name,
+    /// returning it.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`UnknownStall`] error if no stall is registered under the
+    /// name.
+    ///
+    /// [`UnknownStall`]: ../error/struct.UnknownStall.html
+    pub fn unregister(&mut self, name: &str) -> Result<PathBuf, Error> {
+        match self.stalls.remove(name) {
+            Some(stall_dir) => Ok(stall_dir),
+            None => Err(self.unknown(name).into()),
+        }
+    }
+
+    /// Returns the stall directory registered under the given name.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`UnknownStall`] error if no stall is registered under the
+    /// name.
+    ///
+    /// [`UnknownStall`]: ../error/struct.UnknownStall.html
+    pub fn stall_dir(&self, name: &str) -> Result<&Path, Error> {
+        match self.stalls.get(name) {
+            Some(stall_dir) => Ok(stall_dir),
+            None => Err(self.unknown(name).into()),
+        }
+    }
+
+    /// Returns an iterator over the names and directories of the registered
+    /// stalls, in order of their names.
+    pub fn iter(&self) -> impl Iterator<Item=(&str, &Path)> {
+        self.stalls.iter()
+            .map(|(name, stall_dir)| (name.as_str(), stall_dir.as_path()))
+    }
+
+    /// Returns true if no stalls are registered.
+    pub fn is_empty(&self) -> bool {
+        self.stalls.is_empty()
+    }
+
+    /// Returns an [`UnknownStall`] error for the given name.
+    ///
+    /// [`UnknownStall`]: ../error/struct.UnknownStall.html
+    fn unknown(&self, name: &str) -> UnknownStall {
+        UnknownStall {
+            name: name.into(),
+            candidates: self.stalls.keys().cloned().collect(),
+        }
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Helper functions.
+////////////////////////////////////////////////////////////////////////////////
+/// Returns the [`registry_path`], or an error if it can't be determined.
+///
+/// [`registry_path`]: fn.registry_path.html
+fn default_path() -> Result<PathBuf, Error> {
+    registry_path().ok_or_else(|| Error::msg("unable to locate the registry \
+        file: set STALL_REGISTRY or HOME"))
+}
+
+/// Returns the value of the environment variable, if it is set and not
+/// empty.
+fn non_empty_var(name: &str) -> Option<std::ffi::OsString> {
+    std::env::var_os(name).filter(|value| !value.is_empty())
+}
@@ -0,0 +1,454 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licensed using the MIT or Apache 2 license.
+// See license-mit.md and license-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Record of the last successful copy of each entry.
+////////////////////////////////////////////////////////////////////////////////
+#![warn(missing_docs)]
+
+// Local imports.
+use crate::action::Action;
+use crate::action::CopyStats;
+use crate::action::FsCapabilities;
+use crate::action::Location;
+use crate::action::replace_file;
+use crate::action::State;
+use crate::Entry;
+use crate::EntryOrder;
+use crate::error::Context;
+use crate::error::Error;
+use crate::Pin;
+
+// External library imports.
+use log::*;
+use serde::Deserialize;
+use serde::Serialize;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// DEFAULT_STATE_PATH
+////////////////////////////////////////////////////////////////////////////////
+/// The path of the sync state file, relative to the stall directory.
+pub const DEFAULT_STATE_PATH: &str = ".stall-state";
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SyncRecord
+////////////////////////////////////////////////////////////////////////////////
+/// The modification times of both copies of an entry at the time it was last
+/// successfully copied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncRecord {
+    /// The modification time of the copy within the stall directory.
+    pub local_modified: SystemTime,
+    /// The modification time of the copy outside the stall directory.
+    pub remote_modified: SystemTime,
+}
+
+impl SyncRecord {
+    /// Returns true if both copies have been modified since the record was
+    /// made.
+    pub fn is_conflict(
+        &self,
+        local_modified: SystemTime,
+        remote_modified: SystemTime)
+        -> bool
+    {
+        local_modified != self.local_modified
+            && remote_modified != self.remote_modified
+    }
+
+    /// Returns true if neither copy has been modified since the record was
+    /// made.
+    pub fn is_unchanged(
+        &self,
+        local_modified: SystemTime,
+        remote_modified: SystemTime)
+        -> bool
+    {
+        local_modified == self.local_modified
+            && remote_modified == self.remote_modified
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// CachedStatus
+////////////////////////////////////////////////////////////////////////////////
+/// The most recently computed status of an entry, as it would be collected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedStatus {
+    /// The state of the entry.
+    pub state: State,
+    /// The action which would be taken for the entry.
+    pub action: Action,
+    /// The time at which the status was computed.
+    pub checked: SystemTime,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// LastError
+////////////////////////////////////////////////////////////////////////////////
+/// The most recent error encountered while processing an entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastError {
+    /// The error message, including its causes.
+    pub message: String,
+    /// The time at which the error occurred.
+    pub time: SystemTime,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// KnownGood
+////////////////////////////////////////////////////////////////////////////////
+/// The last version of an entry which was distributed without error. A copy
+/// of it is kept so that the entry can be rolled back to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownGood {
+    /// The checksum of the contents of the stall copy when it was
+    /// distributed.
+    pub pin: Pin,
+    /// The time at which it was distributed.
+    pub time: SystemTime,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// RunProgress
+////////////////////////////////////////////////////////////////////////////////
+/// The progress of a collect or distribute, recorded while it runs so that it
+/// can be resumed with the `--resume` option if it is interrupted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunProgress {
+    /// The command being run: `collect` or `distribute`.
+    pub command: String,
+    /// The order chosen for the entries.
+    pub order: EntryOrder,
+    /// The remote paths of the planned entries, in the order they are
+    /// processed.
+    pub entries: Vec<PathBuf>,
+    /// The remote paths of the entries which have been copied.
+    pub completed: BTreeSet<PathBuf>,
+    /// The time at which the run started.
+    pub started: SystemTime,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Throughput
+////////////////////////////////////////////////////////////////////////////////
+/// The files and bytes copied by recent runs of a command, and the time taken
+/// to copy them, used to estimate the duration of later runs.
+///
+/// Each time a run is recorded, the totals of the earlier runs are halved, so
+/// that recent runs count for more.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Throughput {
+    /// The weighted number of files copied.
+    pub files: u64,
+    /// The weighted number of bytes copied.
+    pub bytes: u64,
+    /// The weighted time taken to copy the files.
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    /// Records the [`CopyStats`] of a run.
+    ///
+    /// [`CopyStats`]: ../action/struct.CopyStats.html
+    pub fn record(&mut self, stats: &CopyStats) {
+        self.files = self.files / 2 + stats.files as u64;
+        self.bytes = self.bytes / 2 + stats.bytes;
+        self.elapsed = self.elapsed / 2 + stats.elapsed;
+    }
+
+    /// Returns the expected time to copy the given number of files and
+    /// bytes, or `None` if no files have been recorded.
+    ///
+    /// The time is proportional to the bytes copied, or to the files copied
+    /// if only empty files have been recorded.
+    pub fn estimate(&self, files: usize, bytes: u64) -> Option<Duration> {
+        let ratio = match (self.files, self.bytes) {
+            (0, _)        => return None,
+            (recorded, 0) => files as f64 / recorded as f64,
+            (_, recorded) => bytes as f64 / recorded as f64,
+        };
+        Some(self.elapsed.mul_f64(ratio))
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// SyncState
+////////////////////////////////////////////////////////////////////////////////
+/// The [`SyncRecord`]s for each entry in a stall directory, stored in the
+/// [`DEFAULT_STATE_PATH`] file.
+///
+/// [`SyncRecord`]: struct.SyncRecord.html
+/// [`DEFAULT_STATE_PATH`]: constant.DEFAULT_STATE_PATH.html
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncState {
+    /// The sync records, keyed by the remote path of each entry.
+    #[serde(default)]
+    entries: BTreeMap<PathBuf, SyncRecord>,
+    /// The cached statuses, keyed by the remote path of each entry.
+    #[serde(default)]
+    status: BTreeMap<PathBuf, CachedStatus>,
+    /// The last errors, keyed by the remote path of each entry.
+    #[serde(default)]
+    errors: BTreeMap<PathBuf, LastError>,
+    /// The times each entry was last found in sync by a collect or
+    /// distribute, keyed by the remote path of each entry.
+    #[serde(default)]
+    synced: BTreeMap<PathBuf, SystemTime>,
+    /// The last known good versions, keyed by the remote path of each entry.
+    #[serde(default)]
+    known_good: BTreeMap<PathBuf, KnownGood>,
+    /// The progress of the current or interrupted run, if any.
+    #[serde(default)]
+    progress: Option<RunProgress>,
+    /// The recent throughput of each command.
+    #[serde(default)]
+    throughput: BTreeMap<String, Throughput>,
+    /// The probed capabilities of each file system, keyed by its mount point.
+    #[serde(default)]
+    capabilities: BTreeMap<PathBuf, FsCapabilities>,
+    /// The path the state is saved to as the run progresses.
+    #[serde(skip)]
+    progress_path: Option<PathBuf>,
+}
+
+impl SyncState {
+    /// Loads the `SyncState` for the given stall directory. Returns an empty
+    /// `SyncState` if the state file does not exist.
+    pub fn load(stall_dir: &Path) -> Result<Self, Error> {
+        SyncState::load_from(&stall_dir.join(DEFAULT_STATE_PATH))
+    }
+
+    /// Loads the `SyncState` from the state file at the given path. Returns an
+    /// empty `SyncState` if the state file does not exist.
+    pub fn load_from(path: &Path) -> Result<Self, Error> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => ron::de::from_str(&text)
+                .with_context(|| format!("Unable to parse state file: {:?}",
+                    path)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!("No state file found: {:?}", path);
+                Ok(SyncState::default())
+            },
+            Err(e) => Err(e)
+                .with_context(|| format!("Unable to read state file: {:?}",
+                    path)),
+        }
+    }
+
+    /// Writes the `SyncState` to the state file of the given stall directory.
+    pub fn save(&self, stall_dir: &Path) -> Result<(), Error> {
+        self.save_to(&stall_dir.join(DEFAULT_STATE_PATH))
+    }
+
+    /// Writes the `SyncState` to the state file at the given path.
+    pub fn save_to(&self, path: &Path) -> Result<(), Error> {
+        let temp = path.with_extension("stall-tmp");
+        let text = ron::ser::to_string_pretty(self, Default::default())
+            .with_context(|| "serialize sync state")?;
+        std::fs::write(&temp, text)
+            .with_context(|| format!("Unable to write state file: {:?}",
+                temp))?;
+        replace_file(&temp, path)
+            .with_context(|| format!("Unable to write state file: {:?}",
+                path))?;
+        debug!("Saved sync state: {:?}", path);
+        Ok(())
+    }
+
+    /// Returns the `SyncRecord` for the given [`Entry`], if it has been copied
+    /// before.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn get(&self, entry: &Entry) -> Option<&SyncRecord> {
+        self.entries.get(&Location::remote(entry).display_path())
+    }
+
+    /// Sets the `SyncRecord` for the given [`Entry`].
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn record(&mut self, entry: &Entry, record: SyncRecord) {
+        let _ = self.entries
+            .insert(Location::remote(entry).display_path(), record);
+    }
+
+    /// Returns the `CachedStatus` for the given [`Entry`], if its status has
+    /// been computed before.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn cached_status(&self, entry: &Entry) -> Option<&CachedStatus> {
+        self.status.get(&Location::remote(entry).display_path())
+    }
+
+    /// Sets the `CachedStatus` for the given [`Entry`].
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn cache_status(&mut self, entry: &Entry, status: CachedStatus) {
+        let _ = self.status
+            .insert(Location::remote(entry).display_path(), status);
+    }
+
+    /// Returns the `LastError` for the given [`Entry`], if the last attempt
+    /// to process it failed.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn last_error(&self, entry: &Entry) -> Option<&LastError> {
+        self.errors.get(&Location::remote(entry).display_path())
+    }
+
+    /// Records an error for the given [`Entry`].
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn record_error(&mut self, entry: &Entry, error: &Error) {
+        let _ = self.errors.insert(Location::remote(entry).display_path(),
+            LastError {
+                message: format!("{:#}", error),
+                time: SystemTime::now(),
+            });
+    }
+
+    /// Clears the recorded error for the given [`Entry`].
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn clear_error(&mut self, entry: &Entry) {
+        let _ = self.errors.remove(&Location::remote(entry).display_path());
+    }
+
+    /// Returns the time the given [`Entry`] was last copied or found to be in
+    /// sync by a collect or distribute, if it ever has been.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn last_synced(&self, entry: &Entry) -> Option<SystemTime> {
+        self.synced.get(&Location::remote(entry).display_path()).copied()
+    }
+
+    /// Records that the given [`Entry`] is now in sync.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn record_synced(&mut self, entry: &Entry) {
+        let _ = self.synced
+            .insert(Location::remote(entry).display_path(), SystemTime::now());
+    }
+
+    /// Returns the `KnownGood` version of the given [`Entry`], if it has been
+    /// distributed without error.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn known_good(&self, entry: &Entry) -> Option<&KnownGood> {
+        self.known_good.get(&Location::remote(entry).display_path())
+    }
+
+    /// Records the stall copy of the given [`Entry`] with the given checksum
+    /// as its last known good version.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    pub fn record_known_good(&mut self, entry: &Entry, pin: Pin) {
+        let _ = self.known_good.insert(Location::remote(entry).display_path(),
+            KnownGood { pin, time: SystemTime::now() });
+    }
+
+    /// Returns the `RunProgress` of the current or interrupted run, if any.
+    pub fn progress(&self) -> Option<&RunProgress> {
+        self.progress.as_ref()
+    }
+
+    /// Begins recording the progress of a run, saving the `SyncState` to the
+    /// state file at the given path each time an entry is completed.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the state file can't be written.
+    ///
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn begin_run(&mut self, progress: RunProgress, path: &Path)
+        -> Result<(), Error>
+    {
+        self.progress = Some(progress);
+        self.progress_path = Some(path.to_path_buf());
+        self.save_to(path)
+    }
+
+    /// Records that the given [`Entry`] has been copied by the current run.
+    ///
+    /// ### Errors
+    ///
+    /// Returns an [`Error`] if the state file can't be written.
+    ///
+    /// [`Entry`]: ../struct.Entry.html
+    /// [`Error`]: ../error/struct.Error.html
+    pub fn complete(&mut self, entry: &Entry) -> Result<(), Error> {
+        if let Some(progress) = &mut self.progress {
+            let _ = progress.completed
+                .insert(Location::remote(entry).display_path());
+        }
+        match self.progress_path.clone() {
+            Some(path) => self.save_to(&path),
+            None       => Ok(()),
+        }
+    }
+
+    /// Clears the progress of the current run, once it has finished.
+    pub fn finish_run(&mut self) {
+        self.progress = None;
+        self.progress_path = None;
+    }
+
+    /// Returns the recent [`Throughput`] of the given command, if it has
+    /// copied any files.
+    ///
+    /// [`Throughput`]: struct.Throughput.html
+    pub fn throughput(&self, command: &str) -> Option<&Throughput> {
+        self.throughput.get(command)
+    }
+
+    /// Records the [`CopyStats`] of a run of the given command. Runs which
+    /// copied no files are not recorded.
+    ///
+    /// [`CopyStats`]: ../action/struct.CopyStats.html
+    pub fn record_throughput(&mut self, command: &str, stats: &CopyStats) {
+        if stats.files > 0 {
+            self.throughput.entry(command.into())
+                .or_default()
+                .record(stats);
+        }
+    }
+
+    /// Returns the probed [`FsCapabilities`] of each file system, keyed by
+    /// its mount point.
+    ///
+    /// [`FsCapabilities`]: ../action/struct.FsCapabilities.html
+    pub fn capabilities(&self) -> &BTreeMap<PathBuf, FsCapabilities> {
+        &self.capabilities
+    }
+
+    /// Records the probed [`FsCapabilities`] of each file system, keyed by
+    /// its mount point.
+    ///
+    /// [`FsCapabilities`]: ../action/struct.FsCapabilities.html
+    pub fn record_capabilities(
+        &mut self,
+        capabilities: BTreeMap<PathBuf, FsCapabilities>)
+    {
+        self.capabilities.extend(capabilities);
+    }
+}
@@ -0,0 +1,291 @@
+////////////////////////////////////////////////////////////////////////////////
+// Stall configuration management utility
+////////////////////////////////////////////////////////////////////////////////
+// Copyright 2020 Skylor R. Schermer
+// This code is dual licensed using the MIT or Apache 2 license.
+// See license-mit.md and license-apache.md for details.
+////////////////////////////////////////////////////////////////////////////////
+//! Color themes for command output.
+////////////////////////////////////////////////////////////////////////////////
+#![warn(missing_docs)]
+
+// External library imports.
+#[cfg(feature = "color")]
+use colored::ColoredString;
+#[cfg(feature = "color")]
+use colored::Colorize as _;
+use serde::de::Error as _;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+
+// Standard library imports.
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+// Exports.
+#[cfg(feature = "color")]
+pub use colored::Color;
+
+
+////////////////////////////////////////////////////////////////////////////////
+// STYLE_TABLE
+////////////////////////////////////////////////////////////////////////////////
+/// The default color of each style used in command output.
+///
+/// The `header` and `label` styles apply to the status header and directory
+/// labels, the `drift` style to notes on files whose mode differs from their
+/// entry's, and the `description` style to the descriptions of entries. Each
+/// remaining style applies to the state or action of the same name in a
+/// status line.
+pub const STYLE_TABLE: &[(&str, Option<Color>)] = &[
+    ("header",      Some(Color::BrightWhite)),
+    ("label",       Some(Color::BrightWhite)),
+    ("drift",       Some(Color::BrightYellow)),
+    ("description", Some(Color::BrightBlack)),
+    ("copy",        Some(Color::BrightGreen)),
+    ("skip",        Some(Color::BrightWhite)),
+    ("stop",        Some(Color::BrightRed)),
+    ("absent",      Some(Color::BrightWhite)),
+    ("budget",      Some(Color::BrightYellow)),
+    ("conflict",    Some(Color::BrightRed)),
+    ("disabled",    Some(Color::BrightBlack)),
+    ("error",       Some(Color::BrightRed)),
+    ("force",       Some(Color::BrightWhite)),
+    ("found",       Some(Color::BrightGreen)),
+    ("one-way",     Some(Color::BrightWhite)),
+    ("newer",       Some(Color::BrightGreen)),
+    ("older",       Some(Color::BrightYellow)),
+    ("special",     Some(Color::BrightYellow)),
+    ("synced",      Some(Color::BrightWhite)),
+    ("mismatch",    Some(Color::BrightRed)),
+    ("unknown",     Some(Color::BrightWhite)),
+];
+
+/// The theme used by [`paint`].
+///
+/// [`paint`]: fn.paint.html
+static CURRENT_THEME: RwLock<Theme> = RwLock::new(Theme {
+    bold: true,
+    colors: BTreeMap::new(),
+});
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Theme
+////////////////////////////////////////////////////////////////////////////////
+/// A color theme, overriding the default colors of the [`STYLE_TABLE`].
+///
+/// [`STYLE_TABLE`]: constant.STYLE_TABLE.html
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    /// Whether the status header is printed in bold.
+    #[serde(default = "Theme::default_bold")]
+    pub bold: bool,
+
+    /// The color of each style to override, keyed by style name.
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_colors")]
+    pub colors: BTreeMap<String, ThemeColor>,
+}
+
+impl Theme {
+    /// Returns the default bold setting.
+    fn default_bold() -> bool {
+        true
+    }
+
+    /// Returns the color of the given style, or `None` if the style uses the
+    /// terminal's default color.
+    pub fn color(&self, style: &str) -> Option<Color> {
+        match self.colors.get(style) {
+            Some(color) => color.0,
+            None => STYLE_TABLE.iter()
+                .find(|(name, _)| *name == style)
+                .and_then(|(_, color)| *color),
+        }
+    }
+
+    /// Makes this the theme used by [`paint`].
+    ///
+    /// [`paint`]: fn.paint.html
+    pub fn install(self) {
+        match CURRENT_THEME.write() {
+            Ok(mut current) => *current = self,
+            Err(poisoned) => *poisoned.into_inner() = self,
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            bold: Theme::default_bold(),
+            colors: BTreeMap::new(),
+        }
+    }
+}
+
+/// Deserializes the theme colors, rejecting unknown style names.
+fn deserialize_colors<'de, D>(deserializer: D)
+    -> Result<BTreeMap<String, ThemeColor>, D::Error>
+    where D: Deserializer<'de>
+{
+    let colors = BTreeMap::<String, ThemeColor>::deserialize(deserializer)?;
+    for style in colors.keys() {
+        if !STYLE_TABLE.iter().any(|(name, _)| name == style) {
+            return Err(D::Error::custom(format!("unknown style {:?}", style)));
+        }
+    }
+    Ok(colors)
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// ThemeColor
+////////////////////////////////////////////////////////////////////////////////
+/// A color in a [`Theme`], or `None` to use the terminal's default color.
+///
+/// Colors are written as `"red"`, `"bright-red"`, or `"none"`.
+///
+/// [`Theme`]: struct.Theme.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThemeColor(pub Option<Color>);
+
+impl ThemeColor {
+    /// The names of the available colors.
+    pub const NAMES: &'static [(&'static str, Color)] = &[
+        ("black",          Color::Black),
+        ("red",            Color::Red),
+        ("green",          Color::Green),
+        ("yellow",         Color::Yellow),
+        ("blue",           Color::Blue),
+        ("magenta",        Color::Magenta),
+        ("cyan",           Color::Cyan),
+        ("white",          Color::White),
+        ("bright-black",   Color::BrightBlack),
+        ("bright-red",     Color::BrightRed),
+        ("bright-green",   Color::BrightGreen),
+        ("bright-yellow",  Color::BrightYellow),
+        ("bright-blue",    Color::BrightBlue),
+        ("bright-magenta", Color::BrightMagenta),
+        ("bright-cyan",    Color::BrightCyan),
+        ("bright-white",   Color::BrightWhite),
+    ];
+}
+
+impl std::str::FromStr for ThemeColor {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text == "none" {
+            return Ok(ThemeColor(None));
+        }
+        ThemeColor::NAMES.iter()
+            .find(|(name, _)| *name == text)
+            .map(|(_, color)| ThemeColor(Some(*color)))
+            .ok_or_else(|| format!("unknown color {:?}", text))
+    }
+}
+
+impl std::fmt::Display for ThemeColor {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = ThemeColor::NAMES.iter()
+            .find(|(_, color)| Some(*color) == self.0)
+            .map(|(name, _)| *name)
+            .unwrap_or("none");
+        write!(fmt, "{}", name)
+    }
+}
+
+impl Serialize for ThemeColor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where D: Deserializer<'de>
+    {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(D::Error::custom)
+    }
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// Color
+////////////////////////////////////////////////////////////////////////////////
+/// A terminal color. Without the `color` feature, colors can be given in a
+/// [`Theme`], but are never applied.
+///
+/// [`Theme`]: struct.Theme.html
+#[cfg(not(feature = "color"))]
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    BrightBlack,
+    BrightRed,
+    BrightGreen,
+    BrightYellow,
+    BrightBlue,
+    BrightMagenta,
+    BrightCyan,
+    BrightWhite,
+}
+
+
+////////////////////////////////////////////////////////////////////////////////
+// paint
+////////////////////////////////////////////////////////////////////////////////
+/// Text returned by [`paint`]. Without the `color` feature, this is a plain
+/// `String`.
+///
+/// [`paint`]: fn.paint.html
+#[cfg(feature = "color")]
+pub type Painted = ColoredString;
+
+/// Text returned by [`paint`]. Without the `color` feature, this is a plain
+/// `String`.
+///
+/// [`paint`]: fn.paint.html
+#[cfg(not(feature = "color"))]
+pub type Painted = String;
+
+/// Applies the color of the given style in the current [`Theme`] to `text`.
+///
+/// [`Theme`]: struct.Theme.html
+#[cfg(feature = "color")]
+pub fn paint(style: &str, text: &str) -> Painted {
+    let theme = match CURRENT_THEME.read() {
+        Ok(theme) => theme,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let painted = match theme.color(style) {
+        Some(color) => text.color(color),
+        None => text.normal(),
+    };
+    match style == "header" && theme.bold {
+        true  => painted.bold(),
+        false => painted,
+    }
+}
+
+/// Returns `text` unchanged, as the `color` feature is not enabled.
+#[cfg(not(feature = "color"))]
+pub fn paint(_style: &str, text: &str) -> Painted {
+    text.into()
+}